@@ -0,0 +1,328 @@
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use url::Url;
+
+use crate::cli::{Args, StageLoad, Units};
+use crate::pretty;
+use crate::request::{run_bench, BenchOptions};
+use crate::stats::{mix64, Stats};
+
+// --find-max-rate stops bisecting once the bounds are this close
+// (relative to the lower one), or after MAX_RATE_PROBES stages.
+const RATE_PRECISION: f64 = 0.05;
+const MAX_RATE_PROBES: usize = 12;
+
+struct Stage {
+    load: StageLoad,
+    stats: Stats,
+    // Why the stage did not meet the criteria; `None` means it did.
+    failed: Option<String>,
+}
+
+fn judge(args: &Args, stats: &Stats) -> Option<String> {
+    if stats.interrupted {
+        return Some("interrupted".into());
+    }
+    if let Some(reason) = &stats.aborted {
+        return Some(format!("aborted: {}", reason));
+    }
+    let done = stats.completed();
+    if done == 0 {
+        return Some("no requests completed".into());
+    }
+    let error_rate = stats.fail as f64 * 100.0 / done as f64;
+    if error_rate > args.max_stage_errors {
+        return Some(format!("errors {:.1}%", error_rate));
+    }
+    // Rate stages are judged on latency from the scheduled start, so a
+    // backlog behind --concurrency counts against them.
+    let p99 = stats.corrected_percentile(0.99).or_else(|| stats.latency_percentile(0.99));
+    if let (Some(limit), Some(p99)) = (args.max_stage_p99_ms, p99) {
+        if p99 > limit {
+            return Some(format!("p99 {:.2}ms", p99));
+        }
+    }
+    None
+}
+
+// Rate stages run open loop with --concurrency as the cap on requests in
+// flight.
+async fn run_stage(
+    args: &Args,
+    proxy: &Arc<Url>,
+    proxy_addr: &str,
+    base: &BenchOptions,
+    load: StageLoad,
+) -> Result<Stage> {
+    let (concurrency, rate) = match load {
+        StageLoad::Rate(r) => (base.concurrency, Some(r)),
+        StageLoad::Concurrency(c) => (c, base.rate),
+    };
+    let opts = BenchOptions {
+        concurrency,
+        rate,
+        iterations: usize::MAX,
+        stop_after: Some(args.stage_duration),
+        ..base.clone()
+    };
+    let stats = run_bench(proxy.clone(), proxy_addr, Arc::new(opts)).await?;
+    let failed = judge(args, &stats);
+    if !args.quiet && !args.summary_only {
+        let label = match load {
+            StageLoad::Rate(r) => format!("rate {:>9.1}", r),
+            StageLoad::Concurrency(c) => format!("concurrency {:>5}", c),
+        };
+        eprintln!(
+            "  {}: {} {}",
+            label,
+            pretty::summary_line(&stats),
+            failed.as_deref().map(|r| format!("-> fail ({})", r)).unwrap_or_else(|| "-> ok".into())
+        );
+    }
+    Ok(Stage { load, stats, failed })
+}
+
+// Doubles concurrency until a stage fails, then bisects between the last
+// passing and the first failing level.
+pub async fn run(
+    args: &Args,
+    proxy: Arc<Url>,
+    proxy_addr: &str,
+    base: BenchOptions,
+) -> Result<Stats> {
+    let max = args.max_stage_concurrency.max(1);
+    let mut stages = Vec::new();
+    let mut good: Option<usize> = None;
+    let mut bad: Option<usize> = None;
+    let mut c = 1;
+    loop {
+        let stage = run_stage(args, &proxy, proxy_addr, &base, StageLoad::Concurrency(c)).await?;
+        let ok = stage.failed.is_none();
+        stages.push(stage);
+        if !ok {
+            bad = Some(c);
+            break;
+        }
+        good = Some(c);
+        if c >= max {
+            break;
+        }
+        c = (c * 2).min(max);
+    }
+    if let (Some(mut lo), Some(mut hi)) = (good, bad) {
+        while hi - lo > 1 {
+            let mid = (lo + hi) / 2;
+            let stage = run_stage(args, &proxy, proxy_addr, &base, StageLoad::Concurrency(mid)).await?;
+            if stage.failed.is_none() {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+            stages.push(stage);
+        }
+        good = Some(lo);
+    }
+
+    if !args.quiet && !args.summary_only {
+        print_stages(&stages, "Concurrency", args.units);
+    }
+    let best = good.and_then(|g| stages.iter().rev().find(|s| s.load == StageLoad::Concurrency(g)));
+    let Some(best) = best else {
+        let reason = stages
+            .first()
+            .and_then(|s| s.failed.clone())
+            .unwrap_or_default();
+        return Err(anyhow!("no stage met the criteria (concurrency 1: {})", reason));
+    };
+    if args.summary_only {
+        println!(
+            "max_concurrency={} {}",
+            load_value(best.load),
+            pretty::summary_line(&best.stats)
+        );
+    } else if !args.quiet {
+        println!("\nMax stable concurrency: {}", load_value(best.load));
+        pretty::print_results(
+            &best.stats,
+            best.stats.completed(),
+            pretty::color_enabled(args.no_color),
+            args.units,
+        );
+    }
+    Ok(best.stats.clone())
+}
+
+// Binary-searches the highest --rate between --rate-min and --rate-max
+// that meets the stage criteria. Both bounds are probed first: a failing
+// minimum is an error, a passing maximum ends the search there.
+pub async fn run_rate(
+    args: &Args,
+    proxy: Arc<Url>,
+    proxy_addr: &str,
+    base: BenchOptions,
+) -> Result<Stats> {
+    if args.rate_min >= args.rate_max {
+        return Err(anyhow!("--rate-min must be below --rate-max"));
+    }
+    let mut stages = Vec::new();
+    let low = run_stage(args, &proxy, proxy_addr, &base, StageLoad::Rate(args.rate_min)).await?;
+    let low_failed = low.failed.clone();
+    stages.push(low);
+    let mut unbounded = false;
+    let mut best = None;
+    if low_failed.is_none() {
+        best = Some(0);
+        let high = run_stage(args, &proxy, proxy_addr, &base, StageLoad::Rate(args.rate_max)).await?;
+        unbounded = high.failed.is_none();
+        stages.push(high);
+        if unbounded {
+            best = Some(1);
+        } else {
+            let (mut lo, mut hi) = (args.rate_min, args.rate_max);
+            while hi - lo > lo * RATE_PRECISION && stages.len() < MAX_RATE_PROBES {
+                let mid = (lo + hi) / 2.0;
+                let stage = run_stage(args, &proxy, proxy_addr, &base, StageLoad::Rate(mid)).await?;
+                if stage.failed.is_none() {
+                    lo = mid;
+                    best = Some(stages.len());
+                } else {
+                    hi = mid;
+                }
+                stages.push(stage);
+            }
+        }
+    }
+
+    if !args.quiet && !args.summary_only {
+        print_stages(&stages, "Rate", args.units);
+    }
+    let Some(best) = best.map(|i| &stages[i]) else {
+        return Err(anyhow!(
+            "no stage met the criteria (rate {}: {})",
+            args.rate_min,
+            low_failed.unwrap_or_default()
+        ));
+    };
+    let note = if unbounded { " (unbounded within tested range)" } else { "" };
+    if args.summary_only {
+        println!("max_rate={}{} {}", load_value(best.load), note, pretty::summary_line(&best.stats));
+    } else if !args.quiet {
+        println!("\nMax sustainable rate: {} req/s{}", load_value(best.load), note);
+        pretty::print_results(
+            &best.stats,
+            best.stats.completed(),
+            pretty::color_enabled(args.no_color),
+            args.units,
+        );
+    }
+    Ok(best.stats.clone())
+}
+
+fn load_value(load: StageLoad) -> String {
+    match load {
+        StageLoad::Rate(r) => format!("{:.1}", r),
+        StageLoad::Concurrency(c) => c.to_string(),
+    }
+}
+
+fn print_stages(stages: &[Stage], load_header: &str, units: Units) {
+    let fmt_ms = |v: Option<f64>| pretty::fmt_ms(v, units);
+    println!(
+        "\n{:>5}  {:>11}  {:>8}  {:>8}  {:>9}  {:>10}  {:>10}  Result",
+        "Stage", load_header, "Requests", "Success", "Reqs/sec", "p50", "p99"
+    );
+    for (i, s) in stages.iter().enumerate() {
+        println!(
+            "{:>5}  {:>11}  {:>8}  {:>7.1}%  {:>9.1}  {:>10}  {:>10}  {}",
+            i + 1,
+            load_value(s.load),
+            s.stats.completed(),
+            s.stats.success_rate().unwrap_or(0.0),
+            s.stats.rps_avg().unwrap_or(0.0),
+            fmt_ms(s.stats.latency_percentile(0.50)),
+            fmt_ms(s.stats.latency_percentile(0.99)),
+            s.failed.as_deref().unwrap_or("ok")
+        );
+    }
+}
+
+fn load_label(load: StageLoad) -> String {
+    match load {
+        StageLoad::Rate(r) => format!("{} rps", r),
+        StageLoad::Concurrency(c) => format!("c{}", c),
+    }
+}
+
+// Runs the --stage list in order. Rate stages pace request starts and use
+// --concurrency as the cap on requests in flight.
+pub async fn run_staged(
+    args: &Args,
+    proxy: Arc<Url>,
+    proxy_addr: &str,
+    base: BenchOptions,
+) -> Result<Stats> {
+    let progress = !args.quiet && !args.summary_only;
+    let mut total = Stats::default();
+    let mut stages = Vec::with_capacity(args.stage.len());
+    for (i, spec) in args.stage.iter().enumerate() {
+        let (concurrency, rate) = match spec.load {
+            StageLoad::Rate(r) => (base.concurrency, Some(r)),
+            StageLoad::Concurrency(c) => (c, None),
+        };
+        let opts = BenchOptions {
+            concurrency,
+            rate,
+            iterations: usize::MAX,
+            stop_after: Some(spec.duration),
+            // Sequence numbers restart every stage; a derived seed keeps
+            // their sampling keys independent.
+            seed: mix64(base.seed, i as u64),
+            ..base.clone()
+        };
+        let stats = run_bench(proxy.clone(), proxy_addr, Arc::new(opts)).await?;
+        if progress {
+            eprintln!(
+                "  stage {} ({} for {}): {}",
+                i + 1,
+                load_label(spec.load),
+                humantime::format_duration(spec.duration),
+                pretty::summary_line(&stats)
+            );
+        }
+        total.merge(&stats);
+        let aborted = stats.aborted.is_some() || stats.interrupted;
+        stages.push(stats);
+        if aborted {
+            break;
+        }
+    }
+
+    if args.summary_only {
+        println!("{}", pretty::summary_line(&total));
+    } else if !args.quiet {
+        let fmt_ms = |v: Option<f64>| pretty::fmt_ms(v, args.units);
+        println!(
+            "\n{:>5}  {:>10}  {:>10}  {:>8}  {:>8}  {:>9}  {:>10}  {:>10}",
+            "Stage", "Duration", "Load", "Requests", "Success", "Reqs/sec", "p50", "p99"
+        );
+        for (i, (spec, s)) in args.stage.iter().zip(&stages).enumerate() {
+            println!(
+                "{:>5}  {:>10}  {:>10}  {:>8}  {:>7.1}%  {:>9.1}  {:>10}  {:>10}",
+                i + 1,
+                humantime::format_duration(spec.duration).to_string(),
+                load_label(spec.load),
+                s.completed(),
+                s.success_rate().unwrap_or(0.0),
+                s.rps_avg().unwrap_or(0.0),
+                fmt_ms(s.latency_percentile(0.50)),
+                fmt_ms(s.latency_percentile(0.99))
+            );
+        }
+        println!("\nAll stages:");
+        pretty::print_results(&total, total.completed(), pretty::color_enabled(args.no_color), args.units);
+    }
+    if let Some(reason) = &total.aborted {
+        return Err(anyhow!("aborted: error threshold reached ({})", reason));
+    }
+    Ok(total)
+}
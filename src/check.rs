@@ -0,0 +1,87 @@
+use anyhow::{anyhow, Context, Result};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::cli::{Args, IpFormat};
+use crate::request::{fetch, Fetched};
+
+pub struct CheckOptions<'a> {
+    pub ip_url: &'a str,
+    pub format: IpFormat,
+    pub field: &'a str,
+    pub no_direct: bool,
+}
+
+fn parse_ip(body: &[u8], format: IpFormat, field: &str) -> Result<IpAddr> {
+    let text = match format {
+        IpFormat::Text => String::from_utf8_lossy(body).trim().to_string(),
+        IpFormat::Json => {
+            let value: serde_json::Value =
+                serde_json::from_slice(body).context("IP endpoint did not return JSON")?;
+            // `a.b` walks nested objects.
+            let found = field
+                .split('.')
+                .try_fold(&value, |v, key| v.get(key))
+                .ok_or_else(|| anyhow!("field `{}` missing in IP endpoint response", field))?;
+            found
+                .as_str()
+                .ok_or_else(|| anyhow!("field `{}` is not a string", field))?
+                .trim()
+                .to_string()
+        }
+    };
+    text.parse()
+        .map_err(|_| anyhow!("IP endpoint returned something that is not an IP address: {:?}", text))
+}
+
+async fn lookup(
+    url: &Url,
+    proxy: Option<(&Url, &str)>,
+    args: &Args,
+    opts: &CheckOptions<'_>,
+) -> Result<(IpAddr, Duration)> {
+    let start = Instant::now();
+    let Fetched { status, body, .. } = fetch(
+        url,
+        proxy,
+        args.insecure,
+        args.timeout,
+        &crate::socket_options(args),
+    )
+    .await?;
+    if !(200..300).contains(&status) {
+        return Err(anyhow!("IP endpoint answered HTTP {}", status));
+    }
+    let ip = parse_ip(&body, opts.format, opts.field)?;
+    Ok((ip, start.elapsed()))
+}
+
+pub async fn run(args: &Args, proxy: &Url, proxy_addr: &str, proxy_note: &str, opts: CheckOptions<'_>) -> Result<()> {
+    let url = Url::parse(opts.ip_url).context("invalid --ip-url")?;
+    println!("Proxy: {}://{}{}", proxy.scheme(), proxy_addr, proxy_note);
+    println!("Endpoint: {}", url);
+
+    let (proxied, took) = lookup(&url, Some((proxy, proxy_addr)), args, &opts)
+        .await
+        .map_err(|e| anyhow!("proxied request failed: {:#}", e))?;
+    println!("Proxied IP: {} ({:.0}ms)", proxied, took.as_secs_f64() * 1000.0);
+
+    if opts.no_direct {
+        return Ok(());
+    }
+    match lookup(&url, None, args, &opts).await {
+        Ok((direct, took)) => {
+            println!("Direct IP:  {} ({:.0}ms)", direct, took.as_secs_f64() * 1000.0);
+            if direct == proxied {
+                return Err(anyhow!(
+                    "proxied and direct requests exit from the same address {}: traffic is not going through the tunnel",
+                    direct
+                ));
+            }
+            println!("OK: egress address differs from the direct one");
+        }
+        Err(e) => tracing::warn!("direct request failed, nothing to compare: {:#}", e),
+    }
+    Ok(())
+}
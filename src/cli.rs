@@ -1,73 +1,1786 @@
 use clap::Parser;
 use clap_complete::Shell;
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::pattern::Pattern;
+use crate::xray::InboundSpec;
+
+fn parse_inbound(s: &str) -> Result<InboundSpec, String> {
+    InboundSpec::parse(s).map_err(|e| e.to_string())
+}
+
+// `50%` or `50`.
+pub fn parse_percent(s: &str) -> Result<f64, String> {
+    let v: f64 = s
+        .trim()
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| format!("invalid percentage: {}", s))?;
+    if !(0.0..=100.0).contains(&v) {
+        return Err(format!("percentage out of range: {}", s));
+    }
+    Ok(v)
+}
+
+// A share between 0 and 1, e.g. `0.1`.
+pub fn parse_fraction(s: &str) -> Result<f64, String> {
+    let v: f64 = s.trim().parse().map_err(|_| format!("invalid fraction: {}", s))?;
+    if !(0.0..=1.0).contains(&v) {
+        return Err(format!("fraction out of range 0..1: {}", s));
+    }
+    Ok(v)
+}
+
+// `user:pass`.
+fn parse_credentials(s: &str) -> Result<(String, String), String> {
+    s.split_once(':')
+        .map(|(u, p)| (u.to_string(), p.to_string()))
+        .ok_or_else(|| format!("expected user:pass, got {:?}", s))
+}
+
+// Requests or connections per second; must be positive.
+pub fn parse_rate(s: &str) -> Result<f64, String> {
+    match s.trim().parse::<f64>() {
+        Ok(v) if v > 0.0 && v.is_finite() => Ok(v),
+        _ => Err(format!("expected a positive number per second, got {}", s)),
+    }
+}
+
+// `IP` or `IP:PORT`; a bare IP leaves the port to the OS.
+pub fn parse_local_addr(s: &str) -> Result<SocketAddr, String> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(addr);
+    }
+    s.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, 0))
+        .map_err(|_| format!("expected IP or IP:PORT, got {}", s))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StageLoad {
+    Rate(f64),
+    Concurrency(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StageSpec {
+    pub duration: Duration,
+    pub load: StageLoad,
+}
+
+// --connect-to destination. The host is ASCII (IDNs are punycoded) and
+// IPv6 addresses are kept without brackets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectTo {
+    pub host: String,
+    pub port: u16,
+}
+
+impl fmt::Display for ConnectTo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.host.contains(':') {
+            write!(f, "[{}]:{}", self.host, self.port)
+        } else {
+            write!(f, "{}:{}", self.host, self.port)
+        }
+    }
+}
+
+// `HOST:PORT` or `[IPV6]:PORT`.
+pub fn parse_connect_to(s: &str) -> Result<ConnectTo, String> {
+    let (host, port) = if let Some(rest) = s.strip_prefix('[') {
+        let (host, port) = rest
+            .split_once("]:")
+            .ok_or_else(|| format!("expected [IPV6]:PORT, got {}", s))?;
+        host.parse::<Ipv6Addr>()
+            .map_err(|_| format!("invalid IPv6 address: {}", host))?;
+        (host.to_string(), port)
+    } else {
+        let (host, port) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected HOST:PORT, got {}", s))?;
+        if host.contains(':') {
+            return Err(format!("IPv6 addresses need brackets, e.g. [{}]:{}", host, port));
+        }
+        let host = crate::request::ascii_host(host).map_err(|e| e.to_string())?;
+        if host.is_empty()
+            || !host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+        {
+            return Err(format!("invalid host in {}", s));
+        }
+        (host, port)
+    };
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(ConnectTo { host, port }),
+        _ => Err(format!("invalid port in {}", s)),
+    }
+}
+
+// Longer than any sensible wait; also keeps deadlines far from overflowing
+// `Instant`.
+const MAX_DURATION: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// `500ms`, `5s`, `2m`, `1h30m`; a bare number is milliseconds, as all time
+// flags once were.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let d = if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        let ms: u64 = s.parse().map_err(|_| format!("{} is out of range (at most 7 days)", s))?;
+        Duration::from_millis(ms)
+    } else {
+        humantime::parse_duration(s).map_err(|e| match e {
+            humantime::DurationError::NumberOverflow => format!("{} is out of range (at most 7 days)", s),
+            e => format!("invalid duration {:?}: {} (expected e.g. 500ms, 5s, 2m; bare numbers are milliseconds)", s, e),
+        })?
+    };
+    if d.is_zero() {
+        return Err(format!("{} must be greater than zero", s));
+    }
+    if d > MAX_DURATION {
+        return Err(format!("{} is out of range (at most 7 days)", s));
+    }
+    Ok(d)
+}
+
+// `30s:50` starts 50 requests per second for 30 seconds; `1m:c20` keeps 20
+// requests in flight for a minute.
+pub fn parse_stage(s: &str) -> Result<StageSpec, String> {
+    let (dur, load) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("expected DURATION:RATE or DURATION:cN, got {}", s))?;
+    let duration = parse_duration(dur).map_err(|e| format!("invalid stage duration: {}", e))?;
+    let load = match load.strip_prefix('c') {
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if n > 0 => StageLoad::Concurrency(n),
+            _ => return Err(format!("invalid stage concurrency: {}", load)),
+        },
+        None => match load.parse::<f64>() {
+            Ok(r) if r > 0.0 && r.is_finite() => StageLoad::Rate(r),
+            _ => return Err(format!("invalid stage rate: {}", load)),
+        },
+    };
+    Ok(StageSpec { duration, load })
+}
+
+// `1024`, `64k`, `50MB`, `1.5GiB`: decimal units for K/M/G, binary for KiB/MiB/GiB.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let t = s.trim();
+    let split = t
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(t.len());
+    let (num, unit) = t.split_at(split);
+    let num: f64 = num
+        .parse()
+        .map_err(|_| format!("invalid size: {}", s))?;
+    let mult: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "kib" => 1 << 10,
+        "m" | "mb" => 1_000_000,
+        "mib" => 1 << 20,
+        "g" | "gb" => 1_000_000_000,
+        "gib" => 1 << 30,
+        other => return Err(format!("unknown size unit: {}", other)),
+    };
+    Ok((num * mult as f64).round() as u64)
+}
+
+// One `first-last` span of a Range header; `None` ends are open
+// (`1024-`) or a suffix length (`-500`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteSpan {
+    pub first: Option<u64>,
+    pub last: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteRanges {
+    pub spans: Vec<ByteSpan>,
+}
+
+impl ByteRanges {
+    pub fn header_value(&self) -> String {
+        let spans: Vec<String> = self
+            .spans
+            .iter()
+            .map(|s| {
+                format!(
+                    "{}-{}",
+                    s.first.map(|v| v.to_string()).unwrap_or_default(),
+                    s.last.map(|v| v.to_string()).unwrap_or_default()
+                )
+            })
+            .collect();
+        format!("bytes={}", spans.join(","))
+    }
+}
+
+// `bytes=0-1023`, `bytes=1024-`, `bytes=-500`, `bytes=0-99,200-299`; the
+// `bytes=` prefix is optional.
+pub fn parse_range(s: &str) -> Result<ByteRanges, String> {
+    let t = s.trim();
+    let list = t.strip_prefix("bytes=").unwrap_or(t);
+    let mut spans = Vec::new();
+    for part in list.split(',') {
+        let part = part.trim();
+        let (a, b) = part
+            .split_once('-')
+            .ok_or_else(|| format!("invalid byte range: {:?}", part))?;
+        let num = |v: &str| -> Result<Option<u64>, String> {
+            let v = v.trim();
+            if v.is_empty() {
+                Ok(None)
+            } else {
+                v.parse().map(Some).map_err(|_| format!("invalid byte range: {:?}", part))
+            }
+        };
+        let span = ByteSpan {
+            first: num(a)?,
+            last: num(b)?,
+        };
+        match (span.first, span.last) {
+            (None, None) => return Err(format!("invalid byte range: {:?}", part)),
+            (Some(first), Some(last)) if first > last => {
+                return Err(format!("byte range ends before it starts: {:?}", part))
+            }
+            (None, Some(0)) => return Err(format!("empty suffix range: {:?}", part)),
+            _ => {}
+        }
+        spans.push(span);
+    }
+    Ok(ByteRanges { spans })
+}
+
+#[derive(Debug, Clone)]
+pub enum HeaderMatch {
+    Exact(String),
+    Pattern(Pattern),
+}
+
+// `--expect-header "Name: value"`, or `"Name: ~pattern"` for a match
+// anywhere in the value.
+#[derive(Debug, Clone)]
+pub struct HeaderExpectation {
+    pub name: String,
+    pub expected: HeaderMatch,
+}
+
+impl HeaderExpectation {
+    pub fn matches(&self, value: &str) -> bool {
+        match &self.expected {
+            HeaderMatch::Exact(v) => value == v,
+            HeaderMatch::Pattern(p) => p.is_match(value),
+        }
+    }
+}
+
+impl std::fmt::Display for HeaderExpectation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.expected {
+            HeaderMatch::Exact(v) => write!(f, "{}: {}", self.name, v),
+            HeaderMatch::Pattern(p) => write!(f, "{}: ~{}", self.name, p.as_str()),
+        }
+    }
+}
+
+// Headers that only a proxy's own error or block page carries; used
+// alongside --intercept-header unless --no-builtin-intercept is given.
+pub const BUILTIN_INTERCEPT_HEADERS: [&str; 3] = ["X-Squid-Error: ~.", "Server: ~^[Ss]quid", "Server: ~BlueCoat"];
+
+// One ALPN protocol ID, e.g. `h2`.
+pub fn parse_alpn(s: &str) -> Result<String, String> {
+    match s.trim() {
+        p if p.is_empty() || p.len() > 255 => Err(format!("expected a protocol ID like h2 or http/1.1, got {:?}", s)),
+        p => Ok(p.to_string()),
+    }
+}
+
+// `IP=WEIGHT` for --ip-weights.
+pub fn parse_ip_weight(s: &str) -> Result<(IpAddr, u32), String> {
+    let (ip, weight) = s
+        .trim()
+        .rsplit_once('=')
+        .ok_or_else(|| format!("expected IP=WEIGHT, got {:?}", s))?;
+    let ip = ip
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+        .map_err(|_| format!("invalid IP address: {:?}", ip))?;
+    match weight.parse::<u32>() {
+        Ok(weight) if weight <= 1000 => Ok((ip, weight)),
+        _ => Err(format!("weight must be 0..=1000, got {:?}", weight)),
+    }
+}
+
+pub fn parse_expect_header(s: &str) -> Result<HeaderExpectation, String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Name: value\", got {:?}", s))?;
+    let name = name.trim();
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic()) {
+        return Err(format!("invalid header name: {:?}", name));
+    }
+    let value = value.trim();
+    let expected = match value.strip_prefix('~') {
+        Some(p) => HeaderMatch::Pattern(Pattern::parse(p).map_err(|e| e.to_string())?),
+        None => HeaderMatch::Exact(value.to_string()),
+    };
+    Ok(HeaderExpectation {
+        name: name.to_string(),
+        expected,
+    })
+}
+
+pub fn parse_form_field(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got {:?}", s)),
+    }
+}
+
+pub fn parse_json_body(s: &str) -> Result<String, String> {
+    serde_json::from_str::<serde_json::Value>(s).map_err(|e| format!("invalid JSON: {}", e))?;
+    Ok(s.to_string())
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Units {
+    Us,
+    Ms,
+    S,
+    #[default]
+    Auto,
+}
+
+// Request steps with their own percentile block (--phase).
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    // TCP connect to the proxy (to the target with direct://).
+    ProxyConnect,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurlMode {
+    // Credentials and secret header values replaced by placeholders.
+    Safe,
+    Unsafe,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UploadMethod {
+    Post,
+    Put,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SweepSort {
+    P50,
+    Success,
+    P99,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BenchMode {
+    Http,
+    Download,
+    Upload,
+    Ws,
+    Grpc,
+    UdpDns,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Pretty,
+    // wrk's summary layout, for tools that already parse wrk.
+    Wrk,
+    // The same document --save-report writes.
+    Json,
+}
 
 #[derive(clap::Subcommand, Debug, Clone)]
 pub enum Commands {
+    #[command(about = "Benchmark the proxy (the default when no subcommand is given)")]
+    Bench,
     Completions {
         #[arg(value_enum)]
         shell: Shell,
     },
+    #[command(about = "Print the p50/p99 trend of the last runs stored by --sqlite (of the --proxy one, if given)")]
+    History {
+        #[arg(long, value_name = "PATH", help = "SQLite database written by --sqlite")]
+        db: PathBuf,
+
+        #[arg(long, value_name = "N", default_value_t = 10, help = "How many of the most recent runs to show")]
+        last: usize,
+    },
+    #[command(about = "Print the results saved by --save-report")]
+    Report {
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+    },
+    #[command(about = "Print a roff man page generated from the option definitions")]
+    Manpage {
+        #[arg(short = 'o', id = "manpage_output", value_name = "FILE", help = "Write the man page to FILE instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Benchmark a vless://, vmess://, trojan:// or ss:// share link through a spawned xray-core")]
+    Link {
+        #[arg(value_name = "URI")]
+        uri: String,
+
+        #[arg(
+            long = "xray-bin",
+            value_name = "PATH",
+            default_value = "xray",
+            help = "Path to the xray-core binary (looked up in PATH by default)"
+        )]
+        xray_bin: PathBuf,
+    },
+    #[command(about = "Print an xray-core client config generated from a share link")]
+    Config {
+        #[arg(value_name = "URI")]
+        uri: String,
+
+        #[arg(
+            long,
+            value_name = "TYPE:[ADDR:]PORT",
+            default_value = "socks:2080",
+            value_parser = parse_inbound,
+            help = "Local inbound: socks or http, e.g. 'socks:2080' or 'http:0.0.0.0:8080'"
+        )]
+        inbound: InboundSpec,
+
+        #[arg(short = 'o', id = "config_output", value_name = "FILE", help = "Write the config to FILE instead of stdout")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Benchmark every xray config file in turn and compare the results")]
+    Sweep {
+        #[arg(value_name = "CONFIG", required = true, help = "xray config.json files or directories containing them")]
+        configs: Vec<PathBuf>,
+
+        #[arg(long, value_enum, default_value_t = SweepSort::P50, help = "Metric to sort the comparison table by")]
+        sort: SweepSort,
+
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Print full per-config results as JSON")]
+        json: bool,
+
+        #[arg(
+            long = "xray-bin",
+            value_name = "PATH",
+            default_value = "xray",
+            help = "Path to the xray-core binary (looked up in PATH by default)"
+        )]
+        xray_bin: PathBuf,
+    },
+    #[command(about = "Check that requests through the proxy leave from a different IP than direct ones")]
+    Check {
+        #[arg(
+            long = "ip-url",
+            value_name = "URL",
+            default_value = "https://api.ipify.org",
+            help = "IP echo endpoint"
+        )]
+        ip_url: String,
+
+        #[arg(long = "ip-format", value_enum, default_value_t = IpFormat::Text, help = "Response format of the IP endpoint")]
+        ip_format: IpFormat,
+
+        #[arg(
+            long = "ip-field",
+            value_name = "FIELD",
+            default_value = "ip",
+            help = "JSON field holding the address (dots for nested fields), with --ip-format json"
+        )]
+        ip_field: String,
+
+        #[arg(long = "no-direct", action = clap::ArgAction::SetTrue, help = "Skip the direct request used for comparison")]
+        no_direct: bool,
+    },
+    #[command(about = "Perform one request and print every step of it, for debugging failures")]
+    Trace {
+        #[arg(
+            long = "body-bytes",
+            value_name = "N",
+            default_value_t = 512,
+            help = "How many bytes of the response body to print"
+        )]
+        body_bytes: usize,
+    },
+    #[command(about = "Poll the target through the proxy until it answers successfully, up to a deadline")]
+    Wait {
+        #[arg(long, value_name = "DURATION", default_value = "2s", value_parser = parse_duration, help = "Time between attempts")]
+        interval: Duration,
+
+        #[arg(long, value_name = "DURATION", default_value = "60s", value_parser = parse_duration, help = "Give up and exit non-zero after this long")]
+        deadline: Duration,
+
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Successes in a row required before the target counts as healthy"
+        )]
+        consecutive: u32,
+    },
+    #[command(about = "Run a minimal HTTP CONNECT + SOCKS5 proxy for trying the tool out")]
+    MockProxy {
+        #[arg(long, value_name = "ADDR:PORT", default_value = "127.0.0.1:0", help = "Address to listen on (port 0 picks a free one)")]
+        listen: SocketAddr,
+
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, help = "Delay before answering each tunnel request, e.g. 50ms")]
+        latency: Option<Duration>,
+
+        #[arg(
+            long = "fail-rate",
+            value_name = "FRACTION",
+            default_value_t = 0.0,
+            value_parser = parse_fraction,
+            help = "Share of tunnels to refuse (502 / SOCKS connection refused), e.g. 0.1"
+        )]
+        fail_rate: f64,
+
+        #[arg(long, value_name = "USER:PASS", value_parser = parse_credentials, help = "Require these credentials (Basic for CONNECT, RFC 1929 for SOCKS5)")]
+        auth: Option<(String, String)>,
+
+        #[arg(
+            long = "requests-per-tunnel",
+            value_name = "N",
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Close each tunnel after relaying N plain-HTTP requests, like a proxy that breaks --pipeline batches"
+        )]
+        requests_per_tunnel: Option<u64>,
+    },
+    #[command(about = "Run a trivial HTTP/1.1 server to benchmark against")]
+    MockTarget {
+        #[arg(long, value_name = "ADDR:PORT", default_value = "127.0.0.1:0", help = "Address to listen on (port 0 picks a free one)")]
+        listen: SocketAddr,
+
+        #[arg(long, value_name = "CODE", default_value_t = 200, value_parser = clap::value_parser!(u16).range(100..=599), help = "Status code of every response")]
+        status: u16,
+
+        #[arg(long = "body-size", value_name = "SIZE", default_value = "0", value_parser = parse_size, help = "Response body size, e.g. 1k or 10MiB")]
+        body_size: u64,
+
+        #[arg(long, value_name = "DURATION", value_parser = parse_duration, help = "Delay before each response, e.g. 10ms")]
+        delay: Option<Duration>,
+
+        #[arg(long = "truncate-at", value_name = "SIZE", value_parser = parse_size, help = "Close the connection after this many body bytes, short of the advertised Content-Length")]
+        truncate_at: Option<u64>,
+
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Answer every request with bytes that are not HTTP, then close")]
+        garbage: bool,
+
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Answer requests on a connection in swapped pairs, like a proxy that breaks --pipeline ordering")]
+        reorder: bool,
+    },
+    #[command(about = "Fetch a subscription, test every node through xray-core and rank them")]
+    Sub {
+        #[arg(value_name = "URL")]
+        subscription: String,
+
+        #[arg(
+            long = "parallel-nodes",
+            value_name = "N",
+            default_value_t = 4,
+            help = "How many nodes (xray instances) to test at the same time"
+        )]
+        parallel_nodes: usize,
+
+        #[arg(long, value_name = "N", help = "Show only the N best nodes")]
+        top: Option<usize>,
+
+        #[arg(long, action = clap::ArgAction::SetTrue, help = "Print full per-node results as JSON")]
+        json: bool,
+
+        #[arg(
+            long = "xray-bin",
+            value_name = "PATH",
+            default_value = "xray",
+            help = "Path to the xray-core binary (looked up in PATH by default)"
+        )]
+        xray_bin: PathBuf,
+    },
 }
 
-#[derive(Parser, Debug, Clone)]
-#[command(
-    name = "xray-tester",
-    version,
-    about = "Send HTTP/HTTPS requests via Xray proxy and measure latency"
-)]
-pub struct Args {
-    #[arg(short = 'p', long)]
-    pub proxy: Option<String>,
+#[derive(Parser, Debug, Clone)]
+#[command(
+    name = "xray-tester",
+    version,
+    about = "Send HTTP/HTTPS requests via Xray proxy and measure latency"
+)]
+pub struct Args {
+    // Every setting as resolved from flags, environment and --config, for
+    // the report; filled in by `config::load_args`.
+    #[arg(skip)]
+    pub effective_config: toml::Table,
+
+    #[arg(
+        short = 'p',
+        long,
+        env = "XRAY_TESTER_PROXY",
+        global = true,
+        value_parser = parse_proxy_url,
+        help = "Proxy URL (socks5://, http://, https://, unix:///path, http+unix:///path, or direct:// for none); falls back to ALL_PROXY, HTTPS_PROXY, HTTP_PROXY (direct for targets in NO_PROXY). Required by bench, trace, wait and check"
+    )]
+    pub proxy: Option<String>,
+
+    #[arg(
+        short = 'u',
+        long,
+        value_name = "URL",
+        env = "XRAY_TESTER_URL",
+        global = true,
+        value_parser = parse_target_url,
+        help = "Target URL (http://, https://, ws:// or wss://), or - to bench each URL read from stdin. Required by bench, trace, wait, link, sub and sweep"
+    )]
+    pub url: Option<String>,
+
+    #[arg(
+        long = "per-url",
+        value_name = "N",
+        global = true,
+        help = "With --url -, requests sent to each URL read from stdin (defaults to --iterations)"
+    )]
+    pub per_url: Option<usize>,
+
+    #[arg(short = 'n', long, default_value_t = 100, global = true)]
+    pub iterations: usize,
+
+    #[arg(short = 'c', long, default_value_t = 20, global = true)]
+    pub concurrency: usize,
+
+    #[arg(
+        short = 't',
+        long = "timeout",
+        value_name = "DURATION",
+        default_value = "5s",
+        value_parser = parse_duration,
+        global = true,
+        help = "Per-request timeout, e.g. 500ms or 5s; a bare number is milliseconds"
+    )]
+    pub timeout: Duration,
+
+    #[arg(
+        short = 'H',
+        long = "header",
+        value_name = "NAME: VALUE",
+        global = true,
+        help = "Add a request header, or @FILE with one per line; ${env:NAME} in a value is read from the environment; repeatable"
+    )]
+    pub header: Vec<String>,
+
+    #[arg(short = 'k', long, action = clap::ArgAction::SetTrue, global = true)]
+    pub insecure: bool,
+
+    #[arg(
+        long = "success-codes",
+        value_name = "CODES",
+        global = true,
+        help = "Comma-separated list of HTTP codes and/or ranges considered success, e.g. '200-399,418'. Default: 200-400",
+        long_help = "Comma-separated list of HTTP codes and/or ranges considered success, e.g. '200-399,418'.\n\
+Ranges are inclusive. Without this flag 200-400 counts as success, or 206 with --range and 304 \
+with --if-none-match/--if-modified-since. Cannot be combined with --expect-status."
+    )]
+    pub success_codes: Option<String>,
+
+    #[arg(
+        long = "expect-status",
+        value_name = "CODE",
+        value_parser = clap::value_parser!(u16).range(100..=599),
+        conflicts_with = "success_codes",
+        global = true,
+        help = "Only this exact status counts as success (repeatable); any other status observed makes the run exit non-zero"
+    )]
+    pub expect_status: Vec<u16>,
+
+    #[arg(
+        long = "slow-threshold",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        global = true,
+        help = "Count successful responses slower than this (e.g. 2s) as slow in the report"
+    )]
+    pub slow_threshold: Option<Duration>,
+
+    #[arg(
+        long = "slow-is-failure",
+        action = clap::ArgAction::SetTrue,
+        requires = "slow_threshold",
+        global = true,
+        help = "Exit non-zero if any response was slower than --slow-threshold"
+    )]
+    pub slow_is_failure: bool,
+
+    #[arg(
+        long = "connect-to",
+        value_name = "HOST:PORT",
+        value_parser = parse_connect_to,
+        global = true,
+        help = "Override proxy CONNECT destination while keeping original URL host for SNI/Host",
+        long_help = "Override proxy CONNECT destination while keeping original URL host for SNI/Host.\n\
+The proxy is asked to connect to HOST:PORT, but the TLS handshake and the Host header still use \
+the host from the URL. Useful for testing a specific backend or CDN edge behind a DNS name."
+    )]
+    pub connect_to: Option<ConnectTo>,
+
+    #[arg(
+        long = "resolve-target-locally",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        conflicts_with = "connect_to",
+        help = "Resolve the target host here and CONNECT to its IPs in turn, keeping the host for SNI/Host"
+    )]
+    pub resolve_target_locally: bool,
+
+    #[arg(
+        long = "strict-resolve",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        requires = "resolve_target_locally",
+        help = "Fail if --resolve-target-locally cannot resolve the target instead of letting the proxy resolve it"
+    )]
+    pub strict_resolve: bool,
+
+    #[arg(
+        long = "ip-weights",
+        value_name = "IP=WEIGHT,...",
+        value_parser = parse_ip_weight,
+        value_delimiter = ',',
+        global = true,
+        requires = "resolve_target_locally",
+        help = "Share of requests per resolved address (default 1 each; 0 skips an address)"
+    )]
+    pub ip_weights: Vec<(IpAddr, u32)>,
+
+    #[arg(
+        long = "proxy-insecure",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Skip certificate verification of an https:// proxy (--insecure only covers the target)"
+    )]
+    pub proxy_insecure: bool,
+
+    #[arg(
+        long = "local-addr",
+        value_name = "IP[:PORT]",
+        value_parser = parse_local_addr,
+        global = true,
+        help = "Source address for connections to the proxy"
+    )]
+    pub local_addr: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        global = true,
+        help = "Network interface for connections to the proxy (Linux only, SO_BINDTODEVICE)"
+    )]
+    pub interface: Option<String>,
+
+    #[arg(
+        long = "tcp-nodelay",
+        action = clap::ArgAction::SetTrue,
+        overrides_with = "no_tcp_nodelay",
+        global = true,
+        help = "Disable Nagle's algorithm on proxy connections (default)"
+    )]
+    pub tcp_nodelay: bool,
+
+    #[arg(
+        long = "no-tcp-nodelay",
+        action = clap::ArgAction::SetTrue,
+        overrides_with = "tcp_nodelay",
+        global = true,
+        help = "Keep Nagle's algorithm enabled on proxy connections"
+    )]
+    pub no_tcp_nodelay: bool,
+
+    #[arg(
+        long = "tcp-keepalive",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        global = true,
+        help = "Enable TCP keepalive on proxy connections with this idle time, e.g. 30s"
+    )]
+    pub tcp_keepalive: Option<Duration>,
+
+    #[arg(
+        long = "so-rcvbuf",
+        value_name = "BYTES",
+        value_parser = parse_size,
+        global = true,
+        help = "SO_RCVBUF for proxy connections, e.g. 4MiB"
+    )]
+    pub so_rcvbuf: Option<u64>,
+
+    #[arg(
+        long = "so-sndbuf",
+        value_name = "BYTES",
+        value_parser = parse_size,
+        global = true,
+        help = "SO_SNDBUF for proxy connections, e.g. 4MiB"
+    )]
+    pub so_sndbuf: Option<u64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = BenchMode::Http,
+        global = true,
+        help = "What each request checks: a plain GET (http), a full body download (download), a streamed POST/PUT (upload), a WebSocket upgrade (ws), a gRPC health check (grpc) or a DNS query over SOCKS5 UDP (udp-dns)"
+    )]
+    pub mode: BenchMode,
+
+    #[arg(
+        long = "idle-timeout",
+        value_name = "DURATION",
+        default_value = "10s",
+        value_parser = parse_duration,
+        global = true,
+        help = "In download/upload mode, fail a request when no body data moves for this long"
+    )]
+    pub idle_timeout: Duration,
+
+    #[arg(
+        long = "min-bytes",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        global = true,
+        help = "In download mode, count a response as failed if its body is shorter, e.g. '10MB'"
+    )]
+    pub min_bytes: Option<u64>,
+
+    #[arg(
+        long = "max-body-bytes",
+        value_name = "SIZE",
+        value_parser = parse_size,
+        global = true,
+        help = "In download mode, stop reading a body after this many bytes and count the response as complete"
+    )]
+    pub max_body_bytes: Option<u64>,
+
+    #[arg(
+        long = "upload-size",
+        value_name = "SIZE",
+        default_value = "10MB",
+        value_parser = parse_size,
+        global = true,
+        help = "In upload mode, size of the generated request body, e.g. '50MB'"
+    )]
+    pub upload_size: u64,
+
+    #[arg(
+        long = "upload-method",
+        value_enum,
+        default_value_t = UploadMethod::Post,
+        global = true,
+        help = "HTTP method used in upload mode"
+    )]
+    pub upload_method: UploadMethod,
+
+    #[arg(
+        long,
+        value_name = "KEY=VALUE",
+        value_parser = parse_form_field,
+        conflicts_with = "json_body",
+        global = true,
+        help = "Send a POST with this field in an application/x-www-form-urlencoded body; repeatable"
+    )]
+    pub form: Vec<(String, String)>,
+
+    #[arg(
+        long = "json-body",
+        value_name = "JSON",
+        value_parser = parse_json_body,
+        global = true,
+        help = "Send a POST with this JSON body (Content-Type: application/json); checked before the run"
+    )]
+    pub json_body: Option<String>,
+
+    #[arg(
+        long = "respect-retry-after",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "On 429/503 with Retry-After, wait as asked and retry instead of counting a failure"
+    )]
+    pub respect_retry_after: bool,
+
+    #[arg(
+        long = "retry-after-max",
+        value_name = "DURATION",
+        default_value = "30s",
+        value_parser = parse_duration,
+        global = true,
+        help = "Longest Retry-After wait honoured; longer ones are cut to this"
+    )]
+    pub retry_after_max: Duration,
+
+    #[arg(
+        long = "expect-continue",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "In upload mode, send Expect: 100-continue and hold the body until the server answers 100"
+    )]
+    pub expect_continue: bool,
+
+    #[arg(
+        long = "expect-continue-timeout",
+        value_name = "DURATION",
+        default_value = "1s",
+        value_parser = parse_duration,
+        global = true,
+        help = "Send the upload body anyway when no 100 Continue arrives within this time"
+    )]
+    pub expect_continue_timeout: Duration,
+
+    #[arg(
+        long = "http1.0",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Send HTTP/1.0 requests (no Connection header; bodies may end at connection close)"
+    )]
+    pub http1_0: bool,
+
+    #[arg(
+        long = "dump-failures",
+        value_name = "DIR",
+        global = true,
+        help = "Write status line, headers and start of the body of failed responses to DIR/<seq>.http"
+    )]
+    pub dump_failures: Option<PathBuf>,
+
+    #[arg(
+        long = "dump-limit",
+        value_name = "N",
+        default_value_t = 50,
+        requires = "dump_failures",
+        global = true,
+        help = "Stop writing failure dumps after this many"
+    )]
+    pub dump_limit: usize,
+
+    #[arg(
+        long = "dump-body-size",
+        value_name = "SIZE",
+        default_value = "16KiB",
+        value_parser = parse_size,
+        requires = "dump_failures",
+        global = true,
+        help = "Bytes of the response body kept in each failure dump"
+    )]
+    pub dump_body_size: u64,
+
+    #[arg(
+        long = "dns-name",
+        value_name = "NAME",
+        global = true,
+        help = "In udp-dns mode, name to resolve (defaults to the target host)"
+    )]
+    pub dns_name: Option<String>,
+
+    #[arg(
+        long = "dns-server",
+        value_name = "HOST:PORT",
+        default_value = "1.1.1.1:53",
+        global = true,
+        help = "In udp-dns mode, DNS server the query is relayed to"
+    )]
+    pub dns_server: String,
+
+    #[arg(
+        long = "ws-ping",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "In ws mode, also time one ping/pong round-trip after the upgrade"
+    )]
+    pub ws_ping: bool,
+
+    #[arg(
+        long = "grpc-service",
+        value_name = "NAME",
+        default_value = "",
+        global = true,
+        help = "Service name sent in grpc.health.v1.Health/Check (empty means the whole server)"
+    )]
+    pub grpc_service: String,
+
+    #[arg(
+        long = "latency-csv",
+        value_name = "PATH",
+        global = true,
+        help = "Write raw latency samples (seq,latency_ms) to a CSV file"
+    )]
+    pub latency_csv: Option<PathBuf>,
+
+    #[arg(
+        long = "save-report",
+        value_name = "PATH",
+        global = true,
+        help = "Write the full results as JSON to PATH; print them again with 'xray-tester report PATH'"
+    )]
+    pub save_report: Option<PathBuf>,
+
+    #[arg(
+        long = "influx-file",
+        value_name = "PATH",
+        global = true,
+        help = "Write per-second and summary points in InfluxDB line protocol to PATH"
+    )]
+    pub influx_file: Option<PathBuf>,
+
+    #[arg(
+        long = "influx-url",
+        value_name = "URL",
+        global = true,
+        help = "POST the line-protocol points to this InfluxDB write URL (e.g. http://host:8086/write?db=bench); failures only warn"
+    )]
+    pub influx_url: Option<url::Url>,
 
-    #[arg(short = 'u', long, value_name = "URL")]
-    pub url: Option<String>,
+    #[arg(
+        long = "sqlite",
+        value_name = "PATH",
+        global = true,
+        help = "Append the run and its per-request samples to this SQLite database (created if missing); needs a build with --features sqlite"
+    )]
+    pub sqlite: Option<PathBuf>,
 
-    #[arg(short = 'n', long, default_value_t = 100)]
-    pub iterations: usize,
+    #[arg(
+        long = "sample-latencies",
+        value_name = "N",
+        global = true,
+        help = "Keep a uniform random sample of N latencies for --latency-csv instead of every one"
+    )]
+    pub sample_latencies: Option<usize>,
 
-    #[arg(short = 'c', long, default_value_t = 20)]
-    pub concurrency: usize,
+    #[arg(
+        long = "seed",
+        alias = "sample-seed",
+        value_name = "SEED",
+        global = true,
+        help = "Seed for randomized behaviour such as --sample-latencies (random and shown in the banner by default)"
+    )]
+    pub seed: Option<u64>,
 
-    #[arg(short = 't', long = "timeout", default_value_t = 5000)]
-    pub timeout_ms: u64,
+    #[arg(
+        long = "cache-bust",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Append a unique query parameter to every request so edge caches cannot answer it"
+    )]
+    pub cache_bust: bool,
 
-    #[arg(short = 'k', long, action = clap::ArgAction::SetTrue)]
-    pub insecure: bool,
+    #[arg(
+        long = "cache-bust-param",
+        value_name = "NAME",
+        default_value = "_xt",
+        global = true,
+        help = "Query parameter name used by --cache-bust"
+    )]
+    pub cache_bust_param: String,
 
     #[arg(
-        long = "success-codes",
-        value_name = "CODES",
-        help = "Comma-separated list of HTTP codes and/or ranges considered success, e.g. '200-399,418'. Default: 200-400"
+        long = "if-none-match",
+        value_name = "ETAG",
+        global = true,
+        help = "Send If-None-Match with every request and expect 304 Not Modified"
     )]
-    pub success_codes: Option<String>,
+    pub if_none_match: Option<String>,
 
     #[arg(
-        long = "connect-to",
-        value_name = "HOST:PORT",
-        help = "Override proxy CONNECT destination while keeping original URL host for SNI/Host"
+        long = "if-modified-since",
+        value_name = "DATE",
+        global = true,
+        help = "Send If-Modified-Since with every request and expect 304 Not Modified"
+    )]
+    pub if_modified_since: Option<String>,
+
+    #[arg(
+        long = "prime-cache",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Fetch the target once before the run and revalidate with its ETag/Last-Modified"
+    )]
+    pub prime_cache: bool,
+
+    #[arg(
+        long = "range",
+        value_name = "RANGE",
+        value_parser = parse_range,
+        global = true,
+        help = "Send a Range header (e.g. bytes=0-1023, bytes=1024-, bytes=0-99,200-299) and expect 206 Partial Content"
+    )]
+    pub range: Option<ByteRanges>,
+
+    #[arg(
+        long = "expect-header",
+        value_name = "NAME: VALUE",
+        value_parser = parse_expect_header,
+        global = true,
+        help = "Fail responses whose header does not equal VALUE (or match ~PATTERN); repeatable"
+    )]
+    pub expect_header: Vec<HeaderExpectation>,
+
+    #[arg(
+        long = "pipeline",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64).range(2..=1000),
+        global = true,
+        help = "Send N GETs back to back over each tunnel before reading the responses (HTTP/1.1 pipelining); --mode http only"
+    )]
+    pub pipeline: Option<u64>,
+
+    #[arg(
+        long = "no-reconnect",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Fail a --pipeline batch whose tunnel the proxy closes midway instead of sending the rest over a new tunnel once"
+    )]
+    pub no_reconnect: bool,
+
+    #[arg(
+        long = "alpn",
+        value_name = "LIST",
+        value_parser = parse_alpn,
+        value_delimiter = ',',
+        global = true,
+        help = "ALPN protocols to offer in the target TLS handshake, e.g. 'h2,http/1.1' (requests are still sent as the mode's protocol)"
+    )]
+    pub alpn: Vec<String>,
+
+    #[arg(
+        long = "require-alpn",
+        value_name = "PROTOCOL",
+        global = true,
+        help = "Fail requests whose TLS handshake did not negotiate this ALPN protocol"
+    )]
+    pub require_alpn: Option<String>,
+
+    #[arg(
+        long = "tls-resume",
+        global = true,
+        help = "Share a TLS session cache across connections and report resumed vs full handshakes (needs --tls-backend rustls; native-tls cannot)"
+    )]
+    pub tls_resume: bool,
+
+    #[arg(
+        long = "intercept-header",
+        value_name = "NAME: VALUE",
+        value_parser = parse_expect_header,
+        global = true,
+        help = "Count responses with this header value (or ~PATTERN) as answered by the proxy itself, not the origin; repeatable"
+    )]
+    pub intercept_header: Vec<HeaderExpectation>,
+
+    #[arg(
+        long = "no-builtin-intercept",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Only use --intercept-header rules, not the built-in Squid and Blue Coat ones"
+    )]
+    pub no_builtin_intercept: bool,
+
+    #[arg(
+        long = "no-preflight",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Skip resolving and connecting to the proxy once before the run (for resolvers that come up mid-run)"
+    )]
+    pub no_preflight: bool,
+
+    #[arg(
+        long = "preflight",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        conflicts_with = "no_preflight",
+        help = "Send one full request through the proxy before the run, not counted; abort with a trace if it fails"
+    )]
+    pub preflight: bool,
+
+    #[arg(
+        long = "show-headers",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Print the headers of the first response of the run"
+    )]
+    pub show_headers: bool,
+
+    #[arg(
+        long = "header-stats",
+        value_name = "NAME",
+        global = true,
+        help = "Tally the values of this response header and show the most common ones"
+    )]
+    pub header_stats: Option<String>,
+
+    #[arg(
+        long = "max-locations",
+        value_name = "N",
+        default_value_t = 5,
+        global = true,
+        help = "How many distinct Location targets of 3xx responses to list in the report (0 disables)"
+    )]
+    pub max_locations: usize,
+
+    #[arg(
+        long = "error-samples",
+        value_name = "N",
+        default_value_t = 5,
+        global = true,
+        help = "How many distinct error messages to keep per error category for the report (0 disables)"
+    )]
+    pub error_samples: usize,
+
+    #[arg(
+        long = "record-all-latencies",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Include responses outside the success set in latency statistics (counters are unchanged)"
+    )]
+    pub record_all_latencies: bool,
+
+    #[arg(
+        long = "per-connection",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Print requests, p50, p99 and errors for each worker slot after the report, slowest p99 first"
+    )]
+    pub per_connection: bool,
+
+    #[arg(
+        long = "timeline",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Print a per-second table of successes, failures, timeouts and p50 after the report"
+    )]
+    pub timeline: bool,
+
+    #[arg(
+        long = "slowest",
+        value_name = "K",
+        global = true,
+        help = "Print the K slowest requests (sequence number, total time including connect and handshakes, status, start time, dominant phase) after the report"
+    )]
+    pub slowest: Option<usize>,
+
+    #[arg(
+        long = "baseline-direct",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        conflicts_with_all = ["stage", "find_max_concurrency", "find_max_rate", "watch", "compare_proxy", "output"],
+        help = "Also run the workload without the proxy first and report the latency, failures and throughput the proxy adds"
+    )]
+    pub baseline_direct: bool,
+
+    #[arg(
+        long = "compare-proxy",
+        value_name = "URL",
+        global = true,
+        conflicts_with_all = ["stage", "find_max_concurrency", "find_max_rate", "watch"],
+        help = "Run the same workload through this proxy as well and print both side by side"
+    )]
+    pub compare_proxy: Option<String>,
+
+    #[arg(
+        long = "interleave",
+        action = clap::ArgAction::SetTrue,
+        requires = "compare_proxy",
+        global = true,
+        help = "With --compare-proxy, alternate between the proxies in batches instead of running one after the other"
+    )]
+    pub interleave: bool,
+
+    #[arg(
+        long = "watch",
+        value_name = "INTERVAL",
+        value_parser = parse_duration,
+        global = true,
+        conflicts_with_all = ["stage", "find_max_concurrency", "find_max_rate", "output"],
+        help = "Rerun the benchmark every INTERVAL (e.g. 60s) until Ctrl-C, printing one summary line per run and a trend table at the end"
+    )]
+    pub watch: Option<Duration>,
+
+    #[arg(
+        long = "soak",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        global = true,
+        conflicts_with_all = ["watch", "stage", "find_max_concurrency", "find_max_rate", "compare_proxy", "baseline_direct"],
+        help = "Run for DURATION (e.g. 12h) instead of -n requests, printing a summary every --summary-interval and a per-window p99 table at the end; latencies are kept in a histogram so memory stays flat"
+    )]
+    pub soak: Option<Duration>,
+
+    #[arg(
+        long = "summary-interval",
+        value_name = "DURATION",
+        value_parser = parse_duration,
+        default_value = "10m",
+        global = true,
+        help = "With --soak, length of each window: the cumulative summary and the last window's are printed after each"
+    )]
+    pub summary_interval: Duration,
+
+    #[arg(
+        long = "watch-log",
+        value_name = "PATH",
+        requires = "watch",
+        global = true,
+        help = "Append each --watch run's summary to PATH as one JSON object per line"
+    )]
+    pub watch_log: Option<PathBuf>,
+
+    #[arg(
+        long = "abort-after-failures",
+        value_name = "N",
+        global = true,
+        help = "Stop the run after N failed requests in a row"
+    )]
+    pub abort_after_failures: Option<usize>,
+
+    #[arg(
+        long = "abort-error-rate",
+        value_name = "PERCENT",
+        value_parser = parse_percent,
+        global = true,
+        help = "Stop the run once this share of completed requests has failed, e.g. '50%'"
+    )]
+    pub abort_error_rate: Option<f64>,
+
+    #[arg(
+        long = "abort-min-samples",
+        value_name = "N",
+        default_value_t = 20,
+        global = true,
+        help = "Completed requests required before --abort-error-rate is checked"
+    )]
+    pub abort_min_samples: usize,
+
+    #[arg(
+        long = "rate",
+        value_name = "RPS",
+        value_parser = parse_rate,
+        global = true,
+        help = "Start requests at this fixed rate (open loop); --concurrency caps how many are in flight"
+    )]
+    pub rate: Option<f64>,
+
+    #[arg(
+        long = "max-conn-rate",
+        value_name = "PER_SEC",
+        value_parser = parse_rate,
+        global = true,
+        help = "Open at most this many new proxy connections per second, whatever the concurrency"
+    )]
+    pub max_conn_rate: Option<f64>,
+
+    #[arg(
+        long = "stage",
+        value_name = "DURATION:LOAD",
+        value_parser = parse_stage,
+        action = clap::ArgAction::Append,
+        global = true,
+        help = "Run a timed stage instead of -n iterations: '30s:50' for 50 requests/s, '1m:c20' for 20 in flight (repeatable)"
+    )]
+    pub stage: Vec<StageSpec>,
+
+    #[arg(
+        long = "find-max-concurrency",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        conflicts_with = "stage",
+        help = "Run timed stages at increasing concurrency and report the highest level that meets the stage criteria"
+    )]
+    pub find_max_concurrency: bool,
+
+    #[arg(
+        long = "calibrate",
+        value_name = "N",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "200",
+        global = true,
+        help = "Before the run, time N (default 200) loopback requests to an in-process target without the proxy and report that harness overhead with the results"
+    )]
+    pub calibrate: Option<usize>,
+
+    #[arg(
+        long = "find-max-rate",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        conflicts_with_all = ["stage", "find_max_concurrency", "rate"],
+        help = "Binary-search the highest request rate between --rate-min and --rate-max that meets the stage criteria"
+    )]
+    pub find_max_rate: bool,
+
+    #[arg(
+        long = "rate-min",
+        value_name = "RPS",
+        default_value = "10",
+        value_parser = parse_rate,
+        global = true,
+        help = "With --find-max-rate, lowest rate to try; the search fails if even this one does"
+    )]
+    pub rate_min: f64,
+
+    #[arg(
+        long = "rate-max",
+        value_name = "RPS",
+        default_value = "1000",
+        value_parser = parse_rate,
+        global = true,
+        help = "With --find-max-rate, highest rate to try"
+    )]
+    pub rate_max: f64,
+
+    #[arg(
+        long = "stage-duration",
+        value_name = "DURATION",
+        default_value = "10s",
+        value_parser = parse_duration,
+        global = true,
+        help = "With --find-max-concurrency or --find-max-rate, how long each stage keeps starting requests"
+    )]
+    pub stage_duration: Duration,
+
+    #[arg(
+        long = "max-stage-p99",
+        value_name = "MS",
+        global = true,
+        help = "With --find-max-concurrency or --find-max-rate, fail a stage whose p99 latency exceeds this"
+    )]
+    pub max_stage_p99_ms: Option<f64>,
+
+    #[arg(
+        long = "max-stage-errors",
+        value_name = "PERCENT",
+        default_value = "1%",
+        value_parser = parse_percent,
+        global = true,
+        help = "With --find-max-concurrency or --find-max-rate, fail a stage whose error rate exceeds this"
+    )]
+    pub max_stage_errors: f64,
+
+    #[arg(
+        long = "max-stage-concurrency",
+        value_name = "N",
+        default_value_t = 1024,
+        global = true,
+        help = "With --find-max-concurrency, highest concurrency to try"
+    )]
+    pub max_stage_concurrency: usize,
+
+    #[arg(
+        short = 'q',
+        long,
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Print nothing but errors (no banner, progress or report)"
     )]
-    pub connect_to: Option<String>,
+    pub quiet: bool,
 
-    #[arg(long = "debug", action = clap::ArgAction::SetTrue)]
+    #[arg(
+        long = "summary-only",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        conflicts_with = "quiet",
+        help = "Print a single summary line instead of the banner and full report"
+    )]
+    pub summary_only: bool,
+
+    #[arg(
+        long = "output",
+        value_enum,
+        default_value_t = OutputFormat::Pretty,
+        global = true,
+        conflicts_with_all = ["summary_only", "quiet", "stage", "find_max_concurrency", "find_max_rate"],
+        help = "Report format: the usual report, wrk's summary layout, or the JSON of --save-report (no banner for either)"
+    )]
+    pub output: OutputFormat,
+
+    #[arg(
+        long = "no-color",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Disable colored output (also disabled by NO_COLOR or when stdout is not a terminal)"
+    )]
+    pub no_color: bool,
+
+    #[arg(
+        long = "units",
+        value_enum,
+        default_value_t = Units::Auto,
+        global = true,
+        help = "Unit for durations in the report; auto picks µs, ms or s by magnitude"
+    )]
+    pub units: Units,
+
+    #[arg(
+        long = "phase",
+        value_enum,
+        global = true,
+        help = "Print a percentile block for this request step after the latency distribution"
+    )]
+    pub phase: Vec<Phase>,
+
+    #[arg(
+        long = "log-file",
+        value_name = "PATH",
+        global = true,
+        help = "Write one line per request (timestamp, sequence, phase timings, status or error) to PATH"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[arg(
+        long = "log-level",
+        value_enum,
+        default_value_t = LogLevel::Warn,
+        global = true,
+        help = "Verbosity of diagnostics written to stderr"
+    )]
+    pub log_level: LogLevel,
+
+    #[arg(
+        long = "log-format",
+        value_enum,
+        default_value_t = LogFormat::Text,
+        global = true,
+        help = "Format of stderr diagnostics"
+    )]
+    pub log_format: LogFormat,
+
+    #[arg(
+        long = "debug",
+        action = clap::ArgAction::SetTrue,
+        global = true,
+        help = "Alias for --log-level debug"
+    )]
     pub debug: bool,
 
+    #[arg(
+        long = "startup-timeout",
+        value_name = "DURATION",
+        default_value = "10s",
+        value_parser = parse_duration,
+        global = true,
+        help = "How long to wait for a spawned xray inbound to become ready (link/sub)"
+    )]
+    pub startup_timeout: Duration,
+
+    #[arg(
+        long = "config",
+        value_name = "PATH",
+        help = "Load options from a TOML file; command-line flags and environment variables take precedence"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        long = "print-config",
+        action = clap::ArgAction::SetTrue,
+        help = "Print the effective merged configuration as TOML and exit"
+    )]
+    pub print_config: bool,
+
+    #[arg(
+        long = "print-curl",
+        value_name = "MODE",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "safe",
+        help = "Print a curl command equivalent to one request and exit; --print-curl=unsafe keeps credentials"
+    )]
+    pub print_curl: Option<CurlMode>,
+
     #[command(subcommand)]
     pub cmd: Option<Commands>,
 }
 
 use anyhow::{anyhow, Context, Result};
+use clap::parser::ValueSource;
+use clap::error::ErrorKind;
+use clap::{ArgMatches, CommandFactory};
+
+// Checked in order when neither --proxy nor XRAY_TESTER_PROXY is set.
+pub const PROXY_ENV_FALLBACKS: &[&str] = &[
+    "ALL_PROXY",
+    "all_proxy",
+    "HTTPS_PROXY",
+    "https_proxy",
+    "HTTP_PROXY",
+    "http_proxy",
+];
+
+pub fn resolve_proxy(args: &Args, matches: &ArgMatches) -> Option<(String, Option<&'static str>)> {
+    if let Some(p) = args.proxy.clone() {
+        let origin = match matches.value_source("proxy") {
+            Some(ValueSource::EnvVariable) => Some("XRAY_TESTER_PROXY"),
+            _ => None,
+        };
+        return Some((p, origin));
+    }
+    let fallback = PROXY_ENV_FALLBACKS.iter().find_map(|&name| {
+        std::env::var(name)
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .map(|v| (v.trim().to_string(), Some(name)))
+    })?;
+    // As with curl, targets listed in NO_PROXY bypass the fallback proxy;
+    // the origin then names the NO_PROXY variable.
+    for name in NO_PROXY_VARS {
+        if let Ok(list) = std::env::var(name) {
+            if args.url.as_deref().is_some_and(|url| no_proxy_matches(&list, url)) {
+                return Some((format!("{}://", crate::request::DIRECT_SCHEME), Some(name)));
+            }
+            break;
+        }
+    }
+    Some(fallback)
+}
+
+pub const NO_PROXY_VARS: [&str; 2] = ["NO_PROXY", "no_proxy"];
+
+// NO_PROXY is a comma- or space-separated list of `*`, host names or domain
+// suffixes (a leading `.` or `*.` is optional) and IP addresses, each
+// optionally with `:port`.
+pub fn no_proxy_matches(list: &str, url: &str) -> bool {
+    let Ok(url) = url::Url::parse(url) else {
+        return false;
+    };
+    let Some(host) = url.host_str() else {
+        return false;
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+    let port = url.port_or_known_default();
+    list.split([',', ' ']).filter(|e| !e.is_empty()).any(|entry| {
+        if entry == "*" {
+            return true;
+        }
+        let entry = entry.to_ascii_lowercase();
+        let (name, entry_port) = match entry.strip_prefix('[') {
+            Some(rest) => match rest.split_once(']') {
+                Some((name, "")) => (name, None),
+                Some((name, p)) => (name, p.strip_prefix(':')),
+                None => return false,
+            },
+            // More than one colon: a bare IPv6 address.
+            None => match entry.split_once(':') {
+                Some((name, p)) if !p.contains(':') => (name, Some(p)),
+                _ => (entry.as_str(), None),
+            },
+        };
+        if let Some(p) = entry_port {
+            if p.parse::<u16>().ok() != port {
+                return false;
+            }
+        }
+        let name = name.trim_start_matches('*').trim_start_matches('.');
+        !name.is_empty() && (host == name || host.strip_suffix(name).is_some_and(|rest| rest.ends_with('.')))
+    })
+}
+
+// --proxy and --url may also come from the environment or a config file,
+// so clap cannot require them on its own. Commands that need them call
+// these, which fail the way a missing clap argument does, with the usage of
+// `command` (a subcommand name, or `None` for the top level).
+pub fn require_proxy(args: &Args, matches: &ArgMatches, command: Option<&str>) -> (String, Option<&'static str>) {
+    resolve_proxy(args, matches).unwrap_or_else(|| {
+        missing_argument(
+            command,
+            "--proxy <PROXY> (or XRAY_TESTER_PROXY / ALL_PROXY / HTTPS_PROXY / HTTP_PROXY)",
+        )
+    })
+}
+
+pub fn require_url<'a>(args: &'a Args, command: Option<&str>) -> &'a str {
+    args.url
+        .as_deref()
+        .unwrap_or_else(|| missing_argument(command, "--url <URL> (or XRAY_TESTER_URL)"))
+}
+
+fn missing_argument(command: Option<&str>, what: &str) -> ! {
+    let mut cmd = Args::command();
+    cmd.build();
+    let cmd = match command.and_then(|name| cmd.find_subcommand_mut(name)) {
+        Some(sub) => sub,
+        None => &mut cmd,
+    };
+    cmd.error(
+        ErrorKind::MissingRequiredArgument,
+        format!("the following required arguments were not provided:\n  {}", what),
+    )
+    .exit()
+}
+
+pub fn parse_proxy_url(s: &str) -> Result<String, String> {
+    let url = url::Url::parse(s).map_err(|e| format!("{} (expected e.g. socks5://127.0.0.1:2080)", e))?;
+    match url.scheme() {
+        "socks5" | "http" | "https" | "unix" | "http+unix" | crate::request::DIRECT_SCHEME => Ok(s.to_string()),
+        other => Err(format!(
+            "unsupported proxy scheme {}:// (expected socks5, http, https, unix, http+unix or direct)",
+            other
+        )),
+    }
+}
+
+pub fn parse_target_url(s: &str) -> Result<String, String> {
+    if s == crate::stdin_urls::STDIN_URL {
+        return Ok(s.to_string());
+    }
+    crate::request::parse_url_target(s)
+        .map(|_| s.to_string())
+        .map_err(|e| format!("{:#}", e))
+}
+
+pub fn env_origin(matches: &ArgMatches, id: &str, var: &'static str) -> Option<&'static str> {
+    match matches.value_source(id) {
+        Some(ValueSource::EnvVariable) => Some(var),
+        _ => None,
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SuccessMatcher {
     pub ranges: Vec<(u16, u16)>,
 }
 
-impl SuccessMatcher {
-    pub fn default() -> Self {
+impl Default for SuccessMatcher {
+    fn default() -> Self {
         Self {
             ranges: vec![(200, 400)],
         }
     }
+}
+
+impl SuccessMatcher {
     pub fn parse(spec: &str) -> Result<Self> {
         let mut ranges = Vec::new();
         for part in spec.split(',') {
@@ -102,3 +1815,160 @@ impl SuccessMatcher {
         self.ranges.iter().any(|&(s, e)| code >= s && code <= e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alpn_lists() {
+        let args = Args::try_parse_from(["xray-tester", "--alpn", "h2, http/1.1"]).unwrap();
+        assert_eq!(args.alpn, ["h2", "http/1.1"]);
+        assert!(Args::try_parse_from(["xray-tester", "--alpn", "h2,,http/1.1"]).is_err());
+        assert!(parse_alpn(&"x".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn ip_weights() {
+        let args = Args::try_parse_from([
+            "xray-tester",
+            "--resolve-target-locally",
+            "--ip-weights",
+            "192.0.2.1=3, [2001:db8::1]=0",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.ip_weights,
+            [("192.0.2.1".parse().unwrap(), 3), ("2001:db8::1".parse().unwrap(), 0)]
+        );
+        for bad in ["192.0.2.1", "192.0.2.1=", "192.0.2.1=-1", "192.0.2.1=1001", "example.com=1"] {
+            assert!(parse_ip_weight(bad).is_err(), "{}", bad);
+        }
+        assert!(Args::try_parse_from(["xray-tester", "--ip-weights", "192.0.2.1=1"]).is_err());
+    }
+
+    #[test]
+    fn builtin_intercept_headers_match_block_pages() {
+        let rules: Vec<_> = BUILTIN_INTERCEPT_HEADERS.iter().map(|s| parse_expect_header(s).unwrap()).collect();
+        let hit = |name: &str, value: &str| rules.iter().any(|r| r.name.eq_ignore_ascii_case(name) && r.matches(value));
+        assert!(hit("X-Squid-Error", "ERR_ACCESS_DENIED 0"));
+        assert!(hit("Server", "squid/5.7"));
+        assert!(hit("Server", "BlueCoat-Security-Appliance"));
+        assert!(!hit("Server", "nginx"));
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("5000"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration(" 1h30m "), Ok(Duration::from_secs(5400)));
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("0s").is_err());
+        assert!(parse_duration("8d").is_err());
+        assert!(parse_duration("99999999999999999999999").is_err());
+        assert!(parse_duration("5x").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn connect_to() {
+        let ct = |host: &str, port| Ok(ConnectTo { host: host.into(), port });
+        assert_eq!(parse_connect_to("example.com:443"), ct("example.com", 443));
+        assert_eq!(parse_connect_to("10.0.0.1:8080"), ct("10.0.0.1", 8080));
+        assert_eq!(parse_connect_to("[2001:db8::1]:443"), ct("2001:db8::1", 443));
+        assert_eq!(parse_connect_to("bücher.de:80"), ct("xn--bcher-kva.de", 80));
+        assert_eq!(parse_connect_to("[::1]:443").unwrap().to_string(), "[::1]:443");
+        for bad in [
+            "example.com",
+            "example.com;443",
+            "example.com:",
+            "example.com:0",
+            "example.com:65536",
+            ":443",
+            "ex ample.com:443",
+            "2001:db8::1:443",
+            "[2001:db8::1]",
+            "[not-v6]:443",
+        ] {
+            assert!(parse_connect_to(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn no_proxy_lists() {
+        let url = "https://api.Example.com/v1";
+        for (list, matches) in [
+            ("*", true),
+            ("example.com", true),
+            (".example.com", true),
+            ("*.example.com", true),
+            ("localhost, api.example.com", true),
+            ("localhost api.example.com", true),
+            ("EXAMPLE.COM:443", true),
+            ("example.com:8443", false),
+            ("ample.com", false),
+            ("other.com,", false),
+            ("", false),
+        ] {
+            assert_eq!(no_proxy_matches(list, url), matches, "{:?}", list);
+        }
+        assert!(no_proxy_matches("127.0.0.1", "http://127.0.0.1:8080/"));
+        assert!(no_proxy_matches("::1", "http://[::1]:8080/"));
+        assert!(no_proxy_matches("[::1]:8080", "http://[::1]:8080/"));
+        assert!(!no_proxy_matches("[::1]:80", "http://[::1]:8080/"));
+        assert!(!no_proxy_matches("*", "not a url"));
+    }
+
+    // The only test touching the proxy variables.
+    #[test]
+    fn no_proxy_bypasses_env_fallbacks() {
+        let resolve = |argv: &[&str]| {
+            let matches = Args::command().try_get_matches_from(argv).unwrap();
+            let args = <Args as clap::FromArgMatches>::from_arg_matches(&matches).unwrap();
+            resolve_proxy(&args, &matches)
+        };
+        for name in PROXY_ENV_FALLBACKS.iter().chain(&NO_PROXY_VARS).chain(&["XRAY_TESTER_PROXY"]) {
+            std::env::remove_var(name);
+        }
+        std::env::set_var("HTTPS_PROXY", "socks5://127.0.0.1:2080");
+        std::env::set_var("NO_PROXY", "localhost,.internal");
+        let fallback = Some(("socks5://127.0.0.1:2080".to_string(), Some("HTTPS_PROXY")));
+        assert_eq!(resolve(&["xray-tester", "-u", "https://example.com/"]), fallback);
+        assert_eq!(
+            resolve(&["xray-tester", "-u", "http://db.internal:8080/"]),
+            Some(("direct://".to_string(), Some("NO_PROXY")))
+        );
+        // An explicit proxy is used as given.
+        assert_eq!(
+            resolve(&["xray-tester", "-p", "http://p:3128", "-u", "http://localhost/"]),
+            Some(("http://p:3128".to_string(), None))
+        );
+        std::env::remove_var("HTTPS_PROXY");
+        std::env::remove_var("NO_PROXY");
+    }
+
+    #[test]
+    fn config_output_file_is_not_the_report_format() {
+        let matches = Args::command()
+            .try_get_matches_from(["xray-tester", "--output", "json", "config", "trojan://s@h:443", "-o", "x.json"])
+            .unwrap();
+        let args = <Args as clap::FromArgMatches>::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.output, OutputFormat::Json);
+        match args.cmd {
+            Some(Commands::Config { output, .. }) => assert_eq!(output, Some(PathBuf::from("x.json"))),
+            _ => panic!("expected the config subcommand"),
+        }
+    }
+
+    #[test]
+    fn manpage_output_file_is_not_the_report_format() {
+        let matches = Args::command().try_get_matches_from(["xray-tester", "manpage", "-o", "x.1"]).unwrap();
+        let args = <Args as clap::FromArgMatches>::from_arg_matches(&matches).unwrap();
+        assert_eq!(args.output, OutputFormat::Pretty);
+        match args.cmd {
+            Some(Commands::Manpage { output }) => assert_eq!(output, Some(PathBuf::from("x.1"))),
+            _ => panic!("expected the manpage subcommand"),
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::sync::Arc;
+use url::Url;
+
+use crate::cli::Args;
+use crate::pretty::{self, Segment, Style};
+use crate::report::{Report, SCHEMA_VERSION};
+use crate::request::{run_bench, BenchOptions};
+use crate::stats::Stats;
+
+// With --interleave each proxy's run is split into this many batches, run
+// alternately, so slow drifts in the network hit both sides alike.
+const INTERLEAVE_BATCHES: usize = 10;
+
+pub struct Side<'a> {
+    pub proxy: Arc<Url>,
+    pub proxy_addr: &'a str,
+    // scheme://host:port, without credentials.
+    pub label: String,
+}
+
+// Compare proxy minus primary.
+#[derive(Debug, Serialize)]
+pub struct Deltas {
+    pub success_rate: f64,
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p90_ms: Option<f64>,
+    pub latency_p99_ms: Option<f64>,
+    pub rps: Option<f64>,
+    pub fail: i64,
+}
+
+#[derive(Serialize)]
+pub struct Comparison {
+    pub schema_version: u32,
+    pub primary: Report,
+    pub compare: Report,
+    pub deltas: Deltas,
+}
+
+fn diff(a: Option<f64>, b: Option<f64>) -> Option<f64> {
+    Some(b? - a?)
+}
+
+fn deltas(primary: &Stats, compare: &Stats) -> Deltas {
+    Deltas {
+        success_rate: compare.success_rate().unwrap_or(0.0) - primary.success_rate().unwrap_or(0.0),
+        latency_p50_ms: diff(primary.latency_percentile(0.50), compare.latency_percentile(0.50)),
+        latency_p90_ms: diff(primary.latency_percentile(0.90), compare.latency_percentile(0.90)),
+        latency_p99_ms: diff(primary.latency_percentile(0.99), compare.latency_percentile(0.99)),
+        rps: diff(primary.rps_avg(), compare.rps_avg()),
+        fail: compare.fail as i64 - primary.fail as i64,
+    }
+}
+
+// Batches are laid end to end so the merged run reads as one continuous
+// run per proxy, without the time spent on the other one.
+fn append(total: &mut Option<Stats>, part: Stats) {
+    match total {
+        Some(total) => {
+            let mut part = part;
+            part.started_at = total
+                .started_at
+                .map(|t| t + total.total_duration);
+            total.merge(&part);
+        }
+        None => *total = Some(part),
+    }
+}
+
+async fn run_sides(args: &Args, primary: &Side<'_>, compare: &Side<'_>, opts: BenchOptions) -> Result<(Stats, Stats)> {
+    if !args.interleave {
+        let opts = Arc::new(opts);
+        let a = run_bench(primary.proxy.clone(), primary.proxy_addr, opts.clone()).await?;
+        let b = run_bench(compare.proxy.clone(), compare.proxy_addr, opts).await?;
+        return Ok((a, b));
+    }
+    let batches = INTERLEAVE_BATCHES.min(opts.iterations.max(1));
+    let (mut a, mut b) = (None, None);
+    let mut left = opts.iterations;
+    for i in 0..batches {
+        let n = left / (batches - i);
+        left -= n;
+        let batch = Arc::new(BenchOptions {
+            iterations: n,
+            ..opts.clone()
+        });
+        append(&mut a, run_bench(primary.proxy.clone(), primary.proxy_addr, batch.clone()).await?);
+        append(&mut b, run_bench(compare.proxy.clone(), compare.proxy_addr, batch).await?);
+    }
+    Ok((a.unwrap_or_default(), b.unwrap_or_default()))
+}
+
+// Red when the compare proxy is worse, green when it is better.
+fn delta_segment(delta: Option<f64>, fmt: impl Fn(f64) -> String, higher_is_better: bool) -> Segment {
+    let Some(d) = delta else {
+        return Segment {
+            text: format!("{:>12}", "-"),
+            style: Style::Plain,
+        };
+    };
+    let style = if d == 0.0 {
+        Style::Plain
+    } else if (d > 0.0) == higher_is_better {
+        Style::Green
+    } else {
+        Style::Red
+    };
+    Segment {
+        text: format!("{:>12}", fmt(d)),
+        style,
+    }
+}
+
+fn print_table(args: &Args, a: &Stats, b: &Stats, d: &Deltas, primary: &Side<'_>, compare: &Side<'_>) {
+    let color = pretty::color_enabled(args.no_color);
+    let row = |name: &str, x: String, y: String, delta: Segment| {
+        let mut line = vec![Segment {
+            text: format!("  {:<14}{:>14}  {:>14}  ", name, x, y),
+            style: Style::Plain,
+        }];
+        line.push(delta);
+        println!("{}", pretty::render(&line, color));
+    };
+    println!("\nPrimary: {}\nCompare: {}", primary.label, compare.label);
+    println!("  {:<14}{:>14}  {:>14}  {:>12}", "", "primary", "compare", "delta");
+    row(
+        "Success",
+        format!("{:.2}%", a.success_rate().unwrap_or(0.0)),
+        format!("{:.2}%", b.success_rate().unwrap_or(0.0)),
+        delta_segment(Some(d.success_rate), |d| format!("{:+.2}%", d), true),
+    );
+    for (name, p, delta) in [
+        ("p50", 0.50, d.latency_p50_ms),
+        ("p90", 0.90, d.latency_p90_ms),
+        ("p99", 0.99, d.latency_p99_ms),
+    ] {
+        row(
+            name,
+            pretty::fmt_ms(a.latency_percentile(p), args.units),
+            pretty::fmt_ms(b.latency_percentile(p), args.units),
+            delta_segment(delta, |d| pretty::fmt_delta(d, args.units), false),
+        );
+    }
+    row(
+        "Reqs/sec",
+        format!("{:.2}", a.rps_avg().unwrap_or(0.0)),
+        format!("{:.2}", b.rps_avg().unwrap_or(0.0)),
+        delta_segment(d.rps, |d| format!("{:+.2}", d), true),
+    );
+    for ((name, x), (_, y)) in pretty::error_counts(a).into_iter().zip(pretty::error_counts(b)) {
+        if x > 0 || y > 0 {
+            let delta = Segment {
+                text: format!("{:>12}", format!("{:+}", y as i64 - x as i64)),
+                style: match y.cmp(&x) {
+                    std::cmp::Ordering::Greater => Style::Red,
+                    std::cmp::Ordering::Less => Style::Green,
+                    std::cmp::Ordering::Equal => Style::Plain,
+                },
+            };
+            row(&format!("Errors {}", name), x.to_string(), y.to_string(), delta);
+        }
+    }
+}
+
+// --compare-proxy: the same workload through both proxies, then one table
+// (or with --output json, both reports and the deltas). Returns the
+// primary proxy's stats.
+pub async fn run(
+    args: &Args,
+    url: &str,
+    primary: Side<'_>,
+    compare: Side<'_>,
+    opts: BenchOptions,
+) -> Result<Stats> {
+    let (a, b) = run_sides(args, &primary, &compare, opts).await?;
+    let d = deltas(&a, &b);
+    match args.output {
+        crate::cli::OutputFormat::Json => {
+            let comparison = Comparison {
+                schema_version: SCHEMA_VERSION,
+                primary: Report::new(args, &primary.label, url, a.clone()),
+                compare: Report::new(args, &compare.label, url, b),
+                deltas: d,
+            };
+            println!("{}", serde_json::to_string_pretty(&comparison)?);
+        }
+        _ if args.summary_only => {
+            println!("primary: {}", pretty::summary_line(&a));
+            println!("compare: {}", pretty::summary_line(&b));
+        }
+        _ if !args.quiet => print_table(args, &a, &b, &d, &primary, &compare),
+        _ => {}
+    }
+    Ok(a)
+}
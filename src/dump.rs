@@ -0,0 +1,86 @@
+use anyhow::{Context, Result};
+use hyper::body::Incoming;
+use hyper::http::response::Parts;
+use http_body_util::BodyExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// --dump-failures: failed responses written to `DIR/<seq>.http` as status
+// line, headers and the start of the body. The count is shared by every
+// run of the invocation, so `--dump-limit` caps the whole of it.
+#[derive(Debug, Clone)]
+pub struct FailureDumps {
+    pub dir: PathBuf,
+    pub limit: usize,
+    pub max_body: usize,
+    written: Arc<AtomicUsize>,
+}
+
+impl FailureDumps {
+    pub fn new(dir: PathBuf, limit: usize, max_body: usize) -> Result<Self> {
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            limit,
+            max_body,
+            written: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn full(&self) -> bool {
+        self.written() >= self.limit
+    }
+
+    pub fn written(&self) -> usize {
+        self.written.load(Ordering::Relaxed).min(self.limit)
+    }
+
+    pub fn write(&self, seq: usize, dump: &[u8]) -> Result<()> {
+        if self.written.fetch_add(1, Ordering::Relaxed) >= self.limit {
+            return Ok(());
+        }
+        let path = self.dir.join(format!("{}.http", seq));
+        std::fs::write(&path, dump).with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+// Reads the body of a failed response up to `max` bytes; whatever arrived
+// before an error or the timeout is kept.
+pub async fn read_prefix(body: &mut Incoming, max: usize, timeout: Duration) -> Vec<u8> {
+    let mut out = Vec::new();
+    let read = async {
+        while out.len() < max {
+            match body.frame().await {
+                Some(Ok(frame)) => {
+                    if let Some(data) = frame.data_ref() {
+                        out.extend_from_slice(&data[..data.len().min(max - out.len())]);
+                    }
+                }
+                _ => break,
+            }
+        }
+    };
+    let _ = tokio::time::timeout(timeout, read).await;
+    out
+}
+
+pub fn render(parts: &Parts, body: &[u8]) -> Vec<u8> {
+    let mut out = format!(
+        "{:?} {} {}\r\n",
+        parts.version,
+        parts.status.as_u16(),
+        parts.status.canonical_reason().unwrap_or("")
+    )
+    .into_bytes();
+    for (name, value) in &parts.headers {
+        out.extend_from_slice(name.as_str().as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+    out.extend_from_slice(body);
+    out
+}
@@ -0,0 +1,160 @@
+use anyhow::{anyhow, Context, Result};
+use hyper::header::{HeaderName, HeaderValue};
+
+// A request header from -H. Values may pull secrets from the environment
+// with `${env:NAME}`; those are resolved once at startup and never shown.
+#[derive(Debug, Clone)]
+pub struct CustomHeader {
+    pub name: HeaderName,
+    pub value: HeaderValue,
+    // The value with interpolated parts replaced by `***`.
+    masked: String,
+}
+
+impl std::fmt::Display for CustomHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.masked)
+    }
+}
+
+// Headers whose values are never shown: credentials, and anything filled
+// in from the environment.
+pub const SECRET_HEADERS: [&str; 3] = ["authorization", "proxy-authorization", "cookie"];
+
+pub fn is_secret(name: &HeaderName, value: &HeaderValue) -> bool {
+    SECRET_HEADERS.contains(&name.as_str()) || value.is_sensitive()
+}
+
+// Each -H is either `Name: value` or `@FILE` with one such header per line;
+// blank lines and lines starting with `#` are skipped.
+pub fn load(args: &[String]) -> Result<Vec<CustomHeader>> {
+    let mut out = Vec::new();
+    for arg in args {
+        match arg.strip_prefix('@') {
+            Some(path) => {
+                let text = std::fs::read_to_string(path).with_context(|| format!("reading header file {}", path))?;
+                for (i, line) in text.lines().enumerate() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    out.push(parse(line).with_context(|| format!("{} line {}", path, i + 1))?);
+                }
+            }
+            None => out.push(parse(arg)?),
+        }
+    }
+    Ok(out)
+}
+
+fn parse(line: &str) -> Result<CustomHeader> {
+    let (name, value) = line
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected \"Name: value\", got {:?}", line))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| anyhow!("invalid header name {:?}", name.trim()))?;
+    let (value, masked, secret) = interpolate(value.trim())?;
+    let mut value = HeaderValue::from_str(&value).map_err(|_| anyhow!("invalid value for header {}", name))?;
+    value.set_sensitive(secret);
+    Ok(CustomHeader { name, value, masked })
+}
+
+// Returns the resolved value, its masked form and whether anything was
+// interpolated.
+fn interpolate(value: &str) -> Result<(String, String, bool)> {
+    let (mut resolved, mut masked) = (String::new(), String::new());
+    let mut secret = false;
+    let mut rest = value;
+    while let Some(start) = rest.find("${env:") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("unclosed ${{env:...}} in header value"))?;
+        let var = &rest[start + 6..start + end];
+        let val = std::env::var(var).map_err(|_| anyhow!("environment variable {} used in a header is not set", var))?;
+        resolved.push_str(&rest[..start]);
+        masked.push_str(&rest[..start]);
+        resolved.push_str(&val);
+        masked.push_str("***");
+        secret = true;
+        rest = &rest[start + end + 1..];
+    }
+    resolved.push_str(rest);
+    masked.push_str(rest);
+    Ok((resolved, masked, secret))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn one(arg: &str) -> Result<CustomHeader> {
+        load(&[arg.to_string()]).map(|mut h| h.remove(0))
+    }
+
+    #[test]
+    fn name_and_value() {
+        let h = one("X-Trace:  abc def ").unwrap();
+        assert_eq!((h.name.as_str(), h.value.to_str().unwrap()), ("x-trace", "abc def"));
+        assert_eq!(h.to_string(), "x-trace: abc def");
+        assert!(!is_secret(&h.name, &h.value));
+        // Only the first colon separates the name.
+        let h = one("Referer: https://example.com:8443/").unwrap();
+        assert_eq!(h.value, "https://example.com:8443/");
+        assert_eq!(one("X-Empty:").unwrap().value, "");
+        let h = one("Authorization: Basic dTpw").unwrap();
+        assert!(is_secret(&h.name, &h.value));
+
+        for (arg, err) in [
+            ("no colon", "expected \"Name: value\", got \"no colon\""),
+            ("Bad Name: x", "invalid header name \"Bad Name\""),
+            (": x", "invalid header name \"\""),
+            ("X-Bad: a\u{7f}b", "invalid value for header x-bad"),
+        ] {
+            assert_eq!(one(arg).unwrap_err().to_string(), err);
+        }
+    }
+
+    #[test]
+    fn env_interpolation_is_masked() {
+        std::env::set_var("XRAY_TESTER_HEADERS_TOKEN", "s3cret");
+        std::env::set_var("XRAY_TESTER_HEADERS_USER", "bob");
+        let h = one("X-Auth: Bearer ${env:XRAY_TESTER_HEADERS_TOKEN} for ${env:XRAY_TESTER_HEADERS_USER}!").unwrap();
+        assert_eq!(h.value, "Bearer s3cret for bob!");
+        assert!(h.value.is_sensitive() && is_secret(&h.name, &h.value));
+        assert_eq!(h.to_string(), "x-auth: Bearer *** for ***!");
+
+        assert_eq!(
+            one("X-Auth: ${env:XRAY_TESTER_HEADERS_UNSET}").unwrap_err().to_string(),
+            "environment variable XRAY_TESTER_HEADERS_UNSET used in a header is not set"
+        );
+        assert_eq!(
+            one("X-Auth: ${env:XRAY_TESTER_HEADERS_TOKEN").unwrap_err().to_string(),
+            "unclosed ${env:...} in header value"
+        );
+    }
+
+    #[test]
+    fn header_files() {
+        std::env::set_var("XRAY_TESTER_HEADERS_FILE_TOKEN", "t0ken");
+        let path = std::env::temp_dir().join(format!("xray-tester-{}-headers.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# API access\nAccept: application/json\n\n  X-Api-Key: ${env:XRAY_TESTER_HEADERS_FILE_TOKEN}  \n#X-Off: 1\n",
+        )
+        .unwrap();
+        let file_arg = format!("@{}", path.display());
+        let headers = load(&["X-First: 1".to_string(), file_arg.clone()]).unwrap();
+        let shown: Vec<String> = headers.iter().map(ToString::to_string).collect();
+        assert_eq!(shown, ["x-first: 1", "accept: application/json", "x-api-key: ***"]);
+        assert_eq!(headers[2].value, "t0ken");
+
+        std::fs::write(&path, "Accept: */*\nbroken\n").unwrap();
+        let err = load(&[file_arg]).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            format!("{:#}", err),
+            format!("{} line 2: expected \"Name: value\", got \"broken\"", path.display())
+        );
+        let err = load(&["@/nonexistent/headers.txt".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "reading header file /nonexistent/headers.txt");
+    }
+}
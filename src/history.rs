@@ -0,0 +1,259 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::cli::{Args, Units};
+use crate::pretty;
+use crate::report::RunInfo;
+use crate::stats::Stats;
+
+// --sqlite: every run appends a `runs` row (context, flags and summary
+// metrics) and one `samples` row per request, in a single transaction, so
+// nightly runs can be compared with plain SQL. `history` reads it back.
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    proxy TEXT NOT NULL,
+    target TEXT NOT NULL,
+    mode TEXT NOT NULL,
+    tool_version TEXT NOT NULL,
+    hostname TEXT,
+    parameters TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    success INTEGER NOT NULL,
+    fail INTEGER NOT NULL,
+    success_rate REAL,
+    rps REAL,
+    duration_ms INTEGER NOT NULL,
+    latency_avg_ms REAL,
+    p50_ms REAL,
+    p90_ms REAL,
+    p99_ms REAL,
+    max_ms REAL,
+    aborted TEXT,
+    interrupted INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_proxy_timestamp ON runs (proxy, timestamp);
+CREATE TABLE IF NOT EXISTS samples (
+    run_id INTEGER NOT NULL REFERENCES runs (id),
+    seq INTEGER NOT NULL,
+    latency_us INTEGER,
+    status INTEGER,
+    error_kind TEXT
+);
+CREATE INDEX IF NOT EXISTS samples_run_id ON samples (run_id);
+";
+
+fn open(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path).with_context(|| format!("opening {}", path.display()))?;
+    conn.execute_batch(SCHEMA)
+        .with_context(|| format!("creating tables in {}", path.display()))?;
+    Ok(conn)
+}
+
+// Returns the new run's id.
+pub fn save(path: &Path, args: &Args, proxy: &str, target: &str, stats: &Stats) -> Result<i64> {
+    let mut conn = open(path)?;
+    let run = RunInfo::new(args, stats);
+    let timestamp = humantime::format_rfc3339_millis(stats.started_at.unwrap_or_else(SystemTime::now)).to_string();
+    let mode = clap::ValueEnum::to_possible_value(&args.mode)
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default();
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO runs (timestamp, proxy, target, mode, tool_version, hostname, parameters,
+            requests, success, fail, success_rate, rps, duration_ms,
+            latency_avg_ms, p50_ms, p90_ms, p99_ms, max_ms, aborted, interrupted)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+        params![
+            timestamp,
+            proxy,
+            target,
+            mode,
+            env!("CARGO_PKG_VERSION"),
+            run.hostname,
+            toml::to_string(&run.config)?,
+            stats.completed() as i64,
+            stats.success as i64,
+            stats.fail as i64,
+            stats.success_rate(),
+            stats.rps_avg(),
+            stats.total_duration.as_millis() as i64,
+            stats.latency_avg(),
+            stats.latency_percentile(0.50),
+            stats.latency_percentile(0.90),
+            stats.latency_percentile(0.99),
+            stats.latency_max(),
+            stats.aborted,
+            stats.interrupted,
+        ],
+    )?;
+    let run_id = tx.last_insert_rowid();
+    {
+        let mut insert =
+            tx.prepare("INSERT INTO samples (run_id, seq, latency_us, status, error_kind) VALUES (?1, ?2, ?3, ?4, ?5)")?;
+        for s in stats.samples.iter().flatten() {
+            insert.execute(params![
+                run_id,
+                s.seq as i64,
+                s.latency_us.map(|us| us as i64),
+                s.status,
+                s.error_kind
+            ])?;
+        }
+    }
+    tx.commit().with_context(|| format!("writing the run to {}", path.display()))?;
+    Ok(run_id)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub id: i64,
+    pub timestamp: String,
+    pub proxy: String,
+    pub target: String,
+    pub requests: i64,
+    pub success_rate: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+}
+
+// The `last` most recent runs, of `proxy` only when given; oldest first.
+pub fn load(path: &Path, proxy: Option<&str>, last: usize) -> Result<Vec<Run>> {
+    let conn = open(path)?;
+    let mut query = conn.prepare(
+        "SELECT id, timestamp, proxy, target, requests, success_rate, p50_ms, p99_ms FROM runs
+         WHERE ?1 IS NULL OR proxy = ?1 ORDER BY timestamp DESC, id DESC LIMIT ?2",
+    )?;
+    let rows = query.query_map(params![proxy, last as i64], |row| {
+        Ok(Run {
+            id: row.get(0)?,
+            timestamp: row.get(1)?,
+            proxy: row.get(2)?,
+            target: row.get(3)?,
+            requests: row.get(4)?,
+            success_rate: row.get(5)?,
+            p50_ms: row.get(6)?,
+            p99_ms: row.get(7)?,
+        })
+    })?;
+    let mut runs = rows
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .with_context(|| format!("reading runs from {}", path.display()))?;
+    runs.reverse();
+    Ok(runs)
+}
+
+fn delta_ms(now: Option<f64>, before: Option<f64>, units: Units) -> String {
+    match (now, before) {
+        (Some(a), Some(b)) => pretty::fmt_delta(a - b, units),
+        _ => "-".into(),
+    }
+}
+
+// One line per run with p50/p99 and their change against the run before;
+// the proxy column only when runs of several proxies are listed.
+pub fn lines(runs: &[Run], units: Units) -> Vec<String> {
+    let fmt_ms = |v: Option<f64>| pretty::fmt_ms(v, units);
+    let with_proxy = runs.windows(2).any(|w| w[0].proxy != w[1].proxy);
+    let mut out = vec![format!(
+        "{:>5}  {:<24}  {:>8}  {:>8}  {:>10}  {:>10}  {:>10}  {:>10}  {}",
+        "Run", "Timestamp", "Requests", "Success", "p50", "Δp50", "p99", "Δp99", if with_proxy { "Proxy / target" } else { "Target" }
+    )];
+    let mut before: Option<&Run> = None;
+    for run in runs {
+        out.push(format!(
+            "{:>5}  {:<24}  {:>8}  {:>7.1}%  {:>10}  {:>10}  {:>10}  {:>10}  {}",
+            run.id,
+            run.timestamp,
+            run.requests,
+            run.success_rate.unwrap_or(0.0),
+            fmt_ms(run.p50_ms),
+            delta_ms(run.p50_ms, before.and_then(|b| b.p50_ms), units),
+            fmt_ms(run.p99_ms),
+            delta_ms(run.p99_ms, before.and_then(|b| b.p99_ms), units),
+            match with_proxy {
+                true => format!("{} {}", run.proxy, run.target),
+                false => run.target.clone(),
+            }
+        ));
+        before = Some(run);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    // seq, latency_us, status, error_kind
+    type SampleRow = (i64, Option<i64>, Option<u16>, Option<String>);
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("xray-tester-{}-{}", std::process::id(), name))
+    }
+
+    fn stats(latencies_ms: &[u64], started_at: SystemTime) -> Stats {
+        let mut stats = Stats {
+            started_at: Some(started_at),
+            total_duration: Duration::from_secs(1),
+            samples: Some(Vec::new()),
+            ..Stats::default()
+        };
+        for (i, &ms) in latencies_ms.iter().enumerate() {
+            stats.record_success(i + 1, Duration::from_millis(ms));
+            stats.record_sample(i + 1, Some(Duration::from_millis(ms)), Some(200), None);
+        }
+        stats.fail += 1;
+        stats.record_sample(latencies_ms.len() + 1, None, None, Some("timeout"));
+        stats
+    }
+
+    #[test]
+    fn runs_and_samples_round_trip() {
+        let path = scratch("history.db");
+        let _ = std::fs::remove_file(&path);
+        let args = Args::try_parse_from(["xray-tester", "-u", "http://127.0.0.1:8080/", "-n", "4"]).unwrap();
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+        let a = "socks5://127.0.0.1:1080";
+        let first = save(&path, &args, a, "http://127.0.0.1:8080/", &stats(&[10, 10, 10], t0)).unwrap();
+        save(&path, &args, "http://127.0.0.1:3128", "http://127.0.0.1:8080/", &stats(&[5], t0 + Duration::from_secs(60))).unwrap();
+        let third = save(&path, &args, a, "http://127.0.0.1:8080/", &stats(&[20, 20, 20], t0 + Duration::from_secs(120))).unwrap();
+
+        let conn = open(&path).unwrap();
+        let samples: Vec<SampleRow> = conn
+            .prepare("SELECT seq, latency_us, status, error_kind FROM samples WHERE run_id = ?1 ORDER BY seq")
+            .unwrap()
+            .query_map([first], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[0], (1, Some(10_000), Some(200), None));
+        assert_eq!(samples[3], (4, None, None, Some("timeout".to_string())));
+        let (requests, parameters): (i64, String) = conn
+            .query_row("SELECT requests, parameters FROM runs WHERE id = ?1", [first], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap();
+        assert_eq!(requests, 4);
+        assert_eq!(parameters, toml::to_string(&args.effective_config).unwrap());
+
+        let runs = load(&path, Some(a), 10).unwrap();
+        assert_eq!(runs.iter().map(|r| r.id).collect::<Vec<_>>(), [first, third]);
+        assert_eq!(runs[0].timestamp, "2027-01-15T08:00:00.000Z");
+        assert_eq!((runs[1].p50_ms, runs[1].p99_ms), (Some(20.0), Some(20.0)));
+        assert_eq!(load(&path, None, 2).unwrap().iter().map(|r| r.id).collect::<Vec<_>>(), [first + 1, third]);
+
+        let lines = lines(&runs, Units::Ms);
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].contains("         -"), "{}", lines[1]);
+        assert!(lines[2].contains("+10.00ms"), "{}", lines[2]);
+        assert!(lines[2].ends_with("  http://127.0.0.1:8080/"), "{}", lines[2]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt::Write as _;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::stats::Stats;
+
+// InfluxDB line protocol (--influx-file, --influx-url): one
+// `xray_tester_second` point per second of the run and a closing
+// `xray_tester_summary` point, tagged with the proxy and the target.
+// Timestamps are wall-clock nanoseconds counted from the run start.
+
+// Tag values escape commas, spaces and equals signs.
+fn tag(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+fn nanos(t: SystemTime) -> u128 {
+    t.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+pub fn lines(stats: &Stats, proxy: &str, target: &str) -> String {
+    let start = stats.started_at.unwrap_or_else(SystemTime::now);
+    let tags = format!("proxy={},target={}", tag(proxy), tag(target));
+    let mut out = String::new();
+    if let Some(&last) = stats.completions_per_sec.keys().last() {
+        for sec in 0..=last {
+            let requests = stats.completions_per_sec.get(&sec).copied().unwrap_or(0);
+            let success = stats.success_per_sec.get(&sec).copied().unwrap_or(0);
+            let _ = writeln!(
+                out,
+                "xray_tester_second,{} requests={}i,success={}i,errors={}i {}",
+                tags,
+                requests,
+                success,
+                requests - success,
+                nanos(start + Duration::from_secs(sec))
+            );
+        }
+    }
+    let mut fields = vec![
+        format!("requests={}i", stats.completed()),
+        format!("success={}i", stats.success),
+        format!("fail={}i", stats.fail),
+        format!("success_rate={}", stats.success_rate().unwrap_or(0.0)),
+        format!("duration_ms={}i", stats.total_duration.as_millis()),
+    ];
+    if let Some(rps) = stats.rps_avg() {
+        fields.push(format!("rps={}", rps));
+    }
+    if let Some(avg) = stats.latency_avg() {
+        fields.push(format!("latency_avg_ms={}", avg));
+    }
+    for (name, p) in [("p50", 0.50), ("p90", 0.90), ("p95", 0.95), ("p99", 0.99)] {
+        if let Some(v) = stats.latency_percentile(p) {
+            fields.push(format!("latency_{}_ms={}", name, v));
+        }
+    }
+    let end = start + stats.total_duration;
+    let _ = writeln!(out, "xray_tester_summary,{} {} {}", tags, fields.join(","), nanos(end));
+    out
+}
@@ -0,0 +1,764 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use url::Url;
+
+use crate::request::split_host_port;
+
+#[derive(Debug, Clone)]
+pub struct ShareLink {
+    pub name: String,
+    pub address: String,
+    pub port: u16,
+    pub protocol: Protocol,
+    pub stream: StreamSettings,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum Protocol {
+    Vless {
+        id: String,
+        flow: Option<String>,
+        encryption: String,
+    },
+    Vmess {
+        id: String,
+        alter_id: u32,
+        security: String,
+    },
+    Trojan {
+        password: String,
+    },
+    Shadowsocks {
+        method: String,
+        password: String,
+    },
+}
+
+const STREAM_PARAMS: &[&str] = &[
+    "type",
+    "security",
+    "path",
+    "host",
+    "headerType",
+    "serviceName",
+    "mode",
+    "sni",
+    "peer",
+    "fp",
+    "alpn",
+    "allowInsecure",
+    "pbk",
+    "sid",
+    "spx",
+];
+
+const SS_METHODS: &[&str] = &[
+    "aes-128-gcm",
+    "aes-256-gcm",
+    "chacha20-poly1305",
+    "chacha20-ietf-poly1305",
+    "xchacha20-poly1305",
+    "xchacha20-ietf-poly1305",
+    "2022-blake3-aes-128-gcm",
+    "2022-blake3-aes-256-gcm",
+    "2022-blake3-chacha20-poly1305",
+    "none",
+    "plain",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct StreamSettings {
+    pub network: String,
+    pub security: String,
+    pub path: Option<String>,
+    pub host: Option<String>,
+    pub header_type: Option<String>,
+    pub service_name: Option<String>,
+    pub grpc_mode: Option<String>,
+    pub sni: Option<String>,
+    pub fingerprint: Option<String>,
+    pub alpn: Vec<String>,
+    pub allow_insecure: bool,
+    pub public_key: Option<String>,
+    pub short_id: Option<String>,
+    pub spider_x: Option<String>,
+}
+
+impl ShareLink {
+    pub fn parse(uri: &str) -> Result<Self> {
+        let uri = uri.trim();
+        let scheme = uri
+            .split_once("://")
+            .map(|(s, _)| s.to_ascii_lowercase())
+            .ok_or_else(|| anyhow!("not a share link (missing scheme): {}", uri))?;
+        match scheme.as_str() {
+            "vless" => parse_vless(uri).context("invalid vless:// link"),
+            "vmess" => parse_vmess(uri).context("invalid vmess:// link"),
+            "trojan" => parse_trojan(uri).context("invalid trojan:// link"),
+            "ss" => parse_shadowsocks(uri).context("invalid ss:// link"),
+            other => Err(anyhow!("unsupported share link scheme: {}://", other)),
+        }
+    }
+
+    pub fn protocol_name(&self) -> &'static str {
+        match self.protocol {
+            Protocol::Vless { .. } => "vless",
+            Protocol::Vmess { .. } => "vmess",
+            Protocol::Trojan { .. } => "trojan",
+            Protocol::Shadowsocks { .. } => "shadowsocks",
+        }
+    }
+
+    pub fn outbound(&self, tag: &str) -> Value {
+        let mut out = match &self.protocol {
+            Protocol::Vless {
+                id,
+                flow,
+                encryption,
+            } => vless_outbound(self, id, flow.as_deref(), encryption),
+            Protocol::Vmess {
+                id,
+                alter_id,
+                security,
+            } => vmess_outbound(self, id, *alter_id, security),
+            Protocol::Trojan { password } => trojan_outbound(self, password),
+            Protocol::Shadowsocks { method, password } => {
+                shadowsocks_outbound(self, method, password)
+            }
+        };
+        out["tag"] = json!(tag);
+        out["streamSettings"] = self.stream.to_json();
+        out
+    }
+}
+
+fn vless_outbound(link: &ShareLink, id: &str, flow: Option<&str>, encryption: &str) -> Value {
+    let mut user = json!({ "id": id, "encryption": encryption });
+    if let Some(flow) = flow {
+        user["flow"] = json!(flow);
+    }
+    json!({
+        "protocol": "vless",
+        "settings": {
+            "vnext": [{ "address": link.address, "port": link.port, "users": [user] }]
+        }
+    })
+}
+
+fn vmess_outbound(link: &ShareLink, id: &str, alter_id: u32, security: &str) -> Value {
+    json!({
+        "protocol": "vmess",
+        "settings": {
+            "vnext": [{
+                "address": link.address,
+                "port": link.port,
+                "users": [{ "id": id, "alterId": alter_id, "security": security }]
+            }]
+        }
+    })
+}
+
+fn trojan_outbound(link: &ShareLink, password: &str) -> Value {
+    json!({
+        "protocol": "trojan",
+        "settings": {
+            "servers": [{ "address": link.address, "port": link.port, "password": password }]
+        }
+    })
+}
+
+fn shadowsocks_outbound(link: &ShareLink, method: &str, password: &str) -> Value {
+    json!({
+        "protocol": "shadowsocks",
+        "settings": {
+            "servers": [{
+                "address": link.address,
+                "port": link.port,
+                "method": method,
+                "password": password
+            }]
+        }
+    })
+}
+
+impl StreamSettings {
+    pub fn to_json(&self) -> Value {
+        let mut s = Map::new();
+        s.insert("network".into(), json!(self.network));
+        s.insert("security".into(), json!(self.security));
+        match self.network.as_str() {
+            "ws" | "httpupgrade" => {
+                let mut t = json!({ "path": self.path.clone().unwrap_or_else(|| "/".into()) });
+                if let Some(host) = &self.host {
+                    t["host"] = json!(host);
+                }
+                let key = format!("{}Settings", self.network);
+                s.insert(key, t);
+            }
+            "grpc" => {
+                s.insert(
+                    "grpcSettings".into(),
+                    json!({
+                        "serviceName": self.service_name.clone().unwrap_or_default(),
+                        "multiMode": self.grpc_mode.as_deref() == Some("multi"),
+                    }),
+                );
+            }
+            "http" => {
+                let mut t = json!({ "path": self.path.clone().unwrap_or_else(|| "/".into()) });
+                if let Some(host) = &self.host {
+                    t["host"] = json!(host.split(',').map(str::trim).collect::<Vec<_>>());
+                }
+                s.insert("httpSettings".into(), t);
+            }
+            "xhttp" => {
+                let mut t = json!({ "path": self.path.clone().unwrap_or_else(|| "/".into()) });
+                if let Some(host) = &self.host {
+                    t["host"] = json!(host);
+                }
+                if let Some(mode) = &self.grpc_mode {
+                    t["mode"] = json!(mode);
+                }
+                s.insert("xhttpSettings".into(), t);
+            }
+            _ => {
+                if self.header_type.as_deref() == Some("http") {
+                    let mut request = json!({
+                        "path": [self.path.clone().unwrap_or_else(|| "/".into())]
+                    });
+                    if let Some(host) = &self.host {
+                        request["headers"] = json!({ "Host": [host] });
+                    }
+                    s.insert(
+                        "tcpSettings".into(),
+                        json!({ "header": { "type": "http", "request": request } }),
+                    );
+                }
+            }
+        }
+        match self.security.as_str() {
+            "tls" => {
+                let mut t = Map::new();
+                if let Some(sni) = &self.sni {
+                    t.insert("serverName".into(), json!(sni));
+                }
+                if let Some(fp) = &self.fingerprint {
+                    t.insert("fingerprint".into(), json!(fp));
+                }
+                if !self.alpn.is_empty() {
+                    t.insert("alpn".into(), json!(self.alpn));
+                }
+                if self.allow_insecure {
+                    t.insert("allowInsecure".into(), json!(true));
+                }
+                s.insert("tlsSettings".into(), Value::Object(t));
+            }
+            "reality" => {
+                s.insert(
+                    "realitySettings".into(),
+                    json!({
+                        "serverName": self.sni.clone().unwrap_or_default(),
+                        "fingerprint": self.fingerprint.clone().unwrap_or_else(|| "chrome".into()),
+                        "publicKey": self.public_key.clone().unwrap_or_default(),
+                        "shortId": self.short_id.clone().unwrap_or_default(),
+                        "spiderX": self.spider_x.clone().unwrap_or_default(),
+                    }),
+                );
+            }
+            _ => {}
+        }
+        Value::Object(s)
+    }
+}
+
+fn non_empty(v: Option<&String>) -> Option<String> {
+    v.filter(|s| !s.is_empty()).cloned()
+}
+
+fn link_name(url: &Url) -> String {
+    url.fragment()
+        .map(|f| percent_decode_str(f).decode_utf8_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn unsupported_params(q: &HashMap<String, String>, extra: &[&str]) -> Vec<String> {
+    let mut unknown: Vec<String> = q
+        .keys()
+        .filter(|k| !STREAM_PARAMS.contains(&k.as_str()) && !extra.contains(&k.as_str()))
+        .map(|k| format!("ignoring unsupported link parameter `{}`", k))
+        .collect();
+    unknown.sort();
+    unknown
+}
+
+fn stream_from_query(q: &HashMap<String, String>, default_security: &str) -> Result<StreamSettings> {
+    let network = match non_empty(q.get("type")).as_deref() {
+        None => "tcp".to_string(),
+        Some("h2") => "http".to_string(),
+        Some("splithttp") => "xhttp".to_string(),
+        Some(other) => other.to_string(),
+    };
+    if !matches!(
+        network.as_str(),
+        "tcp" | "raw" | "ws" | "grpc" | "http" | "httpupgrade" | "xhttp"
+    ) {
+        return Err(anyhow!("unsupported transport type: {}", network));
+    }
+    let security = non_empty(q.get("security")).unwrap_or_else(|| default_security.into());
+    if !matches!(security.as_str(), "none" | "tls" | "reality") {
+        return Err(anyhow!("unsupported security: {}", security));
+    }
+    if security == "reality" && non_empty(q.get("pbk")).is_none() {
+        return Err(anyhow!("reality link is missing the public key (pbk)"));
+    }
+    Ok(StreamSettings {
+        network,
+        security,
+        path: non_empty(q.get("path")),
+        host: non_empty(q.get("host")),
+        header_type: non_empty(q.get("headerType")),
+        service_name: non_empty(q.get("serviceName")),
+        grpc_mode: non_empty(q.get("mode")),
+        sni: non_empty(q.get("sni")).or_else(|| non_empty(q.get("peer"))),
+        fingerprint: non_empty(q.get("fp")),
+        alpn: q
+            .get("alpn")
+            .map(|a| {
+                a.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default(),
+        allow_insecure: matches!(
+            q.get("allowInsecure").map(String::as_str),
+            Some("1" | "true")
+        ),
+        public_key: non_empty(q.get("pbk")),
+        short_id: non_empty(q.get("sid")),
+        spider_x: non_empty(q.get("spx")),
+    })
+}
+
+fn host_port(url: &Url) -> Result<(String, u16)> {
+    let host = url
+        .host_str()
+        .filter(|h| !h.is_empty())
+        .ok_or_else(|| anyhow!("server address missing"))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = url.port().ok_or_else(|| anyhow!("server port missing"))?;
+    Ok((host, port))
+}
+
+fn parse_vless(uri: &str) -> Result<ShareLink> {
+    let url = Url::parse(uri)?;
+    let id = percent_decode_str(url.username())
+        .decode_utf8_lossy()
+        .into_owned();
+    if id.is_empty() {
+        return Err(anyhow!("user id (uuid) missing"));
+    }
+    let (address, port) = host_port(&url)?;
+    let q: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let stream = stream_from_query(&q, "none")?;
+    Ok(ShareLink {
+        name: link_name(&url),
+        address,
+        port,
+        protocol: Protocol::Vless {
+            id,
+            flow: non_empty(q.get("flow")),
+            encryption: non_empty(q.get("encryption")).unwrap_or_else(|| "none".into()),
+        },
+        stream,
+        warnings: unsupported_params(&q, &["encryption", "flow"]),
+    })
+}
+
+fn parse_trojan(uri: &str) -> Result<ShareLink> {
+    let url = Url::parse(uri)?;
+    let password = percent_decode_str(url.username())
+        .decode_utf8_lossy()
+        .into_owned();
+    if password.is_empty() {
+        return Err(anyhow!("password missing"));
+    }
+    let (address, port) = host_port(&url)?;
+    let q: HashMap<String, String> = url.query_pairs().into_owned().collect();
+    let stream = stream_from_query(&q, "tls")?;
+    Ok(ShareLink {
+        name: link_name(&url),
+        address,
+        port,
+        protocol: Protocol::Trojan { password },
+        stream,
+        warnings: unsupported_params(&q, &[]),
+    })
+}
+
+// Accepts SIP002 (`ss://BASE64(method:password)@host:port`), its plain
+// percent-encoded userinfo variant used for 2022 ciphers, and the legacy
+// `ss://BASE64(method:password@host:port)` form.
+fn parse_shadowsocks(uri: &str) -> Result<ShareLink> {
+    let rest = &uri["ss://".len()..];
+    let (rest, name) = match rest.split_once('#') {
+        Some((r, f)) => (r, percent_decode_str(f).decode_utf8_lossy().into_owned()),
+        None => (rest, String::new()),
+    };
+    let (rest, query) = match rest.split_once('?') {
+        Some((r, q)) => (r, q),
+        None => (rest, ""),
+    };
+    let rest = rest.trim_end_matches('/');
+    let (userinfo, server) = match rest.rsplit_once('@') {
+        Some((u, s)) => {
+            let u = percent_decode_str(u).decode_utf8_lossy().into_owned();
+            if u.contains(':') {
+                (u, s.to_string())
+            } else {
+                let decoded = String::from_utf8(decode_base64(&u)?)
+                    .map_err(|_| anyhow!("userinfo is not valid UTF-8"))?;
+                (decoded, s.to_string())
+            }
+        }
+        None => {
+            let decoded = String::from_utf8(decode_base64(rest)?)
+                .map_err(|_| anyhow!("payload is not valid UTF-8"))?;
+            let (u, s) = decoded
+                .rsplit_once('@')
+                .ok_or_else(|| anyhow!("server address missing"))?;
+            (u.to_string(), s.to_string())
+        }
+    };
+    let (method, password) = userinfo
+        .split_once(':')
+        .ok_or_else(|| anyhow!("expected method:password in userinfo"))?;
+    let method = method.to_ascii_lowercase();
+    if !SS_METHODS.contains(&method.as_str()) {
+        return Err(anyhow!("unsupported cipher: {}", method));
+    }
+    if password.is_empty() && method != "none" && method != "plain" {
+        return Err(anyhow!("password missing"));
+    }
+    let (address, port) = split_host_port(&server).context("invalid server address")?;
+    let q: HashMap<String, String> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let mut warnings: Vec<String> = q
+        .keys()
+        .map(|k| format!("ignoring unsupported link parameter `{}`", k))
+        .collect();
+    warnings.sort();
+    Ok(ShareLink {
+        name,
+        address,
+        port,
+        protocol: Protocol::Shadowsocks {
+            method,
+            password: password.to_string(),
+        },
+        stream: StreamSettings {
+            network: "tcp".into(),
+            security: "none".into(),
+            ..Default::default()
+        },
+        warnings,
+    })
+}
+
+#[derive(Deserialize)]
+struct VmessJson {
+    #[serde(default)]
+    ps: String,
+    add: String,
+    port: Value,
+    id: String,
+    #[serde(default)]
+    aid: Value,
+    #[serde(default)]
+    scy: String,
+    #[serde(default)]
+    net: String,
+    #[serde(default, rename = "type")]
+    header_type: String,
+    #[serde(default)]
+    host: String,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    tls: String,
+    #[serde(default)]
+    sni: String,
+    #[serde(default)]
+    alpn: String,
+    #[serde(default)]
+    fp: String,
+}
+
+fn json_number(v: &Value) -> Option<u64> {
+    match v {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) if s.trim().is_empty() => Some(0),
+        Value::String(s) => s.trim().parse().ok(),
+        Value::Null => Some(0),
+        _ => None,
+    }
+}
+
+pub fn decode_base64(s: &str) -> Result<Vec<u8>> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    STANDARD
+        .decode(&s)
+        .or_else(|_| STANDARD_NO_PAD.decode(&s))
+        .or_else(|_| URL_SAFE.decode(&s))
+        .or_else(|_| URL_SAFE_NO_PAD.decode(&s))
+        .map_err(|e| anyhow!("invalid base64: {}", e))
+}
+
+fn parse_vmess(uri: &str) -> Result<ShareLink> {
+    let payload = &uri["vmess://".len()..];
+    let payload = payload.split('#').next().unwrap_or_default();
+    let raw = decode_base64(payload)?;
+    let v: VmessJson = serde_json::from_slice(&raw).context("invalid vmess JSON payload")?;
+    if v.id.is_empty() {
+        return Err(anyhow!("user id (uuid) missing"));
+    }
+    if v.add.is_empty() {
+        return Err(anyhow!("server address missing"));
+    }
+    let port = json_number(&v.port)
+        .and_then(|p| u16::try_from(p).ok())
+        .filter(|&p| p != 0)
+        .ok_or_else(|| anyhow!("invalid server port: {}", v.port))?;
+    let alter_id = json_number(&v.aid)
+        .and_then(|a| u32::try_from(a).ok())
+        .ok_or_else(|| anyhow!("invalid alterId: {}", v.aid))?;
+
+    let mut q = HashMap::new();
+    q.insert("type".to_string(), v.net.clone());
+    q.insert("security".to_string(), v.tls.clone());
+    q.insert("host".to_string(), v.host.clone());
+    q.insert("headerType".to_string(), v.header_type.clone());
+    q.insert("sni".to_string(), v.sni);
+    q.insert("alpn".to_string(), v.alpn);
+    q.insert("fp".to_string(), v.fp);
+    if v.net == "grpc" {
+        q.insert("serviceName".to_string(), v.path);
+        q.insert("mode".to_string(), v.header_type);
+    } else {
+        q.insert("path".to_string(), v.path);
+    }
+    let stream = stream_from_query(&q, "none")?;
+    Ok(ShareLink {
+        name: v.ps,
+        address: v.add,
+        port,
+        protocol: Protocol::Vmess {
+            id: v.id,
+            alter_id,
+            security: if v.scy.is_empty() {
+                "auto".into()
+            } else {
+                v.scy
+            },
+        },
+        stream,
+        warnings: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xray::{client_config, InboundSpec};
+    use std::path::Path;
+
+    const UUID: &str = "b831381d-6324-4d53-ad4f-8cda48b30811";
+
+    fn vmess(payload: Value) -> String {
+        format!("vmess://{}", STANDARD.encode(payload.to_string()))
+    }
+
+    // tests/fixtures/links/*.json: a share link and the outbound
+    // client_config must build from it.
+    #[test]
+    fn outbounds_match_fixtures() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/links");
+        let mut seen = Vec::new();
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let fixture: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+            let link = ShareLink::parse(fixture["link"].as_str().unwrap()).unwrap();
+            let config = client_config(&link, &InboundSpec::parse("socks:1080").unwrap());
+            assert_eq!(config["outbounds"][0], fixture["outbound"], "{}", path.display());
+            seen.push(link.protocol_name());
+        }
+        seen.sort_unstable();
+        assert_eq!(seen, ["shadowsocks", "trojan", "vless", "vmess"]);
+    }
+
+    #[test]
+    fn every_protocol_carries_every_transport() {
+        // (link `type`, xray network, its settings object)
+        let transports = [
+            ("", "tcp", None),
+            ("raw", "raw", None),
+            ("ws", "ws", Some("wsSettings")),
+            ("grpc", "grpc", Some("grpcSettings")),
+            ("http", "http", Some("httpSettings")),
+            ("h2", "http", Some("httpSettings")),
+            ("httpupgrade", "httpupgrade", Some("httpupgradeSettings")),
+            ("xhttp", "xhttp", Some("xhttpSettings")),
+            ("splithttp", "xhttp", Some("xhttpSettings")),
+        ];
+        for (kind, network, settings) in transports {
+            let links = [
+                (format!("vless://{}@example.com:443?type={}&path=%2Fp&serviceName=p", UUID, kind), "none"),
+                (format!("trojan://pw@example.com:443?type={}&path=%2Fp&serviceName=p", kind), "tls"),
+                (
+                    vmess(json!({"add": "example.com", "port": 443, "id": UUID, "net": kind, "path": "/p"})),
+                    "none",
+                ),
+            ];
+            for (uri, security) in links {
+                let link = ShareLink::parse(&uri).unwrap();
+                assert_eq!((link.address.as_str(), link.port), ("example.com", 443));
+                assert_eq!(link.stream.network, network, "{} {}", link.protocol_name(), kind);
+                assert_eq!(link.stream.security, security, "{} {}", link.protocol_name(), kind);
+                let json = link.stream.to_json();
+                match settings {
+                    Some(key) => {
+                        let settings = &json[key];
+                        let path = settings.get("serviceName").unwrap_or(&settings["path"]);
+                        assert!(path == "p" || path == "/p", "{} {}: {}", link.protocol_name(), kind, json);
+                    }
+                    None => {
+                        let keys = json.as_object().unwrap().keys();
+                        assert!(keys.filter(|k| k.ends_with("Settings")).all(|k| k == "tlsSettings"), "{}", json);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn security_settings() {
+        let tls = ShareLink::parse(&format!(
+            "vless://{}@example.com:443?security=tls&peer=sni.example.com&fp=chrome&alpn=h2,%20http/1.1&allowInsecure=1",
+            UUID
+        ))
+        .unwrap();
+        assert_eq!(
+            tls.stream.to_json()["tlsSettings"],
+            json!({"serverName": "sni.example.com", "fingerprint": "chrome", "alpn": ["h2", "http/1.1"], "allowInsecure": true})
+        );
+        let plain = ShareLink::parse("trojan://pw@example.com:443?security=none").unwrap();
+        assert_eq!(plain.stream.security, "none");
+        assert!(plain.stream.to_json().get("tlsSettings").is_none());
+        let reality = ShareLink::parse(&format!("vless://{}@example.com:443?security=reality&pbk=key", UUID)).unwrap();
+        let settings = &reality.stream.to_json()["realitySettings"];
+        assert_eq!((&settings["publicKey"], &settings["fingerprint"]), (&json!("key"), &json!("chrome")));
+        let obfs = ShareLink::parse(&format!(
+            "vless://{}@example.com:80?headerType=http&host=cdn.example.com&path=%2Fx",
+            UUID
+        ))
+        .unwrap();
+        assert_eq!(
+            obfs.stream.to_json()["tcpSettings"],
+            json!({"header": {"type": "http", "request": {"path": ["/x"], "headers": {"Host": ["cdn.example.com"]}}}})
+        );
+    }
+
+    #[test]
+    fn protocol_fields() {
+        let link = ShareLink::parse(&format!(
+            "VLESS://{}@[2001:db8::1]:8443?flow=xtls-rprx-vision&foo=1&bar=2#my%20node",
+            UUID
+        ))
+        .unwrap();
+        assert_eq!((link.address.as_str(), link.port, link.name.as_str()), ("2001:db8::1", 8443, "my node"));
+        assert!(matches!(&link.protocol, Protocol::Vless { flow: Some(f), encryption, .. }
+            if f == "xtls-rprx-vision" && encryption == "none"));
+        assert_eq!(
+            link.warnings,
+            ["ignoring unsupported link parameter `bar`", "ignoring unsupported link parameter `foo`"]
+        );
+
+        let link = ShareLink::parse(&vmess(
+            json!({"ps": "n", "add": "example.com", "port": "8080", "id": UUID, "aid": 64, "scy": "aes-128-gcm"}),
+        ))
+        .unwrap();
+        assert!(matches!(&link.protocol, Protocol::Vmess { alter_id: 64, security, .. } if security == "aes-128-gcm"));
+        let link = ShareLink::parse(&vmess(json!({"add": "example.com", "port": 80, "id": UUID, "aid": ""}))).unwrap();
+        assert!(matches!(&link.protocol, Protocol::Vmess { alter_id: 0, security, .. } if security == "auto"));
+
+        let ss = |uri: &str| match ShareLink::parse(uri).unwrap().protocol {
+            Protocol::Shadowsocks { method, password } => (method, password),
+            other => panic!("{:?}", other),
+        };
+        let sip002 = format!("ss://{}@example.com:8388/?plugin=x#n", STANDARD.encode("AES-256-GCM:p@ss"));
+        assert_eq!(ss(&sip002), ("aes-256-gcm".into(), "p@ss".into()));
+        assert_eq!(
+            ss("ss://2022-blake3-aes-128-gcm:a2V5%3D@example.com:8388"),
+            ("2022-blake3-aes-128-gcm".into(), "a2V5=".into())
+        );
+        let legacy = format!("ss://{}#old", STANDARD_NO_PAD.encode("chacha20-poly1305:pw@example.com:8388"));
+        assert_eq!(ss(&legacy), ("chacha20-poly1305".into(), "pw".into()));
+        assert_eq!(ShareLink::parse(&sip002).unwrap().warnings, ["ignoring unsupported link parameter `plugin`"]);
+    }
+
+    #[test]
+    fn malformed_links() {
+        let ss = |userinfo: &str| format!("ss://{}@example.com:8388", STANDARD.encode(userinfo));
+        for (uri, err) in [
+            ("example.com:443".to_string(), "not a share link (missing scheme): example.com:443"),
+            ("wireguard://key@example.com:51820".to_string(), "unsupported share link scheme: wireguard://"),
+            ("vless://@example.com:443".to_string(), "invalid vless:// link: user id (uuid) missing"),
+            (format!("vless://{}@example.com", UUID), "invalid vless:// link: server port missing"),
+            (
+                format!("vless://{}@example.com:443?type=kcp", UUID),
+                "invalid vless:// link: unsupported transport type: kcp",
+            ),
+            (
+                format!("vless://{}@example.com:443?security=xtls", UUID),
+                "invalid vless:// link: unsupported security: xtls",
+            ),
+            (
+                format!("vless://{}@example.com:443?security=reality&sni=a", UUID),
+                "invalid vless:// link: reality link is missing the public key (pbk)",
+            ),
+            ("trojan://@example.com:443".to_string(), "invalid trojan:// link: password missing"),
+            (ss("rc4-md5:pw"), "invalid ss:// link: unsupported cipher: rc4-md5"),
+            (ss("aes-128-gcm:"), "invalid ss:// link: password missing"),
+            (ss("aes-128-gcm"), "invalid ss:// link: expected method:password in userinfo"),
+            (ss("aes-128-gcm:pw").replace(":8388", ""), "invalid ss:// link: invalid server address"),
+            (
+                vmess(json!({"add": "", "port": 443, "id": UUID})),
+                "invalid vmess:// link: server address missing",
+            ),
+            (
+                vmess(json!({"add": "example.com", "port": "0", "id": UUID})),
+                "invalid vmess:// link: invalid server port: \"0\"",
+            ),
+            (
+                vmess(json!({"add": "example.com", "port": 443, "id": UUID, "aid": "x"})),
+                "invalid vmess:// link: invalid alterId: \"x\"",
+            ),
+        ] {
+            let got = format!("{:#}", ShareLink::parse(&uri).unwrap_err());
+            assert!(got.starts_with(err), "{}: {}", uri, got);
+        }
+        let got = format!("{:#}", ShareLink::parse("vmess://e30=").unwrap_err());
+        assert!(got.starts_with("invalid vmess:// link: invalid vmess JSON payload: missing field"), "{}", got);
+        let got = format!("{:#}", ShareLink::parse("ss://***").unwrap_err());
+        assert!(got.starts_with("invalid ss:// link: invalid base64"), "{}", got);
+    }
+}
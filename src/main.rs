@@ -1,78 +1,1720 @@
 use anyhow::{anyhow, Context, Result};
-use clap::Parser;
+use bytes::Bytes;
+use clap::ArgMatches;
+use std::path::Path;
 use std::sync::Arc;
-use std::time::Duration;
 use url::Url;
 
 use clap::CommandFactory;
 use clap_complete::generate;
 use std::io;
+mod capacity;
+mod check;
 mod cli;
+mod compare;
+mod config;
+mod curl;
+mod dump;
+mod headers;
+#[cfg(feature = "sqlite")]
+mod history;
+mod influx;
+mod link;
+mod manpage;
+mod mock;
+mod overhead;
+mod pattern;
+mod pipeline;
+mod prefixed;
 mod pretty;
+mod recorder;
+mod reqlog;
+mod report;
 mod request;
+mod soak;
 mod stats;
-use crate::cli::{Args, Commands, SuccessMatcher};
-use crate::request::{parse_url_target, run_bench};
+mod stdin_urls;
+mod stream;
+mod sub;
+mod sweep;
+mod template;
+mod trace;
+mod udp;
+mod wait;
+mod watch;
+mod ws;
+mod wrk;
+mod xray;
+use crate::cli::{env_origin, parse_expect_header, require_proxy, require_url, resolve_proxy, Args, BenchMode, Commands, CurlMode, LogFormat, LogLevel, OutputFormat, SuccessMatcher, BUILTIN_INTERCEPT_HEADERS};
+use crate::dump::FailureDumps;
+use crate::link::ShareLink;
+use crate::request::{ascii_host, fetch, parse_url_target, post, run_bench, BenchOptions, ConnPacer, SocketOptions, Target};
+use crate::stats::Stats;
+use crate::template::PathTemplate;
+use crate::xray::XrayProcess;
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<()> {
-    let args = Args::parse();
-    if let Some(Commands::Completions { shell }) = args.cmd.clone() {
-        let mut cmd = Args::command();
-        generate(shell, &mut cmd, "xray-tester", &mut io::stdout());
-        return Ok(());
+    let result = run().await;
+    // Ctrl-C during a run: the partial results are out and everything the
+    // run started (xray children, log files) was dropped on the way back.
+    if request::interrupted() {
+        if let Err(e) = &result {
+            eprintln!("Error: {:?}", e);
+        }
+        std::process::exit(130);
     }
-    let proxy_str = args
-        .proxy
-        .as_deref()
-        .ok_or_else(|| anyhow!("--proxy is required"))?;
-    let proxy = Url::parse(proxy_str).context("invalid proxy URL")?;
-    if !matches!(proxy.scheme(), "socks5" | "http") {
-        return Err(anyhow!("unsupported proxy scheme: {}", proxy.scheme()));
+    result
+}
+
+async fn run() -> Result<()> {
+    let (args, matches) = match config::load_args()? {
+        config::Loaded::Run(args, matches) => (*args, matches),
+        config::Loaded::PrintConfig(text) => {
+            print!("{}", text);
+            return Ok(());
+        }
+    };
+    init_logging(&args);
+    match args.cmd.clone() {
+        Some(Commands::Completions { shell }) => {
+            let mut cmd = Args::command();
+            generate(shell, &mut cmd, "xray-tester", &mut io::stdout());
+            Ok(())
+        }
+        Some(Commands::History { db, last }) => print_history(&args, &db, last),
+        Some(Commands::Report { file }) => {
+            let report = report::Report::load(&file)?;
+            match args.output {
+                OutputFormat::Wrk => {
+                    print!("{}", wrk::render(&report.stats, &report.url, report.concurrency));
+                    return Ok(());
+                }
+                OutputFormat::Json => {
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                    return Ok(());
+                }
+                OutputFormat::Pretty => {}
+            }
+            if !args.quiet {
+                println!("Proxy: {}", report.proxy);
+                println!("Target: {}", report.url);
+                println!(
+                    "Mode: {} Concurrency: {} (xray-tester {})",
+                    report.mode, report.concurrency, report.tool_version
+                );
+                if report.run.started_at.is_some() {
+                    println!("{}", report.run.line(&report.tool_version));
+                }
+            }
+            if args.summary_only {
+                println!("{}", pretty::summary_line(&report.stats));
+            } else if !args.quiet {
+                pretty::print_results(&report.stats, report.iterations, pretty::color_enabled(args.no_color), args.units);
+                pretty::print_phases(&report.stats, &args.phase, args.units);
+                if args.timeline {
+                    pretty::print_timeline(&report.stats, args.units);
+                }
+                pretty::print_slowest(&report.stats, args.units);
+            }
+            Ok(())
+        }
+        Some(Commands::Manpage { output }) => {
+            let page = manpage::render(Args::command());
+            match output {
+                Some(path) => std::fs::write(&path, page).with_context(|| format!("writing {}", path.display()))?,
+                None => print!("{}", page),
+            }
+            Ok(())
+        }
+        Some(Commands::Link { uri, xray_bin }) => run_link(&args, &matches, &uri, &xray_bin).await,
+        Some(Commands::Config {
+            uri,
+            inbound,
+            output,
+        }) => {
+            let link = ShareLink::parse(&uri)?;
+            for w in &link.warnings {
+                tracing::warn!("{}", w);
+            }
+            let config = serde_json::to_string_pretty(&xray::client_config(&link, &inbound))?;
+            match output {
+                Some(path) => std::fs::write(&path, config + "\n")
+                    .with_context(|| format!("writing {}", path.display()))?,
+                None => println!("{}", config),
+            }
+            Ok(())
+        }
+        Some(Commands::Sweep {
+            configs,
+            sort,
+            json,
+            xray_bin,
+        }) => {
+            let opts = sweep::SweepOptions {
+                configs: &configs,
+                sort,
+                json,
+                xray_bin: &xray_bin,
+            };
+            sweep::run(&args, opts).await
+        }
+        Some(Commands::Check {
+            ip_url,
+            ip_format,
+            ip_field,
+            no_direct,
+        }) => {
+            let (proxy_str, proxy_env) = require_proxy(&args, &matches, Some("check"));
+            let proxy = Url::parse(&proxy_str).context("invalid proxy URL")?;
+            let proxy_addr = proxy_addr(&proxy)?;
+            let opts = check::CheckOptions {
+                ip_url: &ip_url,
+                format: ip_format,
+                field: &ip_field,
+                no_direct,
+            };
+            check::run(&args, &proxy, &proxy_addr, &from_env_note(proxy_env), opts).await
+        }
+        Some(Commands::Sub {
+            subscription,
+            parallel_nodes,
+            top,
+            json,
+            xray_bin,
+        }) => {
+            let proxy = match resolve_proxy(&args, &matches) {
+                Some((p, _)) => {
+                    let proxy = Url::parse(&p).context("invalid proxy URL")?;
+                    let addr = proxy_addr(&proxy)?;
+                    Some((proxy, addr))
+                }
+                None => None,
+            };
+            let opts = sub::SubOptions {
+                url: &subscription,
+                parallel_nodes,
+                top,
+                json,
+                xray_bin: &xray_bin,
+            };
+            sub::run(&args, proxy, opts).await
+        }
+        Some(Commands::Trace { body_bytes }) => {
+            let (proxy_str, _) = require_proxy(&args, &matches, Some("trace"));
+            let proxy = Url::parse(&proxy_str).context("invalid proxy URL")?;
+            let proxy_addr = proxy_addr(&proxy)?;
+            let url_str = require_url(&args, Some("trace"));
+            let opts = bench_options(&args, parse_url_target(url_str)?)?;
+            trace::run(&proxy, &proxy_addr, &opts, body_bytes).await
+        }
+        Some(Commands::Wait {
+            interval,
+            deadline,
+            consecutive,
+        }) => {
+            let (proxy_str, _) = require_proxy(&args, &matches, Some("wait"));
+            let proxy = Url::parse(&proxy_str).context("invalid proxy URL")?;
+            let proxy_addr = proxy_addr(&proxy)?;
+            let url_str = require_url(&args, Some("wait"));
+            let opts = bench_options(&args, parse_url_target(url_str)?)?;
+            let wait = wait::WaitOptions {
+                interval,
+                deadline,
+                consecutive,
+                quiet: args.quiet,
+            };
+            wait::run(&proxy, &proxy_addr, &opts, wait).await
+        }
+        Some(Commands::MockProxy {
+            listen,
+            latency,
+            fail_rate,
+            auth,
+            requests_per_tunnel,
+        }) => {
+            let (addr, server) = mock::start_proxy(mock::MockProxyOptions {
+                listen,
+                latency,
+                fail_rate,
+                auth,
+                requests_per_tunnel: requests_per_tunnel.map(|n| n as usize),
+            })
+            .await?;
+            serve_forever(addr, server).await
+        }
+        Some(Commands::MockTarget {
+            listen,
+            status,
+            body_size,
+            delay,
+            truncate_at,
+            garbage,
+            reorder,
+        }) => {
+            let (addr, server) = mock::start_target(mock::MockTargetOptions {
+                listen,
+                status,
+                body_size,
+                delay,
+                truncate_at,
+                garbage,
+                reorder,
+            })
+            .await?;
+            serve_forever(addr, server).await
+        }
+        None | Some(Commands::Bench) => {
+            let command = args.cmd.as_ref().map(|_| "bench");
+            let (proxy_str, proxy_env) = require_proxy(&args, &matches, command);
+            let proxy = Url::parse(&proxy_str).context("invalid proxy URL")?;
+            if let Some(mode) = args.print_curl {
+                let url_str = require_url(&args, command);
+                let opts = bench_options(&args, parse_url_target(url_str)?)?;
+                println!("{}", curl::command(&proxy, &opts, mode == CurlMode::Unsafe)?);
+                return Ok(());
+            }
+            bench(&args, &matches, proxy, from_env_note(proxy_env)).await?;
+            Ok(())
+        }
     }
-    let proxy_host = proxy
-        .host_str()
-        .ok_or_else(|| anyhow!("proxy host missing"))?
-        .to_string();
-    let proxy_port = proxy.port().unwrap_or(2080);
+}
+
+// The bound address goes to stdout on its own line so scripts can pick it
+// up; the server then runs until interrupted.
+async fn serve_forever(addr: std::net::SocketAddr, server: tokio::task::JoinHandle<()>) -> Result<()> {
+    println!("{}", addr);
+    io::Write::flush(&mut io::stdout())?;
+    tokio::select! {
+        res = server => res.context("server task failed"),
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+// One subscriber for the whole process; the fmt layer writes each event with
+// a single locked write, so concurrent requests never interleave lines.
+fn init_logging(args: &Args) {
+    let level = if args.debug {
+        args.log_level.max(LogLevel::Debug)
+    } else {
+        args.log_level
+    };
+    let level = match level {
+        LogLevel::Error => tracing::Level::ERROR,
+        LogLevel::Warn => tracing::Level::WARN,
+        LogLevel::Info => tracing::Level::INFO,
+        LogLevel::Debug => tracing::Level::DEBUG,
+        LogLevel::Trace => tracing::Level::TRACE,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(io::stderr)
+        .with_ansi(!args.no_color && std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty()) && {
+            use std::io::IsTerminal;
+            io::stderr().is_terminal()
+        })
+        .with_target(false);
+    match args.log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+async fn run_link(args: &Args, matches: &ArgMatches, uri: &str, xray_bin: &Path) -> Result<()> {
+    let link = ShareLink::parse(uri)?;
+    for w in &link.warnings {
+        tracing::warn!("{}", w);
+    }
+    let xray = XrayProcess::spawn_link(
+        xray_bin,
+        &link,
+        args.startup_timeout,
+    )
+    .await?;
+    let proxy = Url::parse(&format!("socks5://{}", xray.listen))?;
+    let note = format!(
+        " (xray {} {}:{}{})",
+        link.protocol_name(),
+        link.address,
+        link.port,
+        if link.name.is_empty() {
+            String::new()
+        } else {
+            format!(" \"{}\"", link.name)
+        }
+    );
+    bench(args, matches, proxy, note).await?;
+    drop(xray);
+    Ok(())
+}
+
+async fn bench(args: &Args, matches: &ArgMatches, proxy: Url, proxy_note: String) -> Result<Stats> {
+    let proxy_addr = proxy_addr(&proxy)?;
+    if args.mode == BenchMode::UdpDns && proxy.scheme() != "socks5" {
+        return Err(anyhow!("--mode udp-dns needs a socks5:// proxy (UDP ASSOCIATE)"));
+    }
+
+    let command = match &args.cmd {
+        Some(Commands::Link { .. }) => Some("link"),
+        Some(_) => Some("bench"),
+        None => None,
+    };
+    let url_str = require_url(args, command);
+    let preflight = match proxy.scheme() {
+        "socks5" | "http" | "https" if !args.no_preflight => {
+            Some(request::preflight(&proxy_addr, args.timeout, &socket_options(args)).await?)
+        }
+        _ => None,
+    };
+    if url_str == stdin_urls::STDIN_URL {
+        return stdin_urls::run(args, proxy, &proxy_addr, &proxy_note).await;
+    }
+    let mut opts = bench_options(args, parse_url_target(url_str)?)?;
+    let url_env = env_origin(matches, "url", "XRAY_TESTER_URL");
+    if args.resolve_target_locally {
+        let ips = resolve_target(args, &opts.target).await?;
+        for (ip, _) in args.ip_weights.iter().filter(|(ip, _)| !ips.is_empty() && !ips.contains(ip)) {
+            tracing::warn!("--ip-weights names {}, which {} did not resolve to", ip, opts.target.host);
+        }
+        opts.target_ips = request::ip_schedule(&ips, &args.ip_weights);
+        if opts.target_ips.is_empty() && !ips.is_empty() {
+            return Err(anyhow!("--ip-weights leaves no address of {} to connect to", opts.target.host));
+        }
+    }
+    if args.prime_cache {
+        prime_cache(&proxy, &proxy_addr, url_str, &mut opts).await?;
+    }
+
+    let checked = match args.preflight {
+        true => Some(preflight_request(args, &proxy, &proxy_addr, &opts).await?),
+        false => None,
+    };
+
+    let harness = match args.calibrate {
+        Some(requests) => Some(overhead::calibrate(&opts, requests).await?),
+        None => None,
+    };
+
+    if !args.quiet && !args.summary_only && args.output == OutputFormat::Pretty {
+        print_banner(args, &proxy, &proxy_addr, &proxy_note, &opts, url_env);
+        if let Some(connect) = preflight {
+            println!("Proxy connect: {} (preflight)", pretty::fmt_duration(connect.as_secs_f64() * 1000.0, args.units));
+        }
+        if let Some((took, status)) = checked {
+            println!(
+                "Preflight: {}{}",
+                pretty::fmt_duration(took.as_secs_f64() * 1000.0, args.units),
+                status.map(|code| format!(", status {}", code)).unwrap_or_default()
+            );
+        }
+        if let Some(h) = &harness {
+            println!(
+                "Harness baseline: p50 {} p99 {} ({} loopback requests, no proxy)",
+                pretty::fmt_duration(h.p50_ms, args.units),
+                pretty::fmt_duration(h.p99_ms, args.units),
+                h.requests
+            );
+        }
+    }
+
+    let dumps = opts.dump_failures.clone();
+    // Credentials never reach the saved report.
+    let proxy_label = format!("{}://{}", proxy.scheme(), proxy_addr);
+    if let Some(other) = &args.compare_proxy {
+        if args.output == OutputFormat::Wrk {
+            return Err(anyhow!("--output wrk shows a single run; use pretty or json with --compare-proxy"));
+        }
+        let other = Url::parse(other).context("invalid --compare-proxy URL")?;
+        let other_addr = crate::proxy_addr(&other)?;
+        let primary = compare::Side {
+            label: proxy_label.clone(),
+            proxy: Arc::new(proxy),
+            proxy_addr: &proxy_addr,
+        };
+        let compare = compare::Side {
+            label: format!("{}://{}", other.scheme(), other_addr),
+            proxy: Arc::new(other),
+            proxy_addr: &other_addr,
+        };
+        let stats = compare::run(args, url_str, primary, compare, opts).await?;
+        check_exit_criteria(args, &stats)?;
+        return Ok(stats);
+    }
+    if let Some(interval) = args.watch {
+        let stats = watch::run(args, interval, Arc::new(proxy), &proxy_addr, opts).await?;
+        check_exit_criteria(args, &stats)?;
+        return Ok(stats);
+    }
+    if args.soak.is_some() {
+        return soak::run(args, Arc::new(proxy), &proxy_addr, &proxy_label, url_str, opts, harness).await;
+    }
+    if !args.stage.is_empty() || args.find_max_concurrency || args.find_max_rate {
+        let proxy = Arc::new(proxy);
+        let stats = if args.find_max_concurrency {
+            capacity::run(args, proxy, &proxy_addr, opts).await?
+        } else if args.find_max_rate {
+            capacity::run_rate(args, proxy, &proxy_addr, opts).await?
+        } else {
+            capacity::run_staged(args, proxy, &proxy_addr, opts).await?
+        };
+        if let Some(path) = &args.latency_csv {
+            reqlog::write_latency_csv(path, &stats)?;
+        }
+        if let Some(path) = &args.save_report {
+            save_report(path, args, &proxy_label, url_str, &stats)?;
+        }
+        save_sqlite(args, &proxy_label, url_str, &stats)?;
+        export_influx(args, &proxy_label, url_str, &stats).await?;
+        print_dump_note(args, dumps.as_ref());
+        check_exit_criteria(args, &stats)?;
+        return Ok(stats);
+    }
+    let direct = if args.baseline_direct {
+        if args.mode == BenchMode::UdpDns {
+            return Err(anyhow!("--baseline-direct does not support --mode udp-dns"));
+        }
+        Some(overhead::baseline(&opts).await?)
+    } else {
+        None
+    };
+    let mut stats = run_bench(Arc::new(proxy), &proxy_addr, Arc::new(opts)).await?;
+    stats.harness = harness;
+    finish(args, &proxy_label, url_str, args.iterations, &stats, direct.as_ref(), dumps.as_ref()).await?;
+    Ok(stats)
+}
 
-    let url_str = args
-        .url
-        .as_deref()
-        .ok_or_else(|| anyhow!("--url is required"))?;
-    let target = parse_url_target(url_str)?;
+// Prints and exports the result of a bench run and applies the exit
+// criteria; `requested` is how many requests the run was asked for.
+pub async fn finish(
+    args: &Args,
+    proxy_label: &str,
+    url_str: &str,
+    requested: usize,
+    stats: &Stats,
+    direct: Option<&Stats>,
+    dumps: Option<&FailureDumps>,
+) -> Result<()> {
+    if args.summary_only {
+        println!("{}", pretty::summary_line(stats));
+        if let Some(direct) = direct {
+            println!("{}", overhead::summary_line(stats, direct));
+        }
+    } else if args.output == OutputFormat::Wrk {
+        print!("{}", wrk::render(stats, url_str, args.concurrency));
+    } else if args.output == OutputFormat::Json {
+        let report = report::Report::new(args, proxy_label, url_str, stats.clone());
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else if !args.quiet {
+        println!("\n{}", report::RunInfo::new(args, stats).line(env!("CARGO_PKG_VERSION")));
+        pretty::print_results(stats, requested, pretty::color_enabled(args.no_color), args.units);
+        pretty::print_phases(stats, &args.phase, args.units);
+        if args.per_connection {
+            pretty::print_workers(stats, args.units);
+        }
+        pretty::print_ips(stats, args.units);
+        if args.timeline {
+            pretty::print_timeline(stats, args.units);
+        }
+        pretty::print_slowest(stats, args.units);
+        if let Some(direct) = direct {
+            overhead::print(stats, direct, args.units);
+        }
+        print_dump_note(args, dumps);
+    }
+    if let Some(path) = &args.latency_csv {
+        reqlog::write_latency_csv(path, stats)?;
+    }
+    if let Some(path) = &args.save_report {
+        save_report(path, args, proxy_label, url_str, stats)?;
+    }
+    save_sqlite(args, proxy_label, url_str, stats)?;
+    export_influx(args, proxy_label, url_str, stats).await?;
+    if stats.internal_errors > 0 {
+        // Printed whatever the output format: the numbers above include
+        // requests that never really ran.
+        eprintln!("Internal errors (request tasks panicked):");
+        for (message, n) in &stats.panics {
+            eprintln!("  {} (x{})", message, n);
+        }
+        return Err(anyhow!(
+            "{} request task(s) panicked; the results are not trustworthy",
+            stats.internal_errors
+        ));
+    }
+    if let Some(reason) = &stats.aborted {
+        return Err(anyhow!("aborted: error threshold reached ({})", reason));
+    }
+    check_exit_criteria(args, stats)
+}
 
-    println!("Proxy: {}://{}:{}", proxy.scheme(), proxy_host, proxy_port);
+fn print_dump_note(args: &Args, dumps: Option<&FailureDumps>) {
+    let Some(dumps) = dumps else { return };
+    if args.quiet || args.summary_only || args.output != OutputFormat::Pretty {
+        return;
+    }
     println!(
-        "Target: {}://{}:{}{}",
-        target.scheme, target.host, target.port, target.path
+        "\nFailure dumps: {} written to {}{}",
+        dumps.written(),
+        dumps.dir.display(),
+        if dumps.full() { " (limit reached)" } else { "" }
     );
+}
+
+fn save_report(path: &Path, args: &Args, proxy: &str, url: &str, stats: &Stats) -> Result<()> {
+    report::Report::new(args, proxy, url, stats.clone()).save(path)
+}
+
+const NO_SQLITE: &str = "--sqlite and history need a build with the sqlite feature (cargo install xray-tester --features sqlite)";
+
+// --sqlite; builds without the feature have rejected the flag in
+// bench_options already.
+fn save_sqlite(args: &Args, proxy: &str, url: &str, stats: &Stats) -> Result<()> {
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &args.sqlite {
+        history::save(path, args, proxy, url, stats)?;
+    }
+    #[cfg(not(feature = "sqlite"))]
+    let _ = (args, proxy, url, stats);
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn print_history(args: &Args, db: &Path, last: usize) -> Result<()> {
+    // Stored as in the report header: scheme://host:port, no credentials.
+    let proxy = match &args.proxy {
+        Some(p) => {
+            let url = Url::parse(p).context("invalid proxy URL")?;
+            Some(format!("{}://{}", url.scheme(), proxy_addr(&url)?))
+        }
+        None => None,
+    };
+    let runs = history::load(db, proxy.as_deref(), last)?;
+    if runs.is_empty() {
+        return Err(anyhow!("no runs in {}{}", db.display(), proxy.map(|p| format!(" for {}", p)).unwrap_or_default()));
+    }
+    for line in history::lines(&runs, args.units) {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn print_history(_: &Args, _: &Path, _: usize) -> Result<()> {
+    Err(anyhow!(NO_SQLITE))
+}
+
+// The file is required to be written; the URL is best-effort so a down
+// metrics server does not fail the benchmark.
+async fn export_influx(args: &Args, proxy: &str, url: &str, stats: &Stats) -> Result<()> {
+    if args.influx_file.is_none() && args.influx_url.is_none() {
+        return Ok(());
+    }
+    let points = influx::lines(stats, proxy, url);
+    if let Some(path) = &args.influx_file {
+        std::fs::write(path, &points).with_context(|| format!("writing {}", path.display()))?;
+    }
+    if let Some(endpoint) = &args.influx_url {
+        match post(
+            endpoint,
+            "text/plain; charset=utf-8",
+            points.into_bytes(),
+            args.insecure,
+            args.timeout,
+            &socket_options(args),
+        )
+        .await
+        {
+            Ok(resp) if (200..300).contains(&resp.status) => {}
+            Ok(resp) => tracing::warn!(
+                "InfluxDB write to {} returned {}: {}",
+                endpoint,
+                resp.status,
+                String::from_utf8_lossy(&resp.body).trim()
+            ),
+            Err(e) => tracing::warn!("InfluxDB write to {} failed: {:#}", endpoint, e),
+        }
+    }
+    Ok(())
+}
+
+// Conditions that fail the whole run rather than individual requests:
+// --expect-status and --slow-is-failure.
+fn check_exit_criteria(args: &Args, stats: &Stats) -> Result<()> {
+    if !args.expect_status.is_empty() {
+        let unexpected: Vec<String> = stats
+            .status_counts
+            .iter()
+            .filter(|(code, _)| !args.expect_status.contains(code))
+            .map(|(code, n)| format!("{}×{}", code, n))
+            .collect();
+        if !unexpected.is_empty() {
+            return Err(anyhow!("unexpected statuses: {}", unexpected.join(", ")));
+        }
+    }
+    if let (true, Some(threshold)) = (args.slow_is_failure, args.slow_threshold) {
+        if stats.slow > 0 {
+            return Err(anyhow!(
+                "{} response(s) slower than {}",
+                stats.slow,
+                humantime::format_duration(threshold)
+            ));
+        }
+    }
+    Ok(())
+}
+
+// --resolve-target-locally: an empty list leaves resolution to the proxy,
+// which is what happens on failure unless --strict-resolve is set.
+async fn resolve_target(args: &Args, target: &Target) -> Result<Vec<std::net::IpAddr>> {
+    if target.host.parse::<std::net::IpAddr>().is_ok() {
+        return Ok(Vec::new());
+    }
+    let resolved = tokio::time::timeout(args.timeout, tokio::net::lookup_host((target.host.as_str(), target.port)))
+        .await
+        .map_err(|_| anyhow!("timed out"))
+        .and_then(|r| r.map_err(anyhow::Error::from));
+    let mut ips = Vec::new();
+    match resolved {
+        Ok(addrs) => {
+            for addr in addrs {
+                if !ips.contains(&addr.ip()) {
+                    ips.push(addr.ip());
+                }
+            }
+        }
+        Err(e) if args.strict_resolve => {
+            return Err(anyhow!("resolving target {} locally failed: {:#}", target.host, e));
+        }
+        Err(e) => {
+            tracing::warn!("resolving target {} locally failed: {:#}; the proxy will resolve it", target.host, e);
+        }
+    }
+    Ok(ips)
+}
+
+// --preflight: one request outside the stats. If it fails the run is not
+// started; pretty output first retraces the request step by step.
+async fn preflight_request(
+    args: &Args,
+    proxy: &Url,
+    proxy_addr: &str,
+    opts: &BenchOptions,
+) -> Result<(std::time::Duration, Option<u16>)> {
+    let t = std::time::Instant::now();
+    let err = match request::single_request(proxy, proxy_addr, opts, &mut request::Phases::default()).await {
+        Ok(meta) if meta.success => return Ok((t.elapsed(), meta.status)),
+        Ok(meta) => match meta.status {
+            Some(code) => anyhow!("preflight request failed: status {}", code),
+            None => anyhow!("preflight request failed"),
+        },
+        Err(e) => anyhow!("preflight request failed: {:#}", e),
+    };
+    let traceable = matches!(opts.mode, BenchMode::Http | BenchMode::Download) && proxy.scheme() != request::DIRECT_SCHEME;
+    if traceable && !args.quiet && args.output == OutputFormat::Pretty {
+        println!("Preflight request failed; tracing it:");
+        if let Err(e) = trace::run(proxy, proxy_addr, opts, 512).await {
+            println!("trace stopped: {:#}", e);
+        }
+    }
+    Err(err)
+}
+
+// One GET through the proxy to learn the target's validators. Values given
+// on the command line take precedence over the primed ones.
+async fn prime_cache(proxy: &Url, proxy_addr: &str, url: &str, opts: &mut BenchOptions) -> Result<()> {
+    let url = Url::parse(url).map_err(|e| anyhow!("invalid URL: {}", e))?;
+    let resp = fetch(&url, Some((proxy, proxy_addr)), opts.insecure, opts.timeout, &opts.socket)
+        .await
+        .map_err(|e| anyhow!("priming request failed: {:#}", e))?;
+    if !(200..300).contains(&resp.status) {
+        return Err(anyhow!("priming request failed: HTTP {}", resp.status));
+    }
+    let mut found = false;
+    for (name, header) in [("If-None-Match", "etag"), ("If-Modified-Since", "last-modified")] {
+        let Some(value) = resp.headers.get(header).and_then(|v| v.to_str().ok()) else {
+            continue;
+        };
+        found = true;
+        if !opts.conditional.iter().any(|(n, _)| *n == name) {
+            opts.conditional.push((name, value.to_string()));
+        }
+    }
+    if !found {
+        return Err(anyhow!("priming response has neither ETag nor Last-Modified; nothing to revalidate"));
+    }
+    Ok(())
+}
+
+pub fn print_banner(
+    args: &Args,
+    proxy: &Url,
+    proxy_addr: &str,
+    proxy_note: &str,
+    opts: &BenchOptions,
+    url_env: Option<&str>,
+) {
+    let target = &opts.target;
     println!(
-        "Iterations: {} Concurrency: {} Timeout: {}ms Insecure: {} Debug: {}",
-        args.iterations, args.concurrency, args.timeout_ms, args.insecure, args.debug
+        "Proxy: {}://{}{}",
+        proxy.scheme(),
+        proxy_addr,
+        proxy_note
     );
+    if args.url.as_deref() == Some(stdin_urls::STDIN_URL) {
+        println!(
+            "Targets: read from stdin, {} requests each",
+            args.per_url.unwrap_or(args.iterations)
+        );
+    } else {
+        println!(
+            "Target: {}://{}:{}{}{}{}",
+            target.scheme,
+            target.host,
+            target.port,
+            opts.path_template.as_ref().map_or(target.path.as_str(), |t| t.as_str()),
+            unicode_note(&target.host),
+            from_env_note(url_env)
+        );
+    }
+    if args.find_max_concurrency || args.find_max_rate {
+        if args.find_max_rate {
+            println!(
+                "Stages: {} each, rate {}..{} req/s Concurrency: {} Timeout: {} Insecure: {} Debug: {}",
+                humantime::format_duration(args.stage_duration),
+                args.rate_min,
+                args.rate_max,
+                args.concurrency,
+                humantime::format_duration(args.timeout),
+                args.insecure,
+                args.debug
+            );
+        } else {
+            println!(
+                "Stages: {} each, concurrency 1..{} Timeout: {} Insecure: {} Debug: {}",
+                humantime::format_duration(args.stage_duration),
+                args.max_stage_concurrency,
+                humantime::format_duration(args.timeout),
+                args.insecure,
+                args.debug
+            );
+        }
+        println!(
+            "Stage criteria: errors <= {}%{}",
+            args.max_stage_errors,
+            args.max_stage_p99_ms
+                .map(|ms| format!(", p99 <= {}ms", ms))
+                .unwrap_or_default()
+        );
+    } else if !args.stage.is_empty() {
+        println!(
+            "Stages: {} Concurrency: {} Timeout: {} Insecure: {} Debug: {}",
+            args.stage.len(),
+            args.concurrency,
+            humantime::format_duration(args.timeout),
+            args.insecure,
+            args.debug
+        );
+    } else if let Some(length) = args.soak {
+        println!(
+            "Soak: {} (summary every {}) Concurrency: {} Timeout: {} Insecure: {} Debug: {}",
+            humantime::format_duration(length),
+            humantime::format_duration(args.summary_interval),
+            args.concurrency,
+            humantime::format_duration(args.timeout),
+            args.insecure,
+            args.debug
+        );
+        if let Some(rate) = args.rate {
+            println!("Rate: {} req/s", rate);
+        }
+    } else {
+        println!(
+            "Iterations: {} Concurrency: {} Timeout: {} Insecure: {} Debug: {}",
+            args.iterations, args.concurrency, humantime::format_duration(args.timeout), args.insecure, args.debug
+        );
+        if let Some(rate) = args.rate {
+            println!("Rate: {} req/s", rate);
+        }
+    }
+    if let Some(rate) = args.max_conn_rate {
+        println!("Max new connections: {}/s", rate);
+    }
+    if let Some(addr) = args.local_addr {
+        if addr.port() == 0 {
+            print!("Local address: {}", addr.ip());
+        } else {
+            print!("Local address: {}", addr);
+        }
+        if let Some(name) = &args.interface {
+            print!(" (interface {})", name);
+        }
+        println!();
+    } else if let Some(name) = &args.interface {
+        println!("Interface: {}", name);
+    }
+    if !opts.conditional.is_empty() {
+        let list: Vec<String> = opts
+            .conditional
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        println!(
+            "Validators: {}{}",
+            list.join(", "),
+            if args.prime_cache { " (primed)" } else { "" }
+        );
+    }
+    if let Some(range) = &opts.range {
+        println!("Range: {}", range.header_value());
+    }
+    if !opts.target_ips.is_empty() {
+        let mut list: Vec<String> = Vec::new();
+        for (i, ip) in opts.target_ips.iter().enumerate() {
+            if opts.target_ips[..i].contains(ip) {
+                continue;
+            }
+            match opts.target_ips.iter().filter(|other| *other == ip).count() {
+                1 => list.push(ip.to_string()),
+                share => list.push(format!("{} x{}", ip, share)),
+            }
+        }
+        println!("Resolved locally: {} (CONNECT by IP)", list.join(", "));
+    }
+    if let Some(depth) = opts.pipeline {
+        println!("Pipeline: {} requests per tunnel", depth);
+    }
+    for expectation in &opts.expect_headers {
+        println!("Expect header: {}", expectation);
+    }
+    if !args.alpn.is_empty() || args.require_alpn.is_some() {
+        println!(
+            "ALPN: {}{}",
+            opts.alpn.join(","),
+            opts.require_alpn.as_deref().map(|p| format!(" (require {})", p)).unwrap_or_default()
+        );
+    }
+    for rule in &args.intercept_header {
+        println!("Intercept header: {}", rule);
+    }
+    if opts.http10 {
+        println!("HTTP version: 1.0");
+    }
+    for header in &opts.headers {
+        println!("Header: {}", header);
+    }
+    if let Some((content_type, body)) = &opts.body {
+        println!("Body: POST {} ({})", content_type, pretty::fmt_bytes(body.len() as u64));
+    }
+    println!("Seed: {}{}", opts.seed, if args.seed.is_none() { " (random)" } else { "" });
+    match args.mode {
+        BenchMode::Http => {}
+        BenchMode::Download => println!("Mode: download (idle timeout {})", humantime::format_duration(args.idle_timeout)),
+        BenchMode::Upload => println!(
+            "Mode: upload {} {} (idle timeout {})",
+            format!("{:?}", args.upload_method).to_uppercase(),
+            pretty::fmt_bytes(args.upload_size),
+            humantime::format_duration(args.idle_timeout)
+        ),
+        BenchMode::Ws => println!("Mode: ws{}", if args.ws_ping { " (with ping)" } else { "" }),
+        BenchMode::UdpDns => println!(
+            "Mode: udp-dns (A {} via {})",
+            args.dns_name.as_deref().unwrap_or(&target.host),
+            args.dns_server
+        ),
+        BenchMode::Grpc => println!("Mode: grpc health check (service {:?})", args.grpc_service),
+    }
+}
+
+// `host:port` to connect to, or the socket path for unix:// proxies.
+fn proxy_addr(proxy: &Url) -> Result<String> {
+    match proxy.scheme() {
+        "socks5" | "http" | "https" => {}
+        request::DIRECT_SCHEME => return Ok(String::new()),
+        #[cfg(unix)]
+        "unix" | "http+unix" => {
+            if proxy.path().is_empty() || proxy.path() == "/" {
+                return Err(anyhow!("unix proxy URL needs a socket path, e.g. unix:///run/xray/socks.sock"));
+            }
+            return Ok(proxy.path().to_string());
+        }
+        #[cfg(not(unix))]
+        "unix" | "http+unix" => {
+            return Err(anyhow!("unix socket proxies are not supported on this platform"));
+        }
+        other => return Err(anyhow!("unsupported proxy scheme: {}", other)),
+    }
+    let host = proxy
+        .host_str()
+        .ok_or_else(|| anyhow!("proxy host missing"))?;
+    Ok(format!("{}:{}", host, proxy.port_or_known_default().unwrap_or(2080)))
+}
+
+pub fn socket_options(args: &Args) -> SocketOptions {
+    SocketOptions {
+        addr: args.local_addr,
+        interface: args.interface.clone(),
+        nodelay: !args.no_tcp_nodelay,
+        keepalive: args.tcp_keepalive,
+        recv_buffer: args.so_rcvbuf.map(|b| b as usize),
+        send_buffer: args.so_sndbuf.map(|b| b as usize),
+        pacer: args.max_conn_rate.map(|rate| Arc::new(ConnPacer::new(rate))),
+        proxy_insecure: args.proxy_insecure,
+    }
+}
 
+pub fn bench_options(args: &Args, target: Target) -> Result<BenchOptions> {
+    // native-tls neither shares client sessions between connections nor
+    // says whether a handshake was resumed.
+    if args.tls_resume {
+        return Err(anyhow!(
+            "--tls-resume is not supported with native-tls, the only TLS backend in this build; it needs --tls-backend rustls"
+        ));
+    }
+    if args.sqlite.is_some() && !cfg!(feature = "sqlite") {
+        return Err(anyhow!(NO_SQLITE));
+    }
+    let mut conditional = Vec::new();
+    if let Some(etag) = &args.if_none_match {
+        conditional.push(("If-None-Match", etag.clone()));
+    }
+    if let Some(date) = &args.if_modified_since {
+        conditional.push(("If-Modified-Since", date.clone()));
+    }
+    let revalidate = !conditional.is_empty() || args.prime_cache;
+    if (revalidate || args.range.is_some()) && !matches!(args.mode, BenchMode::Http | BenchMode::Download) {
+        return Err(anyhow!(
+            "--range, --if-none-match, --if-modified-since and --prime-cache need --mode http or download"
+        ));
+    }
+    let inspects_headers = !args.expect_header.is_empty()
+        || !args.intercept_header.is_empty()
+        || args.show_headers || args.header_stats.is_some();
+    if inspects_headers && !matches!(args.mode, BenchMode::Http | BenchMode::Download | BenchMode::Upload) {
+        return Err(anyhow!(
+            "--expect-header, --intercept-header, --show-headers and --header-stats need --mode http, download or upload"
+        ));
+    }
+    if args.pipeline.is_some() {
+        if args.mode != BenchMode::Http {
+            return Err(anyhow!("--pipeline needs --mode http"));
+        }
+        // The pipelined path writes bare HTTP/1.1 GETs and reads only the
+        // status line and framing of each response.
+        if !args.form.is_empty() || args.json_body.is_some() || args.http1_0 || args.respect_retry_after || inspects_headers {
+            return Err(anyhow!(
+                "--pipeline cannot be combined with --form, --json-body, --http1.0, --respect-retry-after, --expect-header, --intercept-header, --show-headers or --header-stats"
+            ));
+        }
+    } else if args.no_reconnect {
+        return Err(anyhow!("--no-reconnect needs --pipeline"));
+    }
     let success_matcher = if let Some(spec) = args.success_codes.as_deref() {
         SuccessMatcher::parse(spec)?
+    } else if !args.expect_status.is_empty() {
+        SuccessMatcher {
+            ranges: args.expect_status.iter().map(|&c| (c, c)).collect(),
+        }
+    } else if revalidate || args.range.is_some() {
+        let mut ranges = Vec::new();
+        if args.range.is_some() {
+            ranges.push((206, 206));
+        }
+        if revalidate {
+            ranges.push((304, 304));
+        }
+        SuccessMatcher { ranges }
     } else {
         SuccessMatcher::default()
     };
+    if args.ws_ping && args.mode != BenchMode::Ws {
+        return Err(anyhow!("--ws-ping requires --mode ws"));
+    }
+    // Hourly --soak files are appended to as they come up.
+    if let (Some(path), None) = (&args.log_file, args.soak) {
+        reqlog::create(path)?;
+    }
+    let body = if !args.form.is_empty() {
+        let mut form = url::form_urlencoded::Serializer::new(String::new());
+        form.extend_pairs(&args.form);
+        Some(("application/x-www-form-urlencoded", Bytes::from(form.finish())))
+    } else {
+        args.json_body.clone().map(|json| ("application/json", Bytes::from(json)))
+    };
+    let headers = headers::load(&args.header)?;
+    if !headers.is_empty() && !matches!(args.mode, BenchMode::Http | BenchMode::Download | BenchMode::Upload) {
+        return Err(anyhow!("-H needs --mode http, download or upload"));
+    }
+    if args.respect_retry_after && !matches!(args.mode, BenchMode::Http | BenchMode::Download | BenchMode::Upload) {
+        return Err(anyhow!("--respect-retry-after needs --mode http, download or upload"));
+    }
+    if args.expect_continue && args.mode != BenchMode::Upload {
+        return Err(anyhow!("--expect-continue needs --mode upload"));
+    }
+    if args.http1_0 && !matches!(args.mode, BenchMode::Http | BenchMode::Download | BenchMode::Upload) {
+        return Err(anyhow!("--http1.0 needs --mode http, download or upload"));
+    }
+    if body.is_some() && !matches!(args.mode, BenchMode::Http | BenchMode::Download) {
+        return Err(anyhow!("--form and --json-body need --mode http or download"));
+    }
+    let dump_failures = match &args.dump_failures {
+        Some(dir) => {
+            if !matches!(args.mode, BenchMode::Http | BenchMode::Download | BenchMode::Upload) {
+                return Err(anyhow!("--dump-failures needs --mode http, download or upload"));
+            }
+            Some(FailureDumps::new(dir.clone(), args.dump_limit, args.dump_body_size as usize)?)
+        }
+        None => None,
+    };
+    let seed = args.seed.unwrap_or_else(|| {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64)
+    });
+    let (target, path_template) = target_template(target, seed)?;
+    // gRPC needs HTTP/2, which has to be agreed on during the TLS handshake.
+    // A required protocol is offered on its own unless --alpn says otherwise.
+    let require_alpn = args
+        .require_alpn
+        .clone()
+        .or_else(|| (args.mode == BenchMode::Grpc).then(|| "h2".to_string()));
+    Ok(BenchOptions {
+        target: Arc::new(target),
+        success_matcher: Arc::new(success_matcher),
+        iterations: args.iterations,
+        concurrency: args.concurrency,
+        timeout: args.timeout,
+        insecure: args.insecure,
+        connect_to: args.connect_to.clone(),
+        target_ips: Vec::new(),
+        mode: args.mode,
+        ws_ping: args.ws_ping,
+        grpc_service: args.grpc_service.clone(),
+        idle_timeout: args.idle_timeout,
+        min_bytes: args.min_bytes,
+        max_body_bytes: args.max_body_bytes,
+        upload_size: args.upload_size,
+        upload_method: args.upload_method,
+        body,
+        http10: args.http1_0,
+        headers,
+        expect_continue: args.expect_continue.then_some(args.expect_continue_timeout),
+        retry_after_max: args.respect_retry_after.then_some(args.retry_after_max),
+        dump_failures,
+        dns_name: args.dns_name.as_deref().map(ascii_host).transpose()?,
+        dns_server: args.dns_server.clone(),
+        log_file: args.log_file.clone(),
+        record_all_latencies: args.record_all_latencies,
+        abort_after_failures: args.abort_after_failures,
+        abort_error_rate: args.abort_error_rate,
+        abort_min_samples: args.abort_min_samples,
+        stop_after: None,
+        stop: None,
+        rotate_log: args.soak.is_some(),
+        rate: args.rate,
+        sample_latencies: args.sample_latencies,
+        seed,
+        per_connection: args.per_connection,
+        keep_samples: args.sqlite.is_some(),
+        timeline: args.timeline,
+        slowest: args.slowest,
+        error_samples: args.error_samples,
+        socket: socket_options(args),
+        cache_bust: args.cache_bust.then(|| args.cache_bust_param.clone()),
+        path_template,
+        conditional,
+        range: args.range.clone(),
+        expect_headers: args.expect_header.clone(),
+        alpn: match args.alpn.is_empty() {
+            true => require_alpn.iter().cloned().collect(),
+            false => args.alpn.clone(),
+        },
+        require_alpn,
+        pipeline: args.pipeline.map(|n| n as usize),
+        reconnect: !args.no_reconnect,
+        intercept_headers: {
+            let builtin = match args.no_builtin_intercept {
+                true => &[][..],
+                false => &BUILTIN_INTERCEPT_HEADERS[..],
+            };
+            let mut rules = builtin
+                .iter()
+                .map(|s| parse_expect_header(s).map_err(|e| anyhow!(e)))
+                .collect::<Result<Vec<_>>>()?;
+            rules.extend(args.intercept_header.iter().cloned());
+            rules
+        },
+        show_headers: args.show_headers,
+        header_stats: args.header_stats.clone(),
+        max_locations: args.max_locations,
+        slow_threshold: args.slow_threshold,
+    })
+}
 
-    let stats = run_bench(
-        Arc::new(proxy),
-        &proxy_host,
-        proxy_port,
-        Arc::new(target),
-        Arc::new(success_matcher),
-        args.iterations,
-        args.concurrency,
-        Duration::from_millis(args.timeout_ms),
-        args.insecure,
-        args.debug,
-        args.connect_to,
-    )
-    .await?;
+fn target_template(mut target: Target, seed: u64) -> Result<(Target, Option<PathTemplate>)> {
+    let path_template = PathTemplate::parse(&target.path)?;
+    if let Some(template) = &path_template {
+        // Single-request paths (trace, wait, probes) use the first rendering.
+        target.path = template.render(1, seed);
+    }
+    Ok((target, path_template))
+}
 
-    pretty::print_results(&stats, args.iterations);
-    Ok(())
+// The same options aimed at another target.
+pub fn retarget(opts: &BenchOptions, target: Target) -> Result<BenchOptions> {
+    let (target, path_template) = target_template(target, opts.seed)?;
+    Ok(BenchOptions {
+        target: Arc::new(target),
+        path_template,
+        ..opts.clone()
+    })
+}
+
+// The Unicode form of a punycode host, so an IDN target stays recognisable.
+fn unicode_note(host: &str) -> String {
+    let (unicode, res) = idna::domain_to_unicode(host);
+    if res.is_ok() && unicode != host {
+        format!(" ({})", unicode)
+    } else {
+        String::new()
+    }
+}
+
+fn from_env_note(var: Option<&str>) -> String {
+    match var {
+        Some(name) if cli::NO_PROXY_VARS.contains(&name) => format!(" (target listed in {})", name),
+        Some(name) => format!(" (from env {})", name),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockProxyOptions, MockTargetOptions};
+    use clap::Parser;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    fn target_options(status: u16) -> MockTargetOptions {
+        MockTargetOptions {
+            listen: ([127, 0, 0, 1], 0).into(),
+            status,
+            body_size: 0,
+            delay: None,
+            truncate_at: None,
+            garbage: false,
+            reorder: false,
+        }
+    }
+
+    // A target that answers one request with an empty 200 and hands back
+    // the raw request it received.
+    async fn capture_target() -> (std::net::SocketAddr, tokio::task::JoinHandle<Vec<u8>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 4096];
+            let body_start = loop {
+                let n = conn.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed before the end of the request head");
+                received.extend_from_slice(&buf[..n]);
+                if let Some(pos) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+            };
+            let head = String::from_utf8_lossy(&received[..body_start]).to_ascii_lowercase();
+            let length: usize = head
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length:"))
+                .map_or(0, |v| v.trim().parse().unwrap());
+            while received.len() < body_start + length {
+                let n = conn.read(&mut buf).await.unwrap();
+                assert!(n > 0, "connection closed before the end of the body");
+                received.extend_from_slice(&buf[..n]);
+            }
+            conn.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            received
+        });
+        (addr, handle)
+    }
+
+    // Splits a captured request into its lowercased head (every line
+    // CRLF-terminated) and its body.
+    fn split_request(raw: &[u8]) -> (String, &[u8]) {
+        let pos = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        (String::from_utf8_lossy(&raw[..pos + 2]).to_ascii_lowercase(), &raw[pos + 4..])
+    }
+
+    // Benches `url` directly with `flags` on top of `-u url --quiet`.
+    async fn bench(url: &str, flags: &[&str]) -> (Args, Stats) {
+        bench_via("direct://", url, flags).await
+    }
+
+    async fn bench_via(proxy: &str, url: &str, flags: &[&str]) -> (Args, Stats) {
+        let mut argv = vec!["xray-tester", "-p", proxy, "-u", url, "--quiet"];
+        argv.extend_from_slice(flags);
+        let args = Args::try_parse_from(argv).unwrap();
+        let opts = bench_options(&args, parse_url_target(url).unwrap()).unwrap();
+        let proxy = Url::parse(proxy).unwrap();
+        let addr = proxy_addr(&proxy).unwrap();
+        let stats = run_bench(Arc::new(proxy), &addr, Arc::new(opts)).await.unwrap();
+        (args, stats)
+    }
+
+    fn proxy_options(latency: Option<Duration>) -> MockProxyOptions {
+        MockProxyOptions {
+            listen: ([127, 0, 0, 1], 0).into(),
+            latency,
+            fail_rate: 0.0,
+            auth: None,
+            requests_per_tunnel: None,
+        }
+    }
+
+    // Ctrl-C with a --log-file: the run stops dispatching, flushes the log
+    // and hands back what finished instead of exiting on the spot.
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn ctrl_c_returns_the_partial_run() {
+        // Leaves the process-wide flag set, which only affects runs with a
+        // --log-file; no other test uses one.
+        // Installed first, so the signal never hits the default handler.
+        let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt()).unwrap();
+        let mut opts = target_options(200);
+        opts.delay = Some(Duration::from_millis(10));
+        let (target, server) = mock::start_target(opts).await.unwrap();
+        let log = std::env::temp_dir().join(format!("xray-tester-{}-interrupted.log", std::process::id()));
+        let run = {
+            let (url, log) = (format!("http://{}/", target), log.display().to_string());
+            tokio::spawn(async move { bench(&url, &["-n", "100000", "-c", "2", "--log-file", &log]).await })
+        };
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let pid = std::process::id().to_string();
+        assert!(std::process::Command::new("kill").args(["-INT", &pid]).status().unwrap().success());
+        sigint.recv().await;
+
+        let (_, stats) = tokio::time::timeout(Duration::from_secs(5), run).await.unwrap().unwrap();
+        assert!(stats.interrupted && request::interrupted());
+        assert!((1..100_000).contains(&stats.completed()), "{}", stats.completed());
+        let logged = std::fs::read_to_string(&log).unwrap().lines().count();
+        assert_eq!(logged, stats.completed());
+        let lines = pretty::report_lines(&stats, 100_000, cli::Units::Ms);
+        assert!(lines.iter().any(|line| pretty::render(line, false).contains("Interrupted")));
+        std::fs::remove_file(&log).unwrap();
+        server.abort();
+    }
+
+    #[tokio::test]
+    async fn panicking_request_tasks_fail_the_run() {
+        let (addr, server) = mock::start_target(target_options(200)).await.unwrap();
+        let url = format!("http://{}{}", addr, request::PANIC_PATH);
+        let (args, stats) = bench(&url, &["-n", "3"]).await;
+        server.abort();
+        assert_eq!((stats.internal_errors, stats.fail, stats.success), (3, 3, 0));
+        assert_eq!(stats.panics.get("request task panic (test)"), Some(&3));
+        let err = finish(&args, "direct://", &url, 3, &stats, None, None).await.unwrap_err();
+        assert_eq!(err.to_string(), "3 request task(s) panicked; the results are not trustworthy");
+    }
+
+    #[tokio::test]
+    async fn failed_latencies_are_recorded_on_request() {
+        let (addr, server) = mock::start_target(target_options(403)).await.unwrap();
+        let url = format!("http://{}/", addr);
+        let (_, stats) = bench(&url, &["-n", "4", "-c", "1"]).await;
+        assert_eq!((stats.success, stats.fail), (0, 4));
+        assert!(stats.latencies_us.is_empty() && stats.latencies_by_seq.is_empty());
+        assert_eq!(stats.failure_us.len(), 4);
+
+        let (_, stats) = bench(&url, &["-n", "4", "-c", "1", "--record-all-latencies"]).await;
+        server.abort();
+        // Counters are unchanged; the latencies now describe the 403s.
+        assert_eq!((stats.success, stats.fail), (0, 4));
+        assert_eq!(stats.status_counts.get(&403), Some(&4));
+        assert_eq!(stats.latencies_us.len(), 4);
+        let seqs: Vec<usize> = stats.latencies_by_seq.iter().map(|&(seq, _)| seq).collect();
+        assert_eq!(seqs, [1, 2, 3, 4]);
+
+        // Successes are recorded once either way.
+        let (addr, server) = mock::start_target(target_options(200)).await.unwrap();
+        let url = format!("http://{}/", addr);
+        let (_, stats) = bench(&url, &["-n", "4", "--record-all-latencies"]).await;
+        server.abort();
+        assert_eq!((stats.success, stats.latencies_us.len()), (4, 4));
+    }
+
+    #[test]
+    fn banner_shows_the_unicode_host() {
+        assert_eq!(unicode_note("xn--e1afmkfd.xn--80akhbyknj4f"), " (пример.испытание)");
+        assert_eq!(unicode_note("xn--bcher-kva.example"), " (bücher.example)");
+        assert_eq!(unicode_note("example.com"), "");
+        assert_eq!(unicode_note("127.0.0.1"), "");
+    }
+
+    #[tokio::test]
+    async fn form_body_is_sent_urlencoded() {
+        let (addr, target) = capture_target().await;
+        let url = format!("http://{}/submit", addr);
+        let flags = ["-n", "1", "--form", "a=1", "--form", "b=x y&z", "--form", "ключ=значение"];
+        let (_, stats) = bench(&url, &flags).await;
+        assert_eq!(stats.success, 1);
+        let raw = target.await.unwrap();
+        let (head, body) = split_request(&raw);
+        assert!(head.starts_with("post /submit http/1.1\r\n"), "{}", head);
+        assert!(head.contains("\r\ncontent-type: application/x-www-form-urlencoded\r\n"), "{}", head);
+        assert!(head.contains(&format!("\r\ncontent-length: {}\r\n", body.len())), "{}", head);
+        assert_eq!(
+            body,
+            b"a=1&b=x+y%26z&%D0%BA%D0%BB%D1%8E%D1%87=%D0%B7%D0%BD%D0%B0%D1%87%D0%B5%D0%BD%D0%B8%D0%B5"
+        );
+    }
+
+    #[tokio::test]
+    async fn json_body_is_sent_as_given() {
+        let (addr, target) = capture_target().await;
+        let url = format!("http://{}/", addr);
+        let json = r#"{"user": "тест", "ids": [1, 2]}"#;
+        let (_, stats) = bench(&url, &["-n", "1", "--json-body", json]).await;
+        assert_eq!(stats.success, 1);
+        let raw = target.await.unwrap();
+        let (head, body) = split_request(&raw);
+        assert!(head.starts_with("post / http/1.1\r\n"), "{}", head);
+        assert!(head.contains("\r\ncontent-type: application/json\r\n"), "{}", head);
+        assert_eq!(body, json.as_bytes());
+
+        // Checked before the run, and never together with --form.
+        assert!(Args::try_parse_from(["xray-tester", "--json-body", "{oops"]).is_err());
+        assert!(Args::try_parse_from(["xray-tester", "--json-body", "{}", "--form", "a=1"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn bodies_cut_short_count_as_truncated() {
+        let (addr, server) = mock::start_target(MockTargetOptions {
+            body_size: 100_000,
+            truncate_at: Some(30_000),
+            ..target_options(200)
+        })
+        .await
+        .unwrap();
+        let url = format!("http://{}/", addr);
+        let (_, stats) = bench(&url, &["-n", "3", "--mode", "download"]).await;
+        assert_eq!((stats.success, stats.fail, stats.truncated), (0, 3, 3));
+        assert_eq!(stats.conn_errors, 0);
+        assert_eq!(stats.status_counts.get(&200), Some(&3));
+        assert!(pretty::summary_line(&stats).ends_with(" errors: truncated=3"), "{}", pretty::summary_line(&stats));
+        assert_eq!(serde_json::to_value(&stats).unwrap()["truncated"], 3);
+
+        // Stopping on purpose before the cut is a complete response.
+        let (_, stats) = bench(&url, &["-n", "3", "--mode", "download", "--max-body-bytes", "10000"]).await;
+        server.abort();
+        assert_eq!((stats.success, stats.truncated), (3, 0));
+
+        let (addr, server) = mock::start_target(MockTargetOptions {
+            body_size: 100_000,
+            ..target_options(200)
+        })
+        .await
+        .unwrap();
+        let (_, stats) = bench(&format!("http://{}/", addr), &["-n", "3", "--mode", "download"]).await;
+        server.abort();
+        assert_eq!((stats.success, stats.truncated, stats.download.bytes), (3, 0, 300_000));
+    }
+
+    #[tokio::test]
+    async fn gibberish_counts_as_protocol_errors() {
+        let (addr, server) = mock::start_target(MockTargetOptions {
+            garbage: true,
+            ..target_options(200)
+        })
+        .await
+        .unwrap();
+        let (_, stats) = bench(&format!("http://{}/", addr), &["-n", "3"]).await;
+        server.abort();
+        assert_eq!((stats.success, stats.fail, stats.protocol_errors), (0, 3, 3));
+        assert_eq!((stats.conn_errors, stats.timeout_errors), (0, 0));
+        assert!(pretty::summary_line(&stats).ends_with(" errors: protocol=3"), "{}", pretty::summary_line(&stats));
+    }
+
+    // A proxy that drops each tunnel after one request: the second half of
+    // every batch goes over a reopened tunnel, whose setup (100ms of proxy
+    // latency) lands in the batch latency; --no-reconnect fails the batch.
+    #[tokio::test]
+    async fn pipelined_tunnel_closed_midway_is_reopened_once() {
+        let (target, target_server) = mock::start_target(target_options(200)).await.unwrap();
+        let (proxy, proxy_server) = mock::start_proxy(MockProxyOptions {
+            requests_per_tunnel: Some(1),
+            ..proxy_options(Some(Duration::from_millis(100)))
+        })
+        .await
+        .unwrap();
+        let url = format!("http://{}/", target);
+        let proxy = format!("http://{}", proxy);
+
+        let (_, stats) = bench_via(&proxy, &url, &["-n", "3", "--pipeline", "2"]).await;
+        assert_eq!((stats.success, stats.fail, stats.tunnel_reconnects), (3, 0, 3));
+        assert!(stats.latencies_us.iter().all(|&us| us >= 100_000), "{:?}", stats.latencies_us);
+        let reopened_line = pretty::report_lines(&stats, 3, cli::Units::Ms)
+            .iter()
+            .map(|line| pretty::render(line, false))
+            .any(|line| line == "  Tunnels reopened mid --pipeline batch: 3");
+        assert!(reopened_line);
+
+        // One reconnect per batch: three requests need two.
+        let (_, stats) = bench_via(&proxy, &url, &["-n", "1", "--pipeline", "3"]).await;
+        assert_eq!((stats.success, stats.protocol_errors, stats.tunnel_reconnects), (0, 1, 1));
+
+        let (_, stats) = bench_via(&proxy, &url, &["-n", "3", "--pipeline", "2", "--no-reconnect"]).await;
+        assert_eq!((stats.success, stats.protocol_errors, stats.tunnel_reconnects), (0, 3, 0));
+        proxy_server.abort();
+        target_server.abort();
+    }
+
+    #[test]
+    fn no_reconnect_needs_pipeline() {
+        let args = Args::try_parse_from(["xray-tester", "-u", "http://example.com/", "--no-reconnect"]).unwrap();
+        let e = bench_options(&args, parse_url_target("http://example.com/").unwrap()).err().unwrap();
+        assert_eq!(e.to_string(), "--no-reconnect needs --pipeline");
+    }
+
+    // A proxy that sits on every CONNECT keeps the requests piling up: the
+    // gauge should reach --concurrency, and stop at -n when that is lower.
+    #[tokio::test]
+    async fn in_flight_gauge_behind_a_slow_proxy() {
+        let (target, target_server) = mock::start_target(target_options(200)).await.unwrap();
+        let (proxy, proxy_server) = mock::start_proxy(proxy_options(Some(Duration::from_millis(300))))
+            .await
+            .unwrap();
+        let url = format!("http://{}/", target);
+        let proxy = format!("http://{}", proxy);
+
+        let (_, stats) = bench_via(&proxy, &url, &["-n", "8", "-c", "4"]).await;
+        assert_eq!(stats.success, 8);
+        let gauge = stats.in_flight.as_ref().unwrap();
+        assert_eq!((gauge.peak, gauge.configured), (4, 4));
+        // Two waves of ~300ms, four at a time, all inside the first second.
+        let first = gauge.per_sec[&0];
+        assert!((2.0..=4.0).contains(&first), "{:?}", gauge.per_sec);
+        let peak_line = pretty::report_lines(&stats, 8, cli::Units::Ms)
+            .iter()
+            .map(|line| pretty::render(line, false))
+            .any(|line| line == "  Peak in-flight: 4 / 4 configured");
+        assert!(peak_line);
+
+        let (_, stats) = bench_via(&proxy, &url, &["-n", "3", "-c", "10"]).await;
+        let gauge = stats.in_flight.as_ref().unwrap();
+        assert_eq!((gauge.peak, gauge.configured), (3, 10));
+
+        // A run shorter than a second still shows what was in flight, not
+        // a sample taken before anything started.
+        let (_, stats) = bench_via(&proxy, &url, &["-n", "20", "-c", "20", "--timeline"]).await;
+        let gauge = stats.in_flight.as_ref().unwrap();
+        assert_eq!(gauge.per_sec.len(), 1, "{:?}", gauge.per_sec);
+        assert!(gauge.per_sec[&0] >= 15.0, "{:?}", gauge.per_sec);
+        proxy_server.abort();
+        target_server.abort();
+    }
+
+    // Request tasks are spawned as earlier ones finish, so a huge -n never
+    // has more than -c of them alive. Each slot also holds a connection
+    // driver and the mock target's connection task (one more while a
+    // closed one winds down); add the accept loop and this sampler.
+    #[tokio::test]
+    async fn huge_iteration_counts_keep_tasks_bounded() {
+        let (target, server) = mock::start_target(target_options(200)).await.unwrap();
+        let url = format!("http://{}/", target);
+        for c in [1, 2] {
+            let most = Arc::new(AtomicUsize::new(0));
+            let sampler = {
+                let most = most.clone();
+                tokio::spawn(async move {
+                    loop {
+                        most.fetch_max(tokio::runtime::Handle::current().metrics().num_alive_tasks(), Ordering::Relaxed);
+                        tokio::time::sleep(Duration::from_millis(1)).await;
+                    }
+                })
+            };
+            let (_, stats) = bench(&url, &["-n", "10000", "-c", &c.to_string()]).await;
+            sampler.abort();
+            assert_eq!(stats.success, 10_000, "-c {}", c);
+            assert_eq!(stats.in_flight.as_ref().unwrap().peak, c);
+            let most = most.load(Ordering::Relaxed);
+            assert!(most <= 4 * c + 2, "-c {}: {} tasks alive", c, most);
+        }
+        server.abort();
+    }
+
+    // --sqlite keeps a sample per request, failures included, and the run
+    // lands in a fresh database that `history` reads back.
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlite_stores_the_run_and_its_samples() {
+        let (target, target_server) = mock::start_target(target_options(200)).await.unwrap();
+        let url = format!("http://{}/", target);
+        let db = std::env::temp_dir().join(format!("xray-tester-{}-bench.db", std::process::id()));
+        let _ = std::fs::remove_file(&db);
+        let db_flag = db.to_str().unwrap();
+        let (args, stats) = bench(&url, &["-n", "5", "--sqlite", db_flag]).await;
+        let samples = stats.samples.as_ref().unwrap();
+        assert_eq!(samples.len(), 5);
+        assert!(samples.iter().all(|s| s.status == Some(200) && s.error_kind.is_none() && s.latency_us.is_some()));
+        save_sqlite(&args, "direct://", &url, &stats).unwrap();
+
+        let (args, stats) = bench("http://127.0.0.1:9/", &["-n", "2", "--sqlite", db_flag]).await;
+        let kinds: Vec<_> = stats.samples.as_ref().unwrap().iter().map(|s| (s.status, s.error_kind)).collect();
+        assert_eq!(kinds, [(None, Some("conn")); 2]);
+        save_sqlite(&args, "direct://", "http://127.0.0.1:9/", &stats).unwrap();
+
+        let runs = history::load(&db, Some("direct://"), 10).unwrap();
+        assert_eq!(runs.iter().map(|r| (r.requests, r.target.as_str())).collect::<Vec<_>>(), [(5, url.as_str()), (2, "http://127.0.0.1:9/")]);
+        std::fs::remove_file(&db).unwrap();
+        target_server.abort();
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    #[test]
+    fn sqlite_needs_the_feature() {
+        let url = "http://127.0.0.1:9/";
+        let args = Args::try_parse_from(["xray-tester", "-p", "direct://", "-u", url, "--sqlite", "runs.db"]).unwrap();
+        let err = bench_options(&args, parse_url_target(url).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), NO_SQLITE);
+        let args = Args::try_parse_from(["xray-tester", "history", "--db", "runs.db"]).unwrap();
+        assert_eq!(print_history(&args, Path::new("runs.db"), 10).unwrap_err().to_string(), NO_SQLITE);
+    }
+
+    // The #373 acceptance run: -n 100 through the mock proxy, over both of
+    // its protocols, against the mock target.
+    #[tokio::test]
+    async fn end_to_end_through_the_mock_proxy() {
+        let (target, target_server) = mock::start_target(target_options(200)).await.unwrap();
+        let url = format!("http://{}/", target);
+        let mut opts = proxy_options(None);
+        opts.auth = Some(("u".into(), "p".into()));
+        let (proxy, proxy_server) = mock::start_proxy(opts).await.unwrap();
+        for scheme in ["http", "socks5"] {
+            let (_, stats) = bench_via(&format!("{}://u:p@{}", scheme, proxy), &url, &["-n", "100"]).await;
+            assert_eq!((stats.success, stats.fail), (100, 0), "{}", scheme);
+            assert_eq!(stats.status_counts.get(&200), Some(&100), "{}", scheme);
+
+            let (_, stats) = bench_via(&format!("{}://u:wrong@{}", scheme, proxy), &url, &["-n", "3"]).await;
+            assert_eq!((stats.success, stats.fail), (0, 3), "{}", scheme);
+        }
+        proxy_server.abort();
+
+        let mut opts = proxy_options(None);
+        opts.fail_rate = 1.0;
+        let (proxy, proxy_server) = mock::start_proxy(opts).await.unwrap();
+        for scheme in ["http", "socks5"] {
+            let (_, stats) = bench_via(&format!("{}://{}", scheme, proxy), &url, &["-n", "5"]).await;
+            assert_eq!((stats.success, stats.fail), (0, 5), "{}", scheme);
+        }
+        proxy_server.abort();
+        target_server.abort();
+    }
+
+    // A bad path template fails while the options are built, before any
+    // request goes out.
+    #[test]
+    fn template_errors_fail_at_startup() {
+        let url = "http://127.0.0.1:9/users/{rand:9-1}";
+        let args = Args::try_parse_from(["xray-tester", "-p", "direct://", "-u", url]).unwrap();
+        let err = bench_options(&args, parse_url_target(url).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "maximum below minimum in {rand:9-1}");
+    }
+
+    #[test]
+    fn tls_resume_needs_rustls() {
+        let url = "https://127.0.0.1:9/";
+        let args = Args::try_parse_from(["xray-tester", "-p", "direct://", "-u", url, "--tls-resume"]).unwrap();
+        let err = bench_options(&args, parse_url_target(url).unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--tls-resume is not supported with native-tls, the only TLS backend in this build; it needs --tls-backend rustls"
+        );
+    }
+
+    // Reads requests and never answers; each connection's task ends when
+    // the client closes its socket.
+    async fn silent_target() -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+        use tokio::io::AsyncReadExt;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            loop {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while matches!(conn.read(&mut buf).await, Ok(n) if n > 0) {}
+                });
+            }
+        });
+        (addr, handle)
+    }
+
+    // Every request times out waiting on the target. Afterwards the runtime
+    // must be back to the target's accept loop: no connection drivers left,
+    // and, since the target's tasks only end when the client's sockets
+    // close, no sockets either.
+    #[tokio::test]
+    async fn timed_out_requests_leave_no_connections_behind() {
+        let (target, server) = silent_target().await;
+        let alive = || tokio::runtime::Handle::current().metrics().num_alive_tasks();
+        let baseline = alive();
+        let url = format!("http://{}/", target);
+        let (_, stats) = bench(&url, &["-n", "3000", "-c", "300", "--timeout", "50ms"]).await;
+        assert_eq!(stats.timeout_errors, 3000);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while alive() > baseline && Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(alive(), baseline);
+        server.abort();
+    }
+
+    // The first connection gets a 429 with Retry-After, the rest a 200.
+    async fn rate_limiting_target() -> (std::net::SocketAddr, tokio::task::JoinHandle<()>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            for n in 0.. {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    let read = conn.read(&mut buf).await.unwrap();
+                    if read == 0 {
+                        break;
+                    }
+                    head.extend_from_slice(&buf[..read]);
+                }
+                let reply: &[u8] = if n == 0 {
+                    b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 0\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                } else {
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                };
+                let _ = conn.write_all(reply).await;
+            }
+        });
+        (addr, handle)
+    }
+
+    #[tokio::test]
+    async fn retry_after_retries_instead_of_failing() {
+        let (addr, server) = rate_limiting_target().await;
+        let url = format!("http://{}/", addr);
+        let (_, stats) = bench(&url, &["-n", "2", "-c", "1", "--respect-retry-after"]).await;
+        server.abort();
+        assert_eq!((stats.success, stats.fail, stats.rate_limited), (2, 0, 1));
+
+        let (addr, server) = rate_limiting_target().await;
+        let url = format!("http://{}/", addr);
+        let (_, stats) = bench(&url, &["-n", "2", "-c", "1"]).await;
+        server.abort();
+        assert_eq!((stats.success, stats.fail, stats.rate_limited), (1, 1, 0));
+        assert_eq!(stats.status_counts.get(&429), Some(&1));
+    }
 }
@@ -0,0 +1,188 @@
+use clap::{Arg, Command};
+use std::fmt::Write as _;
+
+// A roff man page built from the clap definitions, so it never drifts from
+// --help: every visible flag with its value name, default and environment
+// variable, then each subcommand with its own arguments.
+
+// Escapes text for roff: backslashes, and dots or quotes at line start.
+fn escape(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let line = line.replace('\\', "\\e").replace('-', "\\-");
+            if line.starts_with('.') || line.starts_with('\'') {
+                format!("\\&{}", line)
+            } else {
+                line
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn arg_heading(arg: &Arg) -> String {
+    let mut names = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("\\fB\\-{}\\fR", short));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let takes_value = arg.get_num_args().is_some_and(|n| n.takes_values());
+    let mut heading = names.join(", ");
+    if arg.is_positional() {
+        let name = arg
+            .get_value_names()
+            .and_then(|v| v.first().map(|s| s.to_string()))
+            .unwrap_or_else(|| arg.get_id().as_str().to_uppercase());
+        heading = format!("\\fI{}\\fR", escape(&name));
+    } else if takes_value {
+        match arg.get_value_names() {
+            Some(values) => {
+                for v in values {
+                    let _ = write!(heading, " \\fI{}\\fR", escape(v));
+                }
+            }
+            None => {
+                let _ = write!(heading, " \\fI{}\\fR", escape(&arg.get_id().as_str().to_uppercase()));
+            }
+        }
+    }
+    heading
+}
+
+fn arg_body(arg: &Arg) -> String {
+    let help = arg
+        .get_long_help()
+        .or_else(|| arg.get_help())
+        .map(|h| h.to_string())
+        .unwrap_or_default();
+    let mut body = escape(&help);
+    let mut notes = Vec::new();
+    let possible: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|v| !v.is_hide_set())
+        .map(|v| v.get_name().to_string())
+        .collect();
+    if !possible.is_empty() && arg.get_num_args().is_some_and(|n| n.takes_values()) {
+        notes.push(format!("possible values: {}", possible.join(", ")));
+    }
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|v| v.to_string_lossy().into_owned())
+        .collect();
+    if !defaults.is_empty() && arg.get_num_args().is_some_and(|n| n.takes_values()) {
+        notes.push(format!("default: {}", defaults.join(", ")));
+    }
+    if let Some(env) = arg.get_env() {
+        notes.push(format!("env: {}", env.to_string_lossy()));
+    }
+    if !notes.is_empty() {
+        if !body.is_empty() {
+            body.push('\n');
+        }
+        let _ = write!(body, "[{}]", escape(&notes.join("; ")));
+    }
+    body
+}
+
+fn write_args<'a>(out: &mut String, args: impl Iterator<Item = &'a Arg>) {
+    for arg in args.filter(|a| !a.is_hide_set()) {
+        let _ = writeln!(out, ".TP\n{}", arg_heading(arg));
+        let body = arg_body(arg);
+        if !body.is_empty() {
+            let _ = writeln!(out, "{}", body);
+        }
+    }
+}
+
+fn write_subcommand(out: &mut String, parent: &str, cmd: &Command) {
+    let name = format!("{} {}", parent, cmd.get_name());
+    let _ = writeln!(out, ".SS \"{}\"", escape(&name));
+    if let Some(about) = cmd.get_long_about().or_else(|| cmd.get_about()) {
+        let _ = writeln!(out, "{}", escape(&about.to_string()));
+    }
+    // Global flags are listed once, under OPTIONS.
+    write_args(out, cmd.get_arguments().filter(|a| !a.is_global_set() && a.get_id() != "help"));
+    for sub in cmd.get_subcommands() {
+        write_subcommand(out, &name, sub);
+    }
+}
+
+pub fn render(mut cmd: Command) -> String {
+    // Building fills in value names, arities and defaults derived by clap.
+    cmd.build();
+    let cmd = &cmd;
+    let name = cmd.get_name();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        ".TH {} 1 \"\" \"{} {}\"",
+        name.to_uppercase(),
+        name,
+        cmd.get_version().unwrap_or_default()
+    );
+    let _ = writeln!(out, ".SH NAME");
+    let _ = writeln!(
+        out,
+        "{} \\- {}",
+        escape(name),
+        escape(&cmd.get_about().map(|a| a.to_string()).unwrap_or_default())
+    );
+    let _ = writeln!(out, ".SH SYNOPSIS");
+    let _ = writeln!(out, "\\fB{}\\fR [\\fIOPTIONS\\fR] [\\fICOMMAND\\fR]", escape(name));
+    if let Some(about) = cmd.get_long_about() {
+        let _ = writeln!(out, ".SH DESCRIPTION\n{}", escape(&about.to_string()));
+    }
+    let _ = writeln!(out, ".SH OPTIONS");
+    write_args(
+        &mut out,
+        cmd.get_arguments().filter(|a| !matches!(a.get_id().as_str(), "help" | "version")),
+    );
+    let subcommands: Vec<&Command> = cmd.get_subcommands().filter(|c| !c.is_hide_set()).collect();
+    if !subcommands.is_empty() {
+        let _ = writeln!(out, ".SH COMMANDS");
+        for sub in subcommands {
+            write_subcommand(&mut out, name, sub);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Args;
+    use clap::CommandFactory;
+
+    fn long_flags(cmd: &Command, out: &mut Vec<String>) {
+        for arg in cmd.get_arguments().filter(|a| !a.is_hide_set()) {
+            if let Some(long) = arg.get_long().filter(|&l| l != "help" && l != "version") {
+                out.push(long.to_string());
+            }
+        }
+        for sub in cmd.get_subcommands().filter(|c| !c.is_hide_set()) {
+            long_flags(sub, out);
+        }
+    }
+
+    #[test]
+    fn every_long_flag_is_documented() {
+        let page = render(Args::command());
+        let mut flags = Vec::new();
+        long_flags(&Args::command(), &mut flags);
+        assert!(flags.len() > 100, "{}", flags.len());
+        for long in flags {
+            let heading = format!("\\fB\\-\\-{}\\fR", escape(&long));
+            assert!(page.contains(&heading), "--{} is missing from the man page", long);
+        }
+    }
+
+    #[test]
+    fn roff_escapes() {
+        assert_eq!(escape(".hidden\n'quoted\nplain"), "\\&.hidden\n\\&'quoted\nplain");
+        assert_eq!(escape("C:\\dir --flag"), "C:\\edir \\-\\-flag");
+    }
+}
@@ -0,0 +1,433 @@
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+use crate::pipeline::SEQ_HEADER;
+use crate::stats::mix64;
+
+// Self-contained servers for trying the tool out (and testing it) without
+// a real proxy or origin. Both bind first and return the address, so a
+// caller can ask for port 0 and learn the one it got.
+
+#[derive(Debug, Clone)]
+pub struct MockProxyOptions {
+    pub listen: SocketAddr,
+    // Added before the proxy answers each CONNECT / SOCKS request.
+    pub latency: Option<Duration>,
+    // Share of tunnels refused (502 / SOCKS "connection refused"), 0..=1.
+    pub fail_rate: f64,
+    // `user:pass` required from clients when set.
+    pub auth: Option<(String, String)>,
+    // Close each tunnel once this many plain-HTTP request heads went
+    // through it, like a proxy with a per-connection request limit.
+    pub requests_per_tunnel: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockTargetOptions {
+    pub listen: SocketAddr,
+    pub status: u16,
+    pub body_size: u64,
+    pub delay: Option<Duration>,
+    // Body bytes sent before the connection is dropped mid-response.
+    pub truncate_at: Option<u64>,
+    // Answer with non-HTTP bytes, like a broken transparent proxy.
+    pub garbage: bool,
+    // Answer requests on a connection in swapped pairs (the second before
+    // the first), like a proxy that breaks pipelining.
+    pub reorder: bool,
+}
+
+// Decides which tunnels fail; deterministic per process start.
+struct FailDice {
+    rate: f64,
+    seed: u64,
+    n: AtomicU64,
+}
+
+impl FailDice {
+    fn roll(&self) -> bool {
+        if self.rate <= 0.0 {
+            return false;
+        }
+        let n = self.n.fetch_add(1, Ordering::Relaxed);
+        (mix64(self.seed, n) as f64 / u64::MAX as f64) < self.rate
+    }
+}
+
+pub async fn start_proxy(opts: MockProxyOptions) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(opts.listen)
+        .await
+        .with_context(|| format!("binding {}", opts.listen))?;
+    let addr = listener.local_addr()?;
+    let dice = Arc::new(FailDice {
+        rate: opts.fail_rate,
+        seed: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64),
+        n: AtomicU64::new(0),
+    });
+    let opts = Arc::new(opts);
+    let handle = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(c) => c,
+                Err(e) => {
+                    debug!("mock proxy accept failed: {}", e);
+                    continue;
+                }
+            };
+            let opts = opts.clone();
+            let dice = dice.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_proxy_conn(stream, &opts, &dice).await {
+                    debug!(%peer, "mock proxy connection ended: {:#}", e);
+                }
+            });
+        }
+    });
+    Ok((addr, handle))
+}
+
+// The first byte tells the protocols apart: SOCKS5 greetings start with 0x05.
+async fn serve_proxy_conn(stream: TcpStream, opts: &MockProxyOptions, dice: &FailDice) -> Result<()> {
+    let mut first = [0u8; 1];
+    stream.peek(&mut first).await?;
+    if first[0] == 0x05 {
+        serve_socks(stream, opts, dice).await
+    } else {
+        serve_connect(stream, opts, dice).await
+    }
+}
+
+async fn serve_connect(mut stream: TcpStream, opts: &MockProxyOptions, dice: &FailDice) -> Result<()> {
+    let mut buf = Vec::with_capacity(1024);
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() > 16 * 1024 {
+            return Err(anyhow!("request head too large"));
+        }
+        let mut chunk = [0u8; 1024];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Err(anyhow!("connection closed before request head"));
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some("CONNECT"), Some(target)) = (parts.next(), parts.next()) else {
+        stream
+            .write_all(b"HTTP/1.1 405 Method Not Allowed\r\nContent-Length: 0\r\n\r\n")
+            .await?;
+        return Err(anyhow!("not a CONNECT request: {:?}", request_line));
+    };
+    if let Some((user, pass)) = &opts.auth {
+        let expected = format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass))
+        );
+        let authorized = lines.any(|l| {
+            l.split_once(':').is_some_and(|(name, value)| {
+                name.trim().eq_ignore_ascii_case("proxy-authorization") && value.trim() == expected
+            })
+        });
+        if !authorized {
+            stream
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic realm=\"mock\"\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            return Ok(());
+        }
+    }
+    if let Some(d) = opts.latency {
+        tokio::time::sleep(d).await;
+    }
+    if dice.roll() {
+        stream
+            .write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n")
+            .await?;
+        return Ok(());
+    }
+    let upstream = match TcpStream::connect(target).await {
+        Ok(s) => s,
+        Err(e) => {
+            stream
+                .write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n")
+                .await?;
+            return Err(anyhow!("connect to {} failed: {}", target, e));
+        }
+    };
+    stream
+        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+        .await?;
+    // Bytes the client sent right after the head belong to the tunnel.
+    relay(stream, upstream, buf.split_off(head_end), opts.requests_per_tunnel).await
+}
+
+// Copies both ways until either side closes. With a `limit`, only that
+// many request heads reach the target; its write side is then shut, so
+// it answers those and hangs up, taking the tunnel down with it. Request
+// bodies are not accounted for.
+async fn relay(mut stream: TcpStream, mut upstream: TcpStream, pending: Vec<u8>, limit: Option<usize>) -> Result<()> {
+    let Some(limit) = limit else {
+        upstream.write_all(&pending).await?;
+        tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+        return Ok(());
+    };
+    let (mut client_read, mut client_write) = stream.split();
+    let (mut target_read, mut target_write) = upstream.split();
+    let upload = async {
+        let (mut buf, mut sent, mut heads) = (pending, 0, 0);
+        loop {
+            while heads < limit {
+                let Some(end) = buf[sent..].windows(4).position(|w| w == b"\r\n\r\n") else {
+                    break;
+                };
+                target_write.write_all(&buf[sent..sent + end + 4]).await?;
+                sent += end + 4;
+                heads += 1;
+            }
+            if heads == limit {
+                return target_write.shutdown().await;
+            }
+            let mut chunk = [0u8; 4096];
+            let n = client_read.read(&mut chunk).await?;
+            if n == 0 {
+                return target_write.shutdown().await;
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    };
+    let download = async {
+        tokio::io::copy(&mut target_read, &mut client_write).await?;
+        client_write.shutdown().await
+    };
+    tokio::try_join!(upload, download)?;
+    Ok(())
+}
+
+async fn socks_reply(stream: &mut TcpStream, code: u8) -> Result<()> {
+    stream
+        .write_all(&[0x05, code, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await?;
+    Ok(())
+}
+
+async fn serve_socks(mut stream: TcpStream, opts: &MockProxyOptions, dice: &FailDice) -> Result<()> {
+    let mut hdr = [0u8; 2];
+    stream.read_exact(&mut hdr).await?;
+    let mut methods = vec![0u8; hdr[1] as usize];
+    stream.read_exact(&mut methods).await?;
+    let wanted = if opts.auth.is_some() { 0x02 } else { 0x00 };
+    if !methods.contains(&wanted) {
+        stream.write_all(&[0x05, 0xff]).await?;
+        return Ok(());
+    }
+    stream.write_all(&[0x05, wanted]).await?;
+    if let Some((user, pass)) = &opts.auth {
+        // RFC 1929 username/password subnegotiation.
+        let mut ver_len = [0u8; 2];
+        stream.read_exact(&mut ver_len).await?;
+        let mut got_user = vec![0u8; ver_len[1] as usize];
+        stream.read_exact(&mut got_user).await?;
+        let mut plen = [0u8; 1];
+        stream.read_exact(&mut plen).await?;
+        let mut got_pass = vec![0u8; plen[0] as usize];
+        stream.read_exact(&mut got_pass).await?;
+        let ok = got_user == user.as_bytes() && got_pass == pass.as_bytes();
+        stream.write_all(&[0x01, if ok { 0x00 } else { 0x01 }]).await?;
+        if !ok {
+            return Ok(());
+        }
+    }
+    let mut req = [0u8; 4];
+    stream.read_exact(&mut req).await?;
+    let host = match req[3] {
+        0x01 => {
+            let mut ip = [0u8; 4];
+            stream.read_exact(&mut ip).await?;
+            Ipv4Addr::from(ip).to_string()
+        }
+        0x04 => {
+            let mut ip = [0u8; 16];
+            stream.read_exact(&mut ip).await?;
+            Ipv6Addr::from(ip).to_string()
+        }
+        0x03 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            let mut name = vec![0u8; len[0] as usize];
+            stream.read_exact(&mut name).await?;
+            String::from_utf8_lossy(&name).into_owned()
+        }
+        other => {
+            socks_reply(&mut stream, 0x08).await?;
+            return Err(anyhow!("unsupported SOCKS address type {:#04x}", other));
+        }
+    };
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port).await?;
+    let port = u16::from_be_bytes(port);
+    if req[1] != 0x01 {
+        socks_reply(&mut stream, 0x07).await?;
+        return Err(anyhow!("unsupported SOCKS command {:#04x}", req[1]));
+    }
+    if let Some(d) = opts.latency {
+        tokio::time::sleep(d).await;
+    }
+    if dice.roll() {
+        socks_reply(&mut stream, 0x05).await?;
+        return Ok(());
+    }
+    let upstream = match TcpStream::connect((host.as_str(), port)).await {
+        Ok(s) => s,
+        Err(e) => {
+            socks_reply(&mut stream, 0x05).await?;
+            return Err(anyhow!("connect to {}:{} failed: {}", host, port, e));
+        }
+    };
+    socks_reply(&mut stream, 0x00).await?;
+    relay(stream, upstream, Vec::new(), opts.requests_per_tunnel).await
+}
+
+pub async fn start_target(opts: MockTargetOptions) -> Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind(opts.listen)
+        .await
+        .with_context(|| format!("binding {}", opts.listen))?;
+    let addr = listener.local_addr()?;
+    let opts = Arc::new(opts);
+    let handle = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(c) => c,
+                Err(e) => {
+                    debug!("mock target accept failed: {}", e);
+                    continue;
+                }
+            };
+            let opts = opts.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_target_conn(stream, &opts).await {
+                    debug!(%peer, "mock target connection ended: {:#}", e);
+                }
+            });
+        }
+    });
+    Ok((addr, handle))
+}
+
+// Answers every request with the configured status and a body of
+// `body_size` filler bytes, echoing the --pipeline sequence header.
+// Request bodies with a Content-Length are read and discarded; connections
+// are kept alive until the client says close.
+async fn serve_target_conn(mut stream: TcpStream, opts: &MockTargetOptions) -> Result<()> {
+    let mut buf: Vec<u8> = Vec::with_capacity(1024);
+    loop {
+        let Some(head) = read_request(&mut stream, &mut buf).await? else {
+            return Ok(());
+        };
+        let mut heads = vec![head];
+        if opts.reorder {
+            if let Some(next) = read_request(&mut stream, &mut buf).await? {
+                heads.insert(0, next);
+            }
+        }
+        for head in heads {
+            if !respond(&mut stream, opts, &head).await? {
+                return Ok(());
+            }
+        }
+    }
+}
+
+// The next request head, lowercased, with its body read and discarded;
+// `None` once the client has closed.
+async fn read_request(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Result<Option<String>> {
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() > 64 * 1024 {
+            return Err(anyhow!("request head too large"));
+        }
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).to_ascii_lowercase();
+    let content_length: u64 = head_value(&head, "content-length").and_then(|v| v.parse().ok()).unwrap_or(0);
+    buf.drain(..head_end);
+    let mut remaining = content_length.saturating_sub(buf.len() as u64);
+    buf.drain(..(content_length.min(buf.len() as u64) as usize));
+    let mut sink = [0u8; 16 * 1024];
+    while remaining > 0 {
+        let n = stream.read(&mut sink).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        remaining = remaining.saturating_sub(n as u64);
+    }
+    Ok(Some(head))
+}
+
+fn head_value(head: &str, name: &str) -> Option<String> {
+    head.split("\r\n")
+        .skip(1)
+        .find_map(|l| l.split_once(':').filter(|(n, _)| n.trim() == name).map(|(_, v)| v.trim().to_string()))
+}
+
+// Writes the response to one request; false once the connection is done.
+async fn respond(stream: &mut TcpStream, opts: &MockTargetOptions, head: &str) -> Result<bool> {
+    let close = head_value(head, "connection").is_some_and(|v| v == "close") || head.starts_with("http/1.0");
+    if let Some(d) = opts.delay {
+        tokio::time::sleep(d).await;
+    }
+    if opts.garbage {
+        stream.write_all(b"\x15\x03\x01\x00\x02\x02\x50SSH-2.0-not-http\r\n\r\n").await?;
+        return Ok(false);
+    }
+    let echo = head_value(head, &SEQ_HEADER.to_ascii_lowercase())
+        .map(|seq| format!("{}: {}\r\n", SEQ_HEADER, seq))
+        .unwrap_or_default();
+    let response_head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\n{}Connection: {}\r\n\r\n",
+        opts.status,
+        hyper::StatusCode::from_u16(opts.status)
+            .ok()
+            .and_then(|s| s.canonical_reason())
+            .unwrap_or(""),
+        opts.body_size,
+        echo,
+        if close { "close" } else { "keep-alive" }
+    );
+    stream.write_all(response_head.as_bytes()).await?;
+    let chunk = vec![b'x'; 16 * 1024];
+    let mut left = opts.truncate_at.map_or(opts.body_size, |t| t.min(opts.body_size));
+    while left > 0 {
+        let n = left.min(chunk.len() as u64) as usize;
+        stream.write_all(&chunk[..n]).await?;
+        left -= n as u64;
+    }
+    if opts.truncate_at.is_some_and(|t| t < opts.body_size) {
+        return Ok(false);
+    }
+    if close {
+        stream.shutdown().await?;
+        return Ok(false);
+    }
+    Ok(true)
+}
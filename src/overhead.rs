@@ -0,0 +1,139 @@
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use url::Url;
+
+use crate::cli::{BenchMode, SuccessMatcher, Units};
+use crate::mock::{self, MockTargetOptions};
+use crate::pretty;
+use crate::request::{parse_url_target, run_bench, BenchOptions, DIRECT_SCHEME};
+use crate::stats::{Harness, Stats};
+
+// --calibrate: `requests` plain GETs one at a time, without the proxy, to
+// an in-process mock target on loopback. Headers and socket options are
+// the run's own, so the result is the floor under every latency measured.
+pub async fn calibrate(opts: &BenchOptions, requests: usize) -> Result<Harness> {
+    let (addr, server) = mock::start_target(MockTargetOptions {
+        listen: ([127, 0, 0, 1], 0).into(),
+        status: 200,
+        body_size: 0,
+        delay: None,
+        truncate_at: None,
+        garbage: false,
+        reorder: false,
+    })
+    .await?;
+    let target = parse_url_target(&format!("http://{}/", addr))?;
+    let direct = Arc::new(Url::parse(&format!("{}://", DIRECT_SCHEME))?);
+    let opts = BenchOptions {
+        target: Arc::new(target),
+        path_template: None,
+        success_matcher: Arc::new(SuccessMatcher::default()),
+        mode: BenchMode::Http,
+        iterations: requests,
+        concurrency: 1,
+        rate: None,
+        stop_after: None,
+        stop: None,
+        body: None,
+        conditional: Vec::new(),
+        range: None,
+        expect_headers: Vec::new(),
+        connect_to: None,
+        target_ips: Vec::new(),
+        log_file: None,
+        dump_failures: None,
+        ..opts.clone()
+    };
+    let stats = run_bench(direct, "", Arc::new(opts)).await;
+    server.abort();
+    let stats = stats?;
+    match (stats.latency_percentile(0.50), stats.latency_percentile(0.99)) {
+        (Some(p50_ms), Some(p99_ms)) => Ok(Harness {
+            requests: stats.success,
+            p50_ms,
+            p99_ms,
+        }),
+        _ => Err(anyhow!("--calibrate: no loopback request succeeded")),
+    }
+}
+
+// --baseline-direct: the same workload without the proxy. Refuses to go on
+// when nothing got through directly, since every delta would be noise.
+pub async fn baseline(opts: &BenchOptions) -> Result<Stats> {
+    let direct = Arc::new(Url::parse(&format!("{}://", DIRECT_SCHEME))?);
+    let opts = BenchOptions {
+        dump_failures: None,
+        ..opts.clone()
+    };
+    let stats = run_bench(direct, "", Arc::new(opts)).await?;
+    if stats.success == 0 {
+        let errors: Vec<String> = pretty::error_counts(&stats)
+            .iter()
+            .filter(|(_, n)| *n > 0)
+            .map(|(name, n)| format!("{}={}", name, n))
+            .collect();
+        return Err(anyhow!(
+            "no direct request succeeded ({}), so the proxy overhead cannot be measured; drop --baseline-direct if the target is only reachable through the proxy",
+            if errors.is_empty() { "no requests".to_string() } else { errors.join(" ") }
+        ));
+    }
+    Ok(stats)
+}
+
+fn failure_rate(stats: &Stats) -> f64 {
+    100.0 - stats.success_rate().unwrap_or(0.0)
+}
+
+pub fn summary_line(proxied: &Stats, direct: &Stats) -> String {
+    let mut parts = Vec::new();
+    for (name, p) in [("p50", 0.50), ("p90", 0.90), ("p99", 0.99)] {
+        if let (Some(a), Some(b)) = (proxied.latency_percentile(p), direct.latency_percentile(p)) {
+            parts.push(format!("{}={:+.2}ms", name, a - b));
+        }
+    }
+    parts.push(format!("fail={:+.1}%", failure_rate(proxied) - failure_rate(direct)));
+    if let (Some(a), Some(b)) = (proxied.rps_avg(), direct.rps_avg()) {
+        parts.push(format!("rps={:.2}x", a / b));
+    }
+    format!("overhead: {}", parts.join(" "))
+}
+
+pub fn print(proxied: &Stats, direct: &Stats, units: Units) {
+    println!("\nOverhead (proxied vs direct):");
+    println!("  {:<12}{:>12}{:>12}{:>12}", "", "proxied", "direct", "added");
+    for (name, p) in [("p50", 0.50), ("p90", 0.90), ("p99", 0.99)] {
+        let (a, b) = (proxied.latency_percentile(p), direct.latency_percentile(p));
+        let added = match (a, b) {
+            (Some(a), Some(b)) if a < b => format!("-{}", pretty::fmt_duration(b - a, units)),
+            (Some(a), Some(b)) => format!("+{}", pretty::fmt_duration(a - b, units)),
+            _ => "-".into(),
+        };
+        println!(
+            "  {:<12}{:>12}{:>12}{:>12}",
+            name,
+            pretty::fmt_ms(a, units),
+            pretty::fmt_ms(b, units),
+            added
+        );
+    }
+    let (fa, fb) = (failure_rate(proxied), failure_rate(direct));
+    println!(
+        "  {:<12}{:>12}{:>12}{:>12}",
+        "Failures",
+        format!("{:.2}%", fa),
+        format!("{:.2}%", fb),
+        format!("{:+.2}%", fa - fb)
+    );
+    let (ra, rb) = (proxied.rps_avg(), direct.rps_avg());
+    let ratio = match (ra, rb) {
+        (Some(a), Some(b)) if b > 0.0 => format!("{:.2}x", a / b),
+        _ => "-".into(),
+    };
+    println!(
+        "  {:<12}{:>12}{:>12}{:>12}",
+        "Reqs/sec",
+        format!("{:.2}", ra.unwrap_or(0.0)),
+        format!("{:.2}", rb.unwrap_or(0.0)),
+        ratio
+    );
+}
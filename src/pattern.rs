@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+
+// A small regular-expression subset for header assertions: literals, `.`,
+// classes (`[a-z]`, `[^0-9]`), `\d` `\w` `\s`, the quantifiers `*` `+` `?`,
+// the anchors `^` and `$`, and top-level alternation with `|`. Groups and
+// counted repetition are rejected rather than silently misread.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    alternatives: Vec<Branch>,
+}
+
+#[derive(Debug, Clone)]
+struct Branch {
+    anchored_start: bool,
+    anchored_end: bool,
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    atom: Atom,
+    min: usize,
+    max: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Atom {
+    Any,
+    Char(char),
+    Class { negated: bool, items: Vec<ClassItem> },
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+}
+
+impl ClassItem {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            ClassItem::Range(lo, hi) => lo <= c && c <= hi,
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => c.is_alphanumeric() || c == '_',
+            ClassItem::Space => c.is_whitespace(),
+        }
+    }
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Any => true,
+            Atom::Char(x) => *x == c,
+            Atom::Class { negated, items } => items.iter().any(|i| i.matches(c)) != *negated,
+        }
+    }
+}
+
+fn escape_item(c: char) -> Option<ClassItem> {
+    match c {
+        'd' => Some(ClassItem::Digit),
+        'w' => Some(ClassItem::Word),
+        's' => Some(ClassItem::Space),
+        _ => None,
+    }
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut alternatives = Vec::new();
+        let mut current = String::new();
+        let mut chars = source.chars();
+        // Split on unescaped `|` outside classes.
+        let mut in_class = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    current.push(c);
+                    if let Some(n) = chars.next() {
+                        current.push(n);
+                    }
+                }
+                '[' => {
+                    in_class = true;
+                    current.push(c);
+                }
+                ']' => {
+                    in_class = false;
+                    current.push(c);
+                }
+                '|' if !in_class => alternatives.push(parse_branch(&std::mem::take(&mut current))?),
+                _ => current.push(c),
+            }
+        }
+        alternatives.push(parse_branch(&current)?);
+        Ok(Self {
+            source: source.to_string(),
+            alternatives,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    // True if the pattern matches anywhere in `text`, like a regex search.
+    pub fn is_match(&self, text: &str) -> bool {
+        let text: Vec<char> = text.chars().collect();
+        self.alternatives.iter().any(|b| {
+            if b.anchored_start {
+                match_here(&b.nodes, &text, b.anchored_end)
+            } else {
+                (0..=text.len()).any(|i| match_here(&b.nodes, &text[i..], b.anchored_end))
+            }
+        })
+    }
+}
+
+fn parse_branch(src: &str) -> Result<Branch> {
+    let mut chars: Vec<char> = src.chars().collect();
+    let anchored_start = chars.first() == Some(&'^');
+    if anchored_start {
+        chars.remove(0);
+    }
+    let anchored_end = chars.last() == Some(&'$') && !(chars.len() >= 2 && chars[chars.len() - 2] == '\\');
+    if anchored_end {
+        chars.pop();
+    }
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        let atom = match c {
+            '.' => Atom::Any,
+            '\\' => {
+                let e = *chars
+                    .get(i)
+                    .ok_or_else(|| anyhow!("pattern ends with a lone backslash"))?;
+                i += 1;
+                match escape_item(e) {
+                    Some(item) => Atom::Class {
+                        negated: false,
+                        items: vec![item],
+                    },
+                    None => Atom::Char(e),
+                }
+            }
+            '[' => {
+                let negated = chars.get(i) == Some(&'^');
+                if negated {
+                    i += 1;
+                }
+                let mut items = Vec::new();
+                let mut first = true;
+                loop {
+                    let c = *chars
+                        .get(i)
+                        .ok_or_else(|| anyhow!("unclosed [ in pattern {:?}", src))?;
+                    i += 1;
+                    if c == ']' && !first {
+                        break;
+                    }
+                    first = false;
+                    let lo = if c == '\\' {
+                        let e = *chars
+                            .get(i)
+                            .ok_or_else(|| anyhow!("pattern ends with a lone backslash"))?;
+                        i += 1;
+                        if let Some(item) = escape_item(e) {
+                            items.push(item);
+                            continue;
+                        }
+                        e
+                    } else {
+                        c
+                    };
+                    if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|&n| n != ']') {
+                        let hi = chars[i + 1];
+                        i += 2;
+                        if hi < lo {
+                            return Err(anyhow!("invalid class range {}-{} in pattern", lo, hi));
+                        }
+                        items.push(ClassItem::Range(lo, hi));
+                    } else {
+                        items.push(ClassItem::Range(lo, lo));
+                    }
+                }
+                Atom::Class { negated, items }
+            }
+            '*' | '+' | '?' => {
+                let Some(last) = nodes.last_mut() else {
+                    return Err(anyhow!("quantifier {} has nothing to repeat", c));
+                };
+                if last.min != 1 || last.max != 1 {
+                    return Err(anyhow!("repeated quantifier in pattern {:?}", src));
+                }
+                match c {
+                    '*' => (last.min, last.max) = (0, usize::MAX),
+                    '+' => last.max = usize::MAX,
+                    _ => last.min = 0,
+                }
+                continue;
+            }
+            '(' | ')' | '{' | '}' => {
+                return Err(anyhow!("{} is not supported in header patterns", c));
+            }
+            _ => Atom::Char(c),
+        };
+        nodes.push(Node { atom, min: 1, max: 1 });
+    }
+    Ok(Branch {
+        anchored_start,
+        anchored_end,
+        nodes,
+    })
+}
+
+// Greedy backtracking; header values are short enough for this to be cheap.
+fn match_here(nodes: &[Node], text: &[char], anchored_end: bool) -> bool {
+    let Some((node, rest)) = nodes.split_first() else {
+        return !anchored_end || text.is_empty();
+    };
+    let mut n = 0;
+    while n < node.max && n < text.len() && node.atom.matches(text[n]) {
+        n += 1;
+    }
+    if n < node.min {
+        return false;
+    }
+    (node.min..=n).rev().any(|k| match_here(rest, &text[k..], anchored_end))
+}
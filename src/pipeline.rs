@@ -0,0 +1,322 @@
+use anyhow::Result;
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::Request;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::recorder::HEAD_BYTES;
+use crate::request::{timed_out, ProtocolError, TimeoutPhase};
+
+// --pipeline: every request carries its position in this header; a server
+// that echoes it (mock-target does) lets responses be matched to requests.
+pub const SEQ_HEADER: &str = "X-Pipeline-Seq";
+
+pub struct Response {
+    pub status: u16,
+    // From the moment the batch was written.
+    pub elapsed: Duration,
+}
+
+// hyper's client sends the next request only once the previous response
+// is in, so the pipelined path writes requests by hand. Bodyless requests
+// only; all but the last ask to keep the connection open.
+pub fn render_request(req: &Request<Full<Bytes>>, seq: usize, last: bool) -> Vec<u8> {
+    let mut out = format!("{} {} HTTP/1.1\r\n", req.method(), req.uri());
+    for (name, value) in req.headers() {
+        if name == hyper::header::CONNECTION {
+            continue;
+        }
+        out.push_str(&format!("{}: {}\r\n", name, String::from_utf8_lossy(value.as_bytes())));
+    }
+    out.push_str(&format!(
+        "Connection: {}\r\n{}: {}\r\n\r\n",
+        if last { "close" } else { "keep-alive" },
+        SEQ_HEADER,
+        seq
+    ));
+    out.into_bytes()
+}
+
+fn malformed(detail: impl Into<String>, head: &[u8]) -> anyhow::Error {
+    ProtocolError {
+        detail: detail.into(),
+        head: head[..head.len().min(HEAD_BYTES)].to_vec(),
+    }
+    .into()
+}
+
+// One batch, possibly spread over a reopened tunnel: responses so far,
+// timed from when the batch was first written.
+pub struct Batch {
+    start: Instant,
+    pub deadline: tokio::time::Instant,
+    pub responses: Vec<Response>,
+}
+
+impl Batch {
+    // `timeout` covers the whole batch, reconnects included.
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            start: Instant::now(),
+            deadline: tokio::time::Instant::now() + timeout,
+            responses: Vec::new(),
+        }
+    }
+}
+
+// The connection closed cleanly after answering part of the batch, the
+// way a proxy with a per-tunnel request limit drops it. The rest of the
+// batch can go over a new tunnel.
+#[derive(Debug)]
+pub struct TunnelClosed {
+    pub missing: usize,
+    pub total: usize,
+}
+
+impl std::fmt::Display for TunnelClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pipelined response {} of {} missing: connection closed", self.missing, self.total)
+    }
+}
+
+impl std::error::Error for TunnelClosed {}
+
+impl TunnelClosed {
+    // What the close counts as once it is not retried.
+    pub fn into_protocol_error(self) -> anyhow::Error {
+        malformed(self.to_string(), &[])
+    }
+}
+
+// Writes the `requests` the batch has no response to yet back to back,
+// then reads one response per request in order. A response echoing
+// another request's SEQ_HEADER, or a connection closing before answering
+// any of them, is a protocol error; one closing after answering some is a
+// `TunnelClosed`.
+pub async fn exchange<S>(mut stream: S, requests: &[Vec<u8>], batch: &mut Batch) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (deadline, first) = (batch.deadline, batch.responses.len() + 1);
+    tokio::time::timeout_at(deadline, stream.write_all(&requests[first - 1..].concat()))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "writing pipelined requests timed out"))??;
+    let mut reader = Reader { stream, buf: Vec::new() };
+    for seq in first..=requests.len() {
+        let response = tokio::time::timeout_at(deadline, reader.response())
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::Request, format!("pipelined response {} of {} timed out", seq, requests.len())))??;
+        let Some((status, echoed)) = response else {
+            let closed = TunnelClosed {
+                missing: seq,
+                total: requests.len(),
+            };
+            return Err(match seq > first {
+                true => closed.into(),
+                false => closed.into_protocol_error(),
+            });
+        };
+        if let Some(echoed) = echoed.filter(|e| *e != seq.to_string()) {
+            return Err(malformed(
+                format!("pipelined response {} answered request {}", seq, echoed),
+                &[],
+            ));
+        }
+        batch.responses.push(Response {
+            status,
+            elapsed: batch.start.elapsed(),
+        });
+    }
+    Ok(())
+}
+
+struct Reader<S> {
+    stream: S,
+    buf: Vec<u8>,
+}
+
+impl<S: AsyncRead + Unpin> Reader<S> {
+    // Reads more into `buf`; false at EOF.
+    async fn fill(&mut self) -> Result<bool> {
+        let mut chunk = [0u8; 16 * 1024];
+        let n = self.stream.read(&mut chunk).await?;
+        self.buf.extend_from_slice(&chunk[..n]);
+        Ok(n > 0)
+    }
+
+    async fn line(&mut self) -> Result<Vec<u8>> {
+        loop {
+            if let Some(pos) = self.buf.windows(2).position(|w| w == b"\r\n") {
+                let line = self.buf[..pos].to_vec();
+                self.buf.drain(..pos + 2);
+                return Ok(line);
+            }
+            if !self.fill().await? {
+                return Err(malformed("connection closed inside chunked body", &self.buf));
+            }
+        }
+    }
+
+    async fn skip(&mut self, mut n: u64) -> Result<()> {
+        loop {
+            let take = n.min(self.buf.len() as u64) as usize;
+            self.buf.drain(..take);
+            n -= take as u64;
+            if n == 0 {
+                return Ok(());
+            }
+            if !self.fill().await? {
+                return Err(malformed("connection closed inside response body", &[]));
+            }
+        }
+    }
+
+    // Status and echoed SEQ_HEADER of the next final response, skipping
+    // 1xx; `None` if the connection closed before one started.
+    async fn response(&mut self) -> Result<Option<(u16, Option<String>)>> {
+        loop {
+            let head_end = loop {
+                if let Some(pos) = self.buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break pos + 4;
+                }
+                if self.buf.len() > 64 * 1024 {
+                    return Err(malformed("response head too large", &self.buf));
+                }
+                if !self.fill().await? {
+                    if self.buf.is_empty() {
+                        return Ok(None);
+                    }
+                    return Err(malformed("connection closed inside response head", &self.buf));
+                }
+            };
+            let raw = self.buf[..head_end].to_vec();
+            let head = String::from_utf8_lossy(&raw);
+            let mut lines = head.split("\r\n");
+            let status = lines
+                .next()
+                .and_then(|l| l.strip_prefix("HTTP/1."))
+                .and_then(|l| l.get(2..5))
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| malformed("bad status line", &raw))?;
+            let (mut length, mut chunked, mut echoed) = (None, false, None);
+            for line in lines.filter(|l| !l.is_empty()) {
+                let (name, value) = line.split_once(':').ok_or_else(|| malformed("bad header line", &raw))?;
+                let value = value.trim();
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = Some(value.parse::<u64>().map_err(|_| malformed("bad Content-Length", &raw))?);
+                } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                    chunked = value.to_ascii_lowercase().ends_with("chunked");
+                } else if name.eq_ignore_ascii_case(SEQ_HEADER) {
+                    echoed = Some(value.to_string());
+                }
+            }
+            self.buf.drain(..head_end);
+            if (100..200).contains(&status) {
+                continue;
+            }
+            if status == 204 || status == 304 {
+                return Ok(Some((status, echoed)));
+            }
+            if chunked {
+                loop {
+                    let line = self.line().await?;
+                    let size = String::from_utf8_lossy(&line);
+                    let size = size.split(';').next().unwrap_or("").trim();
+                    let size = u64::from_str_radix(size, 16).map_err(|_| malformed("bad chunk size", &line))?;
+                    if size == 0 {
+                        // Trailers up to the empty line.
+                        while !self.line().await?.is_empty() {}
+                        break;
+                    }
+                    self.skip(size + 2).await?;
+                }
+            } else if let Some(length) = length {
+                self.skip(length).await?;
+            } else {
+                // Delimited by the connection closing.
+                while self.fill().await? {
+                    self.buf.clear();
+                }
+                self.buf.clear();
+            }
+            return Ok(Some((status, echoed)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{self, MockProxyOptions, MockTargetOptions};
+    use tokio::net::TcpStream;
+
+    async fn run(reorder: bool) -> Result<Vec<Response>> {
+        run_through(reorder, None).await
+    }
+
+    // Through a mock proxy's tunnel when `requests_per_tunnel` is set.
+    async fn run_through(reorder: bool, requests_per_tunnel: Option<usize>) -> Result<Vec<Response>> {
+        let (addr, server) = mock::start_target(MockTargetOptions {
+            listen: ([127, 0, 0, 1], 0).into(),
+            status: 200,
+            body_size: 10,
+            delay: None,
+            truncate_at: None,
+            garbage: false,
+            reorder,
+        })
+        .await?;
+        let req = Request::get("/")
+            .header("Host", addr.to_string())
+            .body(Full::new(Bytes::new()))?;
+        let requests: Vec<_> = (1..=4).map(|seq| render_request(&req, seq, seq == 4)).collect();
+        let mut batch = Batch::new(Duration::from_secs(5));
+        let res = match requests_per_tunnel {
+            None => exchange(TcpStream::connect(addr).await?, &requests, &mut batch).await,
+            Some(n) => {
+                let (proxy, proxy_server) = mock::start_proxy(MockProxyOptions {
+                    listen: ([127, 0, 0, 1], 0).into(),
+                    latency: None,
+                    fail_rate: 0.0,
+                    auth: None,
+                    requests_per_tunnel: Some(n),
+                })
+                .await?;
+                let mut stream = TcpStream::connect(proxy).await?;
+                stream
+                    .write_all(format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n\r\n", addr).as_bytes())
+                    .await?;
+                let mut reply = [0u8; 39];
+                stream.read_exact(&mut reply).await?;
+                assert!(reply.starts_with(b"HTTP/1.1 200"));
+                let res = exchange(stream, &requests, &mut batch).await;
+                proxy_server.abort();
+                res
+            }
+        };
+        server.abort();
+        res.map(|()| batch.responses)
+    }
+
+    #[tokio::test]
+    async fn responses_in_order() {
+        let responses = run(false).await.unwrap();
+        assert_eq!(responses.iter().map(|r| r.status).collect::<Vec<_>>(), [200; 4]);
+        assert!(responses.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+    }
+
+    #[tokio::test]
+    async fn swapped_responses_are_protocol_errors() {
+        let e = run(true).await.err().unwrap();
+        let e = e.downcast_ref::<ProtocolError>().unwrap();
+        assert_eq!(e.detail, "pipelined response 1 answered request 2");
+    }
+
+    #[tokio::test]
+    async fn close_after_some_responses_is_a_tunnel_close() {
+        let e = run_through(false, Some(1)).await.err().unwrap();
+        let e = e.downcast_ref::<TunnelClosed>().unwrap();
+        assert_eq!((e.missing, e.total), (2, 4));
+    }
+}
@@ -1,58 +1,361 @@
-use crate::stats::Stats;
+use hyper::http::HeaderMap;
+use std::collections::BTreeMap;
+use std::time::Duration;
 
-fn fmt_ms_w(ms: f64, width: usize) -> String {
-    if ms.is_finite() && ms < 1.0 {
-        let mut us = (ms * 1000.0).round();
-        if us == 0.0 && ms > 0.0 {
-            us = 1.0;
+use crate::cli::{Phase, Units};
+use crate::stats::{Stats, Throughput};
+
+const HEADER_VALUES_SHOWN: usize = 10;
+
+// Every duration in the report goes through here. `Auto` picks the unit
+// after rounding, so 999.996ms shows as 1.00s rather than 1000.00ms.
+pub fn fmt_duration(ms: f64, units: Units) -> String {
+    if !ms.is_finite() {
+        return "-".to_string();
+    }
+    let us = ms * 1000.0;
+    match units {
+        Units::Us => format!("{:.0}µs", us),
+        Units::Ms => format!("{:.2}ms", ms),
+        Units::S => format!("{:.3}s", ms / 1000.0),
+        Units::Auto if us > 0.0 && us < 9.95 => format!("{:.1}µs", us),
+        Units::Auto if us.round() < 1000.0 => format!("{:.0}µs", us),
+        Units::Auto if ms < 999.995 => format!("{:.2}ms", ms),
+        Units::Auto => format!("{:.2}s", ms / 1000.0),
+    }
+}
+
+fn fmt_ms_w(ms: f64, width: usize, units: Units) -> String {
+    format!("{:>width$}", fmt_duration(ms, units), width = width)
+}
+
+pub fn fmt_ms(ms: Option<f64>, units: Units) -> String {
+    fmt_duration(ms.unwrap_or(f64::NAN), units)
+}
+
+// A signed change between two latencies, e.g. "+1.20ms" or "-850µs".
+pub fn fmt_delta(ms: f64, units: Units) -> String {
+    let sign = if ms < 0.0 { '-' } else { '+' };
+    format!("{}{}", sign, fmt_duration(ms.abs(), units))
+}
+
+pub fn fmt_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    // As in fmt_duration, the unit is picked after rounding: 999_999 bytes
+    // is 1.00 MB, not 1000.00 KB.
+    while value >= 999.995 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+// Binary units, as wrk and bombardier print transfer sizes.
+pub fn fmt_bytes_binary(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1023.995 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} B", value)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+fn fmt_ms_compact(ms: Option<f64>) -> String {
+    match ms {
+        Some(v) if v >= 10.0 => format!("{:.0}ms", v),
+        Some(v) => format!("{:.2}ms", v),
+        None => "-".to_string(),
+    }
+}
+
+// Failures by cause; "status" is whatever no other category explains.
+pub fn error_counts(stats: &Stats) -> [(&'static str, usize); 14] {
+    let header_errors: usize = stats.header_mismatches.values().sum();
+    let intercepted: usize = stats.intercepted.values().sum();
+    let categorized = header_errors
+        + intercepted
+        + stats.timeout_errors
+        + stats.conn_errors
+        + stats.tls_errors
+        + stats.ws_rejected
+        + stats.udp_refused
+        + stats.udp_no_reply
+        + stats.port_exhaustion
+        + stats.truncated
+        + stats.protocol_errors
+        + stats.alpn_mismatch
+        + stats.internal_errors;
+    [
+        ("timeout", stats.timeout_errors),
+        ("conn", stats.conn_errors),
+        ("tls", stats.tls_errors),
+        ("ws_rejected", stats.ws_rejected),
+        ("udp_refused", stats.udp_refused),
+        ("udp_no_reply", stats.udp_no_reply),
+        ("port_exhaustion", stats.port_exhaustion),
+        ("truncated", stats.truncated),
+        ("protocol", stats.protocol_errors),
+        ("alpn", stats.alpn_mismatch),
+        ("intercepted", intercepted),
+        ("header", header_errors),
+        ("internal", stats.internal_errors),
+        ("status", stats.fail.saturating_sub(categorized)),
+    ]
+}
+
+// One line for scripts, e.g.
+// `ok=982/1000 (98.2%) p50=84ms p99=310ms rps=118.4 errors: timeout=12 conn=6`
+pub fn summary_line(stats: &Stats) -> String {
+    let mut line = format!(
+        "ok={}/{} ({:.1}%) p50={} p99={} rps={:.1}",
+        stats.success,
+        stats.completed(),
+        stats.success_rate().unwrap_or(0.0),
+        fmt_ms_compact(stats.latency_percentile(0.50)),
+        fmt_ms_compact(stats.latency_percentile(0.99)),
+        stats.rps_avg().unwrap_or(0.0)
+    );
+    let parts: Vec<String> = error_counts(stats)
+        .iter()
+        .filter(|(_, n)| *n > 0)
+        .map(|(name, n)| format!("{}={}", name, n))
+        .collect();
+    if !parts.is_empty() {
+        line.push_str(" errors: ");
+        line.push_str(&parts.join(" "));
+    }
+    if stats.slow_threshold.is_some() {
+        line.push_str(&format!(" slow={}", stats.slow));
+    }
+    line
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Plain,
+    Dim,
+    Green,
+    Yellow,
+    Red,
+}
+
+// Report text is built from styled segments and only turned into ANSI
+// escapes at the very end, so the plain text stays easy to compare.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub text: String,
+    pub style: Style,
+}
+
+pub type Line = Vec<Segment>;
+
+fn seg(text: impl Into<String>, style: Style) -> Segment {
+    Segment {
+        text: text.into(),
+        style,
+    }
+}
+
+fn plain(text: impl Into<String>) -> Line {
+    vec![seg(text, Style::Plain)]
+}
+
+// Colors only for an interactive terminal, and never with NO_COLOR set
+// (https://no-color.org) or --no-color.
+pub fn color_enabled(no_color_flag: bool) -> bool {
+    use std::io::IsTerminal;
+    !no_color_flag
+        && std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty())
+        && std::io::stdout().is_terminal()
+}
+
+pub fn render(line: &[Segment], color: bool) -> String {
+    let mut out = String::new();
+    for s in line {
+        let code = match s.style {
+            Style::Plain => None,
+            Style::Dim => Some("2"),
+            Style::Green => Some("32"),
+            Style::Yellow => Some("33"),
+            Style::Red => Some("31"),
+        };
+        match code {
+            Some(code) if color => {
+                out.push_str(&format!("\x1b[{}m{}\x1b[0m", code, s.text));
+            }
+            _ => out.push_str(&s.text),
         }
-        format!("{:>width$}", format!("{:.0}µs", us), width = width)
-    } else if ms.is_finite() {
-        format!("{:>width$}", format!("{:.2}ms", ms), width = width)
+    }
+    out
+}
+
+pub fn success_style(pct: f64) -> Style {
+    if pct >= 99.0 {
+        Style::Green
+    } else if pct >= 90.0 {
+        Style::Yellow
     } else {
-        format!("{:>width$}", "-", width = width)
+        Style::Red
+    }
+}
+
+fn error_style(count: usize) -> Style {
+    if count > 0 {
+        Style::Red
+    } else {
+        Style::Plain
+    }
+}
+
+fn throughput_lines(title: &str, total_label: &str, t: &Throughput, total_duration: Duration) -> Vec<Line> {
+    let (Some(avg), Some(max)) = (t.avg_mbps(), t.max_mbps()) else {
+        return Vec::new();
+    };
+    vec![
+        plain(""),
+        plain(format!("  {}", title)),
+        plain(format!("    {:<18}{}", format!("{}:", total_label), fmt_bytes(t.bytes))),
+        plain(format!("    Per connection:   {:.2} MB/s avg, {:.2} MB/s max", avg, max)),
+        plain(format!(
+            "    Aggregate:        {:.2} MB/s",
+            t.aggregate_mbps(total_duration).unwrap_or(0.0)
+        )),
+    ]
+}
+
+// The `shown` most common values, then the rest folded into one line.
+fn top_values(title: &str, counts: &BTreeMap<String, usize>, shown: usize) -> Vec<Line> {
+    let mut top: Vec<_> = counts.iter().collect();
+    top.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    let shown = top.len().min(shown);
+    let mut out = vec![plain(title)];
+    for (value, count) in &top[..shown] {
+        out.push(plain(format!("    {} - {}", crate::sub::truncate(value, 60), count)));
+    }
+    if top.len() > shown {
+        let rest: usize = top[shown..].iter().map(|(_, &n)| n).sum();
+        out.push(plain(format!("    {} other value(s) - {}", top.len() - shown, rest)));
     }
+    out
 }
 
-pub fn print_results(stats: &Stats, iterations: usize) {
-    println!("\nStatistics        Avg        Median        Stdev         Max");
+pub fn report_lines(stats: &Stats, iterations: usize, units: Units) -> Vec<Line> {
+    let mut out = vec![
+        plain(""),
+        plain("Statistics        Avg        Median        Stdev         Max"),
+    ];
     let rps = stats.rps_avg().unwrap_or(0.0);
-    println!(
+    out.push(plain(format!(
         "  Reqs/sec   {:>10.2}   {:>8.2}   {:>8.2}   {:>10.2}",
         rps,
         stats.rps_median().unwrap_or(0.0),
         stats.rps_stddev().unwrap_or(0.0),
         stats.rps_max().unwrap_or(0.0)
-    );
-    println!(
+    )));
+    if stats.fail > 0 {
+        out.push(plain(format!(
+            "  Done/sec   {:>10.2}   {:>8.2}   {:>8.2}   {:>10.2}",
+            stats.completions_avg().unwrap_or(0.0),
+            stats.completions_median().unwrap_or(0.0),
+            stats.completions_stddev().unwrap_or(0.0),
+            stats.completions_max().unwrap_or(0.0)
+        )));
+    }
+    out.push(plain(format!(
         "  Latency    {} {} {}   {}",
-        fmt_ms_w(stats.latency_avg().unwrap_or(0.0), 12),
-        fmt_ms_w(stats.latency_median().unwrap_or(0.0), 10),
-        fmt_ms_w(stats.latency_stddev().unwrap_or(0.0), 10),
-        fmt_ms_w(stats.latency_max().map(|v| v as f64).unwrap_or(0.0), 12)
-    );
+        fmt_ms_w(stats.latency_avg().unwrap_or(0.0), 12, units),
+        fmt_ms_w(stats.latency_median().unwrap_or(0.0), 10, units),
+        fmt_ms_w(stats.latency_stddev().unwrap_or(0.0), 10, units),
+        fmt_ms_w(stats.latency_max().unwrap_or(0.0), 12, units)
+    )));
+    if let (Some(avg), Some(max)) = (stats.proxy_connect_avg(), stats.proxy_connect_max()) {
+        out.push(plain(format!(
+            "  Connect    {} {} {}   {}",
+            fmt_ms_w(avg, 12, units),
+            fmt_ms_w(stats.proxy_connect_median().unwrap_or(0.0), 10, units),
+            fmt_ms_w(stats.proxy_connect_stddev().unwrap_or(0.0), 10, units),
+            fmt_ms_w(max, 12, units)
+        )));
+    }
+    if let (Some(avg), Some(max)) = (stats.ws_ping_avg(), stats.ws_ping_max()) {
+        out.push(plain(format!(
+            "  WS ping    {} {:>10} {:>10}   {}",
+            fmt_ms_w(avg, 12, units),
+            "-",
+            "-",
+            fmt_ms_w(max, 12, units)
+        )));
+    }
 
-    println!("\n  Latency Distribution");
-    println!(
-        "     50%  {}",
-        fmt_ms_w(stats.latency_percentile(0.50).unwrap_or(0.0), 10)
-    );
-    println!(
-        "     75%  {}",
-        fmt_ms_w(stats.latency_percentile(0.75).unwrap_or(0.0), 10)
-    );
-    println!(
-        "     90%  {}",
-        fmt_ms_w(stats.latency_percentile(0.90).unwrap_or(0.0), 10)
-    );
-    println!(
-        "     95%  {}",
-        fmt_ms_w(stats.latency_percentile(0.95).unwrap_or(0.0), 10)
-    );
-    println!(
-        "     99%  {}",
-        fmt_ms_w(stats.latency_percentile(0.99).unwrap_or(0.0), 10)
-    );
+    out.extend(throughput_lines("Download", "Total received", &stats.download, stats.total_duration));
+    out.extend(throughput_lines("Upload", "Total sent", &stats.upload, stats.total_duration));
+    if let Some(rate) = stats.transfer_per_sec() {
+        let mut parts = Vec::new();
+        for (t, dir) in [(&stats.download, "received"), (&stats.upload, "sent")] {
+            if let Some(size) = t.avg_size() {
+                parts.push(format!(
+                    "{} {} ({} per request)",
+                    fmt_bytes_binary(t.bytes as f64),
+                    dir,
+                    fmt_bytes_binary(size)
+                ));
+            }
+        }
+        out.push(plain(""));
+        out.push(plain(format!("  Transfer/sec: {}/s, {}", fmt_bytes_binary(rate), parts.join(", "))));
+    }
+
+    out.push(plain(""));
+    out.push(vec![seg("  Latency Distribution", Style::Dim)]);
+    for (label, p) in [("50%", 0.50), ("75%", 0.75), ("90%", 0.90), ("95%", 0.95), ("99%", 0.99)] {
+        out.push(vec![seg(
+            format!(
+                "     {}  {}",
+                label,
+                fmt_ms_w(stats.latency_percentile(p).unwrap_or(0.0), 10, units)
+            ),
+            Style::Dim,
+        )]);
+    }
+
+    if !stats.corrected_us.is_empty() {
+        out.push(vec![seg("  Corrected for coordinated omission", Style::Dim)]);
+        for (label, p) in [("50%", 0.50), ("75%", 0.75), ("90%", 0.90), ("95%", 0.95), ("99%", 0.99)] {
+            out.push(vec![seg(
+                format!(
+                    "     {}  {}",
+                    label,
+                    fmt_ms_w(stats.corrected_percentile(p).unwrap_or(0.0), 10, units)
+                ),
+                Style::Dim,
+            )]);
+        }
+        if let (Some(raw), Some(corrected)) =
+            (stats.latency_percentile(0.99), stats.corrected_percentile(0.99))
+        {
+            if raw > 0.0 && corrected > raw * 1.1 {
+                out.push(vec![seg(
+                    format!(
+                        "  Note: corrected p99 is {:.0}% above measured; requests could not start on schedule",
+                        (corrected / raw - 1.0) * 100.0
+                    ),
+                    Style::Yellow,
+                )]);
+            }
+        }
+    }
 
     let (mut c1, mut c2, mut c3, mut c4, mut c5, mut other) = (0, 0, 0, 0, 0, 0);
     for (&code, &count) in &stats.status_counts {
@@ -65,25 +368,578 @@ pub fn print_results(stats: &Stats, iterations: usize) {
             _ => other += count,
         }
     }
-    println!("  HTTP codes:");
-    println!(
-        "    1xx - {}, 2xx - {}, 3xx - {}, 4xx - {}, 5xx - {}",
-        c1, c2, c3, c4, c5
-    );
+    out.push(plain("  HTTP codes:"));
+    out.push(vec![
+        seg(format!("    1xx - {}, 2xx - {}, 3xx - {}, ", c1, c2, c3), Style::Plain),
+        seg(format!("4xx - {}", c4), error_style(c4)),
+        seg(", ", Style::Plain),
+        seg(format!("5xx - {}", c5), error_style(c5)),
+    ]);
     if other > 0 {
-        println!("    others - {}", other);
+        out.push(plain(format!("    others - {}", other)));
+    }
+    // The rollup hides which codes make up a mix like 200/403/503.
+    if stats.status_counts.len() > 1 {
+        for (code, count, pct) in stats.status_shares() {
+            let reason = hyper::StatusCode::from_u16(code)
+                .ok()
+                .and_then(|s| s.canonical_reason())
+                .unwrap_or("");
+            let style = if code >= 400 { Style::Red } else { Style::Plain };
+            out.push(vec![seg(
+                format!("    {:>3} {:<22} {:>8} {:>7.2}%", code, reason, count, pct),
+                style,
+            )]);
+        }
+    }
+    // Only worth a line when something other than plain HTTP/1.1 answered.
+    if stats.http_versions.keys().any(|v| v != "HTTP/1.1") {
+        let list: Vec<String> = stats
+            .http_versions
+            .iter()
+            .map(|(version, count)| format!("{} - {}", version, count))
+            .collect();
+        out.push(plain(format!("  HTTP versions: {}", list.join(", "))));
+    }
+    if !stats.alpn.is_empty() {
+        let list: Vec<String> = stats
+            .alpn
+            .iter()
+            .map(|(protocol, count)| format!("{} - {}", protocol, count))
+            .collect();
+        out.push(plain(format!("  Negotiated ALPN: {}", list.join(", "))));
+    }
+    if let Some(locations) = stats.locations.as_ref().filter(|l| !l.counts.is_empty()) {
+        out.extend(top_values("  Redirect targets:", &locations.counts, locations.shown));
+    }
+    if !stats.grpc_status_counts.is_empty() {
+        out.push(plain("  gRPC status:"));
+        let mut line = vec![seg("    ", Style::Plain)];
+        for (i, (&code, &count)) in stats.grpc_status_counts.iter().enumerate() {
+            if i > 0 {
+                line.push(seg(", ", Style::Plain));
+            }
+            let style = if code == 0 { Style::Plain } else { Style::Red };
+            line.push(seg(format!("{} - {}", code, count), style));
+        }
+        out.push(line);
+    }
+    // Responders: listed only once some response named itself.
+    for (name, counts) in [("Server", &stats.server_headers), ("Via", &stats.via_headers)] {
+        if counts.keys().any(|v| v != "(absent)") {
+            out.extend(top_values(&format!("  Responders ({}):", name), counts, HEADER_VALUES_SHOWN));
+        }
+    }
+    if let Some(values) = &stats.header_values {
+        out.extend(top_values(
+            &format!("  {} values:", values.name),
+            &values.counts,
+            HEADER_VALUES_SHOWN,
+        ));
+    }
+    if !stats.intercepted.is_empty() {
+        let total: usize = stats.intercepted.values().sum();
+        out.push(plain(format!("  Intercepted by proxy: {}", total)));
+        for (header, count) in &stats.intercepted {
+            out.push(vec![seg(
+                format!("    {} - {}", crate::sub::truncate(header, 60), count),
+                Style::Red,
+            )]);
+        }
+    }
+    if !stats.header_mismatches.is_empty() {
+        let list: Vec<String> = stats
+            .header_mismatches
+            .iter()
+            .map(|(expectation, count)| format!("{} - {}", expectation, count))
+            .collect();
+        out.push(plain("  Header assertions failed:"));
+        out.push(vec![seg(format!("    {}", list.join(", ")), Style::Red)]);
+    }
+    if let Some(r) = &stats.range {
+        out.push(plain("  Range:"));
+        out.push(vec![
+            seg(format!("    206 partial - {}, ", r.partial), Style::Plain),
+            seg(format!("ignored (200 full body) - {}", r.ignored), error_style(r.ignored)),
+            seg(", ", Style::Plain),
+            seg(format!("bad Content-Range - {}", r.invalid), error_style(r.invalid)),
+        ]);
+    }
+    if let Some(positions) = stats.pipeline.as_ref().filter(|p| !p.is_empty()) {
+        out.push(plain("  Pipelined responses by position:"));
+        // Long batches show their first and last five.
+        let n = positions.len();
+        for (i, h) in positions.iter().enumerate() {
+            if n > 10 && i == 5 {
+                out.push(plain("    ..."));
+            }
+            if n > 10 && (5..n - 5).contains(&i) {
+                continue;
+            }
+            out.push(plain(format!(
+                "    #{:<4} p50 {}, p99 {}",
+                i + 1,
+                fmt_ms_w(h.percentile_ms(0.50).unwrap_or(f64::NAN), 0, units),
+                fmt_ms_w(h.percentile_ms(0.99).unwrap_or(f64::NAN), 0, units)
+            )));
+        }
+    }
+    if let Some(r) = &stats.revalidation {
+        let part = |label: &str, n: usize, p50: Option<f64>, p99: Option<f64>| {
+            format!(
+                "{} - {} (p50 {}, p99 {})",
+                label,
+                n,
+                fmt_ms_w(p50.unwrap_or(f64::NAN), 0, units),
+                fmt_ms_w(p99.unwrap_or(f64::NAN), 0, units)
+            )
+        };
+        out.push(plain("  Revalidation:"));
+        out.push(plain(format!(
+            "    {}, {}",
+            part(
+                "304 Not Modified",
+                r.not_modified_us.len(),
+                r.not_modified_percentile(0.50),
+                r.not_modified_percentile(0.99)
+            ),
+            part(
+                "200 full body",
+                r.full_us.len(),
+                r.full_percentile(0.50),
+                r.full_percentile(0.99)
+            )
+        )));
+        if r.not_modified_us.is_empty() && !r.full_us.is_empty() {
+            out.push(vec![seg(
+                "  Note: no 304 responses; the proxy or origin ignores the validators",
+                Style::Yellow,
+            )]);
+        }
+    }
+    if !stats.proxy_replies.is_empty() {
+        let mut replies: Vec<_> = stats.proxy_replies.iter().collect();
+        replies.sort_by(|a, b| b.1.cmp(a.1));
+        let list: Vec<String> = replies
+            .iter()
+            .map(|(label, count)| format!("{}: {}", label, count))
+            .collect();
+        out.push(plain("  Proxy replies:"));
+        out.push(vec![seg(format!("    {}", list.join(", ")), Style::Red)]);
+    }
+    if let (Some(avg), Some(p50), Some(p99)) = (
+        stats.failure_avg(),
+        stats.failure_percentile(0.50),
+        stats.failure_percentile(0.99),
+    ) {
+        out.push(plain(format!(
+            "  Time to failure: avg {}, p50 {}, p99 {}",
+            fmt_duration(avg, units),
+            fmt_duration(p50, units),
+            fmt_duration(p99, units)
+        )));
+    }
+    if !stats.timeouts_by_phase.is_empty() {
+        // In request order rather than alphabetically.
+        let order = ["proxy_connect", "tunnel", "tls", "request", "body"];
+        let mut phases: Vec<_> = stats.timeouts_by_phase.iter().collect();
+        phases.sort_by_key(|(phase, _)| order.iter().position(|p| p == phase).unwrap_or(order.len()));
+        let list: Vec<String> = phases
+            .iter()
+            .map(|(phase, count)| format!("{} - {}", phase, count))
+            .collect();
+        out.push(plain(format!("  Timeouts by phase ({}):", stats.timeout_errors)));
+        out.push(vec![seg(format!("    {}", list.join(", ")), Style::Red)]);
+    }
+    for (label, count) in [
+        ("UDP ASSOCIATE refused", stats.udp_refused),
+        ("UDP datagram not relayed", stats.udp_no_reply),
+        ("WebSocket upgrade rejected", stats.ws_rejected),
+        ("Local ports exhausted", stats.port_exhaustion),
+        ("Truncated bodies", stats.truncated),
+        ("Malformed HTTP responses", stats.protocol_errors),
+        ("ALPN mismatches", stats.alpn_mismatch),
+        ("Internal errors (panics)", stats.internal_errors),
+    ] {
+        if count > 0 {
+            out.push(vec![seg(format!("  {}: {}", label, count), Style::Red)]);
+        }
+    }
+    if let Some(samples) = stats.error_samples.as_ref().filter(|s| !s.categories.is_empty()) {
+        out.push(plain("  Error samples:"));
+        for (category, list) in &samples.categories {
+            for s in list {
+                out.push(vec![seg(format!("    [{}] {} (x{})", category, s.message, s.count), Style::Dim)]);
+            }
+        }
+    }
+    if stats.rate_limited > 0 {
+        out.push(vec![seg(
+            format!("  Rate limited (retried after Retry-After): {}", stats.rate_limited),
+            Style::Yellow,
+        )]);
+    }
+    if stats.tunnel_reconnects > 0 {
+        out.push(vec![seg(
+            format!("  Tunnels reopened mid --pipeline batch: {}", stats.tunnel_reconnects),
+            Style::Yellow,
+        )]);
+    }
+    if stats.port_exhaustion > 0 {
+        out.push(vec![seg(
+            "  Note: the OS ran out of local ports (EADDRNOTAVAIL/EADDRINUSE); lower --concurrency or cap new connections with --max-conn-rate",
+            Style::Yellow,
+        )]);
     }
 
-    println!("\nResults");
-    println!("  Total requests: {}", iterations);
-    println!(
-        "  Success: {} ({:.2}%)  Fail: {}",
-        stats.success,
-        (stats.success as f64) * 100.0 / (iterations as f64),
-        stats.fail
-    );
+    let pct = stats.success_rate().unwrap_or(0.0);
+    out.push(plain(""));
+    out.push(plain("Results"));
+    if stats.completed() == iterations {
+        out.push(plain(format!("  Total requests: {}", iterations)));
+    } else {
+        out.push(plain(format!(
+            "  Requested: {}  Completed: {}",
+            iterations,
+            stats.completed()
+        )));
+    }
+    out.push(vec![
+        seg("  Success: ", Style::Plain),
+        seg(format!("{} ({:.2}%)", stats.success, pct), success_style(pct)),
+        seg("  Fail: ", Style::Plain),
+        seg(stats.fail.to_string(), error_style(stats.fail)),
+    ]);
+    if stats.interrupted {
+        out.push(vec![seg("  Interrupted: only requests finished before Ctrl-C are counted", Style::Yellow)]);
+    }
+    if let Some(in_flight) = &stats.in_flight {
+        out.push(plain(format!(
+            "  Peak in-flight: {} / {} configured",
+            in_flight.peak, in_flight.configured
+        )));
+    }
+    if let Some(threshold) = stats.slow_threshold {
+        let style = if stats.slow > 0 { Style::Yellow } else { Style::Plain };
+        out.push(vec![seg(
+            format!(
+                "  Slow (>{}): {} ({:.1}%)",
+                humantime::format_duration(threshold),
+                stats.slow,
+                stats.slow as f64 * 100.0 / stats.completed().max(1) as f64
+            ),
+            style,
+        )]);
+    }
+    out.push(plain(""));
+    out.push(plain(format!(
+        "Jitter: {} (p99-p50 spread {})",
+        match stats.jitter() {
+            Some(jitter) => fmt_ms_w(jitter, 0, units),
+            // Request order is not kept once compacted (--soak).
+            None if stats.latency_histogram.is_some() => "-".to_string(),
+            None => fmt_ms_w(0.0, 0, units),
+        },
+        fmt_ms_w(stats.latency_spread().unwrap_or(0.0), 0, units)
+    )));
+    if let Some(h) = &stats.harness {
+        out.push(plain(format!(
+            "Harness overhead \u{2248} {} (p50 of {} loopback requests, p99 {})",
+            fmt_duration(h.p50_ms, units),
+            h.requests,
+            fmt_duration(h.p99_ms, units)
+        )));
+    }
+    out
+}
+
+pub fn print_results(stats: &Stats, iterations: usize, color: bool, units: Units) {
+    for line in report_lines(stats, iterations, units) {
+        println!("{}", render(&line, color));
+    }
+}
+
+// --show-headers: the first response to arrive, not necessarily request #1.
+pub fn print_headers(seq: usize, status: u16, headers: &HeaderMap) {
+    println!("Response headers (request #{}, HTTP {}):", seq, status);
+    for (name, value) in headers {
+        println!("  {}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+    }
+    println!();
+}
+
+// Each worker runs its requests back to back over fresh tunnels, so a
+// worker that stands out usually points at a slow route rather than load.
+// --phase: the same percentiles as the latency distribution, for one step.
+pub fn print_phases(stats: &Stats, phases: &[Phase], units: Units) {
+    for phase in phases {
+        let (title, percentile): (_, fn(&Stats, f64) -> Option<f64>) = match phase {
+            Phase::ProxyConnect => ("Proxy connect", Stats::proxy_connect_percentile),
+        };
+        if percentile(stats, 0.5).is_none() {
+            continue;
+        }
+        println!("\n  {} Distribution", title);
+        for (label, p) in [("50%", 0.50), ("75%", 0.75), ("90%", 0.90), ("95%", 0.95), ("99%", 0.99)] {
+            println!("     {}  {}", label, fmt_ms_w(percentile(stats, p).unwrap_or(0.0), 10, units));
+        }
+    }
+}
+
+pub fn print_timeline(stats: &Stats, units: Units) {
+    let Some(timeline) = &stats.timeline else {
+        return;
+    };
     println!(
-        "\nStdDev: {}",
-        fmt_ms_w(stats.latency_stddev().unwrap_or(0.0), 0)
+        "\n{:>5}  {:>6}  {:>6}  {:>7}  {:>10}  {:>9}",
+        "Sec", "OK", "Fail", "Timeout", "p50", "In-flight"
     );
+    for (sec, s) in timeline.iter().enumerate() {
+        let p50 = s.p50().map(|v| fmt_ms_w(v, 10, units)).unwrap_or_else(|| format!("{:>10}", "-"));
+        let in_flight = stats
+            .in_flight
+            .as_ref()
+            .and_then(|f| f.per_sec.get(&(sec as u64)))
+            .map(|n| format!("{:>9.1}", n))
+            .unwrap_or_else(|| format!("{:>9}", "-"));
+        println!("{:>5}  {:>6}  {:>6}  {:>7}  {}  {}", sec, s.ok, s.fail, s.timeout, p50, in_flight);
+    }
+}
+
+pub fn print_slowest(stats: &Stats, units: Units) {
+    let Some(slowest) = &stats.slowest else {
+        return;
+    };
+    println!("\nSlowest requests:");
+    println!("{:>8}  {:>10}  {:>6}  {:<24}  Phase", "Seq", "Total", "Status", "Started");
+    for r in slowest.sorted() {
+        println!(
+            "{:>8}  {}  {:>6}  {:<24}  {}",
+            r.seq,
+            fmt_ms_w(r.us as f64 / 1000.0, 10, units),
+            r.status.map(|c| c.to_string()).unwrap_or_else(|| "error".into()),
+            r.started_at
+                .map(|t| humantime::format_rfc3339_millis(t).to_string())
+                .unwrap_or_else(|| "-".into()),
+            r.phase.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+pub fn print_ips(stats: &Stats, units: Units) {
+    let Some(ips) = stats.per_ip.as_ref().filter(|ips| !ips.is_empty()) else {
+        return;
+    };
+    println!("\n{:>39}  {:>8}  {:>8}  {:>10}  {:>10}", "Target IP", "Requests", "Success", "p50", "p99");
+    for (ip, w) in ips {
+        println!(
+            "{:>39}  {:>8}  {:>7.2}%  {}  {}",
+            ip,
+            w.requests,
+            (w.requests - w.errors) as f64 * 100.0 / w.requests.max(1) as f64,
+            fmt_ms_w(w.latency_percentile(0.50).unwrap_or(f64::NAN), 10, units),
+            fmt_ms_w(w.latency_percentile(0.99).unwrap_or(f64::NAN), 10, units)
+        );
+    }
+}
+
+pub fn print_workers(stats: &Stats, units: Units) {
+    let Some(workers) = &stats.per_worker else {
+        return;
+    };
+    let mut rows: Vec<_> = workers.iter().collect();
+    rows.sort_by(|a, b| {
+        let p99 = |w: &crate::stats::WorkerStats| w.latency_percentile(0.99).unwrap_or(f64::INFINITY);
+        p99(b.1).total_cmp(&p99(a.1)).then(a.0.cmp(b.0))
+    });
+    println!("\n{:>6}  {:>8}  {:>10}  {:>10}  {:>6}", "Worker", "Requests", "p50", "p99", "Errors");
+    for (id, w) in rows {
+        println!(
+            "{:>6}  {:>8}  {}  {}  {:>6}",
+            id,
+            w.requests,
+            fmt_ms_w(w.latency_percentile(0.50).unwrap_or(f64::NAN), 10, units),
+            fmt_ms_w(w.latency_percentile(0.99).unwrap_or(f64::NAN), 10, units),
+            w.errors
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(v: impl IntoIterator<Item = u64>) -> Vec<Duration> {
+        v.into_iter().map(Duration::from_millis).collect()
+    }
+
+    #[test]
+    fn summary_line_format() {
+        assert_eq!(summary_line(&Stats::default()), "ok=0/0 (0.0%) p50=- p99=- rps=0.0");
+
+        let stats = Stats::builder()
+            .latencies(&ms(1..=100))
+            .success(98)
+            .fail(20)
+            .timeout_errors(12)
+            .conn_errors(6)
+            .total_duration(Duration::from_secs(1))
+            .build();
+        assert_eq!(
+            summary_line(&stats),
+            "ok=98/118 (83.1%) p50=50ms p99=99ms rps=98.0 errors: timeout=12 conn=6 status=2"
+        );
+
+        // Under 10ms keeps two decimals; --slow-threshold adds the slow count.
+        let mut stats = Stats::builder()
+            .latencies(&[Duration::from_micros(1_500), Duration::from_micros(9_996)])
+            .success(2)
+            .slow(1)
+            .total_duration(Duration::from_secs(4))
+            .build();
+        stats.slow_threshold = Some(Duration::from_millis(5));
+        assert_eq!(summary_line(&stats), "ok=2/2 (100.0%) p50=1.50ms p99=10.00ms rps=0.5 slow=1");
+    }
+
+    fn mixed_run() -> Stats {
+        Stats::builder()
+            .latencies(&ms(1..=100))
+            .success(98)
+            .fail(20)
+            .timeout_errors(12)
+            .conn_errors(6)
+            .status(200, 98)
+            .status(503, 2)
+            .total_duration(Duration::from_secs(1))
+            .build()
+    }
+
+    #[test]
+    fn report_text_without_color() {
+        let text: Vec<String> = report_lines(&mixed_run(), 118, Units::Ms)
+            .iter()
+            .map(|line| render(line, false))
+            .collect();
+        assert!(text.iter().all(|line| !line.contains('\x1b')));
+        for expected in [
+            "  Success: 98 (83.05%)  Fail: 20",
+            "  Latency Distribution",
+            "     99%     99.00ms",
+            "    1xx - 0, 2xx - 98, 3xx - 0, 4xx - 0, 5xx - 2",
+        ] {
+            assert!(text.iter().any(|line| line == expected), "{:?} not in {:#?}", expected, text);
+        }
+    }
+
+    #[test]
+    fn report_colors() {
+        let lines = report_lines(&mixed_run(), 118, Units::Ms);
+        let colored: Vec<String> = lines.iter().map(|line| render(line, true)).collect();
+        assert!(colored.contains(&"  Success: \x1b[31m98 (83.05%)\x1b[0m  Fail: \x1b[31m20\x1b[0m".to_string()));
+        assert!(colored.contains(&"\x1b[2m  Latency Distribution\x1b[0m".to_string()));
+        assert!(colored.contains(&"    1xx - 0, 2xx - 98, 3xx - 0, 4xx - 0, \x1b[31m5xx - 2\x1b[0m".to_string()));
+        // Styles never change the text itself.
+        for (line, colored) in lines.iter().zip(&colored) {
+            let plain = render(line, false);
+            let stripped = ["\x1b[0m", "\x1b[2m", "\x1b[31m", "\x1b[32m", "\x1b[33m"]
+                .iter()
+                .fold(colored.clone(), |s, code| s.replace(code, ""));
+            assert_eq!(stripped, plain);
+        }
+
+        assert_eq!([100.0, 99.0, 98.9, 90.0, 89.9].map(success_style), [
+            Style::Green,
+            Style::Green,
+            Style::Yellow,
+            Style::Yellow,
+            Style::Red
+        ]);
+        assert_eq!((error_style(0), error_style(1)), (Style::Plain, Style::Red));
+        assert!(!color_enabled(true));
+    }
+
+    // The "HTTP codes:" block of the report, uncolored.
+    fn status_block(stats: &Stats) -> String {
+        let text: Vec<String> = report_lines(stats, stats.completed(), Units::Ms)
+            .iter()
+            .map(|line| render(line, false))
+            .collect();
+        let start = text.iter().position(|line| line == "  HTTP codes:").unwrap();
+        let len = text[start + 1..].iter().take_while(|line| line.starts_with("    ")).count();
+        text[start..=start + len].join("\n")
+    }
+
+    #[test]
+    fn status_table_snapshot() {
+        let stats = Stats::builder()
+            .success(600)
+            .fail(401)
+            .status(200, 600)
+            .status(403, 250)
+            .status(503, 150)
+            .status(599, 1)
+            .build();
+        assert_eq!(
+            status_block(&stats),
+            "  HTTP codes:
+    1xx - 0, 2xx - 600, 3xx - 0, 4xx - 250, 5xx - 151
+    200 OK                          600   59.94%
+    403 Forbidden                   250   24.98%
+    503 Service Unavailable         150   14.99%
+    599                               1    0.10%"
+        );
+        let colored: Vec<String> = report_lines(&stats, 1001, Units::Ms).iter().map(|l| render(l, true)).collect();
+        assert!(colored.contains(&"\x1b[31m    403 Forbidden                   250   24.98%\x1b[0m".to_string()));
+        assert!(colored.contains(&"    200 OK                          600   59.94%".to_string()));
+
+        // A single code needs no breakdown.
+        let stats = Stats::builder().success(10).status(200, 10).build();
+        assert_eq!(
+            status_block(&stats),
+            "  HTTP codes:\n    1xx - 0, 2xx - 10, 3xx - 0, 4xx - 0, 5xx - 0"
+        );
+    }
+
+    #[test]
+    fn duration_units_across_boundaries() {
+        for (ms, text) in [
+            (0.0, "0µs"),
+            (0.001, "1.0µs"),
+            (0.0099, "9.9µs"),
+            (0.00995, "10µs"),
+            (0.0124, "12µs"),
+            (0.9994, "999µs"),
+            (0.9995, "1.00ms"),
+            (84.126, "84.13ms"),
+            (999.994, "999.99ms"),
+            (999.995, "1.00s"),
+            (5432.1, "5.43s"),
+            (125_000.0, "125.00s"),
+            (f64::NAN, "-"),
+            (f64::INFINITY, "-"),
+        ] {
+            assert_eq!(fmt_duration(ms, Units::Auto), text, "{}ms", ms);
+        }
+        for (units, text) in [(Units::Us, "1500µs"), (Units::Ms, "1.50ms"), (Units::S, "0.002s")] {
+            assert_eq!(fmt_duration(1.5, units), text);
+        }
+        assert_eq!(fmt_delta(1.2, Units::Ms), "+1.20ms");
+        assert_eq!(fmt_delta(-0.85, Units::Auto), "-850µs");
+        assert_eq!(fmt_delta(0.0, Units::Us), "+0µs");
+        assert_eq!(fmt_duration(5432.1, Units::Ms), "5432.10ms");
+        assert_eq!(fmt_duration(0.0004, Units::Ms), "0.00ms");
+        assert_eq!(fmt_ms(None, Units::Auto), "-");
+        assert_eq!(fmt_ms_w(0.25, 8, Units::Auto), "   250µs");
+    }
+
+    #[test]
+    fn byte_units_across_boundaries() {
+        for (bytes, text) in [(0, "0 B"), (999, "999 B"), (1000, "1.00 KB"), (999_994, "999.99 KB"), (999_995, "1.00 MB")] {
+            assert_eq!(fmt_bytes(bytes), text);
+        }
+        assert_eq!(fmt_bytes(u64::MAX), "18446744.07 TB");
+        for (bytes, text) in [(1023.0, "1023 B"), (1024.0, "1.00 KiB"), (1536.0, "1.50 KiB"), (1_048_575.0, "1.00 MiB")] {
+            assert_eq!(fmt_bytes_binary(bytes), text);
+        }
+    }
 }
@@ -0,0 +1,79 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::Poll;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Bytes of the response kept for reporting a malformed one.
+pub const HEAD_BYTES: usize = 64;
+
+// A stream that remembers the first bytes read from it, so that a response
+// hyper refuses to parse can still be shown.
+pub struct Recorder<S> {
+    inner: S,
+    head: Arc<Mutex<Vec<u8>>>,
+}
+
+impl<S> Recorder<S> {
+    pub fn new(inner: S) -> (Self, Arc<Mutex<Vec<u8>>>) {
+        let head = Arc::new(Mutex::new(Vec::new()));
+        (
+            Self {
+                inner,
+                head: head.clone(),
+            },
+            head,
+        )
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Recorder<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let before = buf.filled().len();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            let mut head = this.head.lock().unwrap();
+            if head.len() < HEAD_BYTES {
+                let new = &buf.filled()[before..];
+                let n = new.len().min(HEAD_BYTES - head.len());
+                head.extend_from_slice(&new[..n]);
+            }
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorder<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+}
@@ -0,0 +1,210 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::cli::Args;
+use crate::stats::{rfc3339, Stats};
+
+// Bumped whenever a field is renamed, removed or changes meaning. Adding a
+// field does not need a bump: missing fields load as their defaults.
+pub const SCHEMA_VERSION: u32 = 2;
+
+// A saved run (--save-report): the raw Stats plus enough context to tell
+// runs apart and to print the report again later.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub schema_version: u32,
+    pub tool_version: String,
+    pub proxy: String,
+    pub url: String,
+    pub mode: String,
+    pub iterations: usize,
+    pub concurrency: usize,
+    #[serde(default)]
+    pub run: RunInfo,
+    pub stats: Stats,
+}
+
+// When and where a run happened, so a report can be lined up with proxy
+// and target logs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunInfo {
+    #[serde(with = "rfc3339")]
+    pub started_at: Option<SystemTime>,
+    #[serde(with = "rfc3339")]
+    pub finished_at: Option<SystemTime>,
+    pub hostname: Option<String>,
+    // Flags as resolved from the command line, environment and --config,
+    // with credentials masked.
+    pub config: toml::Table,
+}
+
+impl RunInfo {
+    pub fn new(args: &Args, stats: &Stats) -> Self {
+        Self {
+            started_at: stats.started_at,
+            finished_at: stats
+                .started_at
+                .map(|t| t + stats.total_duration),
+            hostname: hostname(),
+            config: args.effective_config.clone(),
+        }
+    }
+
+    // `Run 2024-05-02T10:31:04Z → 10:31:34Z on host buildbox, xray-tester 0.2.1`
+    pub fn line(&self, tool_version: &str) -> String {
+        let mut line = String::from("Run");
+        if let Some(start) = self.started_at {
+            let start = humantime::format_rfc3339_seconds(start).to_string();
+            line.push_str(&format!(" {}", start));
+            if let Some(end) = self.finished_at {
+                let end = humantime::format_rfc3339_seconds(end).to_string();
+                // Same day: only the time.
+                let end = match end.split_once('T') {
+                    Some((day, time)) if start.starts_with(day) => time.to_string(),
+                    _ => end,
+                };
+                line.push_str(&format!(" \u{2192} {}", end));
+            }
+        }
+        if let Some(host) = &self.hostname {
+            line.push_str(&format!(" on host {}", host));
+        }
+        line.push_str(&format!(", xray-tester {}", tool_version));
+        line
+    }
+}
+
+fn hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .find_map(|path| std::fs::read_to_string(path).ok())
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+impl Report {
+    pub fn new(args: &Args, proxy: &str, url: &str, stats: Stats) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            proxy: proxy.to_string(),
+            url: url.to_string(),
+            mode: clap::ValueEnum::to_possible_value(&args.mode)
+                .map(|v| v.get_name().to_string())
+                .unwrap_or_default(),
+            iterations: args.iterations,
+            concurrency: args.concurrency,
+            run: RunInfo::new(args, &stats),
+            stats,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json + "\n").with_context(|| format!("writing {}", path.display()))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        let mut value: serde_json::Value =
+            serde_json::from_str(&text).with_context(|| format!("parsing {}", path.display()))?;
+        let mut version = value
+            .get("schema_version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| anyhow!("{} is not an xray-tester report (no schema_version)", path.display()))?;
+        // Older schemas are upgraded here, one version at a time.
+        if version == 1 {
+            // 2: stats.total_duration_ms became total_duration_us.
+            if let Some(stats) = value.get_mut("stats").and_then(|s| s.as_object_mut()) {
+                if let Some(ms) = stats.remove("total_duration_ms").and_then(|v| v.as_u64()) {
+                    stats.insert("total_duration_us".into(), (ms * 1000).into());
+                }
+            }
+            version = 2;
+            value["schema_version"] = version.into();
+        }
+        if version != SCHEMA_VERSION as u64 {
+            return Err(anyhow!(
+                "{} uses report schema {}, this build reads schema {}",
+                path.display(),
+                version,
+                SCHEMA_VERSION
+            ));
+        }
+        serde_json::from_value(value).with_context(|| format!("reading report {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("xray-tester-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn report_round_trips() {
+        let args = Args::try_parse_from(["xray-tester", "-p", "direct://", "-u", "http://127.0.0.1:8080/", "-n", "3"])
+            .unwrap();
+        let mut stats = Stats::builder()
+            .latencies(&[Duration::from_millis(1), Duration::from_millis(2), Duration::from_millis(30)])
+            .status(200, 3)
+            .success(3)
+            .total_duration(Duration::from_micros(1_234_567))
+            .build();
+        stats.started_at = Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1_714_645_864));
+        stats.slow_threshold = Some(Duration::from_millis(250));
+        let report = Report::new(&args, "direct://", "http://127.0.0.1:8080/", stats);
+        let path = scratch("round-trip.json");
+        report.save(&path).unwrap();
+        let loaded = Report::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.stats.total_duration, Duration::from_micros(1_234_567));
+        assert_eq!(loaded.stats.slow_threshold, Some(Duration::from_millis(250)));
+        assert_eq!(loaded.run.finished_at, report.run.finished_at);
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&report).unwrap());
+    }
+
+    #[test]
+    fn loads_checked_in_report() {
+        let report = Report::load(&fixture("report-v2.json")).unwrap();
+        assert_eq!((report.iterations, report.concurrency), (4, 2));
+        assert_eq!(report.stats.success, 4);
+        assert_eq!(report.stats.total_duration, Duration::from_micros(3218));
+        assert_eq!(report.stats.status_counts.get(&200), Some(&4));
+        assert_eq!(report.run.hostname.as_deref(), Some("buildbox"));
+    }
+
+    #[test]
+    fn upgrades_schema_1() {
+        let report = Report::load(&fixture("report-v1.json")).unwrap();
+        assert_eq!(report.schema_version, SCHEMA_VERSION);
+        assert_eq!(report.stats.total_duration, Duration::from_millis(3));
+    }
+
+    #[test]
+    fn rejects_other_schema_versions() {
+        let path = scratch("schema-99.json");
+        let text = std::fs::read_to_string(fixture("report-v2.json")).unwrap();
+        std::fs::write(&path, text.replacen("\"schema_version\": 2", "\"schema_version\": 99", 1)).unwrap();
+        let err = Report::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("{} uses report schema 99, this build reads schema 2", path.display())
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::task::JoinError;
+
+use crate::request::{Attempt, RespMeta};
+use crate::stats::Stats;
+
+// One line per request, e.g.
+// 2025-08-12T00:12:05.123Z seq=17 result=ok status=200 connect=0.41ms tunnel=1.20ms tls=18.02ms ttfb=40.11ms total=60.02ms
+pub struct RequestLog {
+    out: BufWriter<File>,
+    // With rotation: the configured path and the hour now written to.
+    rotation: Option<(PathBuf, String)>,
+}
+
+fn ms(d: Duration) -> String {
+    format!("{:.2}ms", d.as_secs_f64() * 1000.0)
+}
+
+// The file is truncated once per invocation (see `bench_options`); runs
+// append so that every node of a `sub`/`sweep` ends up in the same log.
+pub fn create(path: &Path) -> Result<()> {
+    File::create(path).with_context(|| format!("creating log file {}", path.display()))?;
+    Ok(())
+}
+
+// `requests.log` -> `requests.2025-08-12T00.log` for the given UTC hour.
+pub fn hourly_path(path: &Path, hour: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, hour, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, hour),
+    };
+    path.with_file_name(name)
+}
+
+fn utc_hour(t: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(t).to_string()[..13].to_string()
+}
+
+fn open_append(path: &Path) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("opening log file {}", path.display()))?;
+    Ok(BufWriter::new(file))
+}
+
+impl RequestLog {
+    // With `rotate`, lines go to `hourly_path` files by the hour the
+    // request started in.
+    pub fn open(path: &Path, rotate: bool) -> Result<Self> {
+        if !rotate {
+            return Ok(Self {
+                out: open_append(path)?,
+                rotation: None,
+            });
+        }
+        let hour = utc_hour(SystemTime::now());
+        Ok(Self {
+            out: open_append(&hourly_path(path, &hour))?,
+            rotation: Some((path.to_path_buf(), hour)),
+        })
+    }
+
+    fn rotate(&mut self, at: SystemTime) {
+        let Some((path, current)) = self.rotation.as_mut() else {
+            return;
+        };
+        let hour = utc_hour(at);
+        if hour <= *current {
+            return;
+        }
+        match open_append(&hourly_path(path, &hour)) {
+            Ok(out) => {
+                self.flush();
+                self.out = out;
+                if let Some((_, current)) = self.rotation.as_mut() {
+                    *current = hour;
+                }
+            }
+            Err(e) => tracing::error!("rotating log file failed: {:#}", e),
+        }
+    }
+
+    pub fn record(&mut self, attempt: &Attempt, res: &Result<RespMeta>) {
+        self.rotate(attempt.started_at);
+        let mut line = format!(
+            "{} seq={}",
+            humantime::format_rfc3339_millis(attempt.started_at),
+            attempt.seq
+        );
+        if let Some(path) = &attempt.path {
+            let _ = write!(line, " path={}", path);
+        }
+        if let Some(ip) = attempt.target_ip {
+            let _ = write!(line, " target_ip={}", ip);
+        }
+        match res {
+            Ok(meta) => {
+                let _ = write!(line, " result={}", if meta.success { "ok" } else { "fail" });
+                if let Some(code) = meta.status {
+                    let _ = write!(line, " status={}", code);
+                }
+                if let Some(version) = meta.version {
+                    let _ = write!(line, " version={:?}", version);
+                }
+                if let Some(code) = meta.grpc_status {
+                    let _ = write!(line, " grpc_status={}", code);
+                }
+            }
+            Err(e) => {
+                let _ = write!(line, " result=error error={:?}", format!("{:#}", e));
+            }
+        }
+        let phases = &attempt.phases;
+        for (name, d) in [
+            ("connect", phases.connect),
+            ("proxy_tls", phases.proxy_tls),
+            ("tunnel", phases.tunnel),
+            ("tls", phases.tls),
+            ("continue", phases.continue_wait),
+        ] {
+            if let Some(d) = d {
+                let _ = write!(line, " {}={}", name, ms(d));
+            }
+        }
+        if let Some(alpn) = &phases.alpn {
+            let _ = write!(line, " alpn={}", alpn);
+        }
+        if let Ok(meta) = res {
+            if let Some(d) = meta.dur {
+                let _ = write!(line, " ttfb={}", ms(d));
+            }
+            if let Some(d) = meta.ws_ping {
+                let _ = write!(line, " ws_ping={}", ms(d));
+            }
+            if let Some((bytes, _)) = meta.transfer {
+                let _ = write!(line, " bytes={}", bytes);
+            }
+        }
+        let _ = write!(line, " total={}", ms(attempt.total));
+        self.write_line(&line);
+    }
+
+    pub fn record_join_error(&mut self, e: &JoinError) {
+        let line = format!(
+            "{} result=error error={:?}",
+            humantime::format_rfc3339_millis(SystemTime::now()),
+            e.to_string()
+        );
+        self.write_line(&line);
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if let Err(e) = writeln!(self.out, "{}", line) {
+            tracing::error!("writing log file failed: {}", e);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(e) = self.out.flush() {
+            tracing::error!("flushing log file failed: {}", e);
+        }
+    }
+}
+
+// Raw latencies of successful requests (and of all parsed responses with
+// --record-all-latencies) ordered by sequence number. With
+// --sample-latencies only the reservoir is written, and a comment line
+// says so.
+pub fn write_latency_csv(path: &Path, stats: &Stats) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+    let mut out = BufWriter::new(file);
+    let mut rows = match &stats.reservoir {
+        Some(r) => {
+            writeln!(
+                out,
+                "# sample of {} out of {} latencies ({:.4}%)",
+                r.samples().len(),
+                r.seen,
+                r.samples().len() as f64 * 100.0 / r.seen.max(1) as f64
+            )?;
+            r.samples()
+        }
+        None => stats.latencies_by_seq.clone(),
+    };
+    rows.sort_unstable_by_key(|&(seq, _)| seq);
+    match stats.slow_threshold {
+        // With --slow-threshold a third column flags the slow rows.
+        Some(threshold) => {
+            writeln!(out, "seq,latency_ms,slow")?;
+            for (seq, us) in rows {
+                let slow = us > threshold.as_micros();
+                writeln!(out, "{},{:.3},{}", seq, us as f64 / 1000.0, slow as u8)?;
+            }
+        }
+        None => {
+            writeln!(out, "seq,latency_ms")?;
+            for (seq, us) in rows {
+                writeln!(out, "{},{:.3}", seq, us as f64 / 1000.0)?;
+            }
+        }
+    }
+    out.flush()
+        .with_context(|| format!("writing {}", path.display()))
+}
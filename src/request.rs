@@ -1,29 +1,58 @@
 use anyhow::{anyhow, Context, Result};
 use hyper::client::conn;
-use hyper::Request;
-use hyper::http::Uri;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::{Method, Request};
+use hyper::http::{HeaderMap, Uri, Version};
 use bytes::Bytes;
-use http_body_util::Empty;
-use hyper_util::rt::TokioIo;
+use http_body_util::{BodyExt, Full};
+use hyper_util::rt::{TokioExecutor, TokioIo};
 use native_tls::TlsConnector as NativeTlsConnector;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::task::Poll;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, SystemTime};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
-use tokio::sync::Semaphore;
-use tokio_native_tls::TlsConnector as TokioTlsConnector;
-use url::Url;
-use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+use socket2::{SockRef, TcpKeepalive};
+use tokio_native_tls::{TlsConnector as TokioTlsConnector, TlsStream};
+use url::{Position, Url};
+use futures::stream::{self, StreamExt};
+use tracing::{debug, trace, Instrument};
+use percent_encoding::percent_decode_str;
+use base64::Engine;
+use std::net::{IpAddr, SocketAddr};
 
-use crate::cli::SuccessMatcher;
-use crate::stats::Stats;
+use crate::cli::{BenchMode, ByteRanges, ConnectTo, HeaderExpectation, SuccessMatcher, UploadMethod};
+use crate::dump::{self, FailureDumps};
+use crate::headers::CustomHeader;
+use crate::pipeline;
+use crate::prefixed::Prefixed;
+use crate::recorder::{Recorder, HEAD_BYTES};
+use crate::stream::ProxyStream;
+use crate::reqlog::RequestLog;
+use crate::stats::{mix64, HeaderValues, InFlight, Locations, ErrorSamples, Reservoir, Slowest, Stats};
+use crate::template::PathTemplate;
+use crate::udp;
+use crate::ws::{self, Upgrade};
 
 pub const USER_AGENT: &str = "xray-tester/0.1";
 
+// Non-ASCII hostnames go on the wire as punycode. `Url` already converts
+// the target URL's host; this covers hosts given on their own.
+pub fn ascii_host(host: &str) -> Result<String> {
+    if host.is_ascii() {
+        return Ok(host.to_string());
+    }
+    idna::domain_to_ascii(host).map_err(|_| anyhow!("invalid hostname: {}", host))
+}
+
 pub fn parse_url_target(url_str: &str) -> Result<Target> {
     let url = Url::parse(url_str).context("invalid target URL")?;
     let scheme = url.scheme().to_string();
-    if scheme != "http" && scheme != "https" {
+    if !matches!(scheme.as_str(), "http" | "https" | "ws" | "wss") {
         return Err(anyhow!("unsupported URL scheme: {}", scheme));
     }
     let host = url
@@ -32,8 +61,8 @@ pub fn parse_url_target(url_str: &str) -> Result<Target> {
         .to_string();
     let port = match (url.port(), scheme.as_str()) {
         (Some(p), _) => p,
-        (None, "http") => 80,
-        (None, "https") => 443,
+        (None, "http" | "ws") => 80,
+        (None, "https" | "wss") => 443,
         _ => 0,
     };
     let path = if url.path().is_empty() {
@@ -41,7 +70,8 @@ pub fn parse_url_target(url_str: &str) -> Result<Target> {
     } else {
         url.path().to_string()
     };
-    let host_header = if (scheme == "http" && port == 80) || (scheme == "https" && port == 443) {
+    let default_port = if matches!(scheme.as_str(), "https" | "wss") { 443 } else { 80 };
+    let host_header = if port == default_port {
         host.clone()
     } else {
         format!("{}:{}", host, port)
@@ -64,254 +94,2819 @@ pub struct Target {
     pub host_header: String,
 }
 
+impl Target {
+    pub fn is_tls(&self) -> bool {
+        matches!(self.scheme.as_str(), "https" | "wss")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RespMeta {
     pub success: bool,
     pub dur: Option<Duration>,
     pub status: Option<u16>,
+    pub grpc_status: Option<u32>,
+    pub ws_ping: Option<Duration>,
+    pub transfer: Option<(u64, Duration)>,
+    pub range: Option<RangeOutcome>,
+    // The first --expect-header the response failed, if any.
+    pub header_mismatch: Option<String>,
+    // The header that marked the response as the proxy's own page.
+    pub intercepted: Option<String>,
+    // Value of the --header-stats header.
+    pub header_value: Option<String>,
+    // Server and Via of the response, "(absent)" when missing; `None` when
+    // no headers were read.
+    pub responders: Option<(String, String)>,
+    // Kept with --show-headers.
+    pub headers: Option<HeaderMap>,
+    // Location of a 3xx response.
+    pub location: Option<String>,
+    // HTTP version of the response.
+    pub version: Option<Version>,
+    // --expect-continue: time until 100 Continue (or the window closed).
+    pub continue_wait: Option<Duration>,
+    // --respect-retry-after: how long a 429/503 asked us to wait.
+    pub retry_after: Option<Duration>,
+    // The body ended short of its Content-Length or the connection broke
+    // while it was being read.
+    pub truncated: bool,
+    // --pipeline: time to each response of the batch, in request order.
+    pub pipeline: Option<Vec<Duration>>,
+    // Head and start of the body of a failed response, for --dump-failures.
+    pub failure_dump: Option<Vec<u8>>,
     pub finished: Instant,
 }
 
+// How a response answered --range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeOutcome {
+    Partial,
+    // 200 with the whole body.
+    Ignored,
+    // 206 whose Content-Range (or body length) does not match the request.
+    Invalid,
+}
+
+impl RespMeta {
+    pub fn new(success: bool, dur: Duration, status: u16) -> Self {
+        Self {
+            success,
+            dur: Some(dur),
+            status: Some(status),
+            grpc_status: None,
+            ws_ping: None,
+            transfer: None,
+            range: None,
+            header_mismatch: None,
+            intercepted: None,
+            header_value: None,
+            responders: None,
+            headers: None,
+            location: None,
+            version: None,
+            continue_wait: None,
+            retry_after: None,
+            truncated: false,
+            pipeline: None,
+            failure_dump: None,
+            finished: Instant::now(),
+        }
+    }
+}
+
+// Durations of the steps before the request itself; a step that was never
+// reached (or does not apply) stays `None`.
+#[derive(Debug, Clone, Default)]
+pub struct Phases {
+    pub connect: Option<Duration>,
+    pub tunnel: Option<Duration>,
+    pub tls: Option<Duration>,
+    // TLS to an https:// proxy, between connect and tunnel.
+    pub proxy_tls: Option<Duration>,
+    // Waiting for 100 Continue before sending an upload body.
+    pub continue_wait: Option<Duration>,
+    // Protocol agreed on in the target TLS handshake when ALPN was offered,
+    // "none" if none was.
+    pub alpn: Option<String>,
+    // --pipeline: the proxy closed the tunnel mid-batch and the rest of the
+    // batch went over a new one.
+    pub reconnected: bool,
+}
+
+impl Phases {
+    // The longest step of a request, counting `response` (request sent to
+    // response) as one; `None` when nothing was timed.
+    pub fn dominant(&self, response: Option<Duration>) -> Option<&'static str> {
+        [
+            ("connect", self.connect),
+            ("proxy_tls", self.proxy_tls),
+            ("tunnel", self.tunnel),
+            ("tls", self.tls),
+            ("continue", self.continue_wait),
+            ("response", response),
+        ]
+        .into_iter()
+        .filter_map(|(name, d)| Some((name, d?)))
+        .max_by_key(|&(_, d)| d)
+        .map(|(name, _)| name)
+    }
+}
+
+// Bookkeeping around one request, independent of how it ended.
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    pub seq: usize,
+    // Slot among the `concurrency` requests in flight; a slot is reused by
+    // the next request once its current one finishes.
+    pub worker: usize,
+    pub started_at: SystemTime,
+    // How late the request started relative to its --rate schedule.
+    pub send_delay: Option<Duration>,
+    pub total: Duration,
+    pub phases: Phases,
+    // Request path when it differs per request (--cache-bust).
+    pub path: Option<String>,
+    // Address CONNECTed to with --resolve-target-locally.
+    pub target_ip: Option<IpAddr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    pub target: Arc<Target>,
+    pub success_matcher: Arc<SuccessMatcher>,
+    pub iterations: usize,
+    pub concurrency: usize,
+    pub timeout: Duration,
+    pub insecure: bool,
+    pub connect_to: Option<ConnectTo>,
+    // --resolve-target-locally: what the target host resolved to here, in
+    // the order requests take them (see `ip_schedule`); they CONNECT to the
+    // IP instead of the name.
+    pub target_ips: Vec<IpAddr>,
+    pub mode: BenchMode,
+    pub ws_ping: bool,
+    pub grpc_service: String,
+    pub idle_timeout: Duration,
+    pub min_bytes: Option<u64>,
+    pub max_body_bytes: Option<u64>,
+    pub upload_size: u64,
+    pub upload_method: UploadMethod,
+    // --form / --json-body: Content-Type and payload of a POST sent instead
+    // of the GET.
+    pub body: Option<(&'static str, Bytes)>,
+    // --http1.0: requests go out as HTTP/1.0.
+    pub http10: bool,
+    pub headers: Vec<CustomHeader>,
+    // --expect-continue: how long an upload waits for 100 Continue.
+    pub expect_continue: Option<Duration>,
+    // --respect-retry-after: the longest Retry-After that is honoured.
+    pub retry_after_max: Option<Duration>,
+    pub dns_name: Option<String>,
+    pub dns_server: String,
+    pub log_file: Option<PathBuf>,
+    pub record_all_latencies: bool,
+    pub abort_after_failures: Option<usize>,
+    pub abort_error_rate: Option<f64>,
+    pub abort_min_samples: usize,
+    // No new requests are started once this much time has passed.
+    pub stop_after: Option<Duration>,
+    // Nor once this is set (--soak's Ctrl-C); requests in flight finish.
+    pub stop: Option<Arc<AtomicBool>>,
+    // --soak: --log-file is split into one file per UTC hour.
+    pub rotate_log: bool,
+    // Requests per second to start at; concurrency still caps how many
+    // are in flight.
+    pub rate: Option<f64>,
+    pub sample_latencies: Option<usize>,
+    pub seed: u64,
+    pub per_connection: bool,
+    // --sqlite: keep one `stats::Sample` per request.
+    pub keep_samples: bool,
+    pub timeline: bool,
+    pub slowest: Option<usize>,
+    pub error_samples: usize,
+    pub socket: SocketOptions,
+    // Query parameter name for --cache-bust.
+    pub cache_bust: Option<String>,
+    // Placeholders in the URL path, rendered per request.
+    pub path_template: Option<PathTemplate>,
+    // If-None-Match / If-Modified-Since sent with every GET.
+    pub conditional: Vec<(&'static str, String)>,
+    pub range: Option<ByteRanges>,
+    pub expect_headers: Vec<HeaderExpectation>,
+    // Responses matching any of these come from the proxy itself.
+    pub intercept_headers: Vec<HeaderExpectation>,
+    // --alpn / --require-alpn; gRPC offers and requires h2 by default.
+    pub alpn: Vec<String>,
+    pub require_alpn: Option<String>,
+    // --pipeline: GETs written back to back on each tunnel.
+    pub pipeline: Option<usize>,
+    // Reopen a tunnel the proxy closes mid-batch (off with --no-reconnect).
+    pub reconnect: bool,
+    pub show_headers: bool,
+    pub header_stats: Option<String>,
+    pub max_locations: usize,
+    pub slow_threshold: Option<Duration>,
+    pub dump_failures: Option<FailureDumps>,
+}
+
+// Set once Ctrl-C stopped a run that had taken it over. Later such runs
+// dispatch nothing, so loops over runs wind down, and main exits with 130
+// after the partial results are printed and child processes are gone.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
 pub async fn run_bench(
     proxy: Arc<Url>,
-    _proxy_host: &str,
-    _proxy_port: u16,
-    target: Arc<Target>,
-    success_matcher: Arc<SuccessMatcher>,
-    iterations: usize,
-    concurrency: usize,
-    per_timeout: Duration,
-    insecure: bool,
-    debug: bool,
-    connect_to: Option<String>,
+    proxy_addr: &str,
+    opts: Arc<BenchOptions>,
 ) -> Result<Stats> {
+    let proxy_addr: Arc<str> = Arc::from(proxy_addr);
     let started = Instant::now();
-    let sem = Arc::new(Semaphore::new(concurrency));
-    let mut futs = FuturesUnordered::new();
-    for _ in 0..iterations {
-        let sem = sem.clone();
-        let proxy = proxy.clone();
-        let target = target.clone();
-        let success_matcher = success_matcher.clone();
-        let connect_to_inner = connect_to.clone();
-        let insecure_local = insecure;
-        futs.push(tokio::spawn(async move {
-            let _permit = sem.acquire_owned().await.unwrap();
-            single_request(
-                &proxy,
-                &target,
-                success_matcher,
-                insecure_local,
-                &connect_to_inner,
-                per_timeout,
+    // Wall-clock anchor for the run; per-request timestamps are offsets
+    // from it on the monotonic clock, so they line up with the report.
+    let started_at = SystemTime::now();
+    // Tasks are spawned lazily as earlier ones finish, so at most
+    // `concurrency` of them exist at a time whatever the iteration count.
+    let spawn_opts = opts.clone();
+    let stop_after = opts.stop_after;
+    let stop = opts.stop.clone();
+    // Ctrl-C is only taken over when there is a log to flush; otherwise it
+    // keeps its default behaviour. With `stop` the caller handles it.
+    let interruptible = opts.log_file.is_some() && opts.stop.is_none();
+    let rate = opts.rate;
+    let gauge = Arc::new(Gauge::new(started));
+    let task_gauge = gauge.clone();
+    let free_workers = Arc::new(std::sync::Mutex::new(
+        (1..=opts.concurrency.max(1)).rev().collect::<Vec<usize>>(),
+    ));
+    let fresh = stream::iter(1..=opts.iterations)
+        .then(move |seq| async move {
+            // In rate mode each request has a scheduled start; when the
+            // concurrency cap holds it back, the delay is kept so latency
+            // can be corrected for coordinated omission.
+            let scheduled = rate.map(|rate| started + Duration::from_secs_f64((seq - 1) as f64 / rate));
+            if let Some(at) = scheduled {
+                tokio::time::sleep_until(at.into()).await;
+            }
+            (seq, scheduled)
+        })
+        .take_while(move |_| {
+            std::future::ready(
+                stop_after.map_or(true, |d| started.elapsed() < d)
+                    && stop.as_ref().map_or(true, |s| !s.load(Ordering::Relaxed))
+                    && !(interruptible && interrupted()),
             )
-            .await
-        }));
-    }
+        });
+    let (retries, retried) = RetryQueue::new();
+    let source = if opts.retry_after_max.is_some() {
+        let (opener, exhausted) = (retries.clone(), retries.clone());
+        let fresh = fresh
+            .inspect(move |_| opener.opened())
+            .chain(stream::once(async move { exhausted.fresh_exhausted() }).filter_map(|()| std::future::ready(None)));
+        stream::select(fresh, retried.map(|seq| (seq, None))).right_stream()
+    } else {
+        fresh.left_stream()
+    };
+    let mut futs = Box::pin(source
+        .map(move |(seq, scheduled)| {
+            let proxy = proxy.clone();
+            let proxy_addr = proxy_addr.clone();
+            let mut opts = spawn_opts.clone();
+            let path = request_path(&opts, seq);
+            let target_ip = (!opts.target_ips.is_empty()).then(|| opts.target_ips[(seq - 1) % opts.target_ips.len()]);
+            if let Some(ip) = target_ip {
+                opts = Arc::new(BenchOptions {
+                    connect_to: Some(ConnectTo {
+                        host: ip.to_string(),
+                        port: opts.target.port,
+                    }),
+                    ..(*opts).clone()
+                });
+            }
+            if let Some(path) = &path {
+                let target = Target {
+                    path: path.clone(),
+                    ..(*opts.target).clone()
+                };
+                opts = Arc::new(BenchOptions {
+                    target: Arc::new(target),
+                    ..(*opts).clone()
+                });
+            }
+            let free_workers = free_workers.clone();
+            let worker = free_workers.lock().unwrap().pop().unwrap_or_default();
+            let gauge = task_gauge.clone();
+            AbortOnDrop(tokio::spawn(async move {
+                let _in_flight = gauge.enter();
+                #[cfg(test)]
+                if opts.target.path == PANIC_PATH {
+                    panic!("request task panic (test)");
+                }
+                let t = Instant::now();
+                let started_at = started_at + t.duration_since(started);
+                let send_delay = scheduled.map(|at| t.saturating_duration_since(at));
+                let mut phases = Phases::default();
+                let span = tracing::info_span!(
+                    "request",
+                    seq,
+                    proxy = %proxy_addr,
+                    target = %format_args!("{}://{}:{}{}", opts.target.scheme, opts.target.host, opts.target.port, opts.target.path),
+                );
+                let res = single_request(&proxy, &proxy_addr, &opts, &mut phases)
+                    .instrument(span)
+                    .await;
+                free_workers.lock().unwrap().push(worker);
+                let attempt = Attempt {
+                    seq,
+                    worker,
+                    started_at,
+                    send_delay,
+                    total: t.elapsed(),
+                    phases,
+                    path,
+                    target_ip,
+                };
+                (attempt, res)
+            }))
+        })
+        .buffer_unordered(opts.concurrency.max(1)));
+
+    let mut log = match &opts.log_file {
+        Some(path) => Some(RequestLog::open(path, opts.rotate_log)?),
+        None => None,
+    };
+    let ctrl_c = async move {
+        if interruptible {
+            let _ = tokio::signal::ctrl_c().await;
+        } else {
+            std::future::pending::<()>().await
+        }
+    };
+    tokio::pin!(ctrl_c);
 
-    let mut stats = Stats::default();
-    while let Some(join_res) = futs.next().await {
+    let mut stats = Stats {
+        started_at: Some(started_at),
+        reservoir: opts.sample_latencies.map(|n| Reservoir::new(n, opts.seed)),
+        per_worker: opts.per_connection.then(BTreeMap::new),
+        samples: opts.keep_samples.then(Vec::new),
+        per_ip: (!opts.target_ips.is_empty()).then(BTreeMap::new),
+        timeline: opts.timeline.then(Vec::new),
+        slowest: opts.slowest.map(Slowest::new),
+        error_samples: (opts.error_samples > 0).then(|| ErrorSamples::new(opts.error_samples)),
+        slow_threshold: opts.slow_threshold,
+        revalidation: (!opts.conditional.is_empty()).then(Default::default),
+        range: opts.range.is_some().then(Default::default),
+        pipeline: opts.pipeline.is_some().then(Default::default),
+        header_values: opts.header_stats.as_ref().map(|name| HeaderValues {
+            name: name.clone(),
+            counts: BTreeMap::new(),
+        }),
+        locations: (opts.max_locations > 0).then(|| Locations {
+            shown: opts.max_locations,
+            counts: BTreeMap::new(),
+        }),
+        ..Stats::default()
+    };
+    let mut consecutive_failures = 0usize;
+    let mut headers_shown = false;
+    loop {
+        let join_res = tokio::select! {
+            next = futs.next() => match next {
+                Some(r) => r,
+                None => break,
+            },
+            _ = &mut ctrl_c => {
+                if let Some(log) = log.as_mut() {
+                    log.flush();
+                }
+                tracing::warn!("interrupted; reporting the requests finished so far");
+                INTERRUPTED.store(true, Ordering::Relaxed);
+                break;
+            }
+        };
+        let successes_before = stats.success;
+        if let Ok((Attempt { phases: Phases { alpn: Some(alpn), .. }, .. }, _)) = &join_res {
+            stats.record_alpn(alpn);
+        }
+        let worker_sample = match &join_res {
+            Ok((attempt, Ok(meta))) => Some((attempt.worker, meta.success.then(|| meta.dur.unwrap_or_default()))),
+            Ok((attempt, Err(_))) => Some((attempt.worker, None)),
+            Err(_) => None,
+        };
+        if let Some((worker, latency)) = worker_sample {
+            stats.record_worker(worker, latency);
+            if let Ok((Attempt { target_ip: Some(ip), .. }, _)) = &join_res {
+                stats.record_ip(*ip, latency);
+            }
+        }
+        if let Some(log) = log.as_mut() {
+            match &join_res {
+                Ok((attempt, res)) => log.record(attempt, res),
+                Err(e) => log.record_join_error(e),
+            }
+        }
         match join_res {
-            Ok(Ok(meta)) => {
+            Ok((Attempt { seq, .. }, Ok(RespMeta { retry_after: Some(delay), status, .. })))
+                if stop_after.map_or(true, |d| started.elapsed() < d) =>
+            {
+                debug!(seq, status = status.unwrap_or(0), ?delay, "rate limited; retrying after Retry-After");
+                stats.rate_limited += 1;
+                retries.retry(seq, delay);
+                continue;
+            }
+            Ok((Attempt { seq, send_delay, started_at, total, phases, .. }, Ok(mut meta))) => {
+                stats.record_slowest(seq, total, meta.status, started_at, phases.dominant(meta.dur));
+                if let Some(connect) = phases.connect {
+                    stats.record_proxy_connect(connect);
+                }
+                if phases.reconnected {
+                    stats.tunnel_reconnects += 1;
+                }
+                if let (Some(dump), Some(dumps)) = (meta.failure_dump.take(), &opts.dump_failures) {
+                    if let Err(e) = dumps.write(seq, &dump) {
+                        tracing::warn!("{:#}", e);
+                    }
+                }
+                if let Some(headers) = meta.headers.take() {
+                    if !headers_shown {
+                        headers_shown = true;
+                        crate::pretty::print_headers(seq, meta.status.unwrap_or(0), &headers);
+                    }
+                }
+                if let Some(times) = meta.pipeline.take() {
+                    stats.record_pipeline(&times);
+                }
+                if let Some((server, via)) = meta.responders.take() {
+                    stats.record_responders(server, via);
+                }
+                if let Some(value) = meta.header_value.take() {
+                    stats.record_header_value(value);
+                }
+                if let Some(location) = meta.location.take() {
+                    stats.record_location(location);
+                }
+                if let Some(version) = meta.version {
+                    stats.record_http_version(version);
+                }
                 let sec = meta.finished.duration_since(started).as_secs();
-                stats.record_success_bucket(sec);
+                stats.record_completion(sec, meta.success);
+                if !meta.success {
+                    stats.record_failure_time(total);
+                }
                 if let Some(code) = meta.status {
                     stats.record_status(code);
+                    if let Some(dur) = meta.dur {
+                        stats.record_revalidation(code, dur);
+                    }
+                }
+                if let Some(code) = meta.grpc_status {
+                    stats.record_grpc_status(code);
                 }
-                if meta.success {
+                if let Some(outcome) = meta.range {
+                    stats.record_range(outcome);
+                }
+                if let Some((bytes, dur)) = meta.transfer {
+                    match opts.mode {
+                        BenchMode::Upload => stats.upload.record(bytes, dur),
+                        _ => stats.download.record(bytes, dur),
+                    }
+                }
+                if let Some(ping) = meta.ws_ping {
+                    stats.record_ws_ping(ping);
+                }
+                if !meta.success && opts.record_all_latencies {
                     if let Some(dur) = meta.dur {
-                        stats.record_success(dur);
-                    } else {
-                        stats.record_success(Duration::from_millis(0));
+                        stats.record_latency(seq, dur);
+                    }
+                }
+                stats.record_second(sec, meta.success.then(|| meta.dur.unwrap_or_default()), false);
+                // Named as in `pretty::error_counts`.
+                let failure = if meta.success {
+                    let dur = meta.dur.unwrap_or_default();
+                    stats.record_success(seq, dur);
+                    if opts.slow_threshold.is_some_and(|t| dur > t) {
+                        stats.slow += 1;
+                    }
+                    if let Some(delay) = send_delay {
+                        stats.record_corrected(dur + delay);
+                    }
+                    None
+                } else if opts.mode == BenchMode::Grpc {
+                    match meta.grpc_status {
+                        Some(0) => debug!(seq, "gRPC health check returned a non-SERVING status"),
+                        Some(code) => debug!(seq, grpc_status = code, "gRPC call failed"),
+                        None => debug!(seq, status = meta.status.unwrap_or(0), "gRPC response without grpc-status"),
                     }
+                    stats.record_fail();
+                    Some("status")
+                } else if opts.mode == BenchMode::Ws {
+                    debug!(seq, status = meta.status.unwrap_or(0), "WebSocket upgrade rejected");
+                    stats.record_ws_rejected();
+                    Some("ws_rejected")
+                } else if let Some(label) = meta.intercepted.take() {
+                    debug!(seq, header = %label, "response came from the proxy, not the origin");
+                    stats.record_intercepted(label);
+                    Some("intercepted")
+                } else if meta.truncated {
+                    debug!(seq, "response body ended early; counted as truncated");
+                    stats.record_truncated();
+                    Some("truncated")
+                } else if let Some(expectation) = meta.header_mismatch.take() {
+                    debug!(seq, %expectation, "response header assertion failed; counted as fail");
+                    stats.record_header_mismatch(expectation);
+                    Some("header")
+                } else if meta.range == Some(RangeOutcome::Invalid) {
+                    debug!(seq, "Content-Range does not match the requested range; counted as fail");
+                    stats.record_fail();
+                    Some("status")
                 } else {
-                    if let Some(code) = meta.status {
-                        if debug {
-                            eprintln!("[xray-tester] Response status {} not in success set; counted as fail. Consider --success-codes", code);
-                        }
-                    } else {
-                        if debug {
-                            eprintln!("[xray-tester] Request completed without parsable status; counted as fail");
-                        }
+                    match meta.status {
+                        Some(code) => debug!(seq, status = code, "response status not in success set; counted as fail. Consider --success-codes"),
+                        None => debug!(seq, "request completed without parsable status; counted as fail"),
                     }
                     stats.record_fail();
-                }
+                    Some("status")
+                };
+                stats.record_sample(seq, meta.dur, meta.status, failure);
             }
-            Ok(Err(e)) => {
+            Ok((Attempt { seq, started_at, total, phases, .. }, Err(e))) => {
+                stats.record_slowest(seq, total, None, started_at, phases.dominant(None));
+                stats.record_failure_time(total);
+                if let Some(connect) = phases.connect {
+                    stats.record_proxy_connect(connect);
+                }
+                if phases.reconnected {
+                    stats.tunnel_reconnects += 1;
+                }
                 let sec = started.elapsed().as_secs();
-                stats.record_success_bucket(sec);
-                if e.to_string().contains("timed out") {
-                    stats.record_timeout();
-                } else if e.to_string().contains("certificate") || e.to_string().contains("TLS") {
+                stats.record_completion(sec, false);
+                let timeouts_before = stats.timeout_errors;
+                let msg = e.to_string();
+                // Named as in `pretty::error_counts`.
+                let category = if let Some(reply) = e.downcast_ref::<ProxyReply>() {
+                    stats.record_proxy_reply(reply.label());
+                    "conn"
+                } else if let Some(malformed) = e.downcast_ref::<ProtocolError>() {
+                    debug!(seq, first_bytes = %malformed.escaped_head(), "response is not valid HTTP");
+                    stats.record_protocol_error();
+                    "protocol"
+                } else if let Some(mismatch) = e.downcast_ref::<AlpnMismatch>() {
+                    debug!(seq, negotiated = %mismatch.negotiated, required = %mismatch.required, "ALPN mismatch");
+                    stats.record_alpn_mismatch();
+                    "alpn"
+                } else if e.downcast_ref::<PortExhausted>().is_some() {
+                    stats.record_port_exhaustion();
+                    "port_exhaustion"
+                } else if msg.contains(udp::ASSOCIATE_REFUSED) {
+                    stats.record_udp_refused();
+                    "udp_refused"
+                } else if msg.contains(udp::NO_RELAY) {
+                    stats.record_udp_no_reply();
+                    "udp_no_reply"
+                } else if let Some(timeout) = e.downcast_ref::<TimedOut>() {
+                    stats.record_timeout(timeout.phase.name());
+                    "timeout"
+                } else if msg.contains("timed out") {
+                    // The OS gave up (ETIMEDOUT), not one of our deadlines.
+                    stats.record_timeout("other");
+                    "timeout"
+                } else if msg.contains("certificate") || msg.contains("TLS") {
                     stats.record_tls_error();
+                    "tls"
                 } else {
                     stats.record_conn_error();
-                }
-                if debug {
-                    eprintln!("[xray-tester] Request error: {}", e);
+                    "conn"
+                };
+                stats.record_second(sec, None, stats.timeout_errors > timeouts_before);
+                stats.record_error_sample(category, format!("{:#}", e));
+                stats.record_sample(seq, None, None, Some(category));
+                match e.downcast_ref::<TimedOut>() {
+                    Some(timeout) => debug!(seq, phase = timeout.phase.name(), error = %format_args!("{:#}", e), "request timed out"),
+                    None => debug!(seq, error = %format_args!("{:#}", e), "request failed"),
                 }
             }
-            Err(_) => {
+            Err(e) => {
                 let sec = started.elapsed().as_secs();
-                stats.record_success_bucket(sec);
-                stats.record_fail();
-                if debug {
-                    eprintln!("[xray-tester] Internal join error");
-                }
+                stats.record_completion(sec, false);
+                stats.record_second(sec, None, false);
+                let message = match e.try_into_panic() {
+                    Ok(payload) => panic_message(payload),
+                    Err(e) => e.to_string(),
+                };
+                debug!(%message, "request task panicked");
+                stats.record_internal_error(message);
             }
         }
+        if opts.retry_after_max.is_some() {
+            retries.finished();
+        }
+        if stats.success > successes_before {
+            consecutive_failures = 0;
+        } else {
+            consecutive_failures += 1;
+        }
+        if let Some(reason) = abort_reason(&opts, &stats, consecutive_failures) {
+            stats.aborted = Some(reason);
+            break;
+        }
+    }
+    // Dropping the stream aborts requests still in flight.
+    drop(futs);
+    stats.in_flight = Some(InFlight {
+        configured: opts.concurrency.max(1),
+        peak: gauge.peak.load(Ordering::Relaxed),
+        per_sec: gauge.per_sec(Instant::now()),
+    });
+    if let Some(log) = log.as_mut() {
+        log.flush();
     }
-    stats.total_duration_ms = started.elapsed().as_millis();
+    stats.interrupted = interruptible && interrupted();
+    stats.total_duration = started.elapsed();
     Ok(stats)
 }
 
-async fn single_request(
-    proxy: &Url,
-    target: &Target,
-    success_matcher: Arc<SuccessMatcher>,
-    insecure: bool,
-    connect_to: &Option<String>,
-    timeout_dur: Duration,
-) -> Result<RespMeta> {
-    let connect_target = if let Some(ct) = connect_to {
-        ct.clone()
-    } else {
-        format!("{}:{}", target.host, target.port)
-    };
+// Requests currently running single_request, the most seen at once, and
+// their count integrated over time for the per-second averages.
+struct Gauge {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    area: std::sync::Mutex<Area>,
+}
 
-    let proxy_addr = format!(
-        "{}:{}",
-        proxy.host_str().unwrap_or("127.0.0.1"),
-        proxy.port_or_known_default().unwrap_or(80)
-    );
-    let mut stream = tokio::time::timeout(timeout_dur, TcpStream::connect(&proxy_addr))
-        .await
-        .map_err(|_| anyhow!("connect to proxy {} timed out", proxy_addr))?
-        .with_context(|| format!("connect to proxy {} failed", proxy_addr))?;
+// Request-seconds per second of the run, accumulated up to `last` (time
+// since `start`). Every enter and exit closes the span before it, so a
+// burst shorter than a second still counts for its full length.
+struct Area {
+    start: Instant,
+    last: Duration,
+    per_sec: BTreeMap<u64, f64>,
+}
 
-    let connect_req = format!(
-        "CONNECT {} HTTP/1.1\r\nHost: {}\r\nProxy-Connection: Keep-Alive\r\n\r\n",
-        connect_target, connect_target
-    );
-    let write_res = tokio::time::timeout(timeout_dur, stream.write_all(connect_req.as_bytes()))
-        .await
-        .map_err(|_| anyhow!("proxy CONNECT write timed out"))?;
-    write_res?;
+impl Area {
+    fn advance(&mut self, level: usize, now: Instant) {
+        let until = now.saturating_duration_since(self.start);
+        while self.last < until {
+            let sec = self.last.as_secs();
+            let end = until.min(Duration::from_secs(sec + 1));
+            *self.per_sec.entry(sec).or_insert(0.0) += level as f64 * (end - self.last).as_secs_f64();
+            self.last = end;
+        }
+    }
+}
 
-    let mut buf = Vec::with_capacity(1024);
-    let mut tmp = [0u8; 512];
-    loop {
-        let read_res = tokio::time::timeout(timeout_dur, stream.read(&mut tmp))
-            .await
-            .map_err(|_| anyhow!("proxy CONNECT read timed out"))?;
-        let n = read_res?;
-        if n == 0 {
-            break;
+struct GaugeGuard(Arc<Gauge>);
+
+impl Gauge {
+    fn new(start: Instant) -> Self {
+        Self {
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            area: std::sync::Mutex::new(Area {
+                start,
+                last: Duration::ZERO,
+                per_sec: BTreeMap::new(),
+            }),
         }
-        buf.extend_from_slice(&tmp[..n]);
-        if buf.len() >= 4 {
-            if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
-                let head = &buf[..pos];
-                let ok = head.windows(12).any(|w| w == b" 200 Connection")
-                    || head.starts_with(b"HTTP/1.1 200");
-                if !ok {
-                    return Err(anyhow!(
-                        "proxy CONNECT failed: {}",
-                        String::from_utf8_lossy(head)
-                    ));
+    }
+
+    fn enter(self: &Arc<Self>) -> GaugeGuard {
+        let mut area = self.area.lock().unwrap();
+        area.advance(self.current.load(Ordering::Relaxed), Instant::now());
+        let now = self.current.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(now, Ordering::Relaxed);
+        GaugeGuard(self.clone())
+    }
+
+    // Time-weighted average in flight for each second up to `now`; the last
+    // second is averaged over the part of it the run covered.
+    fn per_sec(&self, now: Instant) -> BTreeMap<u64, f64> {
+        let mut area = self.area.lock().unwrap();
+        area.advance(self.current.load(Ordering::Relaxed), now);
+        let covered = area.last;
+        area.per_sec
+            .iter()
+            .map(|(&sec, &total)| {
+                let span = covered.saturating_sub(Duration::from_secs(sec)).min(Duration::from_secs(1));
+                (sec, total / span.as_secs_f64())
+            })
+            .collect()
+    }
+}
+
+// Also runs when the task panics or is aborted.
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        let mut area = self.0.area.lock().unwrap();
+        area.advance(self.0.current.load(Ordering::Relaxed), Instant::now());
+        self.0.current.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Requests for this path panic in tests, to exercise the JoinError path.
+#[cfg(test)]
+pub const PANIC_PATH: &str = "/__panic";
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s.to_string()
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "panic with a non-string payload".to_string()
+    }
+}
+
+// --respect-retry-after: rate-limited requests sleep outside the
+// concurrency limit and come back through this queue. Its stream ends once
+// the fresh requests have run out and none is outstanding.
+struct RetryQueue {
+    tx: tokio::sync::mpsc::UnboundedSender<usize>,
+    fresh_done: AtomicBool,
+    // Requests started and not yet finished, retries included.
+    open: AtomicUsize,
+}
+
+impl RetryQueue {
+    // Sequence numbers start at 1, so 0 only asks the stream to look again.
+    const WAKE: usize = 0;
+
+    fn new() -> (Arc<Self>, impl futures::Stream<Item = usize>) {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let queue = Arc::new(Self {
+            tx,
+            fresh_done: AtomicBool::new(false),
+            open: AtomicUsize::new(0),
+        });
+        let stream = stream::unfold((rx, queue.clone()), |(mut rx, queue)| async move {
+            loop {
+                if queue.fresh_done.load(Ordering::SeqCst) && queue.open.load(Ordering::SeqCst) == 0 {
+                    return None;
+                }
+                match rx.recv().await {
+                    Some(Self::WAKE) => continue,
+                    Some(seq) => return Some((seq, (rx, queue))),
+                    None => return None,
                 }
-                break;
             }
-        }
-        if buf.len() > 8192 {
-            return Err(anyhow!("proxy CONNECT response too large"));
-        }
+        });
+        (queue, stream)
+    }
+
+    fn opened(&self) {
+        self.open.fetch_add(1, Ordering::SeqCst);
     }
 
-    let host_for_sni = &target.host;
-    if target.scheme == "https" {
-        let mut tls_builder = NativeTlsConnector::builder();
-        if insecure {
-            tls_builder.danger_accept_invalid_certs(true);
-            tls_builder.danger_accept_invalid_hostnames(true);
+    fn fresh_exhausted(&self) {
+        self.fresh_done.store(true, Ordering::SeqCst);
+        let _ = self.tx.send(Self::WAKE);
+    }
+
+    fn finished(&self) {
+        if self.open.fetch_sub(1, Ordering::SeqCst) == 1 {
+            let _ = self.tx.send(Self::WAKE);
         }
-        let tls = tls_builder.build().context("building TLS connector")?;
-        let tls = TokioTlsConnector::from(tls);
-        let dns_name = host_for_sni;
-        let tls_stream = tokio::time::timeout(timeout_dur, tls.connect(dns_name, stream))
-            .await
-            .map_err(|_| anyhow!("TLS connect timed out"))??;
-        let io = TokioIo::new(tls_stream);
-        let (mut sender, connection) =
-            tokio::time::timeout(timeout_dur, conn::http1::handshake(io))
-                .await
-                .map_err(|_| anyhow!("handshake timed out"))??;
+    }
+
+    fn retry(&self, seq: usize, delay: Duration) {
+        let tx = self.tx.clone();
         tokio::spawn(async move {
-            let _ = connection.await;
+            tokio::time::sleep(delay).await;
+            let _ = tx.send(seq);
         });
-        let path = if target.path.is_empty() {
-            "/"
-        } else {
-            &target.path
+    }
+}
+
+// The path of request `seq` when it differs from the target's: a
+// rendered URL template, with the cache-busting parameter if asked for.
+fn request_path(opts: &BenchOptions, seq: usize) -> Option<String> {
+    let templated = opts.path_template.as_ref().map(|t| t.render(seq, opts.seed));
+    match opts.cache_bust.as_deref() {
+        Some(param) => {
+            let base = templated.as_deref().unwrap_or(&opts.target.path);
+            Some(cache_bust_path(base, param, seq, opts.seed))
+        }
+        None => templated,
+    }
+}
+
+// Appends `param=<seq>-<hex>`, unique per request and reproducible with
+// --seed, so that no cache in front of the target can answer it.
+fn cache_bust_path(path: &str, param: &str, seq: usize, seed: u64) -> String {
+    let sep = if path.contains('?') { '&' } else { '?' };
+    format!("{}{}{}={}-{:08x}", path, sep, param, seq, mix64(seed, seq as u64) as u32)
+}
+
+fn abort_reason(opts: &BenchOptions, stats: &Stats, consecutive_failures: usize) -> Option<String> {
+    if let Some(n) = opts.abort_after_failures {
+        if consecutive_failures >= n {
+            return Some(format!("{} consecutive failures", consecutive_failures));
+        }
+    }
+    if let Some(limit) = opts.abort_error_rate {
+        let done = stats.completed();
+        let rate = stats.fail as f64 * 100.0 / done.max(1) as f64;
+        if done >= opts.abort_min_samples && rate >= limit {
+            return Some(format!("error rate {:.1}% after {} requests", rate, done));
+        }
+    }
+    None
+}
+
+// --ip-weights: each address appears as often as its weight (1 unless
+// listed, 0 drops it), interleaved by smooth weighted round-robin so a heavy
+// address does not get its requests in one burst.
+pub fn ip_schedule(ips: &[IpAddr], weights: &[(IpAddr, u32)]) -> Vec<IpAddr> {
+    let weighted: Vec<(IpAddr, i64)> = ips
+        .iter()
+        .map(|ip| (*ip, weights.iter().find(|(w, _)| w == ip).map_or(1, |&(_, n)| n as i64)))
+        .filter(|&(_, weight)| weight > 0)
+        .collect();
+    // 2:2 is the same split as 1:1, in a shorter cycle.
+    let gcd = weighted.iter().fold(0, |g, &(_, weight)| {
+        let (mut a, mut b) = (g, weight);
+        while b != 0 {
+            (a, b) = (b, a % b);
+        }
+        a
+    });
+    let weighted: Vec<(IpAddr, i64)> = weighted.into_iter().map(|(ip, weight)| (ip, weight / gcd)).collect();
+    let total: i64 = weighted.iter().map(|&(_, weight)| weight).sum();
+    let mut current = vec![0i64; weighted.len()];
+    let mut schedule = Vec::with_capacity(total as usize);
+    for _ in 0..total {
+        let mut best = 0;
+        for (i, &(_, weight)) in weighted.iter().enumerate() {
+            current[i] += weight;
+            if current[i] > current[best] {
+                best = i;
+            }
+        }
+        current[best] -= total;
+        schedule.push(weighted[best].0);
+    }
+    schedule
+}
+
+// `direct://` in place of a proxy URL: connect straight to the target.
+pub const DIRECT_SCHEME: &str = "direct";
+
+pub async fn single_request(
+    proxy: &Url,
+    proxy_addr: &str,
+    opts: &BenchOptions,
+    phases: &mut Phases,
+) -> Result<RespMeta> {
+    if opts.mode == BenchMode::UdpDns {
+        return udp::dns_query(proxy, proxy_addr, opts).await;
+    }
+    let pipelined = opts.mode == BenchMode::Http && opts.pipeline.is_some();
+    let stream = open_target(proxy, proxy_addr, opts, phases).await?;
+    if !opts.target.is_tls() {
+        if pipelined {
+            let reopen = || async { open_target(proxy, proxy_addr, opts, &mut Phases::default()).await };
+            return pipeline_exchange(stream, opts, phases, reopen).await;
+        }
+        let meta = exchange(stream, opts).await?;
+        phases.continue_wait = meta.continue_wait;
+        return Ok(meta);
+    }
+    let tls_stream = target_tls(stream, opts, phases).await?;
+    if pipelined {
+        let reopen = || async {
+            let mut phases = Phases::default();
+            let stream = open_target(proxy, proxy_addr, opts, &mut phases).await?;
+            target_tls(stream, opts, &mut phases).await
         };
-        let uri: Uri = path.parse().context("invalid request path")?;
-        let req = Request::get(uri)
-            .header("Host", &target.host_header)
-            .header("User-Agent", USER_AGENT)
-            .header("Accept", "*/*")
-            .header("Connection", "close")
-            .body(Empty::<Bytes>::new())
-            .map_err(|e| anyhow!("build request failed: {e}"))?;
+        return pipeline_exchange(tls_stream, opts, phases, reopen).await;
+    }
+    let meta = exchange(tls_stream, opts).await?;
+    phases.continue_wait = meta.continue_wait;
+    Ok(meta)
+}
 
-        let start = Instant::now();
-        let resp = tokio::time::timeout(timeout_dur, sender.send_request(req))
-            .await
-            .map_err(|_| anyhow!("request timed out"))?
-            .map_err(|e| anyhow!("request failed: {e:?}"))?;
-        let status = resp.status().as_u16();
-        let success = success_matcher.contains(status);
-        let dur = Some(start.elapsed());
-        return Ok(RespMeta {
-            success,
-            dur,
-            status: Some(status),
-            finished: Instant::now(),
-        });
+// A connection to the target: direct, or through the proxy's tunnel.
+async fn open_target(proxy: &Url, proxy_addr: &str, opts: &BenchOptions, phases: &mut Phases) -> Result<TunnelStream> {
+    let target = &opts.target;
+    let connect_target = match &opts.connect_to {
+        Some(ct) => ct.to_string(),
+        None => format!("{}:{}", target.host, target.port),
+    };
+    opts.socket.pace().await;
+    let t = Instant::now();
+    Ok(if proxy.scheme() == DIRECT_SCHEME {
+        let stream = connect_proxy(&connect_target, opts.timeout, &opts.socket).await?;
+        phases.connect = Some(t.elapsed());
+        trace!(elapsed = ?t.elapsed(), target = %connect_target, "connected directly");
+        Prefixed::new(ProxyStream::Tcp(stream), Vec::new())
     } else {
-        let io = TokioIo::new(stream);
-        let (mut sender, connection) =
-            tokio::time::timeout(timeout_dur, conn::http1::handshake(io))
-                .await
-                .map_err(|_| anyhow!("handshake timed out"))??;
-        tokio::spawn(async move {
-            let _ = connection.await;
-        });
-        let path = if target.path.is_empty() {
-            "/"
-        } else {
-            &target.path
-        };
-        let uri: Uri = path.parse().context("invalid request path")?;
-        let req = Request::get(uri)
-            .header("Host", &target.host_header)
-            .header("User-Agent", USER_AGENT)
-            .header("Accept", "*/*")
-            .header("Connection", "close")
-            .body(Empty::<Bytes>::new())
-            .map_err(|e| anyhow!("build request failed: {e}"))?;
-        let start = Instant::now();
-        let resp = tokio::time::timeout(timeout_dur, sender.send_request(req))
+        let stream = open_proxy_stream(proxy, proxy_addr, opts.timeout, &opts.socket).await?;
+        phases.connect = Some(t.elapsed());
+        trace!(elapsed = ?t.elapsed(), "connected to proxy");
+        let t = Instant::now();
+        let stream = proxy_tls(stream, proxy, opts.timeout, &opts.socket).await?;
+        if matches!(stream, ProxyStream::Tls(_)) {
+            phases.proxy_tls = Some(t.elapsed());
+            trace!(elapsed = ?t.elapsed(), "TLS to proxy established");
+        }
+        let t = Instant::now();
+        let stream = proxy_handshake(stream, proxy, &connect_target, opts.timeout).await?;
+        phases.tunnel = Some(t.elapsed());
+        trace!(elapsed = ?t.elapsed(), target = %connect_target, "tunnel established");
+        stream
+    })
+}
+
+// TLS to the target over `stream`, checking --require-alpn.
+async fn target_tls(
+    stream: TunnelStream,
+    opts: &BenchOptions,
+    phases: &mut Phases,
+) -> Result<TlsStream<TunnelStream>> {
+    let alpn: Vec<&str> = opts.alpn.iter().map(String::as_str).collect();
+    let t = Instant::now();
+    let tls_stream = tls_handshake(stream, &opts.target.host, opts.insecure, &alpn, opts.timeout).await?;
+    phases.tls = Some(t.elapsed());
+    let negotiated = match tls_stream.get_ref().negotiated_alpn().ok().flatten() {
+        Some(p) => String::from_utf8_lossy(&p).into_owned(),
+        None => "none".to_string(),
+    };
+    trace!(elapsed = ?t.elapsed(), alpn = %negotiated, "TLS handshake done");
+    // Nothing to report when nothing was offered.
+    if !alpn.is_empty() {
+        phases.alpn = Some(negotiated.clone());
+    }
+    if let Some(required) = opts.require_alpn.as_ref().filter(|&r| *r != negotiated) {
+        return Err(AlpnMismatch {
+            required: required.clone(),
+            negotiated,
+        }
+        .into());
+    }
+    Ok(tls_stream)
+}
+
+async fn exchange<S>(stream: S, opts: &BenchOptions) -> Result<RespMeta>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let meta = exchange_mode(stream, opts).await?;
+    trace!(status = ?meta.status, success = meta.success, "response received");
+    Ok(meta)
+}
+
+async fn exchange_mode<S>(stream: S, opts: &BenchOptions) -> Result<RespMeta>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    match opts.mode {
+        BenchMode::Http | BenchMode::Download | BenchMode::Upload => {
+            let (stream, head) = Recorder::new(stream);
+            let io = TokioIo::new(stream);
+            let res = match opts.mode {
+                BenchMode::Http => http_exchange(io, opts).await,
+                BenchMode::Download => download_exchange(io, opts).await,
+                _ => upload_exchange(io, opts).await,
+            };
+            res.map_err(|mut e| {
+                if let Some(malformed) = e.downcast_mut::<ProtocolError>() {
+                    malformed.head = head.lock().unwrap().clone();
+                }
+                e
+            })
+        }
+        BenchMode::Ws => ws_exchange(stream, opts).await,
+        // Plain-text targets use h2c with prior knowledge.
+        BenchMode::Grpc => grpc_exchange(TokioIo::new(stream), opts).await,
+        BenchMode::UdpDns => unreachable!("udp-dns requests do not open a TCP tunnel"),
+    }
+}
+
+// --pipeline: one batch of GETs per tunnel. The batch succeeds when every
+// response does; its latency is the time to the last response. A proxy
+// closing the tunnel mid-batch gets the rest sent once more over a tunnel
+// from `reopen` (unless --no-reconnect), which counts toward that latency.
+async fn pipeline_exchange<S, F, Fut>(stream: S, opts: &BenchOptions, phases: &mut Phases, reopen: F) -> Result<RespMeta>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+    F: FnOnce() -> Fut,
+    Fut: std::future::Future<Output = Result<S>>,
+{
+    let depth = opts.pipeline.unwrap_or(1);
+    let req = get_request(opts)?;
+    let requests: Vec<_> = (1..=depth)
+        .map(|seq| pipeline::render_request(&req, seq, seq == depth))
+        .collect();
+    let mut batch = pipeline::Batch::new(opts.timeout);
+    if let Err(e) = pipeline::exchange(stream, &requests, &mut batch).await {
+        let closed = e.downcast::<pipeline::TunnelClosed>()?;
+        if !opts.reconnect {
+            return Err(closed.into_protocol_error());
+        }
+        // The only place a batch changes tunnels. `reopen` builds the new one
+        // from the same `opts` as the first, so the connect destination, SNI
+        // and scheme match; no tunnel outlives its batch (the last request
+        // says Connection: close), so none is ever handed to another target.
+        debug!(%closed, "proxy closed the pipelined tunnel; reopening it");
+        phases.reconnected = true;
+        let stream = tokio::time::timeout_at(batch.deadline, reopen())
             .await
-            .map_err(|_| anyhow!("request timed out"))?
-            .map_err(|e| anyhow!("request failed: {e:?}"))?;
-        let status = resp.status().as_u16();
-        let success = success_matcher.contains(status);
-        let dur = Some(start.elapsed());
-        return Ok(RespMeta {
-            success,
-            dur,
-            status: Some(status),
-            finished: Instant::now(),
-        });
+            .map_err(|_| timed_out(TimeoutPhase::Tunnel, "reopening the pipelined tunnel timed out"))??;
+        pipeline::exchange(stream, &requests, &mut batch)
+            .await
+            .map_err(|e| match e.downcast::<pipeline::TunnelClosed>() {
+                Ok(closed) => closed.into_protocol_error(),
+                Err(e) => e,
+            })?;
+    }
+    let responses = batch.responses;
+    let last = responses.last().ok_or_else(|| anyhow!("empty pipeline"))?;
+    let failed = responses.iter().find(|r| !opts.success_matcher.contains(r.status));
+    Ok(RespMeta {
+        pipeline: Some(responses.iter().map(|r| r.elapsed).collect()),
+        ..RespMeta::new(failed.is_none(), last.elapsed, failed.unwrap_or(last).status)
+    })
+}
+
+pub async fn open_tunnel(
+    proxy: &Url,
+    proxy_addr: &str,
+    connect_target: &str,
+    timeout_dur: Duration,
+    socket: &SocketOptions,
+) -> Result<TunnelStream> {
+    let stream = open_proxy_stream(proxy, proxy_addr, timeout_dur, socket).await?;
+    let stream = proxy_tls(stream, proxy, timeout_dur, socket).await?;
+    proxy_handshake(stream, proxy, connect_target, timeout_dur).await
+}
+
+// Context attached to connection errors that mean the OS has run out of
+// ephemeral ports, typically because closed connections pile up in
+// TIME_WAIT faster than they are released.
+#[derive(Debug)]
+pub struct PortExhausted;
+
+impl std::fmt::Display for PortExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("out of local ports")
+    }
+}
+
+// --require-alpn (or gRPC's h2) was not what the TLS handshake agreed on.
+#[derive(Debug)]
+pub struct AlpnMismatch {
+    pub required: String,
+    pub negotiated: String,
+}
+
+impl std::fmt::Display for AlpnMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ALPN negotiated {}, required {}", self.negotiated, self.required)
+    }
+}
+
+impl std::error::Error for AlpnMismatch {}
+
+// A deadline that ran out, and the step of the request it cut short.
+#[derive(Debug)]
+pub struct TimedOut {
+    pub phase: TimeoutPhase,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    // TCP (or unix socket) connect to the proxy, or to the target directly.
+    ProxyConnect,
+    // SOCKS5 handshake or HTTP CONNECT through the proxy.
+    Tunnel,
+    Tls,
+    // Sending the request and waiting for the response head.
+    Request,
+    // Reading or writing the body.
+    Body,
+}
+
+impl TimeoutPhase {
+    // Keys of `Stats::timeouts_by_phase`.
+    pub fn name(self) -> &'static str {
+        match self {
+            TimeoutPhase::ProxyConnect => "proxy_connect",
+            TimeoutPhase::Tunnel => "tunnel",
+            TimeoutPhase::Tls => "tls",
+            TimeoutPhase::Request => "request",
+            TimeoutPhase::Body => "body",
+        }
+    }
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+pub fn timed_out(phase: TimeoutPhase, detail: impl Into<String>) -> anyhow::Error {
+    anyhow::Error::new(TimedOut {
+        phase,
+        detail: detail.into(),
+    })
+}
+
+// A response that is not valid HTTP/1.1: a garbled status line or headers,
+// or broken chunked framing. `head` holds the first bytes received.
+#[derive(Debug)]
+pub struct ProtocolError {
+    pub detail: String,
+    pub head: Vec<u8>,
+}
+
+impl ProtocolError {
+    // Non-printable bytes as \xNN, cut at recorder::HEAD_BYTES.
+    pub fn escaped_head(&self) -> String {
+        let mut out: String = self
+            .head
+            .iter()
+            .flat_map(|&b| std::ascii::escape_default(b))
+            .map(char::from)
+            .collect();
+        if self.head.len() >= HEAD_BYTES {
+            out.push_str("...");
+        }
+        out
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "malformed HTTP response: {}", self.detail)
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+fn is_malformed(e: &hyper::Error) -> bool {
+    if e.is_parse() {
+        return true;
+    }
+    // Body decoding errors (bad chunk sizes and the like) come as io errors.
+    let mut source = std::error::Error::source(e);
+    while let Some(s) = source {
+        if let Some(io) = s.downcast_ref::<std::io::Error>() {
+            return matches!(io.kind(), std::io::ErrorKind::InvalidData | std::io::ErrorKind::InvalidInput);
+        }
+        source = s.source();
+    }
+    false
+}
+
+fn response_error(e: hyper::Error) -> anyhow::Error {
+    if is_malformed(&e) {
+        let detail = match std::error::Error::source(&e) {
+            Some(source) => format!("{}: {}", e, source),
+            None => e.to_string(),
+        };
+        ProtocolError { detail, head: Vec::new() }.into()
+    } else {
+        anyhow!("request failed: {e:?}")
+    }
+}
+
+fn connect_error(e: std::io::Error, proxy_addr: &str) -> anyhow::Error {
+    let exhausted = matches!(
+        e.kind(),
+        std::io::ErrorKind::AddrNotAvailable | std::io::ErrorKind::AddrInUse
+    );
+    let e = anyhow::Error::from(e);
+    let e = if exhausted { e.context(PortExhausted) } else { e };
+    e.context(format!("connect to proxy {} failed", proxy_addr))
+}
+
+// Spaces out new proxy connections for --max-conn-rate, independently of
+// how many requests are in flight.
+#[derive(Debug)]
+pub struct ConnPacer {
+    interval: Duration,
+    next: std::sync::Mutex<Instant>,
+}
+
+impl ConnPacer {
+    pub fn new(rate: f64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / rate),
+            next: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let at = {
+            let mut next = self.next.lock().unwrap();
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        tokio::time::sleep_until(at.into()).await;
+    }
+}
+
+// How connections to the proxy are set up: where they originate from
+// (empty means wherever the routing table says) and socket tuning.
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    pub addr: Option<SocketAddr>,
+    pub interface: Option<String>,
+    pub nodelay: bool,
+    pub keepalive: Option<Duration>,
+    pub recv_buffer: Option<usize>,
+    pub send_buffer: Option<usize>,
+    pub pacer: Option<Arc<ConnPacer>>,
+    // Skip certificate checks on the TLS session to an https:// proxy.
+    pub proxy_insecure: bool,
+}
+
+impl SocketOptions {
+    // Binding and buffer sizes (which affect the window scale agreed on in
+    // the handshake) have to be set before connecting.
+    fn needs_socket(&self) -> bool {
+        self.addr.is_some()
+            || self.interface.is_some()
+            || self.recv_buffer.is_some()
+            || self.send_buffer.is_some()
+    }
+
+    // Waits for the next --max-conn-rate slot; called before the connect
+    // timer starts so the wait is not counted as connect time.
+    pub async fn pace(&self) {
+        if let Some(pacer) = &self.pacer {
+            pacer.wait().await;
+        }
+    }
+
+    fn socket_for(&self, remote: SocketAddr) -> Result<TcpSocket> {
+        let socket = if remote.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        if let Some(size) = self.recv_buffer {
+            socket
+                .set_recv_buffer_size(size.try_into().unwrap_or(u32::MAX))
+                .context("setting SO_RCVBUF failed")?;
+        }
+        if let Some(size) = self.send_buffer {
+            socket
+                .set_send_buffer_size(size.try_into().unwrap_or(u32::MAX))
+                .context("setting SO_SNDBUF failed")?;
+        }
+        if let Some(name) = &self.interface {
+            #[cfg(target_os = "linux")]
+            socket
+                .bind_device(Some(name.as_bytes()))
+                .with_context(|| format!("binding to interface {} failed", name))?;
+            #[cfg(not(target_os = "linux"))]
+            return Err(anyhow!("--interface {} is only supported on Linux", name));
+        }
+        if let Some(local) = self.addr {
+            socket.bind(local).map_err(|e| {
+                // With a fixed port this is just a port in use; with port 0
+                // the OS found no free one for this address.
+                let exhausted = local.port() == 0 && e.kind() == std::io::ErrorKind::AddrInUse;
+                let e = anyhow::Error::from(e);
+                let e = if exhausted { e.context(PortExhausted) } else { e };
+                e.context(format!("binding to local address {} failed", local))
+            })?;
+        }
+        Ok(socket)
+    }
+
+    // Tries each resolved address of the proxy that the local address can
+    // reach. Bind failures are reported as such, not as connect failures.
+    async fn connect(&self, addr: &str) -> Result<TcpStream> {
+        let remotes: Vec<SocketAddr> = lookup_host(addr)
+            .await
+            .with_context(|| format!("resolving {} failed", addr))?
+            .filter(|r| self.addr.map_or(true, |l| l.is_ipv4() == r.is_ipv4()))
+            .collect();
+        let mut last_err = None;
+        for remote in remotes {
+            match self.socket_for(remote)?.connect(remote).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        match last_err {
+            Some(e) => Err(connect_error(e, addr)),
+            None => Err(anyhow!(
+                "{} has no address of the same family as --local-addr",
+                addr
+            )),
+        }
+    }
+
+    fn tune(&self, stream: &TcpStream) -> Result<()> {
+        let sock = SockRef::from(stream);
+        sock.set_tcp_nodelay(self.nodelay).context("setting TCP_NODELAY failed")?;
+        if let Some(idle) = self.keepalive {
+            sock.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))
+                .context("setting SO_KEEPALIVE failed")?;
+        }
+        debug!(
+            nodelay = sock.tcp_nodelay().ok(),
+            keepalive = sock.keepalive().ok(),
+            rcvbuf = sock.recv_buffer_size().ok(),
+            sndbuf = sock.send_buffer_size().ok(),
+            "proxy socket options"
+        );
+        Ok(())
+    }
+}
+
+pub async fn connect_proxy(proxy_addr: &str, timeout_dur: Duration, socket: &SocketOptions) -> Result<TcpStream> {
+    let stream = if socket.needs_socket() {
+        tokio::time::timeout(timeout_dur, socket.connect(proxy_addr))
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::ProxyConnect, format!("connect to proxy {} timed out", proxy_addr)))??
+    } else {
+        tokio::time::timeout(timeout_dur, TcpStream::connect(proxy_addr))
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::ProxyConnect, format!("connect to proxy {} timed out", proxy_addr)))?
+            .map_err(|e| connect_error(e, proxy_addr))?
+    };
+    socket.tune(&stream)?;
+    Ok(stream)
+}
+
+// Resolves the proxy host and opens one TCP connection to it before the
+// run, so a misspelt or dead proxy fails once instead of once per request.
+// Returns the connect time.
+pub async fn preflight(proxy_addr: &str, timeout_dur: Duration, socket: &SocketOptions) -> Result<Duration> {
+    let host = proxy_addr.rsplit_once(':').map_or(proxy_addr, |(host, _)| host);
+    let remotes = tokio::time::timeout(timeout_dur, lookup_host(proxy_addr))
+        .await
+        .map_err(|_| anyhow!("proxy host {} did not resolve: timed out", host))?
+        .map_err(|e| anyhow!("proxy host {} did not resolve: {}", host, resolve_error(&e)))?;
+    if !remotes
+        .into_iter()
+        .any(|r| socket.addr.map_or(true, |l| l.is_ipv4() == r.is_ipv4()))
+    {
+        return Err(anyhow!("proxy host {} has no address of the same family as --local-addr", host));
+    }
+    let start = Instant::now();
+    connect_proxy(proxy_addr, timeout_dur, socket)
+        .await
+        .context("preflight connect to the proxy failed (--no-preflight skips it)")?;
+    Ok(start.elapsed())
+}
+
+// getaddrinfo's EAI_NONAME is what an NXDOMAIN answer turns into.
+fn resolve_error(e: &std::io::Error) -> String {
+    let message = e.to_string();
+    if message.contains("not known") || message.contains("nodename nor servname") {
+        "NXDOMAIN".to_string()
+    } else {
+        message.trim_start_matches("failed to lookup address information: ").to_string()
+    }
+}
+
+// The connection to the proxy itself: TCP, or a unix socket for unix://
+// and http+unix:// proxies.
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub async fn open_proxy_stream(
+    proxy: &Url,
+    proxy_addr: &str,
+    timeout_dur: Duration,
+    socket: &SocketOptions,
+) -> Result<ProxyStream> {
+    #[cfg(unix)]
+    if matches!(proxy.scheme(), "unix" | "http+unix") {
+        let stream = tokio::time::timeout(timeout_dur, tokio::net::UnixStream::connect(proxy_addr))
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::ProxyConnect, format!("connect to proxy {} timed out", proxy_addr)))?
+            .with_context(|| format!("connect to proxy {} failed", proxy_addr))?;
+        return Ok(ProxyStream::Unix(stream));
+    }
+    Ok(ProxyStream::Tcp(connect_proxy(proxy_addr, timeout_dur, socket).await?))
+}
+
+// A proxied connection to the target. Bytes the proxy sent right after its
+// CONNECT response are kept in front of the socket.
+pub type TunnelStream = Prefixed<ProxyStream>;
+
+// For https:// proxies, wraps the connection in TLS to the proxy host; the
+// CONNECT and everything after it then travel inside that session.
+pub async fn proxy_tls(
+    stream: ProxyStream,
+    proxy: &Url,
+    timeout_dur: Duration,
+    socket: &SocketOptions,
+) -> Result<ProxyStream> {
+    let stream = match stream {
+        ProxyStream::Tcp(s) if proxy.scheme() == "https" => s,
+        other => return Ok(other),
+    };
+    let host = proxy.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+    let tls = tls_handshake(stream, host, socket.proxy_insecure, &[], timeout_dur)
+        .await
+        .context("TLS handshake with proxy failed")?;
+    Ok(ProxyStream::Tls(Box::new(tls)))
+}
+
+pub async fn proxy_handshake<S>(
+    mut stream: S,
+    proxy: &Url,
+    connect_target: &str,
+    timeout_dur: Duration,
+) -> Result<Prefixed<S>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    // unix:// speaks SOCKS5, http+unix:// speaks HTTP CONNECT.
+    let leftover = if matches!(proxy.scheme(), "socks5" | "unix") {
+        let (host, port) = split_host_port(connect_target)?;
+        tokio::time::timeout(
+            timeout_dur,
+            socks5_connect(&mut stream, proxy, &host, port),
+        )
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Tunnel, "SOCKS5 handshake timed out"))??;
+        Vec::new()
+    } else {
+        http_connect(&mut stream, proxy, connect_target, timeout_dur).await?
+    };
+    Ok(Prefixed::new(stream, leftover))
+}
+
+pub async fn tls_handshake<S>(
+    stream: S,
+    host: &str,
+    insecure: bool,
+    alpn: &[&str],
+    timeout_dur: Duration,
+) -> Result<TlsStream<S>>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let mut tls_builder = NativeTlsConnector::builder();
+    if insecure {
+        tls_builder.danger_accept_invalid_certs(true);
+        tls_builder.danger_accept_invalid_hostnames(true);
+    }
+    if !alpn.is_empty() {
+        tls_builder.request_alpns(alpn);
+    }
+    let tls = tls_builder.build().context("building TLS connector")?;
+    let tls = TokioTlsConnector::from(tls);
+    let tls_stream = tokio::time::timeout(timeout_dur, tls.connect(host, stream))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Tls, "TLS connect timed out"))??;
+    Ok(tls_stream)
+}
+
+pub struct Fetched {
+    pub status: u16,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+// One-off GET that reads the whole body, used for subscriptions and other
+// auxiliary requests rather than for measurements.
+pub async fn fetch(
+    url: &Url,
+    proxy: Option<(&Url, &str)>,
+    insecure: bool,
+    timeout_dur: Duration,
+    socket: &SocketOptions,
+) -> Result<Fetched> {
+    request_once(url, proxy, None, insecure, timeout_dur, socket).await
+}
+
+// One-off direct POST, e.g. shipping results to a metrics database.
+pub async fn post(
+    url: &Url,
+    content_type: &'static str,
+    body: Vec<u8>,
+    insecure: bool,
+    timeout_dur: Duration,
+    socket: &SocketOptions,
+) -> Result<Fetched> {
+    request_once(url, None, Some((content_type, body)), insecure, timeout_dur, socket).await
+}
+
+async fn request_once(
+    url: &Url,
+    proxy: Option<(&Url, &str)>,
+    body: Option<(&'static str, Vec<u8>)>,
+    insecure: bool,
+    timeout_dur: Duration,
+    socket: &SocketOptions,
+) -> Result<Fetched> {
+    let target = parse_url_target(url.as_str())?;
+    let connect_target = format!("{}:{}", target.host, target.port);
+    let stream = match proxy {
+        Some((proxy, proxy_addr)) => {
+            open_tunnel(proxy, proxy_addr, &connect_target, timeout_dur, socket).await?
+        }
+        None => tokio::time::timeout(timeout_dur, TcpStream::connect(&connect_target))
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::ProxyConnect, format!("connect to {} timed out", connect_target)))?
+            .map(|s| Prefixed::new(ProxyStream::Tcp(s), Vec::new()))
+            .with_context(|| format!("connect to {} failed", connect_target))?,
+    };
+    let path_and_query = &url[Position::BeforePath..Position::AfterQuery];
+    if target.is_tls() {
+        let tls_stream = tls_handshake(stream, &target.host, insecure, &[], timeout_dur).await?;
+        fetch_exchange(TokioIo::new(tls_stream), &target, path_and_query, body, timeout_dur).await
+    } else {
+        fetch_exchange(TokioIo::new(stream), &target, path_and_query, body, timeout_dur).await
+    }
+}
+
+async fn fetch_exchange<T>(
+    io: T,
+    target: &Target,
+    path_and_query: &str,
+    body: Option<(&'static str, Vec<u8>)>,
+    timeout_dur: Duration,
+) -> Result<Fetched>
+where
+    T: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = tokio::time::timeout(timeout_dur, conn::http1::handshake(io))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "handshake timed out"))??;
+    let _driver = drive(connection);
+    let uri: Uri = path_and_query.parse().context("invalid request path")?;
+    let builder = Request::builder()
+        .uri(uri)
+        .header("Host", &target.host_header)
+        .header("User-Agent", USER_AGENT)
+        .header("Accept", "*/*")
+        .header("Connection", "close");
+    let req = match body {
+        Some((content_type, body)) => builder
+            .method(Method::POST)
+            .header("Content-Type", content_type)
+            .body(Full::new(Bytes::from(body))),
+        None => builder.body(Full::new(Bytes::new())),
+    }
+    .map_err(|e| anyhow!("build request failed: {e}"))?;
+    let resp = tokio::time::timeout(timeout_dur, sender.send_request(req))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "request timed out"))?
+        .map_err(|e| anyhow!("request failed: {e:?}"))?;
+    let (parts, body) = resp.into_parts();
+    let body = tokio::time::timeout(timeout_dur, body.collect())
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Body, "reading response body timed out"))?
+        .map_err(|e| anyhow!("reading response body failed: {e}"))?
+        .to_bytes();
+    Ok(Fetched {
+        status: parts.status.as_u16(),
+        headers: parts.headers,
+        body,
+    })
+}
+
+// A spawned task that is aborted when its handle is dropped.
+struct AbortOnDrop<T>(tokio::task::JoinHandle<T>);
+
+impl<T> std::future::Future for AbortOnDrop<T> {
+    type Output = std::result::Result<T, tokio::task::JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// Runs a hyper connection in the background for as long as the guard is
+// held, so a request that errors out or stops reading early does not leave
+// the socket open behind it.
+fn drive<F>(connection: F) -> AbortOnDrop<()>
+where
+    F: std::future::Future + Send + 'static,
+{
+    AbortOnDrop(tokio::spawn(async move {
+        let _ = connection.await;
+    }))
+}
+
+// Headers every benchmark request starts with. HTTP/1.0 closes the
+// connection by default, so it goes without a Connection header.
+fn request_head(opts: &BenchOptions, method: Method, uri: Uri) -> hyper::http::request::Builder {
+    let builder = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Host", &opts.target.host_header)
+        .header("User-Agent", USER_AGENT)
+        .header("Accept", "*/*");
+    if opts.http10 {
+        builder.version(Version::HTTP_10)
+    } else {
+        builder.header("Connection", "close")
+    }
+}
+
+pub fn get_request(opts: &BenchOptions) -> Result<Request<Full<Bytes>>> {
+    let target = &opts.target;
+    let path = if target.path.is_empty() {
+        "/"
+    } else {
+        &target.path
+    };
+    let uri: Uri = path.parse().context("invalid request path")?;
+    let method = if opts.body.is_some() { Method::POST } else { Method::GET };
+    let mut builder = request_head(opts, method, uri);
+    for (name, value) in &opts.conditional {
+        builder = builder.header(*name, value.as_str());
+    }
+    if let Some(range) = &opts.range {
+        builder = builder.header("Range", range.header_value());
+    }
+    let body = match &opts.body {
+        Some((content_type, bytes)) => {
+            builder = builder.header("Content-Type", *content_type);
+            bytes.clone()
+        }
+        None => Bytes::new(),
+    };
+    let mut req = builder
+        .body(Full::new(body))
+        .map_err(|e| anyhow!("build request failed: {e}"))?;
+    apply_custom_headers(req.headers_mut(), &opts.headers);
+    Ok(req)
+}
+
+// -H headers replace the defaults of the same name; repeated names are all
+// sent.
+fn apply_custom_headers(headers: &mut HeaderMap, custom: &[CustomHeader]) {
+    for h in custom {
+        headers.remove(&h.name);
+    }
+    for h in custom {
+        headers.append(h.name.clone(), h.value.clone());
+    }
+}
+
+async fn http_exchange<T>(io: T, opts: &BenchOptions) -> Result<RespMeta>
+where
+    T: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = tokio::time::timeout(opts.timeout, conn::http1::handshake(io))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "handshake timed out"))??;
+    let _driver = drive(connection);
+    let req = get_request(opts)?;
+
+    let start = Instant::now();
+    let resp = tokio::time::timeout(opts.timeout, sender.send_request(req))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "request timed out"))?
+        .map_err(response_error)?;
+    let status = resp.status().as_u16();
+    let range = opts
+        .range
+        .as_ref()
+        .and_then(|r| check_range(r, status, resp.headers()).0);
+    let success = opts.success_matcher.contains(status) && range != Some(RangeOutcome::Invalid);
+    let meta = RespMeta {
+        range,
+        version: Some(resp.version()),
+        ..RespMeta::new(success, start.elapsed(), status)
+    };
+    let (parts, mut body) = resp.into_parts();
+    let mut meta = inspect_headers(meta, opts, &parts.headers);
+    if let Some(dumps) = opts.dump_failures.as_ref().filter(|d| !meta.success && !d.full()) {
+        let prefix = dump::read_prefix(&mut body, dumps.max_body, opts.timeout).await;
+        meta.failure_dump = Some(dump::render(&parts, &prefix));
+    }
+    Ok(meta)
+}
+
+// Applies --intercept-header, --expect-header, --header-stats and
+// --show-headers, and keeps the Location of redirects. A proxy's own page
+// fails whatever its status; header assertions are only checked once the
+// status itself passed, so each failure has one cause.
+// Longer Server/Via values are cut; they only need to tell responders apart.
+const RESPONDER_MAX_LEN: usize = 100;
+
+fn inspect_headers(mut meta: RespMeta, opts: &BenchOptions, headers: &HeaderMap) -> RespMeta {
+    meta.intercepted = opts.intercept_headers.iter().find_map(|rule| {
+        headers
+            .get_all(rule.name.as_str())
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .find(|v| rule.matches(v))
+            .map(|v| format!("{}: {}", rule.name, v))
+    });
+    if meta.intercepted.is_some() {
+        meta.success = false;
+    }
+    if meta.success {
+        meta.header_mismatch = opts
+            .expect_headers
+            .iter()
+            .find(|e| {
+                !headers
+                    .get_all(e.name.as_str())
+                    .iter()
+                    .any(|v| v.to_str().is_ok_and(|v| e.matches(v)))
+            })
+            .map(|e| e.to_string());
+        meta.success = meta.header_mismatch.is_none();
+    }
+    if let Some(name) = &opts.header_stats {
+        let value = headers
+            .get(name.as_str())
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned());
+        meta.header_value = Some(value.unwrap_or_else(|| "(absent)".into()));
+    }
+    let responder = |name: &str| {
+        let values: Vec<_> = headers
+            .get_all(name)
+            .iter()
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+            .collect();
+        match values.is_empty() {
+            true => "(absent)".to_string(),
+            false => crate::sub::truncate(&values.join(", "), RESPONDER_MAX_LEN),
+        }
+    };
+    meta.responders = Some((responder("server"), responder("via")));
+    if opts.show_headers {
+        meta.headers = Some(headers.clone());
+    }
+    if let Some(max) = opts.retry_after_max.filter(|_| matches!(meta.status, Some(429 | 503))) {
+        meta.retry_after = headers
+            .get("retry-after")
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_retry_after)
+            .map(|d| d.min(max));
+    }
+    if opts.max_locations > 0 && meta.status.is_some_and(|c| (300..400).contains(&c)) {
+        meta.location = headers
+            .get("location")
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned());
+    }
+    meta
+}
+
+// Retry-After is either delay-seconds or an HTTP-date
+// ("Sun, 06 Nov 1994 08:49:37 GMT"); a date in the past means no wait.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts[..] else {
+        return None;
+    };
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|m| *m == month)? + 1;
+    let rfc3339 = format!("{}-{:02}-{:0>2}T{}Z", year, month, day, time);
+    let at = humantime::parse_rfc3339(&rfc3339).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+// Parses `bytes first-last/total`, where total may be `*`.
+fn parse_content_range(value: &str) -> Option<(u64, u64, Option<u64>)> {
+    let rest = value.trim().strip_prefix("bytes ")?;
+    let (span, total) = rest.split_once('/')?;
+    let (first, last) = span.split_once('-')?;
+    let (first, last): (u64, u64) = (first.trim().parse().ok()?, last.trim().parse().ok()?);
+    let total = match total.trim() {
+        "*" => None,
+        t => Some(t.parse().ok()?),
+    };
+    (first <= last).then_some((first, last, total))
+}
+
+// Classifies a response to a Range request. For a single-part 206 the
+// body length implied by Content-Range is returned so downloads can check
+// it against what actually arrived.
+fn check_range(range: &ByteRanges, status: u16, headers: &HeaderMap) -> (Option<RangeOutcome>, Option<u64>) {
+    match status {
+        206 => {}
+        200 => return (Some(RangeOutcome::Ignored), None),
+        _ => return (None, None),
+    }
+    let content_range = headers.get("content-range").and_then(|v| v.to_str().ok());
+    let Some(content_range) = content_range else {
+        // Several spans come back as multipart/byteranges, each part with
+        // its own Content-Range.
+        let multipart = headers
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.trim_start().to_ascii_lowercase().starts_with("multipart/byteranges"));
+        let outcome = if range.spans.len() > 1 && multipart {
+            RangeOutcome::Partial
+        } else {
+            RangeOutcome::Invalid
+        };
+        return (Some(outcome), None);
+    };
+    let Some((first, last, total)) = parse_content_range(content_range) else {
+        return (Some(RangeOutcome::Invalid), None);
+    };
+    let len = Some(last - first + 1);
+    // A server may coalesce several spans into one; only its syntax is checked.
+    let [span] = range.spans.as_slice() else {
+        return (Some(RangeOutcome::Partial), len);
+    };
+    let consistent = match (span.first, span.last) {
+        (Some(a), Some(b)) => first == a && last <= b && total.map_or(true, |t| last == b.min(t - 1)),
+        (Some(a), None) => first == a && total.map_or(true, |t| last + 1 == t),
+        (None, Some(n)) => total.map_or(true, |t| last + 1 == t && first == t.saturating_sub(n)),
+        (None, None) => false,
+    };
+    let outcome = if consistent {
+        RangeOutcome::Partial
+    } else {
+        RangeOutcome::Invalid
+    };
+    (Some(outcome), len)
+}
+
+// The body is counted frame by frame and dropped. Large objects take as
+// long as they take, so only the gap between frames is bounded.
+async fn download_exchange<T>(io: T, opts: &BenchOptions) -> Result<RespMeta>
+where
+    T: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = tokio::time::timeout(opts.timeout, conn::http1::handshake(io))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "handshake timed out"))??;
+    let _driver = drive(connection);
+    let req = get_request(opts)?;
+
+    let start = Instant::now();
+    let resp = tokio::time::timeout(opts.timeout, sender.send_request(req))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "request timed out"))?
+        .map_err(response_error)?;
+    let ttfb = start.elapsed();
+    let status = resp.status().as_u16();
+    let (mut range, expected_len) = match &opts.range {
+        Some(r) => check_range(r, status, resp.headers()),
+        None => (None, None),
+    };
+    let (parts, mut body) = resp.into_parts();
+    let mut bytes = 0u64;
+    let keep = opts.dump_failures.as_ref().filter(|d| !d.full()).map_or(0, |d| d.max_body);
+    let mut prefix = Vec::new();
+    // Stopping at --max-body-bytes is deliberate and not a truncation.
+    let mut capped = false;
+    let mut body_error = None;
+    loop {
+        match tokio::time::timeout(opts.idle_timeout, body.frame()).await {
+            Err(_) => {
+                return Err(timed_out(
+                    TimeoutPhase::Body,
+                    format!("download timed out: no data for {:?} after {} bytes", opts.idle_timeout, bytes),
+                ))
+            }
+            Ok(None) => break,
+            Ok(Some(Err(e))) if is_malformed(&e) => return Err(response_error(e)),
+            Ok(Some(Err(e))) => {
+                body_error = Some(e);
+                break;
+            }
+            Ok(Some(Ok(frame))) => {
+                if let Some(data) = frame.data_ref() {
+                    bytes += data.len() as u64;
+                    if prefix.len() < keep {
+                        prefix.extend_from_slice(&data[..data.len().min(keep - prefix.len())]);
+                    }
+                }
+                if opts.max_body_bytes.is_some_and(|max| bytes >= max) {
+                    capped = true;
+                    break;
+                }
+            }
+        }
+    }
+    let content_length = parts
+        .headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok());
+    let truncated = !capped && (body_error.is_some() || content_length.is_some_and(|len| bytes < len));
+    if let Some(e) = &body_error {
+        debug!(bytes, "connection broke while reading the response body: {e}");
+    }
+    if !capped && range == Some(RangeOutcome::Partial) && expected_len.is_some_and(|len| len != bytes) {
+        range = Some(RangeOutcome::Invalid);
+    }
+    let success = opts.success_matcher.contains(status)
+        && !truncated
+        && bytes >= opts.min_bytes.unwrap_or(0)
+        && range != Some(RangeOutcome::Invalid);
+    let meta = RespMeta {
+        transfer: Some((bytes, start.elapsed())),
+        range,
+        version: Some(parts.version),
+        truncated,
+        ..RespMeta::new(success, ttfb, status)
+    };
+    let mut meta = inspect_headers(meta, opts, &parts.headers);
+    if !meta.success && keep > 0 {
+        meta.failure_dump = Some(dump::render(&parts, &prefix));
+    }
+    Ok(meta)
+}
+
+// Request body of `remaining` bytes produced by repeating one shared chunk,
+// so large uploads need no allocation beyond the pattern itself.
+struct PatternBody {
+    chunk: Bytes,
+    remaining: u64,
+    sent: Arc<AtomicU64>,
+    gate: Option<ContinueGate>,
+}
+
+// --expect-continue: the body is held back until the server answers
+// 100 Continue or the window closes, whichever comes first.
+struct ContinueGate {
+    rx: tokio::sync::oneshot::Receiver<()>,
+    window: Pin<Box<tokio::time::Sleep>>,
+    started: Instant,
+    waited: Arc<std::sync::Mutex<Option<Duration>>>,
+}
+
+impl ContinueGate {
+    // Whether the body may go out now; records how long that took.
+    fn poll_open(&mut self, cx: &mut std::task::Context<'_>) -> bool {
+        use std::future::Future;
+        let opened = Pin::new(&mut self.rx).poll(cx).is_ready() || self.window.as_mut().poll(cx).is_ready();
+        if opened {
+            *self.waited.lock().unwrap() = Some(self.started.elapsed());
+        }
+        opened
+    }
+}
+
+impl PatternBody {
+    const CHUNK: usize = 64 * 1024;
+
+    fn new(size: u64, sent: Arc<AtomicU64>) -> Self {
+        let chunk: Vec<u8> = (0..Self::CHUNK).map(|i| (i % 251) as u8).collect();
+        Self {
+            chunk: Bytes::from(chunk),
+            remaining: size,
+            sent,
+            gate: None,
+        }
+    }
+}
+
+impl Body for PatternBody {
+    type Data = Bytes;
+    type Error = std::convert::Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        if self.remaining == 0 {
+            return Poll::Ready(None);
+        }
+        if let Some(gate) = self.gate.as_mut() {
+            if !gate.poll_open(cx) {
+                return Poll::Pending;
+            }
+            self.gate = None;
+        }
+        let n = self.remaining.min(self.chunk.len() as u64);
+        self.remaining -= n;
+        self.sent.fetch_add(n, Ordering::Relaxed);
+        Poll::Ready(Some(Ok(Frame::data(self.chunk.slice(..n as usize)))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.remaining == 0
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+// Like downloads, uploads are bounded by lack of progress rather than by a
+// total deadline: the request fails once no body bytes have been handed to
+// the connection for the idle timeout.
+pub fn upload_request<B>(opts: &BenchOptions, body: B) -> Result<Request<B>> {
+    let target = &opts.target;
+    let path = if target.path.is_empty() { "/" } else { &target.path };
+    let uri: Uri = path.parse().context("invalid request path")?;
+    let method = match opts.upload_method {
+        UploadMethod::Post => Method::POST,
+        UploadMethod::Put => Method::PUT,
+    };
+    let mut builder = request_head(opts, method, uri).header("Content-Type", "application/octet-stream");
+    if opts.expect_continue.is_some() {
+        builder = builder.header("Expect", "100-continue");
+    }
+    let mut req = builder
+        .body(body)
+        .map_err(|e| anyhow!("build request failed: {e}"))?;
+    apply_custom_headers(req.headers_mut(), &opts.headers);
+    Ok(req)
+}
+
+async fn upload_exchange<T>(io: T, opts: &BenchOptions) -> Result<RespMeta>
+where
+    T: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = tokio::time::timeout(opts.timeout, conn::http1::handshake(io))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "handshake timed out"))??;
+    let _driver = drive(connection);
+    let sent = Arc::new(AtomicU64::new(0));
+    let mut body = PatternBody::new(opts.upload_size, sent.clone());
+    let waited = Arc::new(std::sync::Mutex::new(None));
+    let mut continue_tx = None;
+    if let Some(window) = opts.expect_continue {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        continue_tx = Some(std::sync::Mutex::new(Some(tx)));
+        body.gate = Some(ContinueGate {
+            rx,
+            window: Box::pin(tokio::time::sleep(window)),
+            started: Instant::now(),
+            waited: waited.clone(),
+        });
+    }
+    let mut req = upload_request(opts, body)?;
+    if let Some(tx) = continue_tx {
+        hyper::ext::on_informational(&mut req, move |res| {
+            if res.status() == hyper::StatusCode::CONTINUE {
+                if let Some(tx) = tx.lock().unwrap().take() {
+                    let _ = tx.send(());
+                }
+            }
+        });
+    }
+
+    let start = Instant::now();
+    let response = sender.send_request(req);
+    tokio::pin!(response);
+    let mut last_sent = 0;
+    let resp = loop {
+        tokio::select! {
+            res = &mut response => break res.map_err(response_error)?,
+            _ = tokio::time::sleep(opts.idle_timeout) => {
+                let now_sent = sent.load(Ordering::Relaxed);
+                if now_sent == last_sent {
+                    return Err(timed_out(
+                        TimeoutPhase::Body,
+                        format!("upload timed out: no progress for {:?} after {} bytes", opts.idle_timeout, now_sent),
+                    ));
+                }
+                last_sent = now_sent;
+            }
+        }
+    };
+    let dur = start.elapsed();
+    let status = resp.status().as_u16();
+    let success = opts.success_matcher.contains(status);
+    let meta = RespMeta {
+        transfer: Some((sent.load(Ordering::Relaxed), dur)),
+        version: Some(resp.version()),
+        continue_wait: *waited.lock().unwrap(),
+        ..RespMeta::new(success, dur, status)
+    };
+    let (parts, mut body) = resp.into_parts();
+    let mut meta = inspect_headers(meta, opts, &parts.headers);
+    if let Some(dumps) = opts.dump_failures.as_ref().filter(|d| !meta.success && !d.full()) {
+        let prefix = dump::read_prefix(&mut body, dumps.max_body, opts.timeout).await;
+        meta.failure_dump = Some(dump::render(&parts, &prefix));
+    }
+    Ok(meta)
+}
+
+// Success means a 101 with a valid Sec-WebSocket-Accept; any other status is
+// reported as a rejected upgrade rather than a transport error.
+async fn ws_exchange<S>(stream: S, opts: &BenchOptions) -> Result<RespMeta>
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+    let start = Instant::now();
+    let (upgrade, mut conn) = ws::handshake(stream, &opts.target, opts.timeout).await?;
+    let dur = start.elapsed();
+    let status = match upgrade {
+        Upgrade::Accepted => 101,
+        Upgrade::Rejected(code) => {
+            return Ok(RespMeta::new(false, dur, code));
+        }
+    };
+    let ws_ping = if opts.ws_ping {
+        Some(conn.ping(opts.timeout).await?)
+    } else {
+        None
+    };
+    conn.close().await;
+    Ok(RespMeta {
+        ws_ping,
+        ..RespMeta::new(true, dur, status)
+    })
+}
+
+const GRPC_HEALTH_PATH: &str = "/grpc.health.v1.Health/Check";
+
+// grpc.health.v1.HealthCheckRequest { string service = 1; } behind the
+// 5-byte gRPC message prefix (uncompressed flag + big-endian length).
+fn grpc_health_request(service: &str) -> Bytes {
+    let mut msg = Vec::with_capacity(service.len() + 6);
+    if !service.is_empty() {
+        msg.push(0x0A);
+        let mut len = service.len();
+        while len >= 0x80 {
+            msg.push((len as u8 & 0x7F) | 0x80);
+            len >>= 7;
+        }
+        msg.push(len as u8);
+        msg.extend_from_slice(service.as_bytes());
+    }
+    let mut framed = Vec::with_capacity(msg.len() + 5);
+    framed.push(0);
+    framed.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+    framed.extend_from_slice(&msg);
+    Bytes::from(framed)
+}
+
+// Extracts HealthCheckResponse.status (field 1, varint); 1 is SERVING.
+fn grpc_health_status(body: &[u8]) -> Option<u64> {
+    let len = u32::from_be_bytes(body.get(1..5)?.try_into().ok()?) as usize;
+    let msg = body.get(5..5 + len)?;
+    let mut i = 0;
+    while i < msg.len() {
+        let key = msg[i];
+        i += 1;
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = *msg.get(i)?;
+            i += 1;
+            value |= u64::from(b & 0x7F) << shift;
+            if b & 0x80 == 0 {
+                break;
+            }
+            shift += 7;
+        }
+        match key {
+            0x08 => return Some(value),
+            k if k & 0x07 == 0 => {}
+            _ => return None,
+        }
+    }
+    // proto3 omits default values: an empty message means UNKNOWN (0).
+    Some(0)
+}
+
+async fn grpc_exchange<T>(io: T, opts: &BenchOptions) -> Result<RespMeta>
+where
+    T: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let target = &opts.target;
+    let timeout_dur = opts.timeout;
+    let (mut sender, connection) = tokio::time::timeout(
+        timeout_dur,
+        conn::http2::handshake(TokioExecutor::new(), io),
+    )
+    .await
+    .map_err(|_| timed_out(TimeoutPhase::Request, "handshake timed out"))??;
+    let _driver = drive(connection);
+    let scheme = if target.is_tls() { "https" } else { "http" };
+    let uri: Uri = format!("{}://{}{}", scheme, target.host_header, GRPC_HEALTH_PATH)
+        .parse()
+        .context("invalid gRPC request URI")?;
+    let req = Request::post(uri)
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .header("user-agent", USER_AGENT)
+        .body(Full::new(grpc_health_request(&opts.grpc_service)))
+        .map_err(|e| anyhow!("build request failed: {e}"))?;
+
+    let start = Instant::now();
+    let exchange = async {
+        let resp = sender
+            .send_request(req)
+            .await
+            .map_err(|e| anyhow!("request failed: {e:?}"))?;
+        let status = resp.status().as_u16();
+        // Trailers-only responses carry grpc-status in the headers.
+        let header_status = resp.headers().get("grpc-status").cloned();
+        let collected = resp
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| anyhow!("reading gRPC response failed: {e}"))?;
+        let grpc_status = collected
+            .trailers()
+            .and_then(|t| t.get("grpc-status").cloned())
+            .or(header_status)
+            .and_then(|v| v.to_str().ok()?.trim().parse::<u32>().ok());
+        Ok::<_, anyhow::Error>((status, grpc_status, collected.to_bytes()))
+    };
+    let (status, grpc_status, body) = tokio::time::timeout(timeout_dur, exchange)
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "request timed out"))??;
+    let success = grpc_status == Some(0) && grpc_health_status(&body) == Some(1);
+    Ok(RespMeta {
+        grpc_status,
+        ..RespMeta::new(success, start.elapsed(), status)
+    })
+}
+
+// Credentials in the proxy URL go out as Basic Proxy-Authorization.
+// Returns whatever arrived after the end of the response head.
+async fn http_connect<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(stream: &mut S, proxy: &Url, connect_target: &str, timeout_dur: Duration) -> Result<Vec<u8>> {
+    let auth = if proxy.username().is_empty() {
+        String::new()
+    } else {
+        let user = percent_decode_str(proxy.username()).decode_utf8_lossy();
+        let pass = percent_decode_str(proxy.password().unwrap_or("")).decode_utf8_lossy();
+        let token = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+        format!("Proxy-Authorization: Basic {}\r\n", token)
+    };
+    let connect_req = format!(
+        "CONNECT {} HTTP/1.1\r\nHost: {}\r\n{}Proxy-Connection: Keep-Alive\r\n\r\n",
+        connect_target, connect_target, auth
+    );
+    let write_res = tokio::time::timeout(timeout_dur, stream.write_all(connect_req.as_bytes()))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Tunnel, "proxy CONNECT write timed out"))?;
+    write_res?;
+
+    let mut buf = Vec::with_capacity(1024);
+    let mut tmp = [0u8; 512];
+    loop {
+        let read_res = tokio::time::timeout(timeout_dur, stream.read(&mut tmp))
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::Tunnel, "proxy CONNECT read timed out"))?;
+        let n = read_res?;
+        if n == 0 {
+            return Err(anyhow!("proxy closed the connection before answering CONNECT"));
+        }
+        buf.extend_from_slice(&tmp[..n]);
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            check_connect_status(&String::from_utf8_lossy(&buf[..pos]))?;
+            return Ok(buf.split_off(pos + 4));
+        }
+        if buf.len() > 8192 {
+            return Err(anyhow!("proxy CONNECT response too large"));
+        }
+    }
+}
+
+// Only the status line matters: any HTTP/1.x version and reason phrase is
+// fine as long as the code is 200.
+fn check_connect_status(head: &str) -> Result<()> {
+    let line = head.lines().next().unwrap_or_default();
+    let mut parts = line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let code = parts.next().and_then(|c| c.parse::<u16>().ok());
+    let reason = parts.next().unwrap_or_default().trim();
+    match code {
+        _ if !version.starts_with("HTTP/1.") => {
+            Err(anyhow!("proxy CONNECT failed: malformed response `{}`", line))
+        }
+        Some(200) => Ok(()),
+        Some(code) => Err(ProxyReply::Http {
+            code,
+            reason: reason.to_string(),
+        }
+        .into()),
+        None => Err(anyhow!("proxy CONNECT failed: malformed response `{}`", line)),
+    }
+}
+
+// A tunnel the proxy itself refused, as opposed to one that could not be
+// set up at all. Counted separately so that "proxy down" and "proxy up but
+// cannot reach the target" can be told apart.
+#[derive(Debug)]
+pub enum ProxyReply {
+    Http { code: u16, reason: String },
+    Socks { command: &'static str, code: u8 },
+}
+
+impl ProxyReply {
+    pub fn label(&self) -> String {
+        match self {
+            ProxyReply::Http { code, reason } if reason.is_empty() => code.to_string(),
+            ProxyReply::Http { code, reason } => format!("{} {}", code, reason),
+            ProxyReply::Socks { code, .. } => {
+                format!("SOCKS 0x{:02x} {}", code, socks5_reply_reason(*code))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for ProxyReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProxyReply::Http { code: 407, reason } => write!(
+                f,
+                "proxy CONNECT failed: 407 {} (proxy requires authentication)",
+                reason
+            ),
+            ProxyReply::Http { code, reason } => write!(f, "proxy CONNECT failed: {} {}", code, reason),
+            ProxyReply::Socks { command, code } => write!(
+                f,
+                "SOCKS5 {} failed: {} (0x{:02x})",
+                command,
+                socks5_reply_reason(*code),
+                code
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProxyReply {}
+
+pub fn split_host_port(s: &str) -> Result<(String, u16)> {
+    let (host, port) = s
+        .rsplit_once(':')
+        .ok_or_else(|| anyhow!("expected HOST:PORT, got {}", s))?;
+    let port: u16 = port
+        .parse()
+        .with_context(|| format!("invalid port in {}", s))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() {
+        return Err(anyhow!("expected HOST:PORT, got {}", s));
+    }
+    Ok((host.to_string(), port))
+}
+
+fn socks5_reply_reason(code: u8) -> &'static str {
+    match code {
+        0x01 => "general SOCKS server failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+async fn socks5_connect<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(stream: &mut S, proxy: &Url, host: &str, port: u16) -> Result<()> {
+    socks5_auth(stream, proxy).await?;
+    socks5_command(stream, SOCKS5_CONNECT, host, port).await?;
+    Ok(())
+}
+
+pub const SOCKS5_CONNECT: u8 = 1;
+pub const SOCKS5_UDP_ASSOCIATE: u8 = 3;
+
+// Method negotiation plus optional username/password auth taken from the
+// proxy URL.
+pub async fn socks5_auth<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(stream: &mut S, proxy: &Url) -> Result<()> {
+    let user = percent_decode_str(proxy.username()).decode_utf8_lossy();
+    let pass = percent_decode_str(proxy.password().unwrap_or("")).decode_utf8_lossy();
+    let with_auth = !user.is_empty();
+    let greeting: &[u8] = if with_auth { &[5, 2, 0, 2] } else { &[5, 1, 0] };
+    stream.write_all(greeting).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != 5 {
+        return Err(anyhow!("proxy is not a SOCKS5 server (version byte {})", choice[0]));
+    }
+    match choice[1] {
+        0 => {}
+        2 if with_auth => {
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(anyhow!("SOCKS5 username/password too long"));
+            }
+            let mut auth = vec![1, user.len() as u8];
+            auth.extend_from_slice(user.as_bytes());
+            auth.push(pass.len() as u8);
+            auth.extend_from_slice(pass.as_bytes());
+            stream.write_all(&auth).await?;
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            // RFC 1929: the sub-negotiation has its own version, 1.
+            if status[0] != 1 {
+                return Err(anyhow!("SOCKS5 auth reply has version {}, expected 1", status[0]));
+            }
+            if status[1] != 0 {
+                return Err(anyhow!("SOCKS5 authentication failed"));
+            }
+        }
+        0xFF => return Err(anyhow!("SOCKS5 proxy rejected all offered auth methods")),
+        m => return Err(anyhow!("SOCKS5 proxy selected unsupported auth method {}", m)),
+    }
+    Ok(())
+}
+
+// Appends ATYP + address + port in SOCKS5 wire format.
+pub fn socks5_encode_addr(out: &mut Vec<u8>, host: &str, port: u16) -> Result<()> {
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            out.push(1);
+            out.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            out.push(4);
+            out.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                return Err(anyhow!("hostname too long for SOCKS5: {}", host));
+            }
+            out.push(3);
+            out.push(host.len() as u8);
+            out.extend_from_slice(host.as_bytes());
+        }
+    }
+    out.extend_from_slice(&port.to_be_bytes());
+    Ok(())
+}
+
+// Sends a request and returns the bound address from the reply; domain-name
+// replies yield `None` for the address.
+pub async fn socks5_command<S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin>(
+    stream: &mut S,
+    cmd: u8,
+    host: &str,
+    port: u16,
+) -> Result<(Option<IpAddr>, u16)> {
+    let mut req = vec![5, cmd, 0];
+    socks5_encode_addr(&mut req, host, port)?;
+    stream.write_all(&req).await?;
+
+    let name = if cmd == SOCKS5_UDP_ASSOCIATE { "UDP ASSOCIATE" } else { "CONNECT" };
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[1] != 0 {
+        return Err(ProxyReply::Socks {
+            command: name,
+            code: head[1],
+        }
+        .into());
+    }
+    let addr_len = match head[3] {
+        1 => 4,
+        4 => 16,
+        3 => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            len[0] as usize
+        }
+        t => return Err(anyhow!("SOCKS5 reply has unknown address type {}", t)),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+    let port = u16::from_be_bytes([bound[addr_len], bound[addr_len + 1]]);
+    let ip = match head[3] {
+        1 => <[u8; 4]>::try_from(&bound[..4]).ok().map(IpAddr::from),
+        4 => <[u8; 16]>::try_from(&bound[..16]).ok().map(IpAddr::from),
+        _ => None,
+    };
+    Ok((ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs socks5_auth for `proxy` against a server that answers with
+    // `replies` and returns the result with what the client sent.
+    async fn socks5_auth_against(proxy: &str, replies: &[u8]) -> (Result<()>, Vec<u8>) {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        server.write_all(replies).await.unwrap();
+        let res = socks5_auth(&mut client, &Url::parse(proxy).unwrap()).await;
+        drop(client);
+        let mut sent = Vec::new();
+        server.read_to_end(&mut sent).await.unwrap();
+        (res, sent)
+    }
+
+    #[tokio::test]
+    async fn socks5_auth_checks_subnegotiation_version() {
+        let (res, sent) = socks5_auth_against("socks5://u:p@127.0.0.1:1080", &[5, 2, 1, 0]).await;
+        res.unwrap();
+        assert_eq!(sent, [5, 2, 0, 2, 1, 1, b'u', 1, b'p']);
+        let (res, _) = socks5_auth_against("socks5://u:p@127.0.0.1:1080", &[5, 2, 5, 0]).await;
+        assert!(res.unwrap_err().to_string().contains("version 5, expected 1"));
+        let (res, _) = socks5_auth_against("socks5://u:p@127.0.0.1:1080", &[5, 2, 1, 1]).await;
+        assert_eq!(res.unwrap_err().to_string(), "SOCKS5 authentication failed");
+        let (res, sent) = socks5_auth_against("socks5://127.0.0.1:1080", &[5, 0]).await;
+        res.unwrap();
+        assert_eq!(sent, [5, 1, 0]);
+    }
+
+    #[test]
+    fn connect_status_lines() {
+        for head in [
+            "HTTP/1.1 200 Connection established",
+            "HTTP/1.0 200 OK",
+            "HTTP/1.1 200",
+            "HTTP/1.1 200 ",
+            "HTTP/1.1 200 Tunnel  is  up\r\nProxy-Agent: test",
+        ] {
+            assert!(check_connect_status(head).is_ok(), "{:?}", head);
+        }
+        for (head, err) in [
+            ("HTTP/1.1 502 Bad Gateway", "proxy CONNECT failed: 502 Bad Gateway"),
+            ("HTTP/1.0 403", "proxy CONNECT failed: 403 "),
+            (
+                "HTTP/1.1 407 Proxy Authentication Required",
+                "proxy CONNECT failed: 407 Proxy Authentication Required (proxy requires authentication)",
+            ),
+            ("HTTP/2 200 OK", "proxy CONNECT failed: malformed response `HTTP/2 200 OK`"),
+            ("HTTP/1.1 OK", "proxy CONNECT failed: malformed response `HTTP/1.1 OK`"),
+            ("SSH-2.0-OpenSSH_9.6", "proxy CONNECT failed: malformed response `SSH-2.0-OpenSSH_9.6`"),
+            ("", "proxy CONNECT failed: malformed response ``"),
+        ] {
+            assert_eq!(check_connect_status(head).unwrap_err().to_string(), err, "{:?}", head);
+        }
+        let err = check_connect_status("HTTP/1.1 502").unwrap_err();
+        assert_eq!(err.downcast_ref::<ProxyReply>().unwrap().label(), "502");
+    }
+
+    // Runs http_connect against a proxy that answers with `chunks`, one
+    // write at a time; returns the result and the request the proxy saw.
+    async fn http_connect_against(chunks: Vec<Vec<u8>>) -> (Result<Vec<u8>>, String) {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        let proxy = tokio::spawn(async move {
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                let mut byte = [0u8];
+                server.read_exact(&mut byte).await.unwrap();
+                request.push(byte[0]);
+            }
+            for chunk in chunks {
+                // The client may be done (and gone) before the last chunks.
+                if server.write_all(&chunk).await.is_err() {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            String::from_utf8(request).unwrap()
+        });
+        let url = Url::parse("http://127.0.0.1:3128").unwrap();
+        let res = http_connect(&mut client, &url, "example.com:443", Duration::from_secs(5)).await;
+        drop(client);
+        (res, proxy.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn http_connect_reassembles_split_replies() {
+        let (res, request) = http_connect_against(vec![
+            b"HTTP/1.1 200 Conn".to_vec(),
+            b"ection established\r\n".to_vec(),
+            b"Proxy-Agent: test\r".to_vec(),
+            b"\n\r".to_vec(),
+            b"\nleftover".to_vec(),
+        ])
+        .await;
+        assert_eq!(
+            request,
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\nProxy-Connection: Keep-Alive\r\n\r\n"
+        );
+        // Whatever came with the last piece of the head is handed on.
+        assert_eq!(res.unwrap(), b"leftover");
+
+        let (res, _) = http_connect_against(vec![b"HTTP/1.1 403 Forbidden\r\n\r\n".to_vec()]).await;
+        assert_eq!(res.unwrap_err().to_string(), "proxy CONNECT failed: 403 Forbidden");
+        let (res, _) = http_connect_against(vec![b"HTTP/1.1 200 OK\r\n".to_vec()]).await;
+        assert_eq!(
+            res.unwrap_err().to_string(),
+            "proxy closed the connection before answering CONNECT"
+        );
+    }
+
+    #[tokio::test]
+    async fn http_connect_limits_the_reply_head() {
+        let mut head = b"HTTP/1.1 200 OK\r\n".to_vec();
+        while head.len() <= 8192 {
+            head.extend_from_slice(b"X-Padding: aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\r\n");
+        }
+        let (res, _) = http_connect_against(vec![head.clone()]).await;
+        assert_eq!(res.unwrap_err().to_string(), "proxy CONNECT response too large");
+        // Just under the limit is fine.
+        head.truncate(8000);
+        head.extend_from_slice(b"\r\n\r\n");
+        let (res, _) = http_connect_against(vec![head]).await;
+        assert_eq!(res.unwrap(), b"");
+    }
+
+    #[tokio::test]
+    async fn bytes_after_the_connect_reply_reach_the_exchange() {
+        use clap::Parser;
+        let (client, mut server) = tokio::io::duplex(64 * 1024);
+        let proxy = tokio::spawn(async move {
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = server.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            // The CONNECT reply and the target's first bytes in one write.
+            server
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nHTTP/1.1 204 No Content\r\n")
+                .await
+                .unwrap();
+            let mut inner = Vec::new();
+            while inner.windows(4).filter(|w| w == b"\r\n\r\n").count() < 2 {
+                let n = server.read(&mut buf).await.unwrap();
+                inner.extend_from_slice(&buf[..n]);
+            }
+            server
+                .write_all(b"Server: early\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(inner).unwrap()
+        });
+        let proxy_url = Url::parse("http://127.0.0.1:3128").unwrap();
+        let stream = proxy_handshake(client, &proxy_url, "example.com:80", Duration::from_secs(5))
+            .await
+            .unwrap();
+        // hyper rejects bytes that arrive before its request goes out, so
+        // the leftover is read by the pipelined exchange, which writes first.
+        let args = crate::cli::Args::try_parse_from(["xray-tester", "-u", "http://example.com/", "--pipeline", "2"]).unwrap();
+        let opts = crate::bench_options(&args, parse_url_target("http://example.com/").unwrap()).unwrap();
+        let reopen = || async { Err(anyhow!("no second tunnel")) };
+        let meta = pipeline_exchange(stream, &opts, &mut Phases::default(), reopen).await.unwrap();
+        assert!(meta.success);
+        assert_eq!(meta.status, Some(204));
+        assert_eq!(meta.pipeline.map(|times| times.len()), Some(2));
+        assert!(proxy.await.unwrap().starts_with("GET / HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn same_seed_same_requests() {
+        use clap::Parser;
+        let url = "http://example.com/item/{rand:1-1000000}/{uuid}";
+        let paths = |flags: &[&str]| {
+            let mut argv = vec!["xray-tester", "-u", url, "--cache-bust"];
+            argv.extend_from_slice(flags);
+            let args = crate::cli::Args::try_parse_from(argv).unwrap();
+            let opts = crate::bench_options(&args, parse_url_target(url).unwrap()).unwrap();
+            let mut reservoir = Reservoir::new(10, opts.seed);
+            (1..=200).for_each(|seq| reservoir.offer((seq, seq as u128)));
+            let mut sampled = reservoir.samples();
+            sampled.sort_unstable();
+            let paths: Vec<String> = (1..=200).map(|seq| request_path(&opts, seq).unwrap()).collect();
+            (opts.target.path.clone(), paths, sampled)
+        };
+        let first = paths(&["--seed", "42"]);
+        assert_eq!(first, paths(&["--seed", "42"]));
+        assert!(first.1[0].starts_with("/item/") && first.1[0].contains("?_xt=1-"), "{}", first.1[0]);
+        // The first request's path is the one shown in the banner.
+        assert_eq!(first.1[0].split('?').next(), Some(first.0.as_str()));
+        let other = paths(&["--seed", "43"]);
+        assert_ne!(first.0, other.0);
+        assert!(first.1.iter().zip(&other.1).all(|(a, b)| a != b));
+        assert_ne!(first.2, other.2);
+    }
+
+    #[tokio::test]
+    async fn socket_options_reach_the_proxy_socket() {
+        use clap::Parser;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap().to_string();
+        let connect = |flags: &'static [&'static str]| {
+            let proxy_addr = proxy_addr.clone();
+            async move {
+                let mut argv = vec!["xray-tester"];
+                argv.extend_from_slice(flags);
+                let args = crate::cli::Args::try_parse_from(argv).unwrap();
+                connect_proxy(&proxy_addr, Duration::from_secs(5), &crate::socket_options(&args))
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let stream = connect(&[
+            "--local-addr",
+            "127.0.0.1",
+            "--tcp-keepalive",
+            "30s",
+            "--so-rcvbuf",
+            "256KiB",
+            "--so-sndbuf",
+            "128KiB",
+        ])
+        .await;
+        let sock = SockRef::from(&stream);
+        assert!(sock.tcp_nodelay().unwrap());
+        assert!(sock.keepalive().unwrap());
+        // The kernel may round buffer sizes up (Linux doubles them).
+        assert!(sock.recv_buffer_size().unwrap() >= 256 * 1024);
+        assert!(sock.send_buffer_size().unwrap() >= 128 * 1024);
+        assert_eq!(stream.local_addr().unwrap().ip(), IpAddr::from([127, 0, 0, 1]));
+
+        let stream = connect(&["--no-tcp-nodelay"]).await;
+        let sock = SockRef::from(&stream);
+        assert!(!sock.tcp_nodelay().unwrap());
+        assert!(!sock.keepalive().unwrap());
+    }
+
+    #[test]
+    fn idn_targets_go_out_as_punycode() {
+        for (url, host, host_header) in [
+            ("https://пример.испытание/", "xn--e1afmkfd.xn--80akhbyknj4f", "xn--e1afmkfd.xn--80akhbyknj4f"),
+            ("https://ПРИМЕР.Испытание/", "xn--e1afmkfd.xn--80akhbyknj4f", "xn--e1afmkfd.xn--80akhbyknj4f"),
+            ("https://xn--e1afmkfd.xn--80akhbyknj4f/", "xn--e1afmkfd.xn--80akhbyknj4f", "xn--e1afmkfd.xn--80akhbyknj4f"),
+            ("http://Bücher.example:8080/x", "xn--bcher-kva.example", "xn--bcher-kva.example:8080"),
+            ("wss://☕.example/ws", "xn--53h.example", "xn--53h.example"),
+            ("http://例子.测试:80/", "xn--fsqu00a.xn--0zwm56d", "xn--fsqu00a.xn--0zwm56d"),
+        ] {
+            let target = parse_url_target(url).unwrap();
+            assert_eq!((target.host.as_str(), target.host_header.as_str()), (host, host_header), "{}", url);
+        }
+        assert_eq!(ascii_host("Bücher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(ascii_host("Example.COM").unwrap(), "Example.COM");
+        assert_eq!(ascii_host("пример.испытание").unwrap(), "xn--e1afmkfd.xn--80akhbyknj4f");
+        assert!(parse_url_target("http://exa mple.com/").is_err());
+    }
+
+    #[tokio::test]
+    async fn gibberish_is_a_protocol_error() {
+        use clap::Parser;
+        let (addr, server) = crate::mock::start_target(crate::mock::MockTargetOptions {
+            listen: ([127, 0, 0, 1], 0).into(),
+            status: 200,
+            body_size: 0,
+            delay: None,
+            truncate_at: None,
+            garbage: true,
+            reorder: false,
+        })
+        .await
+        .unwrap();
+        let url = format!("http://{}/", addr);
+        let args = crate::cli::Args::try_parse_from(["xray-tester", "-u", &url]).unwrap();
+        let opts = crate::bench_options(&args, parse_url_target(&url).unwrap()).unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let err = exchange(stream, &opts).await.unwrap_err();
+        server.abort();
+        let malformed = err.downcast_ref::<ProtocolError>().unwrap();
+        assert!(err.to_string().starts_with("malformed HTTP response: "), "{}", err);
+        assert_eq!(malformed.escaped_head(), "\\x15\\x03\\x01\\x00\\x02\\x02PSSH-2.0-not-http\\r\\n\\r\\n");
+
+        let long = ProtocolError {
+            detail: String::new(),
+            head: vec![0xff; HEAD_BYTES],
+        };
+        assert_eq!(long.escaped_head(), "\\xff".repeat(HEAD_BYTES) + "...");
+    }
+
+    #[test]
+    fn ip_schedule_interleaves_by_weight() {
+        let [a, b, c]: [IpAddr; 3] = ["192.0.2.1", "192.0.2.2", "2001:db8::1"].map(|ip| ip.parse().unwrap());
+        assert_eq!(ip_schedule(&[a, b], &[]), [a, b]);
+        assert_eq!(ip_schedule(&[a, b], &[(a, 3)]), [a, a, b, a]);
+        assert_eq!(ip_schedule(&[a, b, c], &[(b, 0)]), [a, c]);
+        assert_eq!(ip_schedule(&[a, b], &[(a, 4), (b, 2)]), [a, b, a]);
+        assert!(ip_schedule(&[a], &[(a, 0)]).is_empty());
+    }
+
+    #[test]
+    fn gauge_tracks_peak() {
+        let gauge = Arc::new(Gauge::new(Instant::now()));
+        let a = gauge.enter();
+        let b = gauge.enter();
+        drop(a);
+        let c = gauge.enter();
+        assert_eq!(gauge.current.load(Ordering::Relaxed), 2);
+        drop((b, c));
+        assert_eq!(gauge.current.load(Ordering::Relaxed), 0);
+        assert_eq!(gauge.peak.load(Ordering::Relaxed), 2);
+
+        // Two in flight for 0.5s, then one across the second boundary.
+        let start = Instant::now();
+        let mut area = Area { start, last: Duration::ZERO, per_sec: BTreeMap::new() };
+        area.advance(2, start + Duration::from_millis(500));
+        area.advance(1, start + Duration::from_millis(1500));
+        assert_eq!(area.per_sec, BTreeMap::from([(0, 1.5), (1, 0.5)]));
+    }
+
+    #[test]
+    fn retry_after_values() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        // Dates in the past mean no wait.
+        assert_eq!(parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"), Some(Duration::ZERO));
+        let ahead = parse_retry_after("Fri, 01 Jan 2100 00:00:00 GMT").unwrap();
+        assert!(ahead > Duration::from_secs(365 * 24 * 3600));
+        for bad in ["", "-1", "1.5", "soon", "Sun, 06 Nov 1994 08:49:37 UTC", "Sun, 06 Foo 1994 08:49:37 GMT"] {
+            assert_eq!(parse_retry_after(bad), None, "{:?}", bad);
+        }
     }
 }
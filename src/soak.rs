@@ -0,0 +1,107 @@
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use url::Url;
+
+use crate::cli::{Args, OutputFormat};
+use crate::pretty;
+use crate::request::{run_bench, BenchOptions};
+use crate::stats::{mix64, Harness, Stats};
+
+// --soak: back-to-back windows of --summary-interval until `length` has
+// passed. After each window the cumulative Stats is compacted, so memory
+// does not grow with the run; a pretty run prints the window's summary
+// line and the full cumulative report. Ctrl-C stops starting requests,
+// lets the ones in flight finish and reports what was collected.
+pub async fn run(
+    args: &Args,
+    proxy: Arc<Url>,
+    proxy_addr: &str,
+    proxy_label: &str,
+    url_str: &str,
+    opts: BenchOptions,
+    harness: Option<Harness>,
+) -> Result<Stats> {
+    let length = args.soak.unwrap_or_default();
+    if args.output == OutputFormat::Wrk || args.timeline || args.per_connection {
+        return Err(anyhow!("--soak cannot be combined with --output wrk, --timeline or --per-connection"));
+    }
+    if args.latency_csv.is_some() && args.sample_latencies.is_none() {
+        return Err(anyhow!("--soak keeps no raw latencies; use --latency-csv with --sample-latencies"));
+    }
+    let stop = Arc::new(AtomicBool::new(false));
+    let on_ctrl_c = stop.clone();
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        tracing::warn!("interrupted; finishing requests in flight");
+        on_ctrl_c.store(true, Ordering::Relaxed);
+    });
+
+    let pretty_output = !args.quiet && !args.summary_only && args.output == OutputFormat::Pretty;
+    let dumps = opts.dump_failures.clone();
+    let started = Instant::now();
+    let mut total = Stats {
+        harness,
+        ..Stats::default()
+    };
+    // Start time, requests, p50 and p99 of each window.
+    let mut windows: Vec<(SystemTime, usize, Option<f64>, Option<f64>)> = Vec::new();
+    while started.elapsed() < length && !stop.load(Ordering::Relaxed) {
+        let window_opts = BenchOptions {
+            iterations: usize::MAX,
+            stop_after: Some(args.summary_interval.min(length - started.elapsed())),
+            stop: Some(stop.clone()),
+            // Sequence numbers restart every window.
+            seed: mix64(opts.seed, windows.len() as u64),
+            ..opts.clone()
+        };
+        let started_at = SystemTime::now();
+        let stats = run_bench(proxy.clone(), proxy_addr, Arc::new(window_opts)).await?;
+        windows.push((
+            started_at,
+            stats.completed(),
+            stats.latency_percentile(0.50),
+            stats.latency_percentile(0.99),
+        ));
+        total.merge(&stats);
+        total.compact();
+        let summary = format!(
+            "[{}] window #{} {}\n[{}] total     {}",
+            humantime::format_duration(Duration::from_secs(started.elapsed().as_secs())),
+            windows.len(),
+            pretty::summary_line(&stats),
+            humantime::format_duration(Duration::from_secs(started.elapsed().as_secs())),
+            pretty::summary_line(&total)
+        );
+        match args.output {
+            _ if args.quiet => {}
+            OutputFormat::Pretty => println!("\n{}", summary),
+            // Keeps stdout a single JSON document.
+            _ => eprintln!("{}", summary),
+        }
+        if pretty_output && started.elapsed() < length && !stop.load(Ordering::Relaxed) {
+            pretty::print_results(&total, total.completed(), pretty::color_enabled(args.no_color), args.units);
+        }
+        if stats.aborted.is_some() {
+            break;
+        }
+    }
+
+    if pretty_output {
+        println!("\n{:>6}  {:>20}  {:>10}  {:>10}  {:>10}", "Window", "Started", "Requests", "p50", "p99");
+        let fmt_ms = |v: Option<f64>| pretty::fmt_ms(v, args.units);
+        for (i, (started_at, requests, p50, p99)) in windows.iter().enumerate() {
+            println!(
+                "{:>6}  {:>20}  {:>10}  {:>10}  {:>10}",
+                i + 1,
+                humantime::format_rfc3339_seconds(*started_at).to_string(),
+                requests,
+                fmt_ms(*p50),
+                fmt_ms(*p99)
+            );
+        }
+    }
+    crate::finish(args, proxy_label, url_str, total.completed(), &total, None, dumps.as_ref()).await?;
+    Ok(total)
+}
@@ -1,23 +1,423 @@
-use std::collections::BTreeMap;
-use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
 
-#[derive(Debug, Default, Clone)]
+use crate::request::RangeOutcome;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Stats {
     pub latencies_us: Vec<u128>,
+    // Latencies folded out of `latencies_us` by `compact` (--soak); when
+    // present the latency accessors read it instead.
+    pub latency_histogram: Option<Histogram>,
+    // Successful latencies keyed by request sequence number, which follows
+    // request start order; completions arrive in arbitrary order.
+    pub latencies_by_seq: Vec<(usize, u128)>,
+    // Rate mode only: successful latencies plus how late each request
+    // started, i.e. measured from its scheduled start (wrk2-style).
+    pub corrected_us: Vec<u128>,
+    // Time from start to failure of every failed request, errors and
+    // unwanted responses alike: a fast reject and a hang look the same in
+    // `fail`.
+    pub failure_us: Vec<u128>,
+    // TCP connect to the proxy (to the target with direct://), for every
+    // request that got that far.
+    pub proxy_connect_us: Vec<u128>,
     pub success: usize,
     pub fail: usize,
+    // Successes slower than --slow-threshold; still counted in `success`.
+    pub slow: usize,
+    #[serde(with = "duration_us")]
+    pub slow_threshold: Option<Duration>,
     pub conn_errors: usize,
     pub timeout_errors: usize,
+    // `timeout_errors` split by the step that ran out of time (see
+    // `request::TimeoutPhase`).
+    pub timeouts_by_phase: BTreeMap<String, usize>,
     pub tls_errors: usize,
-    pub total_duration_ms: u128,
+    pub ws_rejected: usize,
+    pub udp_refused: usize,
+    pub udp_no_reply: usize,
+    // Connections that failed with EADDRNOTAVAIL/EADDRINUSE.
+    pub port_exhaustion: usize,
+    // Download bodies that ended short of their Content-Length or broke off
+    // after the response head arrived.
+    pub truncated: usize,
+    // Responses that were not valid HTTP at all.
+    pub protocol_errors: usize,
+    // TLS handshakes that did not agree on --require-alpn.
+    pub alpn_mismatch: usize,
+    // Protocol negotiated per target TLS handshake ("none" included).
+    pub alpn: BTreeMap<String, usize>,
+    // Request tasks that panicked: a bug in xray-tester, not a target
+    // failure. Also counted in `fail`.
+    pub internal_errors: usize,
+    // Their panic messages, deduplicated.
+    pub panics: BTreeMap<String, usize>,
+    // 429/503 answers retried after their Retry-After; not failures.
+    pub rate_limited: usize,
+    // --pipeline batches finished over a second tunnel after the proxy
+    // closed the first midway.
+    pub tunnel_reconnects: usize,
+    pub download: Throughput,
+    pub upload: Throughput,
+    pub ws_ping_us: Vec<u128>,
+    #[serde(rename = "total_duration_us", with = "duration_us")]
+    pub total_duration: Duration,
+    // Wall-clock start of the run; lines up per-second buckets in `merge`.
+    #[serde(with = "rfc3339")]
+    pub started_at: Option<SystemTime>,
     pub status_counts: BTreeMap<u16, usize>,
-    pub rps_secs: BTreeMap<u64, u32>,
+    // Responses by HTTP version, e.g. "HTTP/1.1".
+    pub http_versions: BTreeMap<String, usize>,
+    pub grpc_status_counts: BTreeMap<u32, usize>,
+    // Tunnels refused by the proxy, keyed by its reply ("502 Bad Gateway",
+    // "SOCKS 0x04 host unreachable"); also counted in `conn_errors`.
+    pub proxy_replies: BTreeMap<String, usize>,
+    // Requests finished in each second of the run, keyed by seconds since
+    // start: every completion, and the successful ones only.
+    pub completions_per_sec: BTreeMap<u64, u32>,
+    pub success_per_sec: BTreeMap<u64, u32>,
+    // Set when the run was stopped early by an --abort-* threshold.
+    pub aborted: Option<String>,
+    // Set when Ctrl-C stopped the run; the rest covers what finished before.
+    pub interrupted: bool,
+    // Fixed-size uniform sample of latencies, kept with --sample-latencies.
+    pub reservoir: Option<Reservoir>,
+    // Per-worker breakdown keyed by worker slot, kept with --per-connection.
+    pub per_worker: Option<BTreeMap<usize, WorkerStats>>,
+    // The same per target address, keyed by the literal IP; kept with
+    // --resolve-target-locally.
+    pub per_ip: Option<BTreeMap<String, WorkerStats>>,
+    // Every finished request in completion order, kept with --sqlite; too
+    // bulky for the JSON report.
+    #[serde(skip)]
+    pub samples: Option<Vec<Sample>>,
+    // Per-second breakdown indexed by seconds since start, kept with
+    // --timeline; idle seconds are zero entries, so there are no gaps.
+    pub timeline: Option<Vec<TimelineSecond>>,
+    // Conditional requests only: 304 and 200 answers with their latencies.
+    pub revalidation: Option<Revalidation>,
+    // --range only: how responses answered the Range header.
+    pub range: Option<RangeStats>,
+    // --pipeline only: time to each response of a batch, indexed by its
+    // position in the batch.
+    pub pipeline: Option<Vec<Histogram>>,
+    // Pages served by the proxy itself (--intercept-header), keyed by the
+    // matching "Name: value"; also counted in `fail`.
+    pub intercepted: BTreeMap<String, usize>,
+    // Responses that failed an --expect-header, keyed by the expectation.
+    pub header_mismatches: BTreeMap<String, usize>,
+    // --header-stats only.
+    pub header_values: Option<HeaderValues>,
+    // Server and Via response headers by value (cut to 100 characters),
+    // "(absent)" included; every mode that reads response headers.
+    pub server_headers: BTreeMap<String, usize>,
+    pub via_headers: BTreeMap<String, usize>,
+    // Where 3xx responses pointed; `None` with --max-locations 0.
+    pub locations: Option<Locations>,
+    // --slowest only.
+    pub slowest: Option<Slowest>,
+    pub in_flight: Option<InFlight>,
+    // --calibrate only.
+    pub harness: Option<Harness>,
+    // First distinct error messages per category; `None` with
+    // --error-samples 0.
+    pub error_samples: Option<ErrorSamples>,
+}
+
+// --calibrate: latency of loopback requests to an in-process target with
+// no proxy, i.e. what xray-tester itself adds to every measurement.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Harness {
+    pub requests: usize,
+    pub p50_ms: f64,
+    pub p99_ms: f64,
+}
+
+// Requests running at once, to tell whether --concurrency was reached.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InFlight {
+    pub configured: usize,
+    pub peak: usize,
+    // Average in flight during each second of the run, keyed by seconds
+    // since start; weighted by time between requests starting and ending.
+    pub per_sec: BTreeMap<u64, f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ErrorSamples {
+    // Distinct messages kept per category.
+    pub limit: usize,
+    // Keyed by category (see `pretty::error_counts`); messages in the order
+    // they were first seen.
+    pub categories: BTreeMap<String, Vec<ErrorSample>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorSample {
+    pub message: String,
+    pub count: usize,
+}
+
+impl ErrorSamples {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            categories: BTreeMap::new(),
+        }
+    }
+
+    // Messages past the limit are dropped; ones already kept are counted.
+    fn add(&mut self, category: &str, message: &str, count: usize) {
+        let samples = match self.categories.get_mut(category) {
+            Some(samples) => samples,
+            None => self.categories.entry(category.to_string()).or_default(),
+        };
+        if let Some(s) = samples.iter_mut().find(|s| s.message == message) {
+            s.count += count;
+        } else if samples.len() < self.limit {
+            samples.push(ErrorSample {
+                message: message.to_string(),
+                count,
+            });
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Locations {
+    // How many of the most common targets the report lists.
+    pub shown: usize,
+    pub counts: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeaderValues {
+    pub name: String,
+    // Keyed by header value; "(absent)" when the response lacked it.
+    pub counts: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct RangeStats {
+    pub partial: usize,
+    pub ignored: usize,
+    pub invalid: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Revalidation {
+    pub not_modified_us: Vec<u128>,
+    pub full_us: Vec<u128>,
+}
+
+impl Revalidation {
+    pub fn not_modified_percentile(&self, p: f64) -> Option<f64> {
+        percentile_ms(&self.not_modified_us, p)
+    }
+
+    pub fn full_percentile(&self, p: f64) -> Option<f64> {
+        percentile_ms(&self.full_us, p)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimelineSecond {
+    pub ok: u32,
+    // All failures, timeouts included.
+    pub fail: u32,
+    pub timeout: u32,
+    // Successful latencies that finished in this second.
+    pub latencies_us: Vec<u128>,
+}
+
+impl TimelineSecond {
+    pub fn p50(&self) -> Option<f64> {
+        percentile_ms(&self.latencies_us, 0.50)
+    }
+
+    fn add(&mut self, other: &TimelineSecond) {
+        self.ok += other.ok;
+        self.fail += other.fail;
+        self.timeout += other.timeout;
+        self.latencies_us.extend_from_slice(&other.latencies_us);
+    }
+}
+
+// --sqlite: how one request ended. Latency and status are absent when
+// it failed before a response; `error_kind` is set for every failure.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub seq: usize,
+    pub latency_us: Option<u128>,
+    pub status: Option<u16>,
+    pub error_kind: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WorkerStats {
+    pub requests: usize,
+    pub errors: usize,
+    pub latencies_us: Vec<u128>,
+}
+
+impl WorkerStats {
+    pub fn latency_percentile(&self, p: f64) -> Option<f64> {
+        percentile_ms(&self.latencies_us, p)
+    }
 }
 
 impl Stats {
-    pub fn record_success(&mut self, dur: Duration) {
+    pub fn record_success(&mut self, seq: usize, dur: Duration) {
         self.success += 1;
+        self.record_latency(seq, dur);
+    }
+
+    pub fn record_corrected(&mut self, dur: Duration) {
+        self.corrected_us.push(dur.as_micros());
+    }
+
+    // Adds a sample without touching the success/fail counters.
+    pub fn record_proxy_connect(&mut self, dur: Duration) {
+        self.proxy_connect_us.push(dur.as_micros());
+    }
+
+    pub fn record_failure_time(&mut self, dur: Duration) {
+        self.failure_us.push(dur.as_micros());
+    }
+
+    pub fn record_latency(&mut self, seq: usize, dur: Duration) {
         self.latencies_us.push(dur.as_micros());
+        self.latencies_by_seq.push((seq, dur.as_micros()));
+        if let Some(r) = self.reservoir.as_mut() {
+            r.offer((seq, dur.as_micros()));
+        }
+    }
+
+    pub fn record_sample(&mut self, seq: usize, latency: Option<Duration>, status: Option<u16>, error_kind: Option<&'static str>) {
+        if let Some(samples) = self.samples.as_mut() {
+            samples.push(Sample {
+                seq,
+                latency_us: latency.map(|d| d.as_micros()),
+                status,
+                error_kind,
+            });
+        }
+    }
+
+    // `latency` is `None` for a failed request.
+    pub fn record_worker(&mut self, worker: usize, latency: Option<Duration>) {
+        let Some(workers) = self.per_worker.as_mut() else {
+            return;
+        };
+        let w = workers.entry(worker).or_default();
+        w.requests += 1;
+        match latency {
+            Some(dur) => w.latencies_us.push(dur.as_micros()),
+            None => w.errors += 1,
+        }
+    }
+
+    pub fn record_ip(&mut self, ip: IpAddr, latency: Option<Duration>) {
+        let Some(ips) = self.per_ip.as_mut() else {
+            return;
+        };
+        let w = ips.entry(ip.to_string()).or_default();
+        w.requests += 1;
+        match latency {
+            Some(dur) => w.latencies_us.push(dur.as_micros()),
+            None => w.errors += 1,
+        }
+    }
+
+    pub fn record_revalidation(&mut self, status: u16, dur: Duration) {
+        let Some(r) = self.revalidation.as_mut() else {
+            return;
+        };
+        match status {
+            304 => r.not_modified_us.push(dur.as_micros()),
+            200 => r.full_us.push(dur.as_micros()),
+            _ => {}
+        }
+    }
+
+    pub fn record_range(&mut self, outcome: RangeOutcome) {
+        let Some(r) = self.range.as_mut() else {
+            return;
+        };
+        match outcome {
+            RangeOutcome::Partial => r.partial += 1,
+            RangeOutcome::Ignored => r.ignored += 1,
+            RangeOutcome::Invalid => r.invalid += 1,
+        }
+    }
+
+    pub fn record_pipeline(&mut self, times: &[Duration]) {
+        let Some(p) = self.pipeline.as_mut() else {
+            return;
+        };
+        if p.len() < times.len() {
+            p.resize_with(times.len(), Histogram::default);
+        }
+        for (h, t) in p.iter_mut().zip(times) {
+            h.record(t.as_micros());
+        }
+    }
+
+    pub fn record_header_mismatch(&mut self, expectation: String) {
+        self.fail += 1;
+        *self.header_mismatches.entry(expectation).or_insert(0) += 1;
+    }
+
+    pub fn record_intercepted(&mut self, header: String) {
+        self.fail += 1;
+        *self.intercepted.entry(header).or_insert(0) += 1;
+    }
+
+    pub fn record_responders(&mut self, server: String, via: String) {
+        *self.server_headers.entry(server).or_insert(0) += 1;
+        *self.via_headers.entry(via).or_insert(0) += 1;
+    }
+
+    pub fn record_header_value(&mut self, value: String) {
+        if let Some(h) = self.header_values.as_mut() {
+            *h.counts.entry(value).or_insert(0) += 1;
+        }
+    }
+
+    pub fn record_location(&mut self, location: String) {
+        if let Some(l) = self.locations.as_mut() {
+            *l.counts.entry(location).or_insert(0) += 1;
+        }
+    }
+
+    pub fn record_slowest(
+        &mut self,
+        seq: usize,
+        total: Duration,
+        status: Option<u16>,
+        started_at: SystemTime,
+        phase: Option<&str>,
+    ) {
+        if let Some(s) = self.slowest.as_mut() {
+            s.offer(SlowRequest {
+                us: total.as_micros(),
+                seq,
+                status,
+                started_at: Some(started_at),
+                phase: phase.map(str::to_string),
+            });
+        }
+    }
+
+    pub fn record_error_sample(&mut self, category: &str, message: String) {
+        if let Some(s) = self.error_samples.as_mut() {
+            s.add(category, &message, 1);
+        }
     }
 
     pub fn record_fail(&mut self) {
@@ -28,9 +428,18 @@ impl Stats {
         *self.status_counts.entry(code).or_insert(0) += 1;
     }
 
-    pub fn record_timeout(&mut self) {
+    pub fn record_http_version(&mut self, version: hyper::Version) {
+        *self.http_versions.entry(format!("{:?}", version)).or_insert(0) += 1;
+    }
+
+    pub fn record_grpc_status(&mut self, code: u32) {
+        *self.grpc_status_counts.entry(code).or_insert(0) += 1;
+    }
+
+    pub fn record_timeout(&mut self, phase: &str) {
         self.fail += 1;
         self.timeout_errors += 1;
+        *self.timeouts_by_phase.entry(phase.to_string()).or_insert(0) += 1;
     }
 
     pub fn record_conn_error(&mut self) {
@@ -38,29 +447,412 @@ impl Stats {
         self.conn_errors += 1;
     }
 
+    pub fn record_internal_error(&mut self, message: String) {
+        self.fail += 1;
+        self.internal_errors += 1;
+        *self.panics.entry(message).or_insert(0) += 1;
+    }
+
     pub fn record_tls_error(&mut self) {
         self.fail += 1;
         self.tls_errors += 1;
     }
 
-    pub fn record_success_bucket(&mut self, sec: u64) {
-        *self.rps_secs.entry(sec).or_insert(0) += 1;
+    pub fn record_ws_rejected(&mut self) {
+        self.fail += 1;
+        self.ws_rejected += 1;
+    }
+
+    pub fn record_proxy_reply(&mut self, label: String) {
+        self.record_conn_error();
+        *self.proxy_replies.entry(label).or_insert(0) += 1;
+    }
+
+    pub fn record_udp_refused(&mut self) {
+        self.fail += 1;
+        self.udp_refused += 1;
+    }
+
+    pub fn record_port_exhaustion(&mut self) {
+        self.fail += 1;
+        self.port_exhaustion += 1;
+    }
+
+    pub fn record_truncated(&mut self) {
+        self.fail += 1;
+        self.truncated += 1;
+    }
+
+    pub fn record_protocol_error(&mut self) {
+        self.fail += 1;
+        self.protocol_errors += 1;
+    }
+
+    pub fn record_alpn_mismatch(&mut self) {
+        self.fail += 1;
+        self.alpn_mismatch += 1;
+    }
+
+    // Adds a handshake without touching the success/fail counters.
+    pub fn record_alpn(&mut self, protocol: &str) {
+        *self.alpn.entry(protocol.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn record_udp_no_reply(&mut self) {
+        self.fail += 1;
+        self.udp_no_reply += 1;
+    }
+
+    pub fn record_ws_ping(&mut self, dur: Duration) {
+        self.ws_ping_us.push(dur.as_micros());
+    }
+
+    pub fn record_completion(&mut self, sec: u64, success: bool) {
+        *self.completions_per_sec.entry(sec).or_insert(0) += 1;
+        if success {
+            *self.success_per_sec.entry(sec).or_insert(0) += 1;
+        }
+    }
+
+    // `latency` is set for successes only.
+    pub fn record_second(&mut self, sec: u64, latency: Option<Duration>, timeout: bool) {
+        let Some(timeline) = self.timeline.as_mut() else {
+            return;
+        };
+        let sec = sec as usize;
+        if timeline.len() <= sec {
+            timeline.resize_with(sec + 1, Default::default);
+        }
+        let slot = &mut timeline[sec];
+        match latency {
+            Some(dur) => {
+                slot.ok += 1;
+                slot.latencies_us.push(dur.as_micros());
+            }
+            None => slot.fail += 1,
+        }
+        if timeout {
+            slot.timeout += 1;
+        }
+    }
+
+    // Folds the per-request samples into constant-size state so a --soak run
+    // does not grow with its length: latencies go into `latency_histogram`,
+    // the other raw series (sequence order, corrected, time to failure,
+    // connect, ws ping) are dropped.
+    pub fn compact(&mut self) {
+        let histogram = self.latency_histogram.get_or_insert_with(Histogram::default);
+        for us in std::mem::take(&mut self.latencies_us) {
+            histogram.record(us);
+        }
+        self.latencies_by_seq = Vec::new();
+        self.corrected_us = Vec::new();
+        self.failure_us = Vec::new();
+        self.proxy_connect_us = Vec::new();
+        self.ws_ping_us = Vec::new();
+    }
+
+    // Combines another run into this one: a later stage, or a shard that ran
+    // at the same time on another machine. When both sides know their start
+    // time, per-second buckets are moved onto a shared timeline (to the
+    // nearest second) and the duration spans from the earliest start to the
+    // latest end; otherwise both are taken to have started together.
+    // Sequence numbers of the other run are shifted to come after ours.
+    pub fn merge(&mut self, other: &Stats) {
+        let (self_shift, other_shift) = match (self.started_at, other.started_at) {
+            (Some(mine), Some(theirs)) => {
+                let origin = mine.min(theirs);
+                let offset = |t: SystemTime| t.duration_since(origin).unwrap_or_default();
+                let (mine, theirs) = (offset(mine), offset(theirs));
+                self.total_duration = (mine + self.total_duration).max(theirs + other.total_duration);
+                self.started_at = Some(origin);
+                let secs = |d: Duration| ((d.as_millis() + 500) / 1000) as u64;
+                (secs(mine), secs(theirs))
+            }
+            _ => {
+                self.total_duration = self.total_duration.max(other.total_duration);
+                self.started_at = self.started_at.or(other.started_at);
+                (0, 0)
+            }
+        };
+        if self_shift > 0 {
+            for series in [&mut self.completions_per_sec, &mut self.success_per_sec] {
+                *series = std::mem::take(series)
+                    .into_iter()
+                    .map(|(sec, n)| (sec + self_shift, n))
+                    .collect();
+            }
+            if let Some(in_flight) = self.in_flight.as_mut() {
+                in_flight.per_sec = std::mem::take(&mut in_flight.per_sec)
+                    .into_iter()
+                    .map(|(sec, n)| (sec + self_shift, n))
+                    .collect();
+            }
+            if let Some(timeline) = self.timeline.as_mut() {
+                timeline.splice(0..0, (0..self_shift).map(|_| TimelineSecond::default()));
+            }
+        }
+        let seq_offset = self.latencies_by_seq.iter().map(|&(seq, _)| seq).max().unwrap_or(0);
+        self.latencies_us.extend_from_slice(&other.latencies_us);
+        self.corrected_us.extend_from_slice(&other.corrected_us);
+        self.failure_us.extend_from_slice(&other.failure_us);
+        self.proxy_connect_us.extend_from_slice(&other.proxy_connect_us);
+        self.latencies_by_seq
+            .extend(other.latencies_by_seq.iter().map(|&(seq, us)| (seq + seq_offset, us)));
+        // A compacted side leaves nothing to merge sample by sample.
+        if let Some(h) = &other.latency_histogram {
+            self.latency_histogram.get_or_insert_with(Histogram::default).merge(h);
+        }
+        if self.latency_histogram.is_some() {
+            self.compact();
+        }
+        self.success += other.success;
+        self.fail += other.fail;
+        self.slow += other.slow;
+        self.slow_threshold = self.slow_threshold.or(other.slow_threshold);
+        self.conn_errors += other.conn_errors;
+        self.timeout_errors += other.timeout_errors;
+        for (phase, &n) in &other.timeouts_by_phase {
+            *self.timeouts_by_phase.entry(phase.clone()).or_insert(0) += n;
+        }
+        self.tls_errors += other.tls_errors;
+        self.ws_rejected += other.ws_rejected;
+        self.udp_refused += other.udp_refused;
+        self.udp_no_reply += other.udp_no_reply;
+        self.port_exhaustion += other.port_exhaustion;
+        self.internal_errors += other.internal_errors;
+        for (message, &n) in &other.panics {
+            *self.panics.entry(message.clone()).or_insert(0) += n;
+        }
+        self.truncated += other.truncated;
+        self.protocol_errors += other.protocol_errors;
+        self.alpn_mismatch += other.alpn_mismatch;
+        for (protocol, &n) in &other.alpn {
+            *self.alpn.entry(protocol.clone()).or_insert(0) += n;
+        }
+        self.rate_limited += other.rate_limited;
+        self.tunnel_reconnects += other.tunnel_reconnects;
+        self.download.append(&other.download);
+        self.upload.append(&other.upload);
+        self.ws_ping_us.extend_from_slice(&other.ws_ping_us);
+        for (&code, &n) in &other.status_counts {
+            *self.status_counts.entry(code).or_insert(0) += n;
+        }
+        for (version, &n) in &other.http_versions {
+            *self.http_versions.entry(version.clone()).or_insert(0) += n;
+        }
+        for (&code, &n) in &other.grpc_status_counts {
+            *self.grpc_status_counts.entry(code).or_insert(0) += n;
+        }
+        for (label, &n) in &other.proxy_replies {
+            *self.proxy_replies.entry(label.clone()).or_insert(0) += n;
+        }
+        for (&sec, &n) in &other.completions_per_sec {
+            *self.completions_per_sec.entry(sec + other_shift).or_insert(0) += n;
+        }
+        for (&sec, &n) in &other.success_per_sec {
+            *self.success_per_sec.entry(sec + other_shift).or_insert(0) += n;
+        }
+        if let Some(theirs) = &other.in_flight {
+            let mine = self.in_flight.get_or_insert_with(InFlight::default);
+            // Runs that overlap in time add up.
+            mine.configured = mine.configured.max(theirs.configured);
+            mine.peak = mine.peak.max(theirs.peak);
+            for (&sec, &n) in &theirs.per_sec {
+                *mine.per_sec.entry(sec + other_shift).or_insert(0.0) += n;
+            }
+        }
+        if self.harness.is_none() {
+            self.harness = other.harness.clone();
+        }
+        if self.aborted.is_none() {
+            self.aborted = other.aborted.clone();
+        }
+        self.interrupted |= other.interrupted;
+        match (self.reservoir.as_mut(), &other.reservoir) {
+            (Some(mine), Some(theirs)) => mine.merge(theirs, seq_offset),
+            (None, Some(theirs)) => {
+                let mut mine = Reservoir::new(theirs.capacity, theirs.seed);
+                mine.merge(theirs, seq_offset);
+                self.reservoir = Some(mine);
+            }
+            _ => {}
+        }
+        if let Some(theirs) = &other.timeline {
+            let mine = self.timeline.get_or_insert_with(Vec::new);
+            let shift = other_shift as usize;
+            if mine.len() < shift + theirs.len() {
+                mine.resize_with(shift + theirs.len(), Default::default);
+            }
+            for (slot, second) in mine[shift..].iter_mut().zip(theirs) {
+                slot.add(second);
+            }
+        }
+        if let Some(theirs) = &other.samples {
+            self.samples.get_or_insert_with(Vec::new).extend_from_slice(theirs);
+        }
+        if let Some(theirs) = &other.per_worker {
+            let mine = self.per_worker.get_or_insert_with(BTreeMap::new);
+            for (&id, w) in theirs {
+                let m = mine.entry(id).or_default();
+                m.requests += w.requests;
+                m.errors += w.errors;
+                m.latencies_us.extend_from_slice(&w.latencies_us);
+            }
+        }
+        if let Some(theirs) = &other.per_ip {
+            let mine = self.per_ip.get_or_insert_with(BTreeMap::new);
+            for (ip, w) in theirs {
+                let m = mine.entry(ip.clone()).or_default();
+                m.requests += w.requests;
+                m.errors += w.errors;
+                m.latencies_us.extend_from_slice(&w.latencies_us);
+            }
+        }
+        for (mine, theirs) in [
+            (&mut self.server_headers, &other.server_headers),
+            (&mut self.via_headers, &other.via_headers),
+        ] {
+            for (value, &n) in theirs {
+                *mine.entry(value.clone()).or_insert(0) += n;
+            }
+        }
+        for (header, &n) in &other.intercepted {
+            *self.intercepted.entry(header.clone()).or_insert(0) += n;
+        }
+        for (expectation, &n) in &other.header_mismatches {
+            *self.header_mismatches.entry(expectation.clone()).or_insert(0) += n;
+        }
+        if let Some(theirs) = &other.header_values {
+            let mine = self.header_values.get_or_insert_with(|| HeaderValues {
+                name: theirs.name.clone(),
+                counts: BTreeMap::new(),
+            });
+            for (value, &n) in &theirs.counts {
+                *mine.counts.entry(value.clone()).or_insert(0) += n;
+            }
+        }
+        if let Some(theirs) = &other.locations {
+            let mine = self.locations.get_or_insert_with(|| Locations {
+                shown: theirs.shown,
+                counts: BTreeMap::new(),
+            });
+            for (location, &n) in &theirs.counts {
+                *mine.counts.entry(location.clone()).or_insert(0) += n;
+            }
+        }
+        if let Some(theirs) = &other.slowest {
+            let mine = self.slowest.get_or_insert_with(|| Slowest::new(theirs.capacity));
+            for Reverse(r) in &theirs.heap {
+                mine.offer(SlowRequest {
+                    seq: r.seq + seq_offset,
+                    ..r.clone()
+                });
+            }
+        }
+        if let Some(theirs) = &other.error_samples {
+            let mine = self.error_samples.get_or_insert_with(|| ErrorSamples::new(theirs.limit));
+            for (category, samples) in &theirs.categories {
+                for s in samples {
+                    mine.add(category, &s.message, s.count);
+                }
+            }
+        }
+        if let Some(theirs) = &other.range {
+            let mine = self.range.get_or_insert_with(RangeStats::default);
+            mine.partial += theirs.partial;
+            mine.ignored += theirs.ignored;
+            mine.invalid += theirs.invalid;
+        }
+        if let Some(theirs) = &other.pipeline {
+            let mine = self.pipeline.get_or_insert_with(Vec::new);
+            if mine.len() < theirs.len() {
+                mine.resize_with(theirs.len(), Histogram::default);
+            }
+            for (h, t) in mine.iter_mut().zip(theirs) {
+                h.merge(t);
+            }
+        }
+        if let Some(theirs) = &other.revalidation {
+            let mine = self.revalidation.get_or_insert_with(Revalidation::default);
+            mine.not_modified_us.extend_from_slice(&theirs.not_modified_us);
+            mine.full_us.extend_from_slice(&theirs.full_us);
+        }
+    }
+
+    pub fn completed(&self) -> usize {
+        self.success + self.fail
+    }
+
+    // Each status code with its count and share of completed requests, in
+    // percent.
+    pub fn status_shares(&self) -> Vec<(u16, usize, f64)> {
+        let completed = self.completed().max(1) as f64;
+        self.status_counts
+            .iter()
+            .map(|(&code, &n)| (code, n, n as f64 * 100.0 / completed))
+            .collect()
+    }
+
+    // Share of completed requests that succeeded, in percent.
+    pub fn success_rate(&self) -> Option<f64> {
+        match self.completed() {
+            0 => None,
+            n => Some(self.success as f64 * 100.0 / n as f64),
+        }
     }
 
     // === Latency ===
 
     pub fn latency_percentile(&self, p: f64) -> Option<f64> {
-        if self.latencies_us.is_empty() {
-            return None;
+        match &self.latency_histogram {
+            Some(h) => h.percentile_ms(p),
+            None => percentile_ms(&self.latencies_us, p),
         }
-        let mut v = self.latencies_us.clone();
-        v.sort_unstable();
-        let idx = ((v.len() as f64) * p).ceil() as usize;
-        let idx = idx.saturating_sub(1).min(v.len() - 1);
-        Some(v[idx] as f64 / 1000.0)
+    }
+
+    pub fn corrected_percentile(&self, p: f64) -> Option<f64> {
+        percentile_ms(&self.corrected_us, p)
+    }
+
+    pub fn failure_percentile(&self, p: f64) -> Option<f64> {
+        percentile_ms(&self.failure_us, p)
+    }
+
+    pub fn failure_avg(&self) -> Option<f64> {
+        mean_ms(&self.failure_us)
+    }
+
+    // === Proxy connect ===
+
+    pub fn proxy_connect_percentile(&self, p: f64) -> Option<f64> {
+        percentile_ms(&self.proxy_connect_us, p)
+    }
+
+    pub fn proxy_connect_avg(&self) -> Option<f64> {
+        mean_ms(&self.proxy_connect_us)
+    }
+
+    pub fn proxy_connect_median(&self) -> Option<f64> {
+        series_median(self.proxy_connect_us.iter().map(|&us| us as f64 / 1000.0).collect())
+    }
+
+    pub fn proxy_connect_stddev(&self) -> Option<f64> {
+        series_stddev(&self.proxy_connect_us.iter().map(|&us| us as f64 / 1000.0).collect::<Vec<_>>())
+    }
+
+    pub fn proxy_connect_max(&self) -> Option<f64> {
+        self.proxy_connect_us
+            .iter()
+            .copied()
+            .reduce(u128::max)
+            .map(|us| us as f64 / 1000.0)
     }
 
     pub fn latency_avg(&self) -> Option<f64> {
+        if let Some(h) = &self.latency_histogram {
+            return h.mean_ms();
+        }
         if self.latencies_us.is_empty() {
             return None;
         }
@@ -69,6 +861,9 @@ impl Stats {
     }
 
     pub fn latency_median(&self) -> Option<f64> {
+        if let Some(h) = &self.latency_histogram {
+            return h.percentile_ms(0.50);
+        }
         let mut samples: Vec<f64> = self
             .latencies_us
             .iter()
@@ -91,6 +886,9 @@ impl Stats {
     }
 
     pub fn latency_stddev(&self) -> Option<f64> {
+        if let Some(h) = &self.latency_histogram {
+            return h.stddev_ms();
+        }
         if self.latencies_us.len() < 2 {
             return None;
         }
@@ -107,11 +905,55 @@ impl Stats {
         Some(var.sqrt())
     }
 
+    // Mean absolute difference between latencies of consecutive requests.
+    pub fn jitter(&self) -> Option<f64> {
+        if self.latencies_by_seq.len() < 2 {
+            return None;
+        }
+        let mut v = self.latencies_by_seq.clone();
+        v.sort_unstable_by_key(|&(seq, _)| seq);
+        let sum_us: u128 = v.windows(2).map(|w| w[0].1.abs_diff(w[1].1)).sum();
+        Some((sum_us as f64) / 1000.0 / ((v.len() - 1) as f64))
+    }
+
+    pub fn latency_spread(&self) -> Option<f64> {
+        Some(self.latency_percentile(0.99)? - self.latency_percentile(0.50)?)
+    }
+
+    // Body bytes received and sent per second of the whole run; `None` when
+    // no bodies were read or written.
+    pub fn transfer_per_sec(&self) -> Option<f64> {
+        let bytes = self.download.bytes + self.upload.bytes;
+        if self.total_duration.is_zero() || self.download.requests + self.upload.requests == 0 {
+            return None;
+        }
+        Some(bytes as f64 / self.total_duration.as_secs_f64())
+    }
+
+    pub fn ws_ping_avg(&self) -> Option<f64> {
+        if self.ws_ping_us.is_empty() {
+            return None;
+        }
+        let sum_us: u128 = self.ws_ping_us.iter().copied().sum();
+        Some((sum_us as f64) / 1000.0 / (self.ws_ping_us.len() as f64))
+    }
+
+    pub fn ws_ping_max(&self) -> Option<f64> {
+        self.ws_ping_us
+            .iter()
+            .copied()
+            .reduce(u128::max)
+            .map(|us| us as f64 / 1000.0)
+    }
+
     // pub fn latency_min(&self) -> Option<u128> {
     //     self.latencies_ms.iter().copied().reduce(u128::min)
     // }
 
     pub fn latency_max(&self) -> Option<f64> {
+        if let Some(h) = &self.latency_histogram {
+            return (h.count > 0).then(|| h.max_us as f64 / 1000.0);
+        }
         self.latencies_us
             .iter()
             .copied()
@@ -121,13 +963,12 @@ impl Stats {
 
     // === RPS ===
 
-    fn rps_series(&self) -> Option<Vec<f64>> {
-        if self.rps_secs.is_empty() {
-            return None;
-        }
-        let &last_sec = self.rps_secs.keys().last().unwrap();
+    // Both series span the whole run, so seconds in which nothing succeeded
+    // count as zero in the success series.
+    fn per_sec_series(&self, counts: &BTreeMap<u64, u32>) -> Option<Vec<f64>> {
+        let &last_sec = self.completions_per_sec.keys().last()?;
         let mut series = vec![0.0f64; (last_sec as usize) + 1];
-        for (sec, cnt) in &self.rps_secs {
+        for (sec, cnt) in counts {
             if let Some(slot) = series.get_mut(*sec as usize) {
                 *slot = *cnt as f64;
             }
@@ -136,35 +977,953 @@ impl Stats {
     }
 
     pub fn rps_avg(&self) -> Option<f64> {
-        if self.total_duration_ms == 0 || self.success == 0 {
+        if self.total_duration.is_zero() || self.success == 0 {
             return None;
         }
-        Some((self.success as f64) / (self.total_duration_ms as f64 / 1000.0))
+        Some((self.success as f64) / self.total_duration.as_secs_f64())
     }
 
     pub fn rps_median(&self) -> Option<f64> {
-        let mut s = self.rps_series()?;
-        if s.is_empty() { return None; }
-        s.sort_by(|a,b| a.total_cmp(b));
-        let n = s.len();
-        if n % 2 == 1 { Some(s[n/2]) } else { Some((s[n/2 - 1] + s[n/2]) / 2.0) }
+        series_median(self.per_sec_series(&self.success_per_sec)?)
     }
 
     pub fn rps_stddev(&self) -> Option<f64> {
-        let s = self.rps_series()?;
-        if s.len() < 2 { return None; }
-        let mean = s.iter().sum::<f64>() / (s.len() as f64);
-        let var = s.iter().map(|&x| { let d = x - mean; d*d }).sum::<f64>() / (s.len() as f64 - 1.0);
-        Some(var.sqrt())
+        series_stddev(&self.per_sec_series(&self.success_per_sec)?)
     }
 
-    // pub fn rps_min(&self) -> Option<f64> {
-    //     let s = self.rps_series()?;
-    //     s.into_iter().reduce(f64::min)
-    // }
-
     pub fn rps_max(&self) -> Option<f64> {
-        let s = self.rps_series()?;
-        s.into_iter().reduce(f64::max)
+        self.per_sec_series(&self.success_per_sec)?.into_iter().reduce(f64::max)
+    }
+
+    pub fn completions_avg(&self) -> Option<f64> {
+        let done = self.success + self.fail;
+        if self.total_duration.is_zero() || done == 0 {
+            return None;
+        }
+        Some((done as f64) / self.total_duration.as_secs_f64())
+    }
+
+    pub fn completions_median(&self) -> Option<f64> {
+        series_median(self.per_sec_series(&self.completions_per_sec)?)
+    }
+
+    pub fn completions_stddev(&self) -> Option<f64> {
+        series_stddev(&self.per_sec_series(&self.completions_per_sec)?)
+    }
+
+    pub fn completions_max(&self) -> Option<f64> {
+        self.per_sec_series(&self.completions_per_sec)?.into_iter().reduce(f64::max)
+    }
+}
+
+// Fixtures for exercising reporting code without a network run. Only the
+// tests use them so far.
+#[allow(dead_code)]
+impl Stats {
+    // Every latency counts as a success, in sequence order.
+    pub fn from_latencies(latencies: &[Duration]) -> Self {
+        StatsBuilder::default()
+            .latencies(latencies)
+            .success(latencies.len())
+            .build()
+    }
+
+    pub fn with_status_counts(mut self, counts: impl IntoIterator<Item = (u16, usize)>) -> Self {
+        self.status_counts.extend(counts);
+        self
+    }
+
+    pub fn builder() -> StatsBuilder {
+        StatsBuilder::default()
+    }
+}
+
+// Sets counters directly; nothing is derived from anything else, so e.g.
+// `latencies` does not touch `success` (`Stats::from_latencies` does).
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct StatsBuilder {
+    stats: Stats,
+}
+
+macro_rules! counters {
+    ($($field:ident),*) => {
+        $(
+            pub fn $field(mut self, n: usize) -> Self {
+                self.stats.$field = n;
+                self
+            }
+        )*
+    };
+}
+
+#[allow(dead_code)]
+impl StatsBuilder {
+    counters!(
+        success,
+        fail,
+        slow,
+        conn_errors,
+        timeout_errors,
+        tls_errors,
+        ws_rejected,
+        udp_refused,
+        udp_no_reply,
+        port_exhaustion,
+        truncated,
+        protocol_errors,
+        alpn_mismatch,
+        rate_limited,
+        tunnel_reconnects
+    );
+
+    pub fn latencies(mut self, latencies: &[Duration]) -> Self {
+        self.stats.latencies_us = latencies.iter().map(Duration::as_micros).collect();
+        // Runs number requests from 1.
+        self.stats.latencies_by_seq = (1..).zip(self.stats.latencies_us.iter().copied()).collect();
+        self
+    }
+
+    pub fn corrected(mut self, latencies: &[Duration]) -> Self {
+        self.stats.corrected_us = latencies.iter().map(Duration::as_micros).collect();
+        self
+    }
+
+    pub fn status(mut self, code: u16, n: usize) -> Self {
+        self.stats.status_counts.insert(code, n);
+        self
+    }
+
+    pub fn total_duration(mut self, d: Duration) -> Self {
+        self.stats.total_duration = d;
+        self
+    }
+
+    // Completions and successes finished in second `sec` of the run.
+    pub fn second(mut self, sec: u64, completions: u32, successes: u32) -> Self {
+        self.stats.completions_per_sec.insert(sec, completions);
+        self.stats.success_per_sec.insert(sec, successes);
+        self
+    }
+
+    pub fn build(self) -> Stats {
+        self.stats
+    }
+}
+
+fn percentile_ms(samples_us: &[u128], p: f64) -> Option<f64> {
+    if samples_us.is_empty() {
+        return None;
+    }
+    let mut v = samples_us.to_vec();
+    v.sort_unstable();
+    let idx = ((v.len() as f64) * p).ceil() as usize;
+    let idx = idx.saturating_sub(1).min(v.len() - 1);
+    Some(v[idx] as f64 / 1000.0)
+}
+
+fn mean_ms(samples_us: &[u128]) -> Option<f64> {
+    if samples_us.is_empty() {
+        return None;
+    }
+    let sum_us: u128 = samples_us.iter().copied().sum();
+    Some((sum_us as f64) / 1000.0 / (samples_us.len() as f64))
+}
+
+fn series_median(mut s: Vec<f64>) -> Option<f64> {
+    if s.is_empty() { return None; }
+    s.sort_by(|a,b| a.total_cmp(b));
+    let n = s.len();
+    if n % 2 == 1 { Some(s[n/2]) } else { Some((s[n/2 - 1] + s[n/2]) / 2.0) }
+}
+
+fn series_stddev(s: &[f64]) -> Option<f64> {
+    if s.len() < 2 { return None; }
+    let mean = s.iter().sum::<f64>() / (s.len() as f64);
+    let var = s.iter().map(|&x| { let d = x - mean; d*d }).sum::<f64>() / (s.len() as f64 - 1.0);
+    Some(var.sqrt())
+}
+
+
+// SplitMix64 finalizer: turns (seed, index) into a well-mixed 64-bit value.
+pub fn mix64(seed: u64, index: u64) -> u64 {
+    let mut z = seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+// Latencies bucketed to their top 7 significant bits (under 1% error) in
+// constant memory, for runs too long to keep every sample.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Histogram {
+    // Bucket lower bound in µs -> samples in it.
+    pub buckets: BTreeMap<u64, u64>,
+    pub count: u64,
+    pub sum_us: u128,
+    pub sum_sq_ms: f64,
+    pub max_us: u64,
+}
+
+impl Histogram {
+    const BITS: u32 = 7;
+
+    // Lower bound and width of the bucket holding `us`.
+    fn bucket(us: u64) -> (u64, u64) {
+        let shift = (u64::BITS - us.leading_zeros()).saturating_sub(Self::BITS);
+        ((us >> shift) << shift, 1 << shift)
+    }
+
+    pub fn record(&mut self, us: u128) {
+        let us = u64::try_from(us).unwrap_or(u64::MAX);
+        *self.buckets.entry(Self::bucket(us).0).or_insert(0) += 1;
+        self.count += 1;
+        self.sum_us += us as u128;
+        self.sum_sq_ms += (us as f64 / 1000.0).powi(2);
+        self.max_us = self.max_us.max(us);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (&lower, &n) in &other.buckets {
+            *self.buckets.entry(lower).or_insert(0) += n;
+        }
+        self.count += other.count;
+        self.sum_us += other.sum_us;
+        self.sum_sq_ms += other.sum_sq_ms;
+        self.max_us = self.max_us.max(other.max_us);
+    }
+
+    // Same rank as `percentile_ms`, answered with the middle of its bucket.
+    pub fn percentile_ms(&self, p: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64 * p).ceil() as u64).clamp(1, self.count);
+        let mut seen = 0;
+        let (&lower, _) = self.buckets.iter().find(|(_, &n)| {
+            seen += n;
+            seen >= rank
+        })?;
+        let mid = lower + (Self::bucket(lower).1 - 1) / 2;
+        Some(mid.min(self.max_us) as f64 / 1000.0)
+    }
+
+    pub fn mean_ms(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum_us as f64 / 1000.0 / self.count as f64)
+    }
+
+    pub fn stddev_ms(&self) -> Option<f64> {
+        if self.count < 2 {
+            return None;
+        }
+        let n = self.count as f64;
+        let mean = self.mean_ms()?;
+        Some(((self.sum_sq_ms - n * mean * mean) / (n - 1.0)).max(0.0).sqrt())
+    }
+}
+
+// Bottom-k sampling: every sample gets a pseudo-random key derived from the
+// seed and its sequence number, and the `capacity` smallest keys are kept.
+// The result is a uniform sample that depends only on the seed and which
+// requests were offered, not on the order they completed in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reservoir {
+    pub capacity: usize,
+    pub seen: u64,
+    seed: u64,
+    // Max-heap on key: (key, sequence number, microseconds).
+    heap: BinaryHeap<(u64, usize, u128)>,
+}
+
+impl Reservoir {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Self {
+            capacity,
+            seen: 0,
+            seed,
+            heap: BinaryHeap::with_capacity(capacity.min(1 << 16)),
+        }
+    }
+
+    pub fn offer(&mut self, (seq, us): (usize, u128)) {
+        self.seen += 1;
+        if self.capacity == 0 {
+            return;
+        }
+        let key = mix64(self.seed, seq as u64);
+        if self.heap.len() < self.capacity {
+            self.heap.push((key, seq, us));
+        } else if self.heap.peek().is_some_and(|&(max, _, _)| key < max) {
+            self.heap.pop();
+            self.heap.push((key, seq, us));
+        }
+    }
+
+    pub fn samples(&self) -> Vec<(usize, u128)> {
+        self.heap.iter().map(|&(_, seq, us)| (seq, us)).collect()
+    }
+
+    // Combines two reservoirs over disjoint inputs. Keys are kept as drawn,
+    // so the `capacity` smallest of both sides are a sample of the union;
+    // `seq_offset` renumbers the other side's requests.
+    pub fn merge(&mut self, other: &Reservoir, seq_offset: usize) {
+        for &(key, seq, us) in &other.heap {
+            let item = (key, seq + seq_offset, us);
+            if self.heap.len() < self.capacity {
+                self.heap.push(item);
+            } else if self.heap.peek().is_some_and(|&(max, _, _)| key < max) {
+                self.heap.pop();
+                self.heap.push(item);
+            }
+        }
+        self.seen += other.seen;
+    }
+}
+
+// The `capacity` slowest requests by total time, failed ones included. A
+// min-heap on latency, so memory stays bounded however long the run is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Slowest {
+    pub capacity: usize,
+    #[serde(rename = "requests")]
+    heap: BinaryHeap<Reverse<SlowRequest>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SlowRequest {
+    pub us: u128,
+    pub seq: usize,
+    // `None` when the request failed without a response.
+    pub status: Option<u16>,
+    #[serde(with = "rfc3339")]
+    pub started_at: Option<SystemTime>,
+    // Longest step (see `Phases::dominant`).
+    pub phase: Option<String>,
+}
+
+impl Slowest {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            heap: BinaryHeap::with_capacity(capacity.min(1 << 16)),
+        }
+    }
+
+    pub fn offer(&mut self, req: SlowRequest) {
+        if self.heap.len() < self.capacity {
+            self.heap.push(Reverse(req));
+        } else if self.heap.peek().is_some_and(|Reverse(min)| req.us > min.us) {
+            self.heap.pop();
+            self.heap.push(Reverse(req));
+        }
+    }
+
+    // Slowest first.
+    pub fn sorted(&self) -> Vec<&SlowRequest> {
+        let mut out: Vec<_> = self.heap.iter().map(|Reverse(r)| r).collect();
+        out.sort_by(|a, b| b.us.cmp(&a.us).then(a.seq.cmp(&b.seq)));
+        out
+    }
+}
+
+// Bytes moved by download/upload requests, in MB/s (10^6 bytes).
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Throughput {
+    pub bytes: u64,
+    // Requests that moved a body, whatever their duration.
+    pub requests: usize,
+    pub per_request_mbps: Vec<f64>,
+}
+
+impl Throughput {
+    pub fn record(&mut self, bytes: u64, dur: Duration) {
+        self.bytes += bytes;
+        self.requests += 1;
+        let secs = dur.as_secs_f64();
+        if secs > 0.0 {
+            self.per_request_mbps.push(bytes as f64 / 1_000_000.0 / secs);
+        }
+    }
+
+    pub fn append(&mut self, other: &Throughput) {
+        self.bytes += other.bytes;
+        self.requests += other.requests;
+        self.per_request_mbps.extend_from_slice(&other.per_request_mbps);
+    }
+
+    pub fn avg_mbps(&self) -> Option<f64> {
+        if self.per_request_mbps.is_empty() {
+            return None;
+        }
+        Some(self.per_request_mbps.iter().sum::<f64>() / self.per_request_mbps.len() as f64)
+    }
+
+    pub fn avg_size(&self) -> Option<f64> {
+        match self.requests {
+            0 => None,
+            n => Some(self.bytes as f64 / n as f64),
+        }
+    }
+
+    pub fn max_mbps(&self) -> Option<f64> {
+        self.per_request_mbps.iter().copied().reduce(f64::max)
+    }
+
+    // Bytes over the wall-clock time of the whole run, i.e. what all
+    // concurrent connections moved together.
+    pub fn aggregate_mbps(&self, total_duration: Duration) -> Option<f64> {
+        if total_duration.is_zero() || self.bytes == 0 {
+            return None;
+        }
+        Some(self.bytes as f64 / 1_000_000.0 / total_duration.as_secs_f64())
+    }
+}
+
+// Compact per-run figures used when many runs are ranked side by side
+// (subscription nodes, swept config files).
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RunSummary {
+    pub requests: usize,
+    pub success: usize,
+    pub fail: usize,
+    pub success_rate: f64,
+    pub latency_avg_ms: Option<f64>,
+    pub latency_median_ms: Option<f64>,
+    pub latency_p90_ms: Option<f64>,
+    pub latency_p99_ms: Option<f64>,
+    pub jitter_ms: Option<f64>,
+    pub latency_spread_ms: Option<f64>,
+    pub corrected_p50_ms: Option<f64>,
+    pub corrected_p99_ms: Option<f64>,
+    pub aborted: Option<String>,
+}
+
+impl RunSummary {
+    pub fn from_stats(stats: &Stats) -> Self {
+        Self {
+            requests: stats.completed(),
+            success: stats.success,
+            fail: stats.fail,
+            success_rate: stats.success_rate().unwrap_or(0.0),
+            latency_avg_ms: stats.latency_avg(),
+            latency_median_ms: stats.latency_median(),
+            latency_p90_ms: stats.latency_percentile(0.90),
+            latency_p99_ms: stats.latency_percentile(0.99),
+            jitter_ms: stats.jitter(),
+            latency_spread_ms: stats.latency_spread(),
+            corrected_p50_ms: stats.corrected_percentile(0.50),
+            corrected_p99_ms: stats.corrected_percentile(0.99),
+            aborted: stats.aborted.clone(),
+        }
+    }
+}
+
+// Saved reports store durations as integer microseconds, optional or not.
+mod duration_us {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    pub trait Micros: Sized {
+        type Raw: Serialize + for<'de> Deserialize<'de>;
+        fn to_raw(&self) -> Self::Raw;
+        fn from_raw(raw: Self::Raw) -> Self;
+    }
+
+    impl Micros for Duration {
+        type Raw = u64;
+        fn to_raw(&self) -> u64 {
+            self.as_micros() as u64
+        }
+        fn from_raw(raw: u64) -> Self {
+            Duration::from_micros(raw)
+        }
+    }
+
+    impl Micros for Option<Duration> {
+        type Raw = Option<u64>;
+        fn to_raw(&self) -> Option<u64> {
+            self.as_ref().map(Micros::to_raw)
+        }
+        fn from_raw(raw: Option<u64>) -> Self {
+            raw.map(Duration::from_micros)
+        }
+    }
+
+    pub fn serialize<T: Micros, S: Serializer>(d: &T, s: S) -> Result<S::Ok, S::Error> {
+        d.to_raw().serialize(s)
+    }
+
+    pub fn deserialize<'de, T: Micros, D: Deserializer<'de>>(d: D) -> Result<T, D::Error> {
+        T::Raw::deserialize(d).map(T::from_raw)
+    }
+}
+
+// ...and points in time as RFC 3339 UTC timestamps.
+pub mod rfc3339 {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::time::SystemTime;
+
+    pub fn serialize<S: Serializer>(t: &Option<SystemTime>, s: S) -> Result<S::Ok, S::Error> {
+        match t {
+            Some(t) => s.serialize_some(&humantime::format_rfc3339_micros(*t).to_string()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<SystemTime>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|t| humantime::parse_rfc3339(&t).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(v: &[u64]) -> Vec<Duration> {
+        v.iter().map(|&x| Duration::from_millis(x)).collect()
+    }
+
+    #[test]
+    fn percentile_single_sample() {
+        let s = Stats::from_latencies(&ms(&[7]));
+        for p in [0.0, 0.00001, 0.5, 0.99, 1.0] {
+            assert_eq!(s.latency_percentile(p), Some(7.0));
+        }
+    }
+
+    #[test]
+    fn percentile_two_samples() {
+        let s = Stats::from_latencies(&ms(&[20, 10]));
+        assert_eq!(s.latency_percentile(0.00001), Some(10.0));
+        assert_eq!(s.latency_percentile(0.5), Some(10.0));
+        assert_eq!(s.latency_percentile(0.51), Some(20.0));
+        assert_eq!(s.latency_percentile(1.0), Some(20.0));
+    }
+
+    #[test]
+    fn percentile_nearest_rank() {
+        let s = Stats::from_latencies(&ms(&(1..=100).collect::<Vec<_>>()));
+        assert_eq!(s.latency_percentile(0.0), Some(1.0));
+        assert_eq!(s.latency_percentile(0.00001), Some(1.0));
+        assert_eq!(s.latency_percentile(0.5), Some(50.0));
+        assert_eq!(s.latency_percentile(0.99), Some(99.0));
+        assert_eq!(s.latency_percentile(1.0), Some(100.0));
+        assert_eq!(s.latency_spread(), Some(49.0));
+    }
+
+    #[test]
+    fn compacted_latencies_stay_within_a_percent() {
+        let mut s = Stats::from_latencies(&ms(&(1..=1000).collect::<Vec<_>>()));
+        let exact = [0.5, 0.99].map(|p| s.latency_percentile(p).unwrap());
+        s.compact();
+        assert!(s.latencies_us.is_empty() && s.latencies_by_seq.is_empty());
+        for (p, exact) in [0.5, 0.99].into_iter().zip(exact) {
+            let approx = s.latency_percentile(p).unwrap();
+            assert!((approx - exact).abs() / exact < 0.01, "p{} {} vs {}", p, approx, exact);
+        }
+        assert_eq!(s.latency_max(), Some(1000.0));
+        assert_eq!(s.latency_avg(), Some(500.5));
+
+        let mut total = s.clone();
+        total.merge(&Stats::from_latencies(&ms(&[2000])));
+        assert!(total.latencies_us.is_empty());
+        assert_eq!(total.latency_histogram.as_ref().map(|h| h.count), Some(1001));
+        assert_eq!(total.latency_max(), Some(2000.0));
+    }
+
+    #[test]
+    fn empty_stats_have_no_metrics() {
+        let s = Stats::default();
+        assert_eq!(s.latency_percentile(0.5), None);
+        assert_eq!(s.latency_avg(), None);
+        assert_eq!(s.latency_median(), None);
+        assert_eq!(s.latency_stddev(), None);
+        assert_eq!(s.jitter(), None);
+        assert_eq!(s.success_rate(), None);
+        assert_eq!(s.rps_avg(), None);
+        assert_eq!(s.rps_median(), None);
+    }
+
+    #[test]
+    fn latency_moments() {
+        let s = Stats::from_latencies(&ms(&[2, 4, 4, 4, 5, 5, 7, 9]));
+        assert_eq!(s.success, 8);
+        assert_eq!(s.latency_avg(), Some(5.0));
+        assert_eq!(s.latency_median(), Some(4.5));
+        assert_eq!(s.latency_max(), Some(9.0));
+        // Sample variance: 32 / 7.
+        assert!((s.latency_stddev().unwrap() - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+        // |2-4| + 0 + 0 + |4-5| + 0 + |5-7| + |7-9| = 7 over 7 steps.
+        assert_eq!(s.jitter(), Some(1.0));
+    }
+
+    #[test]
+    fn success_rate_and_status_shares() {
+        let s = Stats::builder()
+            .success(3)
+            .fail(1)
+            .build()
+            .with_status_counts([(200, 3), (502, 1)]);
+        assert_eq!(s.completed(), 4);
+        assert_eq!(s.success_rate(), Some(75.0));
+        assert_eq!(s.status_shares(), vec![(200, 3, 75.0), (502, 1, 25.0)]);
+    }
+
+    #[test]
+    fn rps_counts_idle_seconds() {
+        let s = Stats::builder()
+            .success(6)
+            .fail(2)
+            .total_duration(Duration::from_secs(3))
+            .second(0, 4, 4)
+            .second(2, 4, 2)
+            .build();
+        assert_eq!(s.rps_avg(), Some(2.0));
+        assert_eq!(s.completions_avg(), Some(8.0 / 3.0));
+        // Success series [4, 0, 2].
+        assert_eq!(s.rps_median(), Some(2.0));
+        assert_eq!(s.rps_max(), Some(4.0));
+        assert_eq!(s.rps_stddev(), Some(2.0));
+        assert_eq!(s.completions_median(), Some(4.0));
+    }
+
+    #[test]
+    fn per_second_series_split_successes_from_completions() {
+        let mut s = Stats::builder().total_duration(Duration::from_secs(3)).build();
+        // (second, success): two errors in the first second, only errors in
+        // the second.
+        let sequence = [
+            (0, true),
+            (0, false),
+            (0, true),
+            (0, false),
+            (1, false),
+            (1, false),
+            (2, true),
+            (2, true),
+            (2, true),
+        ];
+        for (sec, ok) in sequence {
+            s.record_completion(sec, ok);
+            if ok {
+                s.success += 1;
+            } else {
+                s.record_timeout("tunnel");
+            }
+        }
+        assert_eq!(s.completions_per_sec.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(), [(0, 4), (1, 2), (2, 3)]);
+        assert_eq!(s.success_per_sec.iter().map(|(&k, &v)| (k, v)).collect::<Vec<_>>(), [(0, 2), (2, 3)]);
+        // Reqs/sec counts successes only, the idle second included: [2, 0, 3].
+        assert_eq!(s.rps_avg(), Some(5.0 / 3.0));
+        assert_eq!(s.rps_median(), Some(2.0));
+        assert_eq!(s.rps_max(), Some(3.0));
+        // Done/sec counts every completion: [4, 2, 3].
+        assert_eq!(s.completions_avg(), Some(3.0));
+        assert_eq!(s.completions_median(), Some(3.0));
+        assert_eq!(s.completions_max(), Some(4.0));
+    }
+
+    #[test]
+    fn merge_adds_counters() {
+        let mut a = Stats::from_latencies(&ms(&[1, 2])).with_status_counts([(200, 2)]);
+        let b = Stats::builder()
+            .latencies(&ms(&[3]))
+            .success(1)
+            .fail(1)
+            .timeout_errors(1)
+            .status(200, 1)
+            .build();
+        a.merge(&b);
+        assert_eq!((a.success, a.fail, a.timeout_errors), (3, 1, 1));
+        assert_eq!(a.status_counts.get(&200), Some(&3));
+        assert_eq!(a.latency_max(), Some(3.0));
+    }
+
+    #[test]
+    fn merged_shards_match_the_concatenation() {
+        let a = ms(&(1..=100).map(|i| i * 3).collect::<Vec<_>>());
+        let b = ms(&(1..=57).map(|i| i * 7 + 1).collect::<Vec<_>>());
+        let mut merged = Stats::from_latencies(&a);
+        merged.merge(&Stats::from_latencies(&b));
+        let whole = Stats::from_latencies(&[a, b].concat());
+        assert_eq!(merged.success, whole.success);
+        for p in [0.5, 0.9, 0.99] {
+            assert_eq!(merged.latency_percentile(p), whole.latency_percentile(p), "p{}", p);
+        }
+        // The second shard's sequence numbers come after the first's.
+        let seqs: Vec<usize> = merged.latencies_by_seq.iter().map(|&(seq, _)| seq).collect();
+        assert_eq!(seqs, (1..=157).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn merge_rebases_per_second_series() {
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut a = Stats::builder()
+            .total_duration(Duration::from_secs(3))
+            .second(0, 5, 5)
+            .second(2, 1, 1)
+            .build();
+        a.started_at = Some(start + Duration::from_millis(2_400));
+        let mut b = Stats::builder()
+            .total_duration(Duration::from_secs(2))
+            .second(0, 3, 2)
+            .second(1, 4, 4)
+            .build();
+        b.started_at = Some(start);
+        a.merge(&b);
+        assert_eq!(a.started_at, Some(start));
+        // a ends at 2.4s + 3s.
+        assert_eq!(a.total_duration, Duration::from_millis(5_400));
+        // a moves 2 seconds later (2.4s rounded), b stays put; second 2 holds both.
+        let completions: Vec<(u64, u32)> = a.completions_per_sec.iter().map(|(&s, &n)| (s, n)).collect();
+        assert_eq!(completions, vec![(0, 3), (1, 4), (2, 5), (4, 1)]);
+        let successes: Vec<(u64, u32)> = a.success_per_sec.iter().map(|(&s, &n)| (s, n)).collect();
+        assert_eq!(successes, vec![(0, 2), (1, 4), (2, 5), (4, 1)]);
+
+        // Without start times both are taken to have started together.
+        let mut c = Stats::builder().total_duration(Duration::from_secs(1)).second(0, 1, 1).build();
+        c.merge(&Stats::builder().total_duration(Duration::from_secs(2)).second(0, 2, 2).build());
+        assert_eq!(c.total_duration, Duration::from_secs(2));
+        assert_eq!(c.completions_per_sec.get(&0), Some(&3));
+    }
+
+    #[test]
+    fn reservoir_is_a_uniform_sample() {
+        const OFFERED: usize = 10_000;
+        const KEPT: usize = 1_000;
+        const SEEDS: u64 = 200;
+        let mut per_decile = [0usize; 10];
+        let mut sum = 0.0;
+        for seed in 0..SEEDS {
+            let mut r = Reservoir::new(KEPT, seed);
+            for seq in 1..=OFFERED {
+                r.offer((seq, seq as u128));
+            }
+            let samples = r.samples();
+            assert_eq!((samples.len(), r.seen), (KEPT, OFFERED as u64));
+            for &(seq, _) in &samples {
+                per_decile[(seq - 1) * 10 / OFFERED] += 1;
+            }
+            sum += samples.iter().map(|&(_, us)| us as f64).sum::<f64>();
+        }
+        // Each decile should hold a tenth of every sample: 20_000 here,
+        // with a standard deviation of about 130.
+        let expected = KEPT * SEEDS as usize / 10;
+        for (decile, &n) in per_decile.iter().enumerate() {
+            assert!(n.abs_diff(expected) < expected / 50, "decile {}: {} vs {}", decile, n, expected);
+        }
+        // Values 1..=10_000 average 5_000.5; the pooled sample mean is
+        // within about 6 of it.
+        let mean = sum / (KEPT as u64 * SEEDS) as f64;
+        assert!((mean - 5_000.5).abs() < 25.0, "{}", mean);
+    }
+
+    #[test]
+    fn reservoir_ignores_completion_order() {
+        let mut forward = Reservoir::new(50, 7);
+        let mut backward = Reservoir::new(50, 7);
+        for seq in 1..=1_000 {
+            forward.offer((seq, seq as u128 * 10));
+            backward.offer((1_001 - seq, (1_001 - seq) as u128 * 10));
+        }
+        let (mut a, mut b) = (forward.samples(), backward.samples());
+        a.sort_unstable();
+        b.sort_unstable();
+        assert_eq!(a, b);
+        let mut other_seed = Reservoir::new(50, 8);
+        (1..=1_000).for_each(|seq| other_seed.offer((seq, seq as u128 * 10)));
+        let mut c = other_seed.samples();
+        c.sort_unstable();
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn reservoir_merge_keeps_the_smallest_keys_of_both() {
+        let (mut a, mut b) = (Reservoir::new(100, 1), Reservoir::new(100, 2));
+        for seq in 1..=600 {
+            a.offer((seq, 1));
+        }
+        for seq in 1..=400 {
+            b.offer((seq, 2));
+        }
+        // Every key of the union, the other side renumbered after ours.
+        let mut keys: Vec<(u64, usize)> = (1..=600)
+            .map(|seq| (mix64(1, seq as u64), seq))
+            .chain((1..=400).map(|seq| (mix64(2, seq as u64), seq + 600)))
+            .collect();
+        keys.sort_unstable();
+        let mut expected: Vec<usize> = keys[..100].iter().map(|&(_, seq)| seq).collect();
+        expected.sort_unstable();
+
+        a.merge(&b, 600);
+        assert_eq!(a.seen, 1_000);
+        let mut samples = a.samples();
+        samples.sort_unstable();
+        assert_eq!(samples.iter().map(|&(seq, _)| seq).collect::<Vec<_>>(), expected);
+        assert!(samples.iter().all(|&(seq, us)| us == if seq > 600 { 2 } else { 1 }));
+        // Roughly in proportion to the sides' sizes.
+        let from_b = samples.iter().filter(|&&(seq, _)| seq > 600).count();
+        assert!((25..=55).contains(&from_b), "{}", from_b);
+
+        let mut empty = Reservoir::new(100, 3);
+        empty.merge(&a, 0);
+        assert_eq!((empty.samples().len(), empty.seen), (100, 1_000));
+    }
+
+    #[test]
+    fn transfer_rate() {
+        let mut s = Stats::builder().total_duration(Duration::from_secs(2)).build();
+        assert_eq!(s.transfer_per_sec(), None);
+        s.download.record(3000, Duration::from_millis(10));
+        s.download.record(1000, Duration::ZERO);
+        s.upload.record(2000, Duration::from_millis(10));
+        assert_eq!(s.transfer_per_sec(), Some(3000.0));
+        assert_eq!(s.download.avg_size(), Some(2000.0));
+        assert_eq!(s.download.per_request_mbps.len(), 1);
+    }
+
+    #[test]
+    fn proxy_connect_moments() {
+        let s = Stats {
+            proxy_connect_us: vec![80, 120, 100, 100],
+            ..Stats::default()
+        };
+        assert_eq!(s.proxy_connect_avg(), Some(0.1));
+        assert_eq!(s.proxy_connect_median(), Some(0.1));
+        assert_eq!(s.proxy_connect_max(), Some(0.12));
+        assert_eq!(s.proxy_connect_percentile(0.25), Some(0.08));
+        let sd = s.proxy_connect_stddev().unwrap();
+        assert!((sd - (0.0008f64 / 3.0).sqrt()).abs() < 1e-12);
+    }
+
+    #[test]
+    fn time_to_failure() {
+        let mut s = Stats::default();
+        assert_eq!(s.failure_avg(), None);
+        for d in ms(&[50, 5000, 5000, 10]) {
+            s.record_failure_time(d);
+        }
+        assert_eq!(s.failure_avg(), Some(2515.0));
+        assert_eq!(s.failure_percentile(0.5), Some(50.0));
+        assert_eq!(s.failure_percentile(0.99), Some(5000.0));
+        assert_eq!(s.latency_percentile(0.5), None);
+    }
+
+    #[test]
+    fn timeouts_by_phase() {
+        let mut a = Stats::default();
+        a.record_timeout("tunnel");
+        a.record_timeout("request");
+        let mut b = Stats::default();
+        b.record_timeout("tunnel");
+        a.merge(&b);
+        assert_eq!((a.fail, a.timeout_errors), (3, 3));
+        assert_eq!(a.timeouts_by_phase.get("tunnel"), Some(&2));
+        assert_eq!(a.timeouts_by_phase.get("request"), Some(&1));
+    }
+
+    #[test]
+    fn panics_are_deduplicated() {
+        let mut a = Stats::default();
+        a.record_internal_error("boom".into());
+        a.record_internal_error("boom".into());
+        let mut b = Stats::default();
+        b.record_internal_error("bang".into());
+        a.merge(&b);
+        assert_eq!((a.fail, a.internal_errors), (3, 3));
+        assert_eq!(a.panics.get("boom"), Some(&2));
+        assert_eq!(a.panics.get("bang"), Some(&1));
+    }
+
+    #[test]
+    fn success_matcher_default() {
+        let m = crate::cli::SuccessMatcher::default();
+        assert!(m.contains(200) && m.contains(399) && m.contains(400));
+        assert!(!m.contains(199) && !m.contains(401));
+    }
+
+    #[test]
+    fn slowest_keeps_the_k_largest() {
+        let mut stats = Stats::builder().latencies(&ms(&[1, 2, 3])).build();
+        stats.slowest = Some(Slowest::new(10));
+        let us: Vec<u64> = (0..10_000).map(|i| mix64(7, i) % 1_000_000).collect();
+        for (seq, &v) in (1..).zip(&us) {
+            stats.record_slowest(seq, Duration::from_micros(v), Some(200), SystemTime::UNIX_EPOCH, None);
+        }
+        let slowest = stats.slowest.as_ref().unwrap();
+        assert_eq!(slowest.heap.len(), 10);
+        let mut expected = us.clone();
+        expected.sort_unstable_by(|a, b| b.cmp(a));
+        let got: Vec<u64> = slowest.sorted().iter().map(|r| r.us as u64).collect();
+        assert_eq!(got, expected[..10]);
+
+        // Shards keep their own K; the merge keeps the K slowest of both.
+        let mut other = Stats::builder().build();
+        other.slowest = Some(Slowest::new(10));
+        other.record_slowest(1, Duration::from_secs(5), None, SystemTime::UNIX_EPOCH, Some("connect"));
+        stats.merge(&other);
+        let top = stats.slowest.as_ref().unwrap().sorted();
+        assert_eq!(top.len(), 10);
+        assert_eq!((top[0].us, top[0].seq, top[0].status), (5_000_000, 4, None));
+        assert_eq!(top[0].phase.as_deref(), Some("connect"));
+        assert_eq!(top[9].us as u64, expected[8]);
+    }
+
+    #[test]
+    fn error_samples_are_distinct_and_bounded() {
+        let mut stats = Stats::builder().build();
+        stats.error_samples = Some(ErrorSamples::new(2));
+        for (category, message) in [
+            ("timeout", "read timed out"),
+            ("conn", "connection refused"),
+            ("timeout", "read timed out"),
+            ("timeout", "connect timed out"),
+            // Past the limit: dropped, while known messages still count.
+            ("timeout", "TLS timed out"),
+            ("timeout", "read timed out"),
+        ] {
+            stats.record_error_sample(category, message.to_string());
+        }
+        let counts = |stats: &Stats| -> Vec<(String, String, usize)> {
+            let samples = stats.error_samples.as_ref().unwrap();
+            samples
+                .categories
+                .iter()
+                .flat_map(|(c, v)| v.iter().map(move |s| (c.clone(), s.message.clone(), s.count)))
+                .collect()
+        };
+        let own = |c: &str, m: &str, n| (c.to_string(), m.to_string(), n);
+        assert_eq!(
+            counts(&stats),
+            [
+                own("conn", "connection refused", 1),
+                own("timeout", "read timed out", 3),
+                own("timeout", "connect timed out", 1),
+            ]
+        );
+
+        let mut other = Stats::builder().build();
+        other.error_samples = Some(ErrorSamples::new(2));
+        other.record_error_sample("conn", "connection reset".to_string());
+        other.record_error_sample("conn", "connection refused".to_string());
+        other.record_error_sample("tls", "handshake failed".to_string());
+        stats.merge(&other);
+        assert_eq!(
+            counts(&stats),
+            [
+                own("conn", "connection refused", 2),
+                own("conn", "connection reset", 1),
+                own("timeout", "read timed out", 3),
+                own("timeout", "connect timed out", 1),
+                own("tls", "handshake failed", 1),
+            ]
+        );
+
+        // They ride along in the JSON output.
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["error_samples"]["categories"]["timeout"][0]["count"], 3);
     }
 }
@@ -0,0 +1,100 @@
+use anyhow::{anyhow, Context, Result};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use url::Url;
+
+use crate::cli::{Args, OutputFormat};
+use crate::request::{parse_url_target, run_bench, BenchOptions};
+use crate::stats::{mix64, Stats};
+use crate::{bench_options, finish, pretty, print_banner, retarget};
+
+// `--url -`: newline-separated URLs read from stdin as they arrive.
+pub const STDIN_URL: &str = "-";
+
+// Benches each URL in turn with --per-url requests, printing one summary
+// line per URL as it finishes, then the merged report once stdin closes.
+// Blank lines and `#` comments are skipped, and so (with a warning) are
+// lines that are not valid target URLs.
+pub async fn run(args: &Args, proxy: Url, proxy_addr: &str, proxy_note: &str) -> Result<Stats> {
+    if args.compare_proxy.is_some()
+        || args.watch.is_some()
+        || args.soak.is_some()
+        || !args.stage.is_empty()
+        || args.find_max_concurrency
+        || args.find_max_rate
+        || args.baseline_direct
+        || args.prime_cache
+    {
+        return Err(anyhow!(
+            "--url - cannot be combined with --compare-proxy, --watch, --soak, --stage, --find-max-concurrency, --find-max-rate, --baseline-direct or --prime-cache"
+        ));
+    }
+    let per_url = args.per_url.unwrap_or(args.iterations);
+    let proxy = Arc::new(proxy);
+    let proxy_label = format!("{}://{}", proxy.scheme(), proxy_addr);
+    let pretty_output = !args.quiet && !args.summary_only && args.output == OutputFormat::Pretty;
+
+    let mut base: Option<BenchOptions> = None;
+    let mut total = Stats::default();
+    let mut urls = 0;
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await.context("reading URLs from stdin")? {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let target = match parse_url_target(line) {
+            Ok(target) => target,
+            Err(e) => {
+                tracing::warn!("skipping {:?}: {:#}", line, e);
+                continue;
+            }
+        };
+        let opts = match &base {
+            Some(base) => retarget(base, target)?,
+            None => {
+                let opts = bench_options(args, target)?;
+                if pretty_output {
+                    print_banner(args, &proxy, proxy_addr, proxy_note, &opts, None);
+                    println!();
+                }
+                base = Some(opts.clone());
+                opts
+            }
+        };
+        let opts = BenchOptions {
+            iterations: per_url,
+            // Sequence numbers restart for every URL; a derived seed keeps
+            // their sampling keys independent.
+            seed: mix64(opts.seed, urls as u64),
+            ..opts
+        };
+        let stats = run_bench(proxy.clone(), proxy_addr, Arc::new(opts)).await?;
+        let summary = format!("{} {}", line, pretty::summary_line(&stats));
+        match args.output {
+            _ if args.quiet => {}
+            OutputFormat::Pretty => println!("{}", summary),
+            // Keeps stdout a single JSON or wrk document.
+            _ => eprintln!("{}", summary),
+        }
+        total.merge(&stats);
+        urls += 1;
+        if stats.aborted.is_some() || stats.interrupted {
+            break;
+        }
+    }
+    let Some(base) = base else {
+        return Err(anyhow!("no URLs read from stdin"));
+    };
+    finish(
+        args,
+        &proxy_label,
+        STDIN_URL,
+        per_url * urls,
+        &total,
+        None,
+        base.dump_failures.as_ref(),
+    )
+    .await?;
+    Ok(total)
+}
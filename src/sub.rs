@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Result};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use url::Url;
+
+use crate::cli::{require_url, Args, Units};
+use crate::pretty;
+use crate::link::{decode_base64, ShareLink};
+use crate::request::{self, fetch, parse_url_target, run_bench, Fetched};
+use crate::stats::{RunSummary, Stats};
+use crate::xray::XrayProcess;
+
+#[derive(Debug, Serialize)]
+pub struct NodeReport {
+    pub name: String,
+    pub protocol: String,
+    pub address: String,
+    pub port: u16,
+    #[serde(flatten)]
+    pub summary: RunSummary,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Unparsed {
+    pub index: usize,
+    pub link: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubReport {
+    pub nodes: Vec<NodeReport>,
+    pub unparsed: Vec<Unparsed>,
+}
+
+pub struct SubOptions<'a> {
+    pub url: &'a str,
+    pub parallel_nodes: usize,
+    pub top: Option<usize>,
+    pub json: bool,
+    pub xray_bin: &'a Path,
+}
+
+// Subscriptions are usually a base64 blob of newline-separated links, but
+// some panels serve the plain list.
+pub fn split_links(body: &[u8]) -> Result<Vec<String>> {
+    let text = String::from_utf8_lossy(body);
+    let text = if text.contains("://") {
+        text.into_owned()
+    } else {
+        String::from_utf8(decode_base64(text.trim())?)
+            .map_err(|_| anyhow!("decoded subscription is not valid UTF-8"))?
+    };
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(str::to_string)
+        .collect())
+}
+
+pub async fn run(args: &Args, proxy: Option<(Url, String)>, opts: SubOptions<'_>) -> Result<()> {
+    let sub_url = Url::parse(opts.url).map_err(|e| anyhow!("invalid subscription URL: {}", e))?;
+    let timeout = args.timeout;
+    let Fetched { status, body, .. } = fetch(
+        &sub_url,
+        proxy.as_ref().map(|(u, a)| (u, a.as_str())),
+        args.insecure,
+        timeout,
+        &crate::socket_options(args),
+    )
+    .await
+    .map_err(|e| anyhow!("fetching subscription failed: {}", e))?;
+    if !(200..300).contains(&status) {
+        return Err(anyhow!("fetching subscription failed: HTTP {}", status));
+    }
+    let links = split_links(&body)?;
+
+    let mut nodes = Vec::new();
+    let mut unparsed = Vec::new();
+    for (i, raw) in links.into_iter().enumerate() {
+        match ShareLink::parse(&raw) {
+            Ok(link) => nodes.push(link),
+            Err(e) => unparsed.push(Unparsed {
+                index: i + 1,
+                link: raw,
+                error: format!("{:#}", e),
+            }),
+        }
+    }
+    if nodes.is_empty() && unparsed.is_empty() {
+        return Err(anyhow!("subscription contains no links"));
+    }
+
+    let url_str = require_url(args, Some("sub"));
+    let bench_opts = Arc::new(crate::bench_options(args, parse_url_target(url_str)?)?);
+    let startup_timeout = args.startup_timeout;
+    let total = nodes.len();
+    let progress = !opts.json && !args.quiet;
+    let units = args.units;
+    if progress {
+        eprintln!(
+            "Testing {} node(s), {} in parallel, {} request(s) each",
+            total, opts.parallel_nodes, args.iterations
+        );
+    }
+
+    let mut reports: Vec<NodeReport> = stream::iter(nodes)
+        .map(|link| {
+            let bench_opts = bench_opts.clone();
+            async move {
+                let result = async {
+                    // Ctrl-C: the nodes not started yet are skipped.
+                    if request::interrupted() {
+                        return Err(anyhow!("interrupted"));
+                    }
+                    let xray =
+                        XrayProcess::spawn_link(opts.xray_bin, &link, startup_timeout).await?;
+                    let proxy = Arc::new(Url::parse(&format!("socks5://{}", xray.listen))?);
+                    let stats = run_bench(proxy, &xray.listen.to_string(), bench_opts).await?;
+                    drop(xray);
+                    Ok::<Stats, anyhow::Error>(stats)
+                }
+                .await;
+                node_report(&link, result)
+            }
+        })
+        .buffer_unordered(opts.parallel_nodes.max(1))
+        .inspect(|r| {
+            if progress {
+                match &r.error {
+                    Some(e) => eprintln!("  {}: error: {}", r.name, e),
+                    None => eprintln!(
+                        "  {}: {:.1}% ok, median {}",
+                        r.name,
+                        r.summary.success_rate,
+                        pretty::fmt_ms(r.summary.latency_median_ms, units)
+                    ),
+                }
+            }
+        })
+        .collect()
+        .await;
+
+    reports.sort_by(|a, b| {
+        a.error
+            .is_some()
+            .cmp(&b.error.is_some())
+            .then_with(|| b.summary.success_rate.total_cmp(&a.summary.success_rate))
+            .then_with(|| match (a.summary.latency_median_ms, b.summary.latency_median_ms) {
+                (Some(x), Some(y)) => x.total_cmp(&y),
+                (Some(_), None) => std::cmp::Ordering::Less,
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (None, None) => std::cmp::Ordering::Equal,
+            })
+    });
+    if let Some(n) = opts.top {
+        reports.truncate(n);
+    }
+
+    let report = SubReport {
+        nodes: reports,
+        unparsed,
+    };
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else if !args.quiet {
+        print_table(&report, units);
+    }
+    Ok(())
+}
+
+fn node_report(link: &ShareLink, result: Result<Stats>) -> NodeReport {
+    let name = if link.name.is_empty() {
+        format!("{}:{}", link.address, link.port)
+    } else {
+        link.name.clone()
+    };
+    let mut report = NodeReport {
+        name,
+        protocol: link.protocol_name().to_string(),
+        address: link.address.clone(),
+        port: link.port,
+        summary: RunSummary::default(),
+        error: None,
+    };
+    match result {
+        Ok(stats) => report.summary = RunSummary::from_stats(&stats),
+        Err(e) => report.error = Some(format!("{:#}", e)),
+    }
+    report
+}
+
+pub fn truncate(s: &str, max: usize) -> String {
+    if s.chars().count() <= max {
+        s.to_string()
+    } else {
+        let mut t: String = s.chars().take(max - 1).collect();
+        t.push('…');
+        t
+    }
+}
+
+fn print_table(report: &SubReport, units: Units) {
+    println!(
+        "\n{:>4}  {:<32}  {:<12}  {:>8}  {:>10}",
+        "Rank", "Node", "Protocol", "Success", "Median"
+    );
+    let ranked = report.nodes.iter().filter(|n| n.error.is_none());
+    for (i, n) in ranked.enumerate() {
+        let median = pretty::fmt_ms(n.summary.latency_median_ms, units);
+        println!(
+            "{:>4}  {:<32}  {:<12}  {:>7.1}%  {:>10}",
+            i + 1,
+            truncate(&n.name, 32),
+            n.protocol,
+            n.summary.success_rate,
+            median
+        );
+    }
+    let failed: Vec<&NodeReport> = report.nodes.iter().filter(|n| n.error.is_some()).collect();
+    if !failed.is_empty() {
+        println!("\nFailed to start:");
+        for n in failed {
+            println!("  {}: {}", n.name, n.error.as_deref().unwrap_or_default());
+        }
+    }
+    if !report.unparsed.is_empty() {
+        println!("\nFailed to parse:");
+        for u in &report.unparsed {
+            println!("  #{} {}: {}", u.index, truncate(&u.link, 48), u.error);
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use url::Url;
+
+use crate::cli::{require_url, Args, SweepSort};
+use crate::pretty;
+use crate::request::{self, parse_url_target, run_bench, BenchOptions};
+use crate::stats::RunSummary;
+use crate::sub::truncate;
+use crate::xray::{free_local_port, inject_inbound, InboundKind, InboundSpec, XrayProcess};
+
+#[derive(Debug, Serialize)]
+pub struct ConfigReport {
+    pub file: String,
+    #[serde(flatten)]
+    pub summary: RunSummary,
+    pub error: Option<String>,
+}
+
+pub struct SweepOptions<'a> {
+    pub configs: &'a [PathBuf],
+    pub sort: SweepSort,
+    pub json: bool,
+    pub xray_bin: &'a Path,
+}
+
+fn expand_configs(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    for p in paths {
+        if p.is_dir() {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(p)
+                .with_context(|| format!("reading directory {}", p.display()))?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|f| f.extension().is_some_and(|x| x == "json"))
+                .collect();
+            files.sort();
+            out.extend(files);
+        } else {
+            out.push(p.clone());
+        }
+    }
+    Ok(out)
+}
+
+async fn bench_config(
+    path: &Path,
+    xray_bin: &Path,
+    startup_timeout: Duration,
+    opts: Arc<BenchOptions>,
+) -> Result<RunSummary> {
+    let text = std::fs::read_to_string(path).context("reading config")?;
+    let mut config: serde_json::Value = serde_json::from_str(&text).context("parsing config")?;
+    let inbound = InboundSpec {
+        kind: InboundKind::Socks,
+        listen: free_local_port()?,
+    };
+    inject_inbound(&mut config, &inbound)?;
+    let xray = XrayProcess::spawn(xray_bin, &config, inbound.listen, startup_timeout).await?;
+    let proxy = Arc::new(Url::parse(&format!("socks5://{}", xray.listen))?);
+    let stats = run_bench(proxy, &xray.listen.to_string(), opts).await?;
+    drop(xray);
+    Ok(RunSummary::from_stats(&stats))
+}
+
+fn cmp_opt_asc(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+pub async fn run(args: &Args, opts: SweepOptions<'_>) -> Result<()> {
+    let files = expand_configs(opts.configs)?;
+    if files.is_empty() {
+        return Err(anyhow!("no config files to sweep"));
+    }
+    let url_str = require_url(args, Some("sweep"));
+    let bench_opts = Arc::new(crate::bench_options(args, parse_url_target(url_str)?)?);
+    let startup_timeout = args.startup_timeout;
+
+    let progress = !opts.json && !args.quiet;
+    let mut reports = Vec::with_capacity(files.len());
+    for (i, file) in files.iter().enumerate() {
+        // Ctrl-C: rank what was measured, without starting more xrays.
+        if request::interrupted() {
+            break;
+        }
+        let name = file.display().to_string();
+        if progress {
+            eprintln!("[{}/{}] {}", i + 1, files.len(), name);
+        }
+        let report = match bench_config(file, opts.xray_bin, startup_timeout, bench_opts.clone()).await {
+            Ok(summary) => ConfigReport {
+                file: name,
+                summary,
+                error: None,
+            },
+            Err(e) => {
+                if progress {
+                    eprintln!("  error: {:#}", e);
+                }
+                ConfigReport {
+                    file: name,
+                    summary: RunSummary::default(),
+                    error: Some(format!("{:#}", e)),
+                }
+            }
+        };
+        reports.push(report);
+    }
+
+    reports.sort_by(|a, b| {
+        a.error.is_some().cmp(&b.error.is_some()).then_with(|| match opts.sort {
+            SweepSort::P50 => cmp_opt_asc(a.summary.latency_median_ms, b.summary.latency_median_ms),
+            SweepSort::P99 => cmp_opt_asc(a.summary.latency_p99_ms, b.summary.latency_p99_ms),
+            SweepSort::Success => b.summary.success_rate.total_cmp(&a.summary.success_rate),
+        })
+    });
+
+    if opts.json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+        return Ok(());
+    }
+    if args.quiet {
+        return Ok(());
+    }
+    let fmt_ms = |v: Option<f64>| pretty::fmt_ms(v, args.units);
+    println!(
+        "\n{:>4}  {:<40}  {:>8}  {:>10}  {:>10}",
+        "Rank", "Config", "Success", "p50", "p99"
+    );
+    for (i, r) in reports.iter().filter(|r| r.error.is_none()).enumerate() {
+        println!(
+            "{:>4}  {:<40}  {:>7.1}%  {:>10}  {:>10}",
+            i + 1,
+            truncate(&r.file, 40),
+            r.summary.success_rate,
+            fmt_ms(r.summary.latency_median_ms),
+            fmt_ms(r.summary.latency_p99_ms)
+        );
+    }
+    let failed: Vec<&ConfigReport> = reports.iter().filter(|r| r.error.is_some()).collect();
+    if !failed.is_empty() {
+        println!("\nFailed:");
+        for r in failed {
+            println!("  {}: {}", r.file, r.error.as_deref().unwrap_or_default());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+
+use crate::stats::mix64;
+
+// Per-request placeholders in the URL path: `{seq}` (request number),
+// `{rand:A-B}` (integer in A..=B) and `{uuid}` (random v4 UUID). Random
+// values derive from --seed and the request number, so a run with the same
+// seed requests the same paths.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Seq,
+    Rand(u64, u64),
+    Uuid,
+}
+
+impl PathTemplate {
+    // `None` when the path has no placeholders. The URL parser escapes
+    // braces in paths, so `%7B`/`%7D` count as braces too.
+    pub fn parse(path: &str) -> Result<Option<Self>> {
+        let source = path
+            .replace("%7B", "{")
+            .replace("%7b", "{")
+            .replace("%7D", "}")
+            .replace("%7d", "}");
+        if !source.contains('{') && !source.contains('}') {
+            return Ok(None);
+        }
+        let mut parts = Vec::new();
+        let mut rest = source.as_str();
+        while let Some(open) = rest.find(['{', '}']) {
+            if rest[open..].starts_with('}') {
+                return Err(anyhow!("unmatched }} in URL path template {:?}", source));
+            }
+            if open > 0 {
+                parts.push(Part::Literal(rest[..open].to_string()));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| anyhow!("unclosed {{ in URL path template {:?}", source))?;
+            parts.push(parse_placeholder(&rest[open + 1..open + close])?);
+            rest = &rest[open + close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_string()));
+        }
+        Ok(Some(Self { source, parts }))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn render(&self, seq: usize, seed: u64) -> String {
+        let mut out = String::new();
+        for (i, part) in self.parts.iter().enumerate() {
+            // Each placeholder draws from its own stream.
+            let draw = |k: u64| mix64(mix64(seed, (i as u64) << 8 | k), seq as u64);
+            match part {
+                Part::Literal(s) => out.push_str(s),
+                Part::Seq => out.push_str(&seq.to_string()),
+                Part::Rand(lo, hi) => {
+                    let span = hi - lo;
+                    let v = if span == u64::MAX { draw(0) } else { lo + draw(0) % (span + 1) };
+                    out.push_str(&v.to_string());
+                }
+                Part::Uuid => {
+                    let (a, b) = (draw(0), draw(1));
+                    // Version 4, RFC 4122 variant.
+                    let a = (a & !0xf000) | 0x4000;
+                    let b = (b & !(0b11 << 62)) | (0b10 << 62);
+                    out.push_str(&format!(
+                        "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+                        a >> 32,
+                        (a >> 16) & 0xffff,
+                        a & 0xffff,
+                        b >> 48,
+                        b & 0xffff_ffff_ffff
+                    ));
+                }
+            }
+        }
+        out
+    }
+}
+
+fn parse_placeholder(name: &str) -> Result<Part> {
+    match name {
+        "seq" => return Ok(Part::Seq),
+        "uuid" => return Ok(Part::Uuid),
+        _ => {}
+    }
+    if let Some(range) = name.strip_prefix("rand:") {
+        let (lo, hi) = range
+            .split_once('-')
+            .ok_or_else(|| anyhow!("expected {{rand:MIN-MAX}}, got {{{}}}", name))?;
+        let lo: u64 = lo.trim().parse().map_err(|_| anyhow!("invalid minimum in {{{}}}", name))?;
+        let hi: u64 = hi.trim().parse().map_err(|_| anyhow!("invalid maximum in {{{}}}", name))?;
+        if hi < lo {
+            return Err(anyhow!("maximum below minimum in {{{}}}", name));
+        }
+        return Ok(Part::Rand(lo, hi));
+    }
+    Err(anyhow!(
+        "unknown URL placeholder {{{}}}; supported: {{seq}}, {{rand:MIN-MAX}}, {{uuid}}",
+        name
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(path: &str, seq: usize, seed: u64) -> String {
+        PathTemplate::parse(path).unwrap().unwrap().render(seq, seed)
+    }
+
+    #[test]
+    fn plain_paths_are_not_templates() {
+        assert!(PathTemplate::parse("/users/42/profile").unwrap().is_none());
+        assert!(PathTemplate::parse("/").unwrap().is_none());
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(render("/users/{seq}/profile", 7, 0), "/users/7/profile");
+        // As the URL parser hands them over.
+        assert_eq!(render("/users/%7Bseq%7D", 3, 0), "/users/3");
+        assert_eq!(PathTemplate::parse("/a/%7bseq%7d").unwrap().unwrap().as_str(), "/a/{seq}");
+        for seq in 0..200 {
+            let v: u64 = render("{rand:5-9}", seq, 1).parse().unwrap();
+            assert!((5..=9).contains(&v));
+        }
+        assert_eq!(render("/{rand:3-3}", 1, 1), "/3");
+        render("{rand:0-18446744073709551615}", 1, 1).parse::<u64>().unwrap();
+
+        let uuid = render("{uuid}", 1, 1);
+        let groups: Vec<&str> = uuid.split('-').collect();
+        assert_eq!(groups.iter().map(|g| g.len()).collect::<Vec<_>>(), [8, 4, 4, 4, 12], "{}", uuid);
+        assert!(groups[2].starts_with('4'), "{}", uuid);
+        assert!("89ab".contains(&groups[3][..1]), "{}", uuid);
+    }
+
+    #[test]
+    fn same_seed_same_paths() {
+        let path = "/{uuid}/{rand:1-1000000}";
+        assert_eq!(render(path, 5, 42), render(path, 5, 42));
+        assert_ne!(render(path, 5, 42), render(path, 6, 42));
+        assert_ne!(render(path, 5, 42), render(path, 5, 43));
+        // Two placeholders of one kind draw independently.
+        let both = render("{uuid}/{uuid}", 1, 1);
+        let (a, b) = both.split_once('/').unwrap();
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for (path, err) in [
+            ("/a/{seq", "unclosed { in URL path template \"/a/{seq\""),
+            ("/a}", "unmatched } in URL path template \"/a}\""),
+            ("/{id}", "unknown URL placeholder {id}; supported: {seq}, {rand:MIN-MAX}, {uuid}"),
+            ("/{rand:5}", "expected {rand:MIN-MAX}, got {rand:5}"),
+            ("/{rand:x-5}", "invalid minimum in {rand:x-5}"),
+            ("/{rand:1-}", "invalid maximum in {rand:1-}"),
+            ("/{rand:9-1}", "maximum below minimum in {rand:9-1}"),
+        ] {
+            assert_eq!(PathTemplate::parse(path).unwrap_err().to_string(), err);
+        }
+    }
+}
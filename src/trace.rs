@@ -0,0 +1,352 @@
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use http_body_util::BodyExt;
+use hyper::client::conn;
+use hyper::http::HeaderMap;
+use hyper_util::rt::TokioIo;
+use percent_encoding::percent_decode_str;
+use sha1::{Digest, Sha1};
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context as TaskContext, Poll};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::lookup_host;
+use url::Url;
+
+use crate::cli::BenchMode;
+use crate::{curl, headers};
+use crate::request::{get_request, open_proxy_stream, proxy_handshake, proxy_tls, tls_handshake, BenchOptions};
+use crate::stream::ProxyStream;
+
+struct Tracer {
+    start: Instant,
+    // Proxy credentials, masked wherever they show up in raw bytes.
+    secrets: Vec<Vec<u8>>,
+}
+
+impl Tracer {
+    fn step(&self, msg: impl std::fmt::Display) {
+        println!("[{:>9.2}ms] {}", self.start.elapsed().as_secs_f64() * 1000.0, msg);
+    }
+
+    fn detail(&self, msg: impl std::fmt::Display) {
+        println!("              {}", msg);
+    }
+
+    fn mask(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = data.to_vec();
+        for secret in self.secrets.iter().filter(|s| !s.is_empty()) {
+            let mut i = 0;
+            while i + secret.len() <= out.len() {
+                if out[i..i + secret.len()] == secret[..] {
+                    out[i..i + secret.len()].fill(b'*');
+                    i += secret.len();
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+
+    // Text protocols are shown line by line, binary ones (SOCKS) as hex.
+    fn dump(&self, prefix: &str, data: &[u8]) {
+        let data = self.mask(data);
+        let text = data
+            .iter()
+            .all(|&b| b == b'\r' || b == b'\n' || b == b'\t' || (0x20..0x7f).contains(&b));
+        if text {
+            for line in String::from_utf8_lossy(&data).split("\r\n").filter(|l| !l.is_empty()) {
+                self.detail(format!("{} {}", prefix, line));
+            }
+        } else {
+            for chunk in data.chunks(16) {
+                let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                self.detail(format!("{} {}", prefix, hex.join(" ")));
+            }
+        }
+    }
+
+    fn headers(&self, prefix: &str, headers: &HeaderMap) {
+        for (name, value) in headers {
+            let value = if headers::is_secret(name, value) {
+                "***".into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            self.detail(format!("{} {}: {}", prefix, name, value));
+        }
+    }
+}
+
+// Bytes seen by a `Tap`, in order; `true` marks the ones sent.
+type TapLog = Arc<Mutex<Vec<(bool, Vec<u8>)>>>;
+
+// Records everything that passes through the proxy handshake.
+struct Tap<S> {
+    inner: S,
+    log: TapLog,
+}
+
+impl<S> Tap<S> {
+    fn record(&self, sent: bool, data: &[u8]) {
+        if data.is_empty() {
+            return;
+        }
+        let mut log = self.log.lock().unwrap();
+        match log.last_mut() {
+            Some((dir, buf)) if *dir == sent => buf.extend_from_slice(data),
+            _ => log.push((sent, data.to_vec())),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tap<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            self.record(false, &buf.filled()[before..]);
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tap<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            self.record(true, &buf[..n]);
+        }
+        res
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// One DER element: tag, contents and whatever follows it.
+fn der_next(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let n = first & 0x7f;
+        if n == 0 || n > 4 {
+            return None;
+        }
+        let len = data.get(2..2 + n)?.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        (len, 2 + n)
+    };
+    let body = data.get(header..header + len)?;
+    Some((tag, body, &data[header + len..]))
+}
+
+// `CN=example.com, O=Example` from an X.501 Name.
+fn der_name(mut name: &[u8]) -> String {
+    let mut parts = Vec::new();
+    while let Some((_, set, rest)) = der_next(name) {
+        name = rest;
+        let Some((_, attr, _)) = der_next(set) else {
+            continue;
+        };
+        let Some((_, oid, value)) = der_next(attr) else {
+            continue;
+        };
+        let label = match oid {
+            [0x55, 0x04, 0x03] => "CN",
+            [0x55, 0x04, 0x06] => "C",
+            [0x55, 0x04, 0x0a] => "O",
+            [0x55, 0x04, 0x0b] => "OU",
+            _ => continue,
+        };
+        if let Some((_, v, _)) = der_next(value) {
+            parts.push(format!("{}={}", label, String::from_utf8_lossy(v)));
+        }
+    }
+    parts.join(", ")
+}
+
+// Subject and issuer of a DER certificate.
+fn cert_names(der: &[u8]) -> Option<(String, String)> {
+    let (_, cert, _) = der_next(der)?;
+    let (_, tbs, _) = der_next(cert)?;
+    let mut rest = tbs;
+    if rest.first() == Some(&0xa0) {
+        rest = der_next(rest)?.2;
+    }
+    let (_, _serial, rest) = der_next(rest)?;
+    let (_, _sig, rest) = der_next(rest)?;
+    let (_, issuer, rest) = der_next(rest)?;
+    let (_, _validity, rest) = der_next(rest)?;
+    let (_, subject, _) = der_next(rest)?;
+    Some((der_name(subject), der_name(issuer)))
+}
+
+fn tls_details<S>(tracer: &Tracer, tls: &tokio_native_tls::TlsStream<S>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let inner = tls.get_ref();
+    match inner.negotiated_alpn() {
+        Ok(Some(alpn)) => tracer.detail(format!("ALPN: {}", String::from_utf8_lossy(&alpn))),
+        _ => tracer.detail("ALPN: none"),
+    }
+    tracer.detail("protocol version and cipher: not exposed by the platform TLS library");
+    if let Ok(Some(cert)) = inner.peer_certificate() {
+        if let Ok(der) = cert.to_der() {
+            if let Some((subject, issuer)) = cert_names(&der) {
+                tracer.detail(format!("certificate subject: {}", subject));
+                tracer.detail(format!("certificate issuer: {}", issuer));
+            }
+            let sha1: Vec<String> = Sha1::digest(&der).iter().map(|b| format!("{:02X}", b)).collect();
+            tracer.detail(format!("certificate SHA-1: {}", sha1.join(":")));
+        }
+    }
+}
+
+// Performs a single request and prints each step as it happens.
+pub async fn run(proxy: &Url, proxy_addr: &str, opts: &BenchOptions, body_bytes: usize) -> Result<()> {
+    if !matches!(opts.mode, BenchMode::Http | BenchMode::Download) {
+        return Err(anyhow!("trace supports --mode http and download only"));
+    }
+    let user = percent_decode_str(proxy.username()).decode_utf8_lossy().into_owned();
+    let pass = percent_decode_str(proxy.password().unwrap_or("")).decode_utf8_lossy().into_owned();
+    let mut secrets = Vec::new();
+    if !user.is_empty() {
+        let basic = base64::engine::general_purpose::STANDARD.encode(format!("{}:{}", user, pass));
+        secrets.push(basic.into_bytes());
+        secrets.push(pass.into_bytes());
+    }
+    let tracer = Tracer {
+        start: Instant::now(),
+        secrets,
+    };
+    tracer.step(format!("curl equivalent: {}", curl::command(proxy, opts, false)?));
+    let target = &opts.target;
+    let connect_target = opts
+        .connect_to
+        .as_ref()
+        .map_or_else(|| format!("{}:{}", target.host, target.port), ToString::to_string);
+
+    if matches!(proxy.scheme(), "socks5" | "http" | "https") {
+        tracer.step(format!("resolving proxy {}", proxy_addr));
+        let addrs: Vec<String> = lookup_host(proxy_addr)
+            .await
+            .with_context(|| format!("resolving {} failed", proxy_addr))?
+            .map(|a| a.to_string())
+            .collect();
+        tracer.step(format!("resolved to {}", addrs.join(", ")));
+    }
+
+    tracer.step(format!("connecting to proxy {}", proxy_addr));
+    let t = Instant::now();
+    let stream = open_proxy_stream(proxy, proxy_addr, opts.timeout, &opts.socket).await?;
+    let took = t.elapsed();
+    match &stream {
+        ProxyStream::Tcp(s) => tracer.step(format!(
+            "connected in {:.2}ms ({} -> {})",
+            took.as_secs_f64() * 1000.0,
+            s.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+            s.peer_addr().map(|a| a.to_string()).unwrap_or_default()
+        )),
+        _ => tracer.step(format!("connected in {:.2}ms", took.as_secs_f64() * 1000.0)),
+    }
+
+    let t = Instant::now();
+    let stream = proxy_tls(stream, proxy, opts.timeout, &opts.socket).await?;
+    if let ProxyStream::Tls(tls) = &stream {
+        tracer.step(format!("TLS to proxy established in {:.2}ms", t.elapsed().as_secs_f64() * 1000.0));
+        tls_details(&tracer, tls);
+    }
+
+    let log = TapLog::default();
+    let tap = Tap {
+        inner: stream,
+        log: log.clone(),
+    };
+    let protocol = if matches!(proxy.scheme(), "socks5" | "unix") { "SOCKS5" } else { "CONNECT" };
+    tracer.step(format!("{} handshake for {}", protocol, connect_target));
+    let t = Instant::now();
+    let result = proxy_handshake(tap, proxy, &connect_target, opts.timeout).await;
+    for (sent, data) in log.lock().unwrap().iter() {
+        tracer.dump(if *sent { ">" } else { "<" }, data);
+    }
+    let stream = result?;
+    tracer.step(format!("tunnel established in {:.2}ms", t.elapsed().as_secs_f64() * 1000.0));
+
+    if target.is_tls() {
+        let t = Instant::now();
+        tracer.step(format!("TLS handshake with {}", target.host));
+        let tls = tls_handshake(stream, &target.host, opts.insecure, &[], opts.timeout).await?;
+        tracer.step(format!("TLS established in {:.2}ms", t.elapsed().as_secs_f64() * 1000.0));
+        tls_details(&tracer, &tls);
+        exchange(&tracer, TokioIo::new(tls), opts, body_bytes).await
+    } else {
+        exchange(&tracer, TokioIo::new(stream), opts, body_bytes).await
+    }
+}
+
+async fn exchange<T>(tracer: &Tracer, io: T, opts: &BenchOptions, body_bytes: usize) -> Result<()>
+where
+    T: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
+{
+    let (mut sender, connection) = tokio::time::timeout(opts.timeout, conn::http1::handshake(io))
+        .await
+        .map_err(|_| anyhow!("handshake timed out"))??;
+    let driver = tokio::spawn(connection);
+    let req = get_request(opts)?;
+    tracer.step("sending request");
+    tracer.detail(format!("> {} {} HTTP/1.1", req.method(), req.uri()));
+    tracer.headers(">", req.headers());
+    let t = Instant::now();
+    let resp = tokio::time::timeout(opts.timeout, sender.send_request(req))
+        .await
+        .map_err(|_| anyhow!("request timed out"))?
+        .map_err(|e| anyhow!("request failed: {e:?}"))?;
+    tracer.step(format!("response headers after {:.2}ms", t.elapsed().as_secs_f64() * 1000.0));
+    tracer.detail(format!("< HTTP/1.1 {}", resp.status()));
+    tracer.headers("<", resp.headers());
+
+    let mut body = resp.into_body();
+    let mut head = Vec::new();
+    let mut total = 0u64;
+    while let Some(frame) = tokio::time::timeout(opts.idle_timeout, body.frame())
+        .await
+        .map_err(|_| anyhow!("body read timed out after {} bytes", total))?
+    {
+        let frame = frame.map_err(|e| anyhow!("reading response body failed: {e}"))?;
+        if let Some(data) = frame.data_ref() {
+            total += data.len() as u64;
+            let room = body_bytes.saturating_sub(head.len());
+            head.extend_from_slice(&data[..room.min(data.len())]);
+        }
+        if head.len() >= body_bytes && body_bytes > 0 {
+            break;
+        }
+    }
+    driver.abort();
+    tracer.step(format!(
+        "read {} body byte(s){}",
+        total,
+        if head.len() as u64 >= total {
+            String::new()
+        } else {
+            format!(", showing the first {}", head.len())
+        }
+    ));
+    if !head.is_empty() {
+        for line in String::from_utf8_lossy(&head).lines() {
+            tracer.detail(format!("| {}", line));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Result};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Instant;
+use tokio::net::UdpSocket;
+use url::Url;
+
+use crate::request::{
+    connect_proxy, socks5_auth, socks5_command, socks5_encode_addr, split_host_port, timed_out, BenchOptions,
+    RespMeta, TimeoutPhase, SOCKS5_UDP_ASSOCIATE,
+};
+use crate::ws::random_bytes;
+
+// Prefixes run_bench uses to tell the two UDP failure modes apart.
+pub const ASSOCIATE_REFUSED: &str = "UDP ASSOCIATE refused";
+pub const NO_RELAY: &str = "no UDP reply";
+
+fn build_query(id: u16, name: &str) -> Result<Vec<u8>> {
+    let mut q = Vec::with_capacity(name.len() + 18);
+    q.extend_from_slice(&id.to_be_bytes());
+    // RD set, one question, no other records.
+    q.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(anyhow!("invalid DNS name: {}", name));
+        }
+        q.push(label.len() as u8);
+        q.extend_from_slice(label.as_bytes());
+    }
+    q.push(0);
+    q.extend_from_slice(&[0, 1, 0, 1]); // QTYPE A, QCLASS IN
+    Ok(q)
+}
+
+// Strips the SOCKS5 UDP request header (RSV, FRAG, ATYP, DST.ADDR, DST.PORT).
+// Fragmented datagrams are not supported and yield `None`.
+fn strip_udp_header(packet: &[u8]) -> Option<&[u8]> {
+    if packet.len() < 4 || packet[2] != 0 {
+        return None;
+    }
+    let addr_len = match packet[3] {
+        1 => 4,
+        4 => 16,
+        3 => 1 + *packet.get(4)? as usize,
+        _ => return None,
+    };
+    packet.get(4 + addr_len + 2..)
+}
+
+fn is_reply_to(dns: &[u8], id: u16) -> bool {
+    dns.len() >= 12 && dns[..2] == id.to_be_bytes() && dns[2] & 0x80 != 0
+}
+
+// One A query through SOCKS5 UDP ASSOCIATE. The control connection has to
+// stay open for as long as the relay is used.
+pub async fn dns_query(proxy: &Url, proxy_addr: &str, opts: &BenchOptions) -> Result<RespMeta> {
+    let timeout_dur = opts.timeout;
+    opts.socket.pace().await;
+    let mut control = connect_proxy(proxy_addr, timeout_dur, &opts.socket).await?;
+    tokio::time::timeout(timeout_dur, socks5_auth(&mut control, proxy))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Tunnel, "SOCKS5 handshake timed out"))??;
+    let (bound_ip, bound_port) = tokio::time::timeout(
+        timeout_dur,
+        socks5_command(&mut control, SOCKS5_UDP_ASSOCIATE, "0.0.0.0", 0),
+    )
+    .await
+    .map_err(|_| anyhow!("{}: no reply from proxy", ASSOCIATE_REFUSED))?
+    .map_err(|e| anyhow!("{}: {:#}", ASSOCIATE_REFUSED, e))?;
+
+    // Servers commonly answer with 0.0.0.0, meaning "the address you
+    // reached me at".
+    let relay_ip = match bound_ip {
+        Some(ip) if !ip.is_unspecified() => ip,
+        _ => control.peer_addr()?.ip(),
+    };
+    let relay = SocketAddr::new(relay_ip, bound_port);
+    let local: SocketAddr = match (relay_ip, opts.socket.addr) {
+        (_, Some(l)) if l.is_ipv4() == relay_ip.is_ipv4() => (l.ip(), 0).into(),
+        (IpAddr::V4(_), _) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        (IpAddr::V6(_), _) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(relay).await?;
+
+    let (server_host, server_port) = split_host_port(&opts.dns_server)?;
+    let name = opts.dns_name.as_deref().unwrap_or(&opts.target.host);
+    let id = u16::from_be_bytes(random_bytes::<2>());
+    let mut packet = vec![0, 0, 0];
+    socks5_encode_addr(&mut packet, &server_host, server_port)?;
+    packet.extend_from_slice(&build_query(id, name)?);
+
+    let start = Instant::now();
+    socket.send(&packet).await?;
+    let deadline = start + timeout_dur;
+    let mut buf = vec![0u8; 4096];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let n = match tokio::time::timeout(left, socket.recv(&mut buf)).await {
+            Ok(res) => res?,
+            Err(_) => {
+                return Err(anyhow!(
+                    "{} from relay {} within {:?}",
+                    NO_RELAY,
+                    relay,
+                    timeout_dur
+                ))
+            }
+        };
+        if strip_udp_header(&buf[..n]).is_some_and(|dns| is_reply_to(dns, id)) {
+            break;
+        }
+    }
+    let rtt = start.elapsed();
+    drop(control);
+    Ok(RespMeta {
+        status: None,
+        ..RespMeta::new(true, rtt, 0)
+    })
+}
@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Result};
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::request::{single_request, BenchOptions, Phases};
+
+pub struct WaitOptions {
+    pub interval: Duration,
+    pub deadline: Duration,
+    pub consecutive: u32,
+    pub quiet: bool,
+}
+
+// Sends one request per interval until `consecutive` in a row meet the
+// success criteria, or fails once the deadline has passed.
+pub async fn run(proxy: &Url, proxy_addr: &str, opts: &BenchOptions, wait: WaitOptions) -> Result<()> {
+    let start = Instant::now();
+    let mut streak = 0u32;
+    for attempt in 1.. {
+        let t = Instant::now();
+        let mut phases = Phases::default();
+        let outcome = match single_request(proxy, proxy_addr, opts, &mut phases).await {
+            Ok(meta) if meta.success => {
+                streak += 1;
+                format!(
+                    "ok {} in {:.2}ms",
+                    meta.status.unwrap_or(0),
+                    meta.dur.unwrap_or_default().as_secs_f64() * 1000.0
+                )
+            }
+            Ok(meta) => {
+                streak = 0;
+                match meta.status {
+                    Some(code) => format!("fail: status {}", code),
+                    None => "fail".to_string(),
+                }
+            }
+            Err(e) => {
+                streak = 0;
+                format!("error: {:#}", e)
+            }
+        };
+        if !wait.quiet {
+            println!("[{:>6.1}s] #{} {}", start.elapsed().as_secs_f64(), attempt, outcome);
+        }
+        if streak >= wait.consecutive {
+            if !wait.quiet {
+                println!("healthy after {} attempt(s), {:.1}s", attempt, start.elapsed().as_secs_f64());
+            }
+            return Ok(());
+        }
+        let next = t + wait.interval;
+        if next.duration_since(start) > wait.deadline {
+            break;
+        }
+        tokio::time::sleep_until(next.into()).await;
+    }
+    Err(anyhow!(
+        "not healthy within {}",
+        humantime::format_duration(wait.deadline)
+    ))
+}
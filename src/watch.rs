@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use url::Url;
+
+use crate::cli::{Args, Units};
+use crate::pretty;
+use crate::request::{run_bench, BenchOptions};
+use crate::stats::{RunSummary, Stats};
+
+#[derive(Serialize)]
+struct WatchEntry {
+    run: usize,
+    started_at: String,
+    #[serde(flatten)]
+    summary: RunSummary,
+}
+
+fn delta_ms(now: Option<f64>, before: Option<f64>, units: Units) -> String {
+    match (now, before) {
+        (Some(a), Some(b)) => pretty::fmt_delta(a - b, units),
+        _ => "-".into(),
+    }
+}
+
+// --watch: reruns the bench every `interval` (right away if a run took
+// longer) with a fresh Stats each time, printing one line per run with the
+// change against the previous one. Ctrl-C lets the current run finish, then
+// prints the trend table.
+pub async fn run(
+    args: &Args,
+    interval: Duration,
+    proxy: Arc<Url>,
+    proxy_addr: &str,
+    opts: BenchOptions,
+) -> Result<Stats> {
+    let mut log = match &args.watch_log {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("opening {}", path.display()))?,
+        ),
+        None => None,
+    };
+    // Listening for Ctrl-C replaces the default of killing the process, so
+    // a run in progress is not cut short.
+    let (stop_tx, mut stop) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        let _ = stop_tx.send(true);
+    });
+
+    let opts = Arc::new(opts);
+    // Start time, summary and p50 (as in the summary line) of each run.
+    let mut runs: Vec<(SystemTime, RunSummary, Option<f64>)> = Vec::new();
+    let last = loop {
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        let stats = run_bench(proxy.clone(), proxy_addr, opts.clone()).await?;
+        let summary = RunSummary::from_stats(&stats);
+        let p50 = stats.latency_percentile(0.50);
+        if !args.quiet {
+            let trend = match runs.last() {
+                Some((_, prev, prev_p50)) => format!(
+                    "  Δp50 {}  Δok {:+.1}%",
+                    delta_ms(p50, *prev_p50, args.units),
+                    summary.success_rate - prev.success_rate
+                ),
+                None => String::new(),
+            };
+            println!(
+                "{} #{} {}{}",
+                humantime::format_rfc3339_seconds(started_at),
+                runs.len() + 1,
+                pretty::summary_line(&stats),
+                trend
+            );
+        }
+        if let Some(file) = log.as_mut() {
+            let entry = WatchEntry {
+                run: runs.len() + 1,
+                started_at: humantime::format_rfc3339_millis(started_at).to_string(),
+                summary: summary.clone(),
+            };
+            writeln!(file, "{}", serde_json::to_string(&entry)?).context("writing --watch-log")?;
+        }
+        runs.push((started_at, summary, p50));
+        if *stop.borrow() {
+            break stats;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval.saturating_sub(started.elapsed())) => {}
+            _ = stop.changed() => break stats,
+        }
+    };
+
+    if !args.quiet {
+        println!(
+            "\n{:>4}  {:>20}  {:>8}  {:>8}  {:>10}  {:>10}",
+            "Run", "Started", "Requests", "Success", "p50", "p99"
+        );
+        let fmt_ms = |v: Option<f64>| pretty::fmt_ms(v, args.units);
+        for (i, (started_at, s, p50)) in runs.iter().enumerate() {
+            println!(
+                "{:>4}  {:>20}  {:>8}  {:>7.1}%  {:>10}  {:>10}",
+                i + 1,
+                humantime::format_rfc3339_seconds(*started_at).to_string(),
+                s.requests,
+                s.success_rate,
+                fmt_ms(*p50),
+                fmt_ms(s.latency_p99_ms)
+            );
+        }
+    }
+    Ok(last)
+}
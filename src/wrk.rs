@@ -0,0 +1,226 @@
+use std::fmt::Write as _;
+
+use crate::stats::Stats;
+
+// Summary in wrk's exact layout (--output wrk), so tools that parse wrk can
+// read our results unchanged. Number formatting follows wrk's units.c: two
+// decimals, the unit switched up once a value reaches 85% of the next one.
+
+struct Units {
+    scale: f64,
+    base: &'static str,
+    units: &'static [&'static str],
+}
+
+const TIME_US: Units = Units {
+    scale: 1000.0,
+    base: "us",
+    units: &["ms", "s"],
+};
+const TIME_S: Units = Units {
+    scale: 60.0,
+    base: "s",
+    units: &["m", "h"],
+};
+const METRIC: Units = Units {
+    scale: 1000.0,
+    base: "",
+    units: &["k", "M", "G", "T", "P"],
+};
+const BINARY: Units = Units {
+    scale: 1024.0,
+    base: "",
+    units: &["K", "M", "G", "T", "P"],
+};
+
+fn format_units(mut n: f64, m: &Units, precision: usize) -> String {
+    let mut unit = m.base;
+    let threshold = m.scale * 0.85;
+    // wrk never moves to the last unit in the table.
+    for &next in &m.units[..m.units.len() - 1] {
+        if n < threshold {
+            break;
+        }
+        n /= m.scale;
+        unit = next;
+    }
+    format!("{:.*}{}", precision, n, unit)
+}
+
+fn format_time_us(n: f64) -> String {
+    if n >= 1_000_000.0 {
+        format_units(n / 1_000_000.0, &TIME_S, 2)
+    } else {
+        format_units(n, &TIME_US, 2)
+    }
+}
+
+fn format_metric(n: f64) -> String {
+    format_units(n, &METRIC, 2)
+}
+
+fn format_binary(n: f64) -> String {
+    format_units(n, &BINARY, 2)
+}
+
+// wrk reserves two characters for the unit suffix so columns line up
+// whether or not a value has one.
+fn units_column(out: &mut String, msg: &str, width: usize) {
+    let bytes = msg.as_bytes();
+    let mut pad = 2;
+    for &b in bytes.iter().rev().take(2) {
+        if b.is_ascii_alphabetic() {
+            pad -= 1;
+        }
+    }
+    let _ = write!(out, "{:>w$}{}", msg, &"  "[..pad], w = width - pad);
+}
+
+// Mean, sample standard deviation, max and the share of samples within one
+// standard deviation of the mean, as in wrk's stats.c.
+fn describe(samples: &[f64]) -> (f64, f64, f64, f64) {
+    if samples.is_empty() {
+        return (0.0, 0.0, 0.0, 0.0);
+    }
+    let n = samples.len() as f64;
+    let mean = samples.iter().sum::<f64>() / n;
+    let stdev = if samples.len() > 1 {
+        (samples.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+    } else {
+        0.0
+    };
+    let max = samples.iter().copied().fold(0.0, f64::max);
+    let within = samples.iter().filter(|&&v| v >= mean - stdev && v <= mean + stdev).count();
+    (mean, stdev, max, within as f64 * 100.0 / n)
+}
+
+fn stats_row(out: &mut String, name: &str, samples: &[f64], fmt: fn(f64) -> String) {
+    let (mean, stdev, max, within) = describe(samples);
+    let _ = write!(out, "    {:<10}", name);
+    units_column(out, &fmt(mean), 8);
+    units_column(out, &fmt(stdev), 10);
+    units_column(out, &fmt(max), 9);
+    let _ = writeln!(out, "{:8.2}%", within);
+}
+
+// There is one event loop rather than wrk's threads, so the run is reported
+// as a single thread and Req/Sec is the per-second series of the whole run.
+// Bytes are response bodies read (download mode); http mode stops at the
+// headers and reports 0B.
+pub fn render(stats: &Stats, url: &str, connections: usize) -> String {
+    let mut out = String::new();
+    let runtime_us = stats.total_duration.as_micros() as f64;
+    let _ = writeln!(out, "Running {} test @ {}", format_units(runtime_us / 1_000_000.0, &TIME_S, 0), url);
+    let _ = writeln!(out, "  1 threads and {} connections", connections);
+    let _ = writeln!(out, "  Thread Stats{:>6}{:>11}{:>8}{:>12}", "Avg", "Stdev", "Max", "+/- Stdev");
+    let latencies: Vec<f64> = stats.latencies_us.iter().map(|&us| us as f64).collect();
+    stats_row(&mut out, "Latency", &latencies, format_time_us);
+    let per_sec: Vec<f64> = match stats.completions_per_sec.keys().last() {
+        Some(&last) => (0..=last)
+            .map(|sec| stats.completions_per_sec.get(&sec).copied().unwrap_or(0) as f64)
+            .collect(),
+        None => Vec::new(),
+    };
+    stats_row(&mut out, "Req/Sec", &per_sec, format_metric);
+    let _ = writeln!(out, "  Latency Distribution");
+    for p in [50.0, 75.0, 90.0, 99.0] {
+        let us = stats.latency_percentile(p / 100.0).unwrap_or(0.0) * 1000.0;
+        let _ = write!(out, "{:7.0}%", p);
+        units_column(&mut out, &format_time_us(us), 10);
+        out.push('\n');
+    }
+    let bytes = stats.download.bytes as f64;
+    let _ = writeln!(
+        out,
+        "  {} requests in {}, {}B read",
+        stats.completed(),
+        format_time_us(runtime_us),
+        format_binary(bytes)
+    );
+    let connect = stats.conn_errors + stats.tls_errors;
+    if connect > 0 || stats.truncated > 0 || stats.timeout_errors > 0 {
+        let _ = writeln!(
+            out,
+            "  Socket errors: connect {}, read {}, write 0, timeout {}",
+            connect, stats.truncated, stats.timeout_errors
+        );
+    }
+    let non_2xx_3xx: usize = stats
+        .status_counts
+        .iter()
+        .filter(|(&code, _)| !(200..400).contains(&code))
+        .map(|(_, &n)| n)
+        .sum();
+    if non_2xx_3xx > 0 {
+        let _ = writeln!(out, "  Non-2xx or 3xx responses: {}", non_2xx_3xx);
+    }
+    let secs = runtime_us / 1_000_000.0;
+    let (req_per_s, bytes_per_s) = if secs > 0.0 {
+        (stats.completed() as f64 / secs, bytes / secs)
+    } else {
+        (0.0, 0.0)
+    };
+    let _ = writeln!(out, "Requests/sec: {:9.2}", req_per_s);
+    let _ = writeln!(out, "Transfer/sec: {:>10}B", format_binary(bytes_per_s));
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn units_switch_at_85_percent() {
+        assert_eq!(format_time_us(849.0), "849.00us");
+        assert_eq!(format_time_us(850.0), "0.85ms");
+        assert_eq!(format_time_us(1_500.0), "1.50ms");
+        assert_eq!(format_time_us(2_500_000.0), "2.50s");
+        assert_eq!(format_time_us(90_000_000.0), "1.50m");
+        assert_eq!(format_metric(849.0), "849.00");
+        assert_eq!(format_metric(999.0), "1.00k");
+        assert_eq!(format_metric(1_234.0), "1.23k");
+        assert_eq!(format_binary(870.0), "870.00");
+        assert_eq!(format_binary(871.0), "0.85K");
+        assert_eq!(format_binary(3.0 * 1024.0 * 1024.0), "3.00M");
+        // Never the last unit in the table.
+        assert_eq!(format_units(5e6, &TIME_US, 2), "5000.00ms");
+    }
+
+    #[test]
+    fn golden_summary() {
+        let mut stats = Stats::builder()
+            .latencies(&[1, 2, 3, 4, 10].map(Duration::from_millis))
+            .success(4)
+            .fail(1)
+            .status(200, 4)
+            .status(502, 1)
+            .total_duration(Duration::from_secs(2))
+            .second(0, 3, 3)
+            .second(1, 2, 1)
+            .build();
+        stats.download.record(3 * 1024 * 1024, Duration::from_millis(10));
+        let expected = "\
+Running 2s test @ http://127.0.0.1:8080/
+  1 threads and 4 connections
+  Thread Stats   Avg      Stdev     Max   +/- Stdev
+    Latency     4.00ms    3.54ms  10.00ms   80.00%
+    Req/Sec     2.50      0.71     3.00    100.00%
+  Latency Distribution
+     50%    3.00ms
+     75%    4.00ms
+     90%   10.00ms
+     99%   10.00ms
+  5 requests in 2.00s, 3.00MB read
+  Non-2xx or 3xx responses: 1
+Requests/sec:      2.50
+Transfer/sec:      1.50MB
+";
+        assert_eq!(render(&stats, "http://127.0.0.1:8080/", 4), expected);
+
+        stats.conn_errors = 2;
+        stats.timeout_errors = 1;
+        assert!(render(&stats, "http://127.0.0.1:8080/", 4)
+            .contains("\n  Socket errors: connect 2, read 0, write 0, timeout 1\n"));
+    }
+}
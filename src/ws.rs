@@ -0,0 +1,190 @@
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::request::{timed_out, Target, TimeoutPhase, USER_AGENT};
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HEAD: usize = 16 * 1024;
+
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+pub enum Upgrade {
+    Accepted,
+    Rejected(u16),
+}
+
+// The key and frame masks only have to be unpredictable to intermediaries,
+// so std's randomly seeded hasher is enough here.
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut out = [0u8; N];
+    for chunk in out.chunks_mut(8) {
+        let bytes = RandomState::new().build_hasher().finish().to_le_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+    out
+}
+
+pub fn accept_key(key: &str) -> String {
+    let mut sha = Sha1::new();
+    sha.update(key.as_bytes());
+    sha.update(WS_GUID.as_bytes());
+    STANDARD.encode(sha.finalize())
+}
+
+// A WebSocket connection right after the upgrade. `buf` holds bytes the
+// server sent past the end of the response head.
+pub struct WsConn<S> {
+    stream: S,
+    buf: Vec<u8>,
+}
+
+pub async fn handshake<S>(mut stream: S, target: &Target, timeout_dur: Duration) -> Result<(Upgrade, WsConn<S>)>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let key = STANDARD.encode(random_bytes::<16>());
+    let path = if target.path.is_empty() { "/" } else { &target.path };
+    let req = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path, target.host_header, USER_AGENT, key
+    );
+    tokio::time::timeout(timeout_dur, stream.write_all(req.as_bytes()))
+        .await
+        .map_err(|_| timed_out(TimeoutPhase::Request, "WebSocket upgrade write timed out"))??;
+
+    let mut buf = Vec::with_capacity(1024);
+    let mut tmp = [0u8; 1024];
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > MAX_HEAD {
+            return Err(anyhow!("WebSocket upgrade response head too large"));
+        }
+        let n = tokio::time::timeout(timeout_dur, stream.read(&mut tmp))
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::Request, "WebSocket upgrade response timed out"))??;
+        if n == 0 {
+            return Err(anyhow!("connection closed during WebSocket upgrade"));
+        }
+        buf.extend_from_slice(&tmp[..n]);
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    buf.drain(..head_end + 4);
+
+    let mut lines = head.split("\r\n");
+    let status: u16 = lines
+        .next()
+        .and_then(|l| l.split_whitespace().nth(1))
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| anyhow!("malformed WebSocket upgrade response"))?;
+    let conn = WsConn { stream, buf };
+    if status != 101 {
+        return Ok((Upgrade::Rejected(status), conn));
+    }
+    let header = |name: &str| {
+        head.split("\r\n").skip(1).find_map(|l| {
+            let (k, v) = l.split_once(':')?;
+            k.trim().eq_ignore_ascii_case(name).then(|| v.trim().to_string())
+        })
+    };
+    if !header("upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
+        return Err(anyhow!("WebSocket handshake invalid: missing `Upgrade: websocket`"));
+    }
+    match header("sec-websocket-accept") {
+        Some(v) if v == accept_key(&key) => Ok((Upgrade::Accepted, conn)),
+        Some(_) => Err(anyhow!("WebSocket handshake invalid: Sec-WebSocket-Accept mismatch")),
+        None => Err(anyhow!("WebSocket handshake invalid: missing Sec-WebSocket-Accept")),
+    }
+}
+
+impl<S> WsConn<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    async fn fill(&mut self, n: usize) -> Result<()> {
+        let mut tmp = [0u8; 1024];
+        while self.buf.len() < n {
+            let read = self.stream.read(&mut tmp).await?;
+            if read == 0 {
+                return Err(anyhow!("connection closed mid WebSocket frame"));
+            }
+            self.buf.extend_from_slice(&tmp[..read]);
+        }
+        Ok(())
+    }
+
+    async fn take(&mut self, n: usize) -> Result<Vec<u8>> {
+        self.fill(n).await?;
+        Ok(self.buf.drain(..n).collect())
+    }
+
+    async fn send(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        // Control frames only; their payload is capped at 125 bytes.
+        let mask = random_bytes::<4>();
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+
+    async fn read_frame(&mut self) -> Result<(u8, Vec<u8>)> {
+        let head = self.take(2).await?;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+        let len = match head[1] & 0x7F {
+            126 => {
+                let b = self.take(2).await?;
+                u16::from_be_bytes([b[0], b[1]]) as usize
+            }
+            127 => {
+                let b = self.take(8).await?;
+                let mut n = [0u8; 8];
+                n.copy_from_slice(&b);
+                usize::try_from(u64::from_be_bytes(n))
+                    .map_err(|_| anyhow!("WebSocket frame too large"))?
+            }
+            n => n as usize,
+        };
+        let mask = if masked { Some(self.take(4).await?) } else { None };
+        let mut payload = self.take(len).await?;
+        if let Some(m) = mask {
+            payload.iter_mut().enumerate().for_each(|(i, b)| *b ^= m[i % 4]);
+        }
+        Ok((opcode, payload))
+    }
+
+    // Sends one ping and waits for the matching pong, skipping any data
+    // frames the server pushes in between.
+    pub async fn ping(&mut self, timeout_dur: Duration) -> Result<Duration> {
+        let payload = random_bytes::<8>();
+        let start = Instant::now();
+        let exchange = async {
+            self.send(OP_PING, &payload).await?;
+            loop {
+                match self.read_frame().await? {
+                    (OP_PONG, p) if p == payload => return Ok(start.elapsed()),
+                    (OP_CLOSE, _) => return Err(anyhow!("server closed the WebSocket instead of answering ping")),
+                    _ => continue,
+                }
+            }
+        };
+        tokio::time::timeout(timeout_dur, exchange)
+            .await
+            .map_err(|_| timed_out(TimeoutPhase::Body, "WebSocket ping timed out"))?
+    }
+
+    pub async fn close(mut self) {
+        let _ = self.send(OP_CLOSE, &1000u16.to_be_bytes()).await;
+        let _ = self.stream.shutdown().await;
+    }
+}
@@ -0,0 +1,481 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, Once};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::link::ShareLink;
+
+// Children are tracked globally so the panic hook can kill them even when
+// a panic skips XrayProcess's destructor. Only main-thread panics count:
+// request tasks run on the runtime's workers, and the bench loop reports
+// their panics while xray keeps serving the rest of the run.
+static CHILDREN: Mutex<Option<HashMap<u32, Child>>> = Mutex::new(None);
+static PANIC_HOOK: Once = Once::new();
+
+fn install_panic_hook() {
+    PANIC_HOOK.call_once(|| {
+        let prev = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if std::thread::current().name() == Some("main") {
+                kill_all();
+            }
+            prev(info);
+        }));
+    });
+}
+
+fn kill_all() {
+    if let Ok(mut guard) = CHILDREN.lock() {
+        if let Some(children) = guard.as_mut() {
+            for child in children.values_mut() {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            children.clear();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InboundKind {
+    Socks,
+    Http,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InboundSpec {
+    pub kind: InboundKind,
+    pub listen: SocketAddr,
+}
+
+impl InboundSpec {
+    // `socks:2080`, `http:127.0.0.1:8080`, `socks:[::1]:1080`
+    pub fn parse(spec: &str) -> Result<Self> {
+        let (kind, rest) = spec
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected TYPE:PORT or TYPE:ADDR:PORT, got {}", spec))?;
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "socks" | "socks5" => InboundKind::Socks,
+            "http" => InboundKind::Http,
+            other => return Err(anyhow!("unsupported inbound type: {} (use socks or http)", other)),
+        };
+        let listen = if let Ok(port) = rest.parse::<u16>() {
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+        } else {
+            rest.parse::<SocketAddr>()
+                .map_err(|_| anyhow!("invalid inbound address: {}", rest))?
+        };
+        Ok(Self { kind, listen })
+    }
+}
+
+pub fn client_config(link: &ShareLink, inbound: &InboundSpec) -> Value {
+    let (protocol, settings) = match inbound.kind {
+        InboundKind::Socks => ("socks", json!({ "auth": "noauth", "udp": true })),
+        InboundKind::Http => ("http", json!({})),
+    };
+    let inbound_tag = format!("{}-in", protocol);
+    json!({
+        "log": { "loglevel": "warning" },
+        "inbounds": [{
+            "tag": inbound_tag,
+            "listen": inbound.listen.ip().to_string(),
+            "port": inbound.listen.port(),
+            "protocol": protocol,
+            "settings": settings
+        }],
+        "outbounds": [
+            link.outbound("proxy"),
+            { "protocol": "freedom", "tag": "direct" }
+        ],
+        "routing": {
+            "domainStrategy": "AsIs",
+            "rules": [{ "type": "field", "inboundTag": [inbound_tag], "outboundTag": "proxy" }]
+        }
+    })
+}
+
+pub const SWEEP_INBOUND_TAG: &str = "xray-tester-in";
+
+// Replaces the inbounds of a user config with a single local inbound and
+// routes it to the first (default) outbound, so user routing rules such as
+// "private IPs go direct" cannot bypass the outbound under test.
+pub fn inject_inbound(config: &mut Value, inbound: &InboundSpec) -> Result<()> {
+    let root = config
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("config root is not a JSON object"))?;
+    let outbounds = root
+        .get_mut("outbounds")
+        .and_then(Value::as_array_mut)
+        .filter(|o| !o.is_empty())
+        .ok_or_else(|| anyhow!("config has no outbounds"))?;
+    let first = outbounds[0]
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("first outbound is not a JSON object"))?;
+    let tag = match first.get("tag").and_then(Value::as_str) {
+        Some(t) if !t.is_empty() => t.to_string(),
+        _ => {
+            first.insert("tag".into(), json!("xray-tester-out"));
+            "xray-tester-out".to_string()
+        }
+    };
+
+    let (protocol, settings) = match inbound.kind {
+        InboundKind::Socks => ("socks", json!({ "auth": "noauth", "udp": true })),
+        InboundKind::Http => ("http", json!({})),
+    };
+    root.insert(
+        "inbounds".into(),
+        json!([{
+            "tag": SWEEP_INBOUND_TAG,
+            "listen": inbound.listen.ip().to_string(),
+            "port": inbound.listen.port(),
+            "protocol": protocol,
+            "settings": settings
+        }]),
+    );
+
+    let rule = json!({ "type": "field", "inboundTag": [SWEEP_INBOUND_TAG], "outboundTag": tag });
+    let routing = root
+        .entry("routing")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("`routing` is not a JSON object"))?;
+    match routing.get_mut("rules").and_then(Value::as_array_mut) {
+        Some(rules) => rules.insert(0, rule),
+        None => {
+            routing.insert("rules".into(), json!([rule]));
+        }
+    }
+    Ok(())
+}
+
+pub struct XrayProcess {
+    pid: u32,
+    config_path: PathBuf,
+    output: Arc<Mutex<OutputTail>>,
+    pub listen: SocketAddr,
+}
+
+// Keeps the last few KiB of the child's stdout/stderr. The pipes must be
+// drained for the whole lifetime of the child, otherwise xray blocks on a
+// full pipe once it logs enough.
+#[derive(Default)]
+struct OutputTail {
+    buf: Vec<u8>,
+}
+
+impl OutputTail {
+    const LIMIT: usize = 8 * 1024;
+
+    fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        if self.buf.len() > Self::LIMIT {
+            let cut = self.buf.len() - Self::LIMIT;
+            self.buf.drain(..cut);
+        }
+    }
+}
+
+fn drain_into(mut pipe: impl Read + Send + 'static, tail: Arc<Mutex<OutputTail>>) {
+    std::thread::spawn(move || {
+        let mut chunk = [0u8; 4096];
+        loop {
+            match pipe.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if let Ok(mut t) = tail.lock() {
+                        t.push(&chunk[..n]);
+                    }
+                }
+            }
+        }
+    });
+}
+
+impl XrayProcess {
+    pub async fn spawn_link(bin: &Path, link: &ShareLink, startup_timeout: Duration) -> Result<Self> {
+        let inbound = InboundSpec {
+            kind: InboundKind::Socks,
+            listen: free_local_port()?,
+        };
+        let config = client_config(link, &inbound);
+        let listen = inbound.listen;
+        Self::spawn(bin, &config, listen, startup_timeout).await
+    }
+
+    pub async fn spawn(
+        bin: &Path,
+        config: &Value,
+        listen: SocketAddr,
+        startup_timeout: Duration,
+    ) -> Result<Self> {
+        install_panic_hook();
+        let config_path = std::env::temp_dir().join(format!(
+            "xray-tester-{}-{}.json",
+            std::process::id(),
+            listen.port()
+        ));
+        std::fs::write(&config_path, serde_json::to_vec_pretty(config)?)
+            .with_context(|| format!("writing xray config {}", config_path.display()))?;
+
+        let child = Command::new(bin)
+            .arg("run")
+            .arg("-c")
+            .arg(&config_path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = std::fs::remove_file(&config_path);
+                return Err(anyhow!(
+                    "failed to start xray binary `{}`: {} (use --xray-bin to point at it)",
+                    bin.display(),
+                    e
+                ));
+            }
+        };
+        let output = Arc::new(Mutex::new(OutputTail::default()));
+        if let Some(out) = child.stdout.take() {
+            drain_into(out, output.clone());
+        }
+        if let Some(err) = child.stderr.take() {
+            drain_into(err, output.clone());
+        }
+        let pid = child.id();
+        CHILDREN
+            .lock()
+            .map_err(|_| anyhow!("xray process registry poisoned"))?
+            .get_or_insert_with(HashMap::new)
+            .insert(pid, child);
+        let proc = Self {
+            pid,
+            config_path,
+            output,
+            listen,
+        };
+        proc.wait_ready(startup_timeout).await?;
+        Ok(proc)
+    }
+
+    fn try_wait(&self) -> Option<std::process::ExitStatus> {
+        let mut guard = CHILDREN.lock().ok()?;
+        let child = guard.as_mut()?.get_mut(&self.pid)?;
+        child.try_wait().ok().flatten()
+    }
+
+    fn output_tail(&self) -> String {
+        let text = self
+            .output
+            .lock()
+            .map(|t| String::from_utf8_lossy(&t.buf).trim().to_string())
+            .unwrap_or_default();
+        if text.is_empty() {
+            String::new()
+        } else {
+            format!("\n--- xray output ---\n{}", text)
+        }
+    }
+
+    async fn wait_ready(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = self.try_wait() {
+                // Give the reader threads a moment to collect the last lines.
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                return Err(anyhow!(
+                    "xray exited with {} before its inbound came up{}",
+                    status,
+                    self.output_tail()
+                ));
+            }
+            let probe = probe_socks_inbound(self.listen);
+            if let Ok(Ok(())) = tokio::time::timeout(Duration::from_millis(250), probe).await {
+                return Ok(());
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "xray inbound {} was not ready within {:?}{}",
+                    self.listen,
+                    timeout,
+                    self.output_tail()
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+// A bare TCP accept is not enough: xray binds the port slightly before the
+// inbound handler is wired up, so speak the first step of SOCKS5.
+pub async fn probe_socks_inbound(addr: SocketAddr) -> Result<()> {
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(&[5, 1, 0]).await?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply != [5, 0] {
+        return Err(anyhow!("unexpected SOCKS5 greeting reply {:?}", reply));
+    }
+    Ok(())
+}
+
+impl Drop for XrayProcess {
+    fn drop(&mut self) {
+        if let Ok(mut guard) = CHILDREN.lock() {
+            if let Some(mut child) = guard.as_mut().and_then(|c| c.remove(&self.pid)) {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+        }
+        let _ = std::fs::remove_file(&self.config_path);
+    }
+}
+
+// Binding port 0 lets the OS pick a free port; the listener is dropped right
+// away so xray can bind it. The window for a race is tiny on a local box.
+pub fn free_local_port() -> Result<SocketAddr> {
+    let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .context("allocating a local port for the xray inbound")?;
+    Ok(listener.local_addr()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inject(mut config: Value, spec: &str) -> Result<Value> {
+        inject_inbound(&mut config, &InboundSpec::parse(spec)?)?;
+        Ok(config)
+    }
+
+    #[test]
+    fn inject_without_routing() {
+        let config = json!({
+            "log": { "loglevel": "debug" },
+            "inbounds": [{ "tag": "user-in", "port": 10808, "protocol": "socks" }],
+            "outbounds": [{ "tag": "proxy", "protocol": "vless" }, { "tag": "direct", "protocol": "freedom" }]
+        });
+        assert_eq!(
+            inject(config, "http:127.0.0.1:8080").unwrap(),
+            json!({
+                "log": { "loglevel": "debug" },
+                "inbounds": [{
+                    "tag": "xray-tester-in",
+                    "listen": "127.0.0.1",
+                    "port": 8080,
+                    "protocol": "http",
+                    "settings": {}
+                }],
+                "outbounds": [{ "tag": "proxy", "protocol": "vless" }, { "tag": "direct", "protocol": "freedom" }],
+                "routing": {
+                    "rules": [{ "type": "field", "inboundTag": ["xray-tester-in"], "outboundTag": "proxy" }]
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn inject_rule_goes_first() {
+        let config = json!({
+            "outbounds": [{ "tag": "proxy", "protocol": "trojan" }, { "tag": "direct", "protocol": "freedom" }],
+            "routing": {
+                "domainStrategy": "IPIfNonMatch",
+                "rules": [
+                    { "type": "field", "ip": ["geoip:private"], "outboundTag": "direct" },
+                    { "type": "field", "network": "tcp,udp", "outboundTag": "proxy" }
+                ]
+            }
+        });
+        assert_eq!(
+            inject(config, "socks:[::1]:1080").unwrap(),
+            json!({
+                "outbounds": [{ "tag": "proxy", "protocol": "trojan" }, { "tag": "direct", "protocol": "freedom" }],
+                "routing": {
+                    "domainStrategy": "IPIfNonMatch",
+                    "rules": [
+                        { "type": "field", "inboundTag": ["xray-tester-in"], "outboundTag": "proxy" },
+                        { "type": "field", "ip": ["geoip:private"], "outboundTag": "direct" },
+                        { "type": "field", "network": "tcp,udp", "outboundTag": "proxy" }
+                    ]
+                },
+                "inbounds": [{
+                    "tag": "xray-tester-in",
+                    "listen": "::1",
+                    "port": 1080,
+                    "protocol": "socks",
+                    "settings": { "auth": "noauth", "udp": true }
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn inject_tags_an_untagged_first_outbound() {
+        for first in [json!({ "protocol": "vmess" }), json!({ "protocol": "vmess", "tag": "" })] {
+            let config = inject(
+                json!({ "outbounds": [first, { "tag": "direct", "protocol": "freedom" }], "routing": {} }),
+                "socks:1080",
+            )
+            .unwrap();
+            assert_eq!(config["outbounds"][0], json!({ "protocol": "vmess", "tag": "xray-tester-out" }));
+            assert_eq!(config["outbounds"][1], json!({ "tag": "direct", "protocol": "freedom" }));
+            assert_eq!(
+                config["routing"]["rules"],
+                json!([{ "type": "field", "inboundTag": ["xray-tester-in"], "outboundTag": "xray-tester-out" }])
+            );
+        }
+    }
+
+    #[test]
+    fn inject_rejects_unusable_configs() {
+        for (config, err) in [
+            (json!({ "outbounds": [] }), "config has no outbounds"),
+            (json!({ "inbounds": [] }), "config has no outbounds"),
+            (json!({ "outbounds": {} }), "config has no outbounds"),
+            (json!({ "outbounds": ["proxy"] }), "first outbound is not a JSON object"),
+            (json!({ "outbounds": [{ "tag": "p" }], "routing": [] }), "`routing` is not a JSON object"),
+            (json!([]), "config root is not a JSON object"),
+        ] {
+            assert_eq!(inject(config, "socks:1080").unwrap_err().to_string(), err);
+        }
+    }
+
+    // Byte-for-byte what `config <URI> --inbound <SPEC> -o FILE` writes.
+    #[test]
+    fn full_configs_match_fixtures() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/configs");
+        let cases = [
+    ("vless-tcp-tls", "vless://b831381d-6324-4d53-ad4f-8cda48b30811@vl.example.com:443?type=tcp&security=tls&sni=vl.example.com&fp=chrome&alpn=h2,http/1.1&flow=xtls-rprx-vision#tcp-tls", "socks:1080"),
+    ("vless-tcp-reality", "vless://b831381d-6324-4d53-ad4f-8cda48b30811@198.51.100.1:443?type=tcp&security=reality&sni=www.example.com&fp=chrome&pbk=SbVKOEMjK0sIlbwg4akyBg5mL5KZwwB-ed4eEE7YnRc&sid=6ba85179e30d4fc2&flow=xtls-rprx-vision#tcp-reality", "http:127.0.0.1:8080"),
+    ("vless-ws-tls", "vless://b831381d-6324-4d53-ad4f-8cda48b30811@vl.example.com:443?type=ws&path=%2Fws&host=vl.example.com&security=tls&sni=vl.example.com&fp=chrome&alpn=h2,http/1.1#ws-tls", "socks:0.0.0.0:2080"),
+    ("vless-ws-reality", "vless://b831381d-6324-4d53-ad4f-8cda48b30811@198.51.100.1:443?type=ws&path=%2Fws&security=reality&sni=www.example.com&fp=chrome&pbk=SbVKOEMjK0sIlbwg4akyBg5mL5KZwwB-ed4eEE7YnRc&sid=6ba85179e30d4fc2#ws-reality", "http:8080"),
+    ("vless-grpc-tls", "vless://b831381d-6324-4d53-ad4f-8cda48b30811@vl.example.com:443?type=grpc&serviceName=gun&mode=multi&security=tls&sni=vl.example.com&fp=chrome&alpn=h2,http/1.1#grpc-tls", "socks:[::1]:1080"),
+    ("vless-grpc-reality", "vless://b831381d-6324-4d53-ad4f-8cda48b30811@198.51.100.1:443?type=grpc&serviceName=gun&security=reality&sni=www.example.com&fp=chrome&pbk=SbVKOEMjK0sIlbwg4akyBg5mL5KZwwB-ed4eEE7YnRc&sid=6ba85179e30d4fc2#grpc-reality", "http:127.0.0.1:3128"),
+    ("vmess-tcp-tls", "vmess://eyJ2IjogIjIiLCAicHMiOiAidm0tdGNwIiwgImFkZCI6ICJ2bS5leGFtcGxlLmNvbSIsICJwb3J0IjogIjQ0MyIsICJpZCI6ICJiODMxMzgxZC02MzI0LTRkNTMtYWQ0Zi04Y2RhNDhiMzA4MTEiLCAiYWlkIjogIjAiLCAic2N5IjogImF1dG8iLCAibmV0IjogInRjcCIsICJ0eXBlIjogIm5vbmUiLCAiaG9zdCI6ICJ2bS5leGFtcGxlLmNvbSIsICJwYXRoIjogIiIsICJ0bHMiOiAidGxzIiwgInNuaSI6ICJ2bS5leGFtcGxlLmNvbSJ9", "socks:1080"),
+    ("vmess-ws-tls", "vmess://eyJ2IjogIjIiLCAicHMiOiAidm0td3MiLCAiYWRkIjogInZtLmV4YW1wbGUuY29tIiwgInBvcnQiOiAiNDQzIiwgImlkIjogImI4MzEzODFkLTYzMjQtNGQ1My1hZDRmLThjZGE0OGIzMDgxMSIsICJhaWQiOiAiMCIsICJzY3kiOiAiYXV0byIsICJuZXQiOiAid3MiLCAidHlwZSI6ICJub25lIiwgImhvc3QiOiAidm0uZXhhbXBsZS5jb20iLCAicGF0aCI6ICIvdm0iLCAidGxzIjogInRscyIsICJzbmkiOiAidm0uZXhhbXBsZS5jb20ifQ==", "http:127.0.0.1:8080"),
+    ("vmess-grpc-tls", "vmess://eyJ2IjogIjIiLCAicHMiOiAidm0tZ3JwYyIsICJhZGQiOiAidm0uZXhhbXBsZS5jb20iLCAicG9ydCI6ICI0NDMiLCAiaWQiOiAiYjgzMTM4MWQtNjMyNC00ZDUzLWFkNGYtOGNkYTQ4YjMwODExIiwgImFpZCI6ICIwIiwgInNjeSI6ICJhdXRvIiwgIm5ldCI6ICJncnBjIiwgInR5cGUiOiAibm9uZSIsICJob3N0IjogInZtLmV4YW1wbGUuY29tIiwgInBhdGgiOiAidm1ndW4iLCAidGxzIjogInRscyIsICJzbmkiOiAidm0uZXhhbXBsZS5jb20ifQ==", "socks:0.0.0.0:2080"),
+    ("trojan-tcp-tls", "trojan://s3cret@tr.example.com:443?security=tls&sni=tr.example.com#tr-tcp", "http:8080"),
+    ("trojan-tcp-reality", "trojan://s3cret@198.51.100.3:443?type=tcp&security=reality&sni=www.example.com&fp=chrome&pbk=SbVKOEMjK0sIlbwg4akyBg5mL5KZwwB-ed4eEE7YnRc&sid=6ba85179e30d4fc2#tr-reality", "socks:1080"),
+    ("trojan-ws-tls", "trojan://s3cret@tr.example.com:443?type=ws&path=%2Ftr&host=tr.example.com&security=tls&sni=tr.example.com#tr-ws", "socks:[::1]:1080"),
+    ("trojan-grpc-tls", "trojan://s3cret@tr.example.com:443?type=grpc&serviceName=trgun&security=tls&sni=tr.example.com#tr-grpc", "http:127.0.0.1:3128"),
+    ("shadowsocks", "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpwYTU1@198.51.100.2:8388#ss", "http:8080"),
+        ];
+        for (name, uri, inbound) in cases {
+            let link = ShareLink::parse(uri).unwrap();
+            let config = serde_json::to_string_pretty(&client_config(&link, &InboundSpec::parse(inbound).unwrap())).unwrap();
+            let path = dir.join(format!("{name}.json"));
+            assert_eq!(config + "\n", std::fs::read_to_string(&path).unwrap(), "{}", path.display());
+        }
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), cases.len());
+    }
+}